@@ -0,0 +1,883 @@
+use super::resolver::{self, Resolver};
+use super::{Channel, ChannelBuilder, RetryMethods, DEFAULT_BUFFER_SIZE};
+use crate::service::{Connection, DynamicServiceStream, Subset, Unready};
+use crate::BoxBody;
+
+use http::{header::HeaderName, Request, Uri};
+use rand::Rng;
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap, VecDeque},
+    fmt,
+    hash::{Hash, Hasher},
+    time::Duration,
+};
+use tokio::sync::mpsc::{channel, Sender};
+use tower::discover::Change;
+
+/// Metadata describing an endpoint, which is available to balancing [`Policy`]s.
+///
+/// Attach metadata to an endpoint with [`ChannelBuilder::metadata`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EndpointMetadata {
+    /// The zone (or other locality) the endpoint is in.
+    pub zone: Option<String>,
+    /// The relative weight of the endpoint, defaults to 1.
+    pub weight: u32,
+    /// Arbitrary labels.
+    pub labels: HashMap<String, String>,
+}
+
+impl Default for EndpointMetadata {
+    fn default() -> Self {
+        EndpointMetadata {
+            zone: None,
+            weight: 1,
+            labels: HashMap::new(),
+        }
+    }
+}
+
+/// A load balancing policy, which picks the endpoint to handle each request.
+pub trait Policy: Send + 'static {
+    /// Return the index of the endpoint in `endpoints` which should handle `request`.
+    ///
+    /// `endpoints` contains only the endpoints which are ready and is never empty. An out of
+    /// range index is treated as the last endpoint.
+    fn pick(&mut self, request: &Request<BoxBody>, endpoints: &Endpoints<'_>) -> usize;
+}
+
+/// The ready endpoints a [`Policy`] can choose from.
+///
+/// The order of endpoints is not stable between calls to [`Policy::pick`].
+pub struct Endpoints<'a> {
+    ready: &'a dyn ReadyEndpoints,
+}
+
+pub(crate) trait ReadyEndpoints {
+    fn len(&self) -> usize;
+    fn get(&self, index: usize) -> &Connection;
+}
+
+impl<'a> Endpoints<'a> {
+    pub(crate) fn new(ready: &'a dyn ReadyEndpoints) -> Self {
+        Endpoints { ready }
+    }
+
+    /// The number of ready endpoints.
+    pub fn len(&self) -> usize {
+        self.ready.len()
+    }
+
+    /// Returns `true` if there are no ready endpoints.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The URI of the endpoint at `index`.
+    ///
+    /// # Panics
+    ///
+    /// If `index` is out of range.
+    pub fn uri(&self, index: usize) -> &Uri {
+        self.ready.get(index).uri()
+    }
+
+    /// The metadata of the endpoint at `index`.
+    ///
+    /// # Panics
+    ///
+    /// If `index` is out of range.
+    pub fn metadata(&self, index: usize) -> &EndpointMetadata {
+        self.ready.get(index).metadata()
+    }
+
+    /// The number of requests sent to the endpoint at `index` which are awaiting a response.
+    ///
+    /// # Panics
+    ///
+    /// If `index` is out of range.
+    pub fn in_flight(&self, index: usize) -> usize {
+        self.ready.get(index).in_flight()
+    }
+
+    /// The round-trip time of the most recently acknowledged HTTP/2 PING on the connection to
+    /// the endpoint at `index`, or `None` if no PING has been acknowledged.
+    ///
+    /// PINGs are sent when keepalive or an adaptive window is enabled.
+    ///
+    /// # Panics
+    ///
+    /// If `index` is out of range.
+    pub fn ping_rtt(&self, index: usize) -> Option<Duration> {
+        self.ready.get(index).ping_rtt().get()
+    }
+
+    /// Pick one of the endpoints for which `filter` returns `true` at random, in proportion to
+    /// their weights. Returns `None` if no endpoint matches.
+    pub fn pick_weighted(&self, mut filter: impl FnMut(usize) -> bool) -> Option<usize> {
+        let mut count = 0;
+        let mut total: u64 = 0;
+        for i in 0..self.len() {
+            if filter(i) {
+                count += 1;
+                total += u64::from(self.metadata(i).weight);
+            }
+        }
+        if count == 0 {
+            return None;
+        }
+
+        let mut rng = rand::thread_rng();
+        if total == 0 {
+            // All candidates have zero weight, so pick uniformly.
+            let nth = rng.gen_range(0..count);
+            return (0..self.len()).filter(|i| filter(*i)).nth(nth);
+        }
+
+        let mut remaining = rng.gen_range(0..total);
+        for i in 0..self.len() {
+            if !filter(i) {
+                continue;
+            }
+            let weight = u64::from(self.metadata(i).weight);
+            if remaining < weight {
+                return Some(i);
+            }
+            remaining -= weight;
+        }
+        unreachable!("weights sum to total")
+    }
+}
+
+impl fmt::Debug for Endpoints<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Endpoints")
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+/// Picks a ready endpoint uniformly at random, the default policy.
+#[derive(Debug, Clone, Default)]
+pub struct Random;
+
+impl Policy for Random {
+    fn pick(&mut self, _: &Request<BoxBody>, endpoints: &Endpoints<'_>) -> usize {
+        rand::thread_rng().gen_range(0..endpoints.len())
+    }
+}
+
+/// Prefers endpoints in the local zone, spilling over to other zones when local capacity is
+/// exhausted.
+///
+/// An endpoint has capacity while it has fewer than [`max_in_flight`](ZoneAware::max_in_flight)
+/// requests in flight; by default capacity is unlimited, so other zones are only used when there
+/// is no ready endpoint in the local zone. Requests which don't fit in the local zone are spread
+/// over the other zones' endpoints with capacity, and if every endpoint is at capacity, over all
+/// endpoints. Endpoints are chosen at random in proportion to their
+/// [`weight`](EndpointMetadata::weight).
+#[derive(Debug, Clone)]
+pub struct ZoneAware {
+    zone: String,
+    max_in_flight: Option<usize>,
+}
+
+impl ZoneAware {
+    /// Create a policy preferring endpoints whose [`zone`](EndpointMetadata::zone) is `zone`.
+    pub fn new(zone: impl Into<String>) -> Self {
+        ZoneAware {
+            zone: zone.into(),
+            max_in_flight: None,
+        }
+    }
+
+    /// Set the number of in-flight requests at which an endpoint is considered to be at capacity.
+    pub fn max_in_flight(self, max_in_flight: usize) -> Self {
+        ZoneAware {
+            max_in_flight: Some(max_in_flight),
+            ..self
+        }
+    }
+}
+
+impl Policy for ZoneAware {
+    fn pick(&mut self, _: &Request<BoxBody>, endpoints: &Endpoints<'_>) -> usize {
+        let is_local = |i| endpoints.metadata(i).zone.as_deref() == Some(&*self.zone);
+        let has_capacity =
+            |i| !matches!(self.max_in_flight, Some(max) if endpoints.in_flight(i) >= max);
+
+        endpoints
+            .pick_weighted(|i| is_local(i) && has_capacity(i))
+            .or_else(|| {
+                let spilled = endpoints.pick_weighted(|i| !is_local(i) && has_capacity(i));
+                if spilled.is_some() {
+                    tracing::trace!(zone = %self.zone, "spilling over to another zone");
+                }
+                spilled
+            })
+            .or_else(|| endpoints.pick_weighted(|_| true))
+            .unwrap_or(0)
+    }
+}
+
+/// A request extension carrying a routing hint, such as a shard key.
+///
+/// Policies can use the hint to send related requests to the same endpoint, see [`Affinity`].
+///
+/// ```no_run
+/// # use tonic_transport::RoutingHint;
+/// let mut request = tonic::Request::new(());
+/// request.extensions_mut().insert(RoutingHint::new("shard-7"));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RoutingHint(String);
+
+impl RoutingHint {
+    /// Create a routing hint.
+    pub fn new(hint: impl Into<String>) -> Self {
+        RoutingHint(hint.into())
+    }
+
+    /// The hint as a string.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+/// Routes requests with a [`RoutingHint`] to an endpoint selected by the hint.
+///
+/// By default, the endpoint is chosen by rendezvous hashing of the hint with the endpoints' URIs,
+/// so requests with the same hint go to the same endpoint while the set of ready endpoints is
+/// unchanged. If a [`label`](Affinity::label) is set, requests are sent to an endpoint whose
+/// label matches the hint, falling back to hashing if there is no such ready endpoint. Requests
+/// without a hint are handled by the fallback policy, [`Random`] by default.
+#[derive(Debug, Clone)]
+pub struct Affinity<P = Random> {
+    label: Option<String>,
+    fallback: P,
+}
+
+impl Affinity {
+    /// Create an affinity policy.
+    pub fn new() -> Self {
+        Affinity {
+            label: None,
+            fallback: Random,
+        }
+    }
+}
+
+impl Default for Affinity {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<P> Affinity<P> {
+    /// Route requests to endpoints whose [`labels`](EndpointMetadata::labels) value for `label`
+    /// equals the request's hint.
+    pub fn label(self, label: impl Into<String>) -> Self {
+        Affinity {
+            label: Some(label.into()),
+            ..self
+        }
+    }
+
+    /// Set the policy used for requests without a hint.
+    pub fn fallback<Q: Policy>(self, fallback: Q) -> Affinity<Q> {
+        Affinity {
+            label: self.label,
+            fallback,
+        }
+    }
+}
+
+impl<P: Policy> Policy for Affinity<P> {
+    fn pick(&mut self, request: &Request<BoxBody>, endpoints: &Endpoints<'_>) -> usize {
+        let hint = match request.extensions().get::<RoutingHint>() {
+            Some(hint) => hint,
+            None => return self.fallback.pick(request, endpoints),
+        };
+
+        if let Some(label) = &self.label {
+            let labelled = (0..endpoints.len())
+                .find(|i| endpoints.metadata(*i).labels.get(label) == Some(&hint.0));
+            if let Some(index) = labelled {
+                return index;
+            }
+            tracing::debug!(hint = %hint.0, "no ready endpoint matches routing hint");
+        }
+
+        rendezvous(hint, endpoints)
+    }
+}
+
+/// A request extension identifying a session, see [`Sticky`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SessionKey(String);
+
+impl SessionKey {
+    /// Create a session key.
+    pub fn new(key: impl Into<String>) -> Self {
+        SessionKey(key.into())
+    }
+
+    /// The key as a string.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+const DEFAULT_MAX_SESSIONS: usize = 10_000;
+
+/// Pins each session to an endpoint, for as long as that endpoint is ready.
+///
+/// A request's session is identified by its [`SessionKey`] extension or, if a
+/// [`header`](Sticky::header) is set and there is no extension, the value of that header. The
+/// first request of a session is handled by the fallback policy ([`Random`] by default) and
+/// later requests of the session go to the same endpoint. If that endpoint is not ready, the
+/// session is pinned to a new endpoint. Requests without a session use the fallback policy.
+///
+/// At most [`max_sessions`](Sticky::max_sessions) sessions are remembered, the oldest are
+/// forgotten first.
+#[derive(Debug, Clone)]
+pub struct Sticky<P = Random> {
+    header: Option<HeaderName>,
+    max_sessions: usize,
+    sessions: HashMap<String, Uri>,
+    order: VecDeque<String>,
+    fallback: P,
+}
+
+impl Sticky {
+    /// Create a sticky policy.
+    pub fn new() -> Self {
+        Sticky {
+            header: None,
+            max_sessions: DEFAULT_MAX_SESSIONS,
+            sessions: HashMap::new(),
+            order: VecDeque::new(),
+            fallback: Random,
+        }
+    }
+}
+
+impl Default for Sticky {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<P> Sticky<P> {
+    /// Identify sessions by the value of the `header` request header.
+    pub fn header(self, header: HeaderName) -> Self {
+        Sticky {
+            header: Some(header),
+            ..self
+        }
+    }
+
+    /// Set the maximum number of sessions to remember, defaults to 10,000.
+    pub fn max_sessions(self, max_sessions: usize) -> Self {
+        Sticky {
+            max_sessions,
+            ..self
+        }
+    }
+
+    /// Set the policy used to pick an endpoint for new sessions and requests without a session.
+    pub fn fallback<Q: Policy>(self, fallback: Q) -> Sticky<Q> {
+        Sticky {
+            header: self.header,
+            max_sessions: self.max_sessions,
+            sessions: self.sessions,
+            order: self.order,
+            fallback,
+        }
+    }
+
+    fn session<'a>(&self, request: &'a Request<BoxBody>) -> Option<&'a str> {
+        if let Some(key) = request.extensions().get::<SessionKey>() {
+            return Some(&key.0);
+        }
+        let header = self.header.as_ref()?;
+        request.headers().get(header)?.to_str().ok()
+    }
+}
+
+impl<P: Policy> Policy for Sticky<P> {
+    fn pick(&mut self, request: &Request<BoxBody>, endpoints: &Endpoints<'_>) -> usize {
+        let session = match self.session(request) {
+            Some(session) => session,
+            None => return self.fallback.pick(request, endpoints),
+        };
+
+        if let Some(uri) = self.sessions.get(session) {
+            if let Some(index) = (0..endpoints.len()).find(|i| endpoints.uri(*i) == uri) {
+                return index;
+            }
+            tracing::debug!(%session, %uri, "pinned endpoint is unavailable, re-pinning session");
+        }
+
+        let index = self
+            .fallback
+            .pick(request, endpoints)
+            .min(endpoints.len() - 1);
+        let uri = endpoints.uri(index).clone();
+        if self.sessions.insert(session.to_owned(), uri).is_none() {
+            self.order.push_back(session.to_owned());
+            while self.order.len() > self.max_sessions {
+                if let Some(oldest) = self.order.pop_front() {
+                    self.sessions.remove(&oldest);
+                }
+            }
+        }
+        index
+    }
+}
+
+/// Returns the endpoint with the highest hash of `key` and the endpoint's URI.
+fn rendezvous(key: &impl Hash, endpoints: &Endpoints<'_>) -> usize {
+    (0..endpoints.len())
+        .max_by_key(|i| {
+            let mut hasher = DefaultHasher::new();
+            key.hash(&mut hasher);
+            endpoints.uri(*i).hash(&mut hasher);
+            hasher.finish()
+        })
+        .unwrap_or(0)
+}
+
+/// Builder for load balanced [`Channel`]s.
+pub struct BalanceBuilder {
+    policy: Box<dyn Policy>,
+    // The type of `policy`, for `Debug` output.
+    policy_name: &'static str,
+    buffer_size: usize,
+    subset: Option<(usize, u64)>,
+    unready: Unready,
+    retry_methods: Vec<String>,
+    method_stats: bool,
+    ping_health: Option<(Duration, Duration)>,
+}
+
+impl BalanceBuilder {
+    /// Create a builder which uses the [`Random`] policy.
+    pub fn new() -> Self {
+        BalanceBuilder {
+            policy: Box::new(Random),
+            policy_name: std::any::type_name::<Random>(),
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            subset: None,
+            unready: Unready::Wait,
+            retry_methods: Vec::new(),
+            method_stats: false,
+            ping_health: None,
+        }
+    }
+
+    /// Set the policy used to pick an endpoint for each request.
+    pub fn policy<P: Policy>(self, policy: P) -> Self {
+        BalanceBuilder {
+            policy_name: std::any::type_name::<P>(),
+            policy: Box::new(policy),
+            ..self
+        }
+    }
+
+    /// Set the size of the channel's request buffer.
+    pub fn buffer_size(self, buffer_size: usize) -> Self {
+        BalanceBuilder {
+            buffer_size,
+            ..self
+        }
+    }
+
+    /// Fail requests immediately with an `Unavailable` status while no endpoint is ready.
+    ///
+    /// By default, requests wait until an endpoint becomes ready.
+    pub fn fail_fast(self) -> Self {
+        BalanceBuilder {
+            unready: Unready::FailFast,
+            ..self
+        }
+    }
+
+    /// Queue requests for up to `timeout` while no endpoint is ready.
+    ///
+    /// If no endpoint becomes ready within `timeout`, queued and new requests fail with an
+    /// `Unavailable` status until an endpoint is ready. Requests also fail immediately when the
+    /// queue, whose length is set by [`buffer_size`](BalanceBuilder::buffer_size), is full.
+    pub fn queue_timeout(self, timeout: Duration) -> Self {
+        BalanceBuilder {
+            unready: Unready::Queue(timeout),
+            ..self
+        }
+    }
+
+    /// Retry requests for `method` which fail because of a transport error.
+    ///
+    /// See [`ChannelBuilder::retry_transport_errors`].
+    pub fn retry_transport_errors(mut self, method: impl Into<String>) -> Self {
+        self.retry_methods.push(method.into());
+        self
+    }
+
+    /// Record statistics of the calls made on the channel, see [`Channel::method_stats`].
+    ///
+    /// Disabled by default.
+    pub fn method_stats(self, enabled: bool) -> Self {
+        BalanceBuilder {
+            method_stats: enabled,
+            ..self
+        }
+    }
+
+    /// Check the health of each endpoint's connection with HTTP/2 PINGs.
+    ///
+    /// A PING is sent every `interval`, even when the connection is idle, and a connection whose
+    /// PING is not acknowledged within `timeout` is closed. The endpoint is then taken out of the
+    /// set of ready endpoints, so that new requests are sent to other endpoints, until it has
+    /// reconnected. This detects endpoints which have stopped responding, for example because
+    /// their host has failed, without waiting for requests to time out. This overrides the
+    /// keepalive settings of the endpoints.
+    pub fn ping_health(self, interval: Duration, timeout: Duration) -> Self {
+        BalanceBuilder {
+            ping_health: Some((interval, timeout)),
+            ..self
+        }
+    }
+
+    /// Only connect to a subset of at most `size` endpoints.
+    ///
+    /// The subset is chosen deterministically from `client_id` and the endpoints' keys using
+    /// rendezvous hashing, so that clients with different ids spread their connections across
+    /// the endpoints, and adding or removing an endpoint changes the subset by at most one
+    /// endpoint. Hashes are only stable between processes built with the same version of Rust.
+    pub fn subset(self, size: usize, client_id: impl Hash) -> Self {
+        let mut hasher = DefaultHasher::new();
+        client_id.hash(&mut hasher);
+        BalanceBuilder {
+            subset: Some((size, hasher.finish())),
+            ..self
+        }
+    }
+
+    /// Create a [`Channel`] which balances across a fixed list of endpoints.
+    ///
+    /// Endpoints whose settings are invalid, see [`ConfigError`](crate::ConfigError), are
+    /// ignored with a warning.
+    pub fn list(self, list: impl Iterator<Item = ChannelBuilder>) -> Channel {
+        let (channel, tx) = self.channel(DEFAULT_BUFFER_SIZE);
+        list.for_each(|endpoint| {
+            tx.try_send(Change::Insert(endpoint.uri.clone(), endpoint))
+                .unwrap();
+        });
+
+        channel
+    }
+
+    /// Create a [`Channel`] balancing across the endpoints found by `resolver`, which are kept
+    /// up to date until the channel is dropped.
+    ///
+    /// Each endpoint is configured by `template`, with the resolved address in place of the
+    /// template's. Unless the template has a
+    /// [`tls_verify_domain`](ChannelBuilder::tls_verify_domain), endpoints are verified for the
+    /// template's host. If the template's settings are invalid, see
+    /// [`ConfigError`](crate::ConfigError), an error is logged and no endpoints are added.
+    pub fn resolver<R: Resolver>(self, resolver: R, template: ChannelBuilder) -> Channel {
+        resolver::channel(resolver, self, template)
+    }
+
+    /// Create a [`Channel`] which listens to a stream of change events and will add or remove
+    /// endpoints.
+    ///
+    /// Inserted endpoints whose settings are invalid, see [`ConfigError`](crate::ConfigError),
+    /// are ignored with a warning.
+    pub fn channel<K>(self, capacity: usize) -> (Channel, Sender<Change<K, ChannelBuilder>>)
+    where
+        K: Hash + Eq + Send + Clone + 'static,
+    {
+        let (tx, rx) = channel(capacity);
+        let subset = self.subset.map(|(size, seed)| Subset::new(size, seed));
+        let list = DynamicServiceStream::new(rx, subset, self.ping_health);
+        let retry_methods = RetryMethods::new(self.retry_methods);
+        let channel = Channel::balance(
+            list,
+            self.buffer_size,
+            self.policy,
+            self.policy_name,
+            self.unready,
+            retry_methods,
+        )
+        .with_method_stats(self.method_stats);
+        (channel, tx)
+    }
+}
+
+impl Default for BalanceBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for BalanceBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BalanceBuilder")
+            .field("policy", &self.policy_name)
+            .field("buffer_size", &self.buffer_size)
+            .field("subset", &self.subset.map(|(size, _)| size))
+            .field("unready", &self.unready)
+            .field("ping_health", &self.ping_health)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::raw_connector;
+    use std::collections::HashSet;
+    use tokio::time::Instant;
+    use tonic::{Code, Status};
+    use tower::ServiceExt;
+
+    struct Ready(Vec<Connection>);
+
+    impl ReadyEndpoints for Ready {
+        fn len(&self) -> usize {
+            self.0.len()
+        }
+
+        fn get(&self, index: usize) -> &Connection {
+            &self.0[index]
+        }
+    }
+
+    /// A ready endpoint at `uri`, in `zone` if it is not empty.
+    fn endpoint(uri: &'static str, zone: &str) -> Connection {
+        let metadata = EndpointMetadata {
+            zone: Some(zone.to_owned()).filter(|zone| !zone.is_empty()),
+            ..EndpointMetadata::default()
+        };
+        let endpoint = ChannelBuilder::new_plaintext(uri)
+            .unwrap()
+            .metadata(metadata);
+        Connection::lazy(raw_connector(endpoint.tcp_connector()), endpoint)
+    }
+
+    fn request() -> Request<BoxBody> {
+        Request::new(BoxBody::default())
+    }
+
+    /// The URIs of the endpoints picked by `policy` for 100 requests.
+    fn picks(policy: &mut impl Policy, ready: &Ready) -> HashSet<String> {
+        let endpoints = Endpoints::new(ready);
+        (0..100)
+            .map(|_| {
+                endpoints
+                    .uri(policy.pick(&request(), &endpoints))
+                    .to_string()
+            })
+            .collect()
+    }
+
+    /// The URI of the endpoint picked by `policy` for a request with `extension`.
+    fn pick<T: Clone + Send + Sync + 'static>(
+        policy: &mut impl Policy,
+        ready: &Ready,
+        extension: &T,
+    ) -> String {
+        let mut request = request();
+        request.extensions_mut().insert(extension.clone());
+        let endpoints = Endpoints::new(ready);
+        endpoints.uri(policy.pick(&request, &endpoints)).to_string()
+    }
+
+    fn uris(uris: &[&str]) -> HashSet<String> {
+        uris.iter().map(|uri| format!("{}/", uri)).collect()
+    }
+
+    #[tokio::test]
+    async fn zone_aware_prefers_the_local_zone() {
+        let ready = Ready(vec![
+            endpoint("http://10.0.0.1", "a"),
+            endpoint("http://10.0.0.2", "b"),
+            endpoint("http://10.0.0.3", "a"),
+            endpoint("http://10.0.0.4", ""),
+        ]);
+        assert_eq!(
+            picks(&mut ZoneAware::new("a"), &ready),
+            uris(&["http://10.0.0.1", "http://10.0.0.3"])
+        );
+        assert_eq!(
+            picks(&mut ZoneAware::new("b"), &ready),
+            uris(&["http://10.0.0.2"])
+        );
+    }
+
+    #[tokio::test]
+    async fn zone_aware_spills_over_without_local_endpoints() {
+        // The local zone's endpoints are all unhealthy, so none of them are ready.
+        let ready = Ready(vec![
+            endpoint("http://10.0.0.2", "b"),
+            endpoint("http://10.0.0.4", ""),
+        ]);
+        assert_eq!(
+            picks(&mut ZoneAware::new("a"), &ready),
+            uris(&["http://10.0.0.2", "http://10.0.0.4"])
+        );
+
+        // Every endpoint is at capacity, so requests are spread over all of them.
+        let ready = Ready(vec![
+            endpoint("http://10.0.0.1", "a"),
+            endpoint("http://10.0.0.2", "b"),
+        ]);
+        assert_eq!(
+            picks(&mut ZoneAware::new("a").max_in_flight(0), &ready),
+            uris(&["http://10.0.0.1", "http://10.0.0.2"])
+        );
+    }
+
+    fn three_endpoints() -> Vec<Connection> {
+        vec![
+            endpoint("http://10.0.0.1", ""),
+            endpoint("http://10.0.0.2", ""),
+            endpoint("http://10.0.0.3", ""),
+        ]
+    }
+
+    #[tokio::test]
+    async fn affinity_routes_hints_to_the_same_endpoint() {
+        let mut policy = Affinity::new();
+        let ready = Ready(three_endpoints());
+        let hints: Vec<_> = (0..20).map(|i| RoutingHint::new(i.to_string())).collect();
+        let routed: Vec<_> = hints
+            .iter()
+            .map(|hint| pick(&mut policy, &ready, hint))
+            .collect();
+
+        // The order of the ready endpoints doesn't matter.
+        let mut reversed = three_endpoints();
+        reversed.reverse();
+        let reversed = Ready(reversed);
+        for (hint, uri) in hints.iter().zip(&routed) {
+            assert_eq!(&pick(&mut policy, &reversed, hint), uri);
+        }
+
+        // When an endpoint is removed, only its hints move.
+        let mut remaining = three_endpoints();
+        let removed = remaining.remove(0).uri().to_string();
+        let remaining = Ready(remaining);
+        for (hint, uri) in hints.iter().zip(&routed) {
+            let moved = pick(&mut policy, &remaining, hint);
+            if *uri == removed {
+                assert_ne!(moved, removed);
+            } else {
+                assert_eq!(&moved, uri);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn affinity_routes_hints_to_labelled_endpoints() {
+        let labelled = |uri, shard: &str| {
+            let mut metadata = EndpointMetadata::default();
+            metadata.labels.insert("shard".to_owned(), shard.to_owned());
+            let endpoint = ChannelBuilder::new_plaintext(uri)
+                .unwrap()
+                .metadata(metadata);
+            Connection::lazy(raw_connector(endpoint.tcp_connector()), endpoint)
+        };
+        let mut policy = Affinity::new().label("shard");
+        let ready = Ready(vec![
+            labelled("http://10.0.0.1", "1"),
+            labelled("http://10.0.0.2", "2"),
+        ]);
+        for _ in 0..10 {
+            let uri = pick(&mut policy, &ready, &RoutingHint::new("2"));
+            assert_eq!(uri, "http://10.0.0.2/");
+        }
+
+        // Without a matching ready endpoint, the hint is hashed.
+        let ready = Ready(vec![labelled("http://10.0.0.1", "1")]);
+        let uri = pick(&mut policy, &ready, &RoutingHint::new("2"));
+        assert_eq!(uri, "http://10.0.0.1/");
+    }
+
+    #[tokio::test]
+    async fn sticky_pins_sessions_while_their_endpoint_is_ready() {
+        let mut policy = Sticky::new();
+        let ready = Ready(three_endpoints());
+        let session = SessionKey::new("session");
+        let pinned = pick(&mut policy, &ready, &session);
+        for _ in 0..10 {
+            assert_eq!(pick(&mut policy, &ready, &session), pinned);
+        }
+
+        // When the endpoint is removed, the session is pinned to another, where it stays once the
+        // first endpoint is ready again.
+        let remaining = Ready(
+            three_endpoints()
+                .into_iter()
+                .filter(|endpoint| endpoint.uri().to_string() != pinned)
+                .collect(),
+        );
+        let repinned = pick(&mut policy, &remaining, &session);
+        assert_ne!(repinned, pinned);
+        for _ in 0..10 {
+            assert_eq!(pick(&mut policy, &ready, &session), repinned);
+        }
+    }
+
+    #[tokio::test]
+    async fn sticky_identifies_sessions_by_header() {
+        let mut policy = Sticky::new().header(HeaderName::from_static("x-session"));
+        let ready = Ready(three_endpoints());
+        let mut request = request();
+        request
+            .headers_mut()
+            .insert("x-session", "session".parse().unwrap());
+        let endpoints = Endpoints::new(&ready);
+        let pinned = policy.pick(&request, &endpoints);
+        for _ in 0..10 {
+            assert_eq!(policy.pick(&request, &endpoints), pinned);
+        }
+        // A session key with the same value identifies the same session.
+        assert_eq!(
+            pick(&mut policy, &ready, &SessionKey::new("session")),
+            endpoints.uri(pinned).to_string()
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn fail_fast_fails_without_ready_endpoints() {
+        let (channel, _tx) = BalanceBuilder::new().fail_fast().channel::<u32>(1);
+        let start = Instant::now();
+        let error = channel.oneshot(request()).await.unwrap_err();
+        assert_eq!(start.elapsed(), Duration::ZERO);
+        assert_eq!(
+            Status::from_error(Box::new(error)).code(),
+            Code::Unavailable
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn queue_timeout_fails_once_it_expires() {
+        let (channel, _tx) = BalanceBuilder::new()
+            .queue_timeout(Duration::from_secs(5))
+            .channel::<u32>(1);
+        let start = Instant::now();
+        let error = channel.clone().oneshot(request()).await.unwrap_err();
+        assert!((Duration::from_secs(5)..Duration::from_secs(6)).contains(&start.elapsed()));
+        assert_eq!(
+            Status::from_error(Box::new(error)).code(),
+            Code::Unavailable
+        );
+
+        // Until an endpoint is ready, later requests fail immediately.
+        let start = Instant::now();
+        channel.oneshot(request()).await.unwrap_err();
+        assert_eq!(start.elapsed(), Duration::ZERO);
+    }
+}
@@ -0,0 +1,181 @@
+use crate::Error;
+
+use http::Response;
+use std::{collections::HashMap, sync::Mutex, time::Duration};
+use tokio::time::Instant;
+use tonic::Code;
+
+// The number of gRPC status codes, `Ok` to `Unauthenticated`.
+const CODES: usize = 17;
+// Latencies are counted in buckets whose bounds grow by a factor of 2^(1/4), starting from one
+// microsecond. The last bucket, from about 71 minutes, also counts all longer calls.
+const BUCKETS: usize = 128;
+const BUCKETS_PER_DOUBLING: f64 = 4.0;
+
+/// Statistics of the calls to one gRPC method on a [`Channel`](super::Channel).
+///
+/// A call is counted when its response headers are received, or when it fails before that. Its
+/// latency is the time from sending the request until then, including time spent waiting for a
+/// connection. Calls whose status is only sent in the trailers, after a streamed response, are
+/// counted with the code `Ok`.
+///
+/// See [`Channel::method_stats`](super::Channel::method_stats).
+#[derive(Debug, Clone)]
+pub struct MethodStats {
+    codes: [u64; CODES],
+    latencies: [u64; BUCKETS],
+}
+
+impl MethodStats {
+    fn new() -> Self {
+        MethodStats {
+            codes: [0; CODES],
+            latencies: [0; BUCKETS],
+        }
+    }
+
+    /// The number of calls.
+    pub fn calls(&self) -> u64 {
+        self.codes.iter().sum()
+    }
+
+    /// The number of calls which did not succeed.
+    pub fn errors(&self) -> u64 {
+        self.calls() - self.count(Code::Ok)
+    }
+
+    /// The number of calls which completed with `code`.
+    pub fn count(&self, code: Code) -> u64 {
+        self.codes[code as usize]
+    }
+
+    /// The number of calls for each code which occurred.
+    pub fn codes(&self) -> impl Iterator<Item = (Code, u64)> + '_ {
+        self.codes
+            .iter()
+            .enumerate()
+            .filter(|(_, &count)| count > 0)
+            .map(|(code, &count)| (Code::from_i32(code as i32), count))
+    }
+
+    /// An estimate of the latency below which `percentile` percent of calls completed, for
+    /// example `latency_percentile(99.0)` for the 99th percentile.
+    ///
+    /// Latencies are counted in buckets, so the estimate may be up to a fifth larger than the
+    /// actual latency. Returns `None` if there have been no calls.
+    pub fn latency_percentile(&self, percentile: f64) -> Option<Duration> {
+        let calls = self.calls();
+        if calls == 0 {
+            return None;
+        }
+        let rank = ((percentile.clamp(0.0, 100.0) / 100.0 * calls as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        let bucket = self.latencies.iter().position(|&count| {
+            seen += count;
+            seen >= rank
+        })?;
+        Some(bucket_bound(bucket))
+    }
+
+    fn record(&mut self, code: Code, latency: Duration) {
+        self.codes[code as usize] += 1;
+        self.latencies[bucket(latency)] += 1;
+    }
+}
+
+fn bucket(latency: Duration) -> usize {
+    let micros = latency.as_secs_f64() * 1e6;
+    if micros <= 1.0 {
+        return 0;
+    }
+    ((micros.log2() * BUCKETS_PER_DOUBLING).ceil() as usize).min(BUCKETS - 1)
+}
+
+// The largest latency counted in `bucket`.
+fn bucket_bound(bucket: usize) -> Duration {
+    Duration::from_secs_f64((bucket as f64 / BUCKETS_PER_DOUBLING).exp2() / 1e6)
+}
+
+/// The statistics of a channel's calls, keyed by method path.
+#[derive(Debug, Default)]
+pub(crate) struct MethodStatsMap {
+    methods: Mutex<HashMap<String, MethodStats>>,
+}
+
+impl MethodStatsMap {
+    pub(crate) fn record<B>(
+        &self,
+        path: &str,
+        started: Instant,
+        result: Result<&Response<B>, &Error>,
+    ) {
+        let code = match result {
+            Ok(response) => response_code(response),
+            Err(error) => error_code(error),
+        };
+        let latency = started.elapsed();
+
+        let mut methods = self.methods.lock().unwrap();
+        match methods.get_mut(path) {
+            Some(stats) => stats.record(code, latency),
+            None => methods
+                .entry(path.to_owned())
+                .or_insert_with(MethodStats::new)
+                .record(code, latency),
+        }
+    }
+
+    pub(crate) fn snapshot(&self) -> HashMap<String, MethodStats> {
+        self.methods.lock().unwrap().clone()
+    }
+}
+
+fn response_code<B>(response: &Response<B>) -> Code {
+    match response.headers().get("grpc-status") {
+        Some(status) => Code::from_bytes(status.as_bytes()),
+        None if response.status().is_success() => Code::Ok,
+        None => Code::Unknown,
+    }
+}
+
+fn error_code(error: &Error) -> Code {
+    let Error::Other(source) = error else {
+        return Code::Unavailable;
+    };
+    match source.downcast_ref::<tonic::Status>() {
+        Some(status) => status.code(),
+        None if super::is_transport_error(source) => Code::Unavailable,
+        None => Code::Unknown,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn estimates_latency_percentiles() {
+        let mut stats = MethodStats::new();
+        assert_eq!(stats.latency_percentile(50.0), None);
+
+        for millis in 1..=100 {
+            stats.record(Code::Ok, Duration::from_millis(millis));
+        }
+        stats.record(Code::Unavailable, Duration::from_secs(2));
+
+        assert_eq!(stats.calls(), 101);
+        assert_eq!(stats.errors(), 1);
+        assert_eq!(
+            stats.codes().collect::<Vec<_>>(),
+            [(Code::Ok, 100), (Code::Unavailable, 1)]
+        );
+        for (percentile, expected) in [(50.0, 51), (99.0, 100), (100.0, 2000)] {
+            let estimate = stats.latency_percentile(percentile).unwrap();
+            let expected = Duration::from_millis(expected);
+            assert!(
+                estimate >= expected && estimate < expected * 6 / 5,
+                "{percentile}: {estimate:?}"
+            );
+        }
+    }
+}
@@ -0,0 +1,298 @@
+use super::Channel;
+use crate::BoxBody;
+
+use bytes::Bytes;
+use http::{HeaderMap, Request};
+use http_body::Body;
+use rand::Rng;
+use std::{
+    fmt,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tonic::Status;
+use tower::{Layer, ServiceExt};
+use tower_service::Service;
+
+/// A layer which sends a copy of a percentage of requests to a shadow [`Channel`].
+///
+/// Responses from the shadow channel are discarded, and its failures don't affect the original
+/// request. This is useful for validating a new backend against production traffic. The body of a
+/// mirrored request is buffered until the shadow channel has sent it, so mirroring streaming
+/// requests to a slow shadow uses memory for the whole stream.
+///
+/// ```no_run
+/// # use tonic_transport::{Channel, MirrorLayer};
+/// # use tower::Layer;
+/// # fn example(primary: Channel, shadow: Channel) {
+/// let channel = MirrorLayer::new(shadow, 5.0).layer(primary);
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct MirrorLayer {
+    shadow: Channel,
+    percentage: f64,
+}
+
+impl MirrorLayer {
+    /// Create a layer which mirrors `percentage` (between 0 and 100) of requests to `shadow`.
+    pub fn new(shadow: Channel, percentage: f64) -> Self {
+        MirrorLayer {
+            shadow,
+            percentage: percentage.clamp(0.0, 100.0),
+        }
+    }
+}
+
+impl<S> Layer<S> for MirrorLayer {
+    type Service = Mirror<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Mirror {
+            inner,
+            shadow: self.shadow.clone(),
+            percentage: self.percentage,
+        }
+    }
+}
+
+/// Middleware which mirrors requests to a shadow channel, see [`MirrorLayer`].
+#[derive(Clone)]
+pub struct Mirror<S> {
+    inner: S,
+    shadow: Channel,
+    percentage: f64,
+}
+
+impl<S> Service<Request<BoxBody>> for Mirror<S>
+where
+    S: Service<Request<BoxBody>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<BoxBody>) -> Self::Future {
+        if rand::thread_rng().gen_range(0.0..100.0) >= self.percentage {
+            return self.inner.call(request);
+        }
+
+        let (parts, body) = request.into_parts();
+        let (tx, rx) = mpsc::unbounded_channel();
+
+        let mut shadow_request = Request::new(BoxBody::new(ShadowBody { rx, trailers: None }));
+        *shadow_request.method_mut() = parts.method.clone();
+        *shadow_request.uri_mut() = parts.uri.clone();
+        *shadow_request.version_mut() = parts.version;
+        *shadow_request.headers_mut() = parts.headers.clone();
+
+        let shadow = self.shadow.clone();
+        tokio::spawn(async move {
+            let path = shadow_request.uri().path().to_owned();
+            if let Err(error) = shadow.oneshot(shadow_request).await {
+                tracing::debug!(%error, %path, "mirrored request failed");
+            }
+        });
+
+        let body = BoxBody::new(TeeBody::new(body, tx));
+        self.inner.call(Request::from_parts(parts, body))
+    }
+}
+
+impl<S> fmt::Debug for Mirror<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Mirror")
+            .field("percentage", &self.percentage)
+            .finish()
+    }
+}
+
+enum Frame {
+    Data(Bytes),
+    Trailers(Option<HeaderMap>),
+}
+
+/// The body of a mirrored request, which copies each frame to the shadow request.
+struct TeeBody {
+    body: BoxBody,
+    tx: Option<UnboundedSender<Frame>>,
+}
+
+impl TeeBody {
+    fn new(body: BoxBody, tx: UnboundedSender<Frame>) -> Self {
+        let mut tee = TeeBody { body, tx: Some(tx) };
+        tee.end_if_done();
+        tee
+    }
+
+    fn send(&mut self, frame: Frame) {
+        if let Some(tx) = &self.tx {
+            if tx.send(frame).is_err() {
+                self.tx = None;
+            }
+        }
+    }
+
+    /// End the shadow request once the body has ended, because hyper doesn't poll the trailers
+    /// of a body which says it has ended.
+    fn end_if_done(&mut self) {
+        if self.body.is_end_stream() {
+            self.send(Frame::Trailers(None));
+            self.tx = None;
+        }
+    }
+}
+
+impl Body for TeeBody {
+    type Data = Bytes;
+    type Error = Status;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let result = futures_util::ready!(Pin::new(&mut self.body).poll_data(cx));
+        match &result {
+            Some(Ok(chunk)) => self.send(Frame::Data(chunk.clone())),
+            Some(Err(_)) => self.tx = None,
+            None => {}
+        }
+        self.end_if_done();
+        Poll::Ready(result)
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        let result = futures_util::ready!(Pin::new(&mut self.body).poll_trailers(cx));
+        if let Ok(trailers) = &result {
+            self.send(Frame::Trailers(trailers.clone()));
+        }
+        self.tx = None;
+        Poll::Ready(result)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.body.is_end_stream()
+    }
+
+    fn size_hint(&self) -> http_body::SizeHint {
+        self.body.size_hint()
+    }
+}
+
+/// The body of a shadow request, which yields the frames copied by a [`TeeBody`].
+struct ShadowBody {
+    rx: UnboundedReceiver<Frame>,
+    trailers: Option<Option<HeaderMap>>,
+}
+
+fn original_failed() -> Status {
+    Status::cancelled("mirrored request body was not completed")
+}
+
+impl Body for ShadowBody {
+    type Data = Bytes;
+    type Error = Status;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        if self.trailers.is_some() {
+            return Poll::Ready(None);
+        }
+        match futures_util::ready!(self.rx.poll_recv(cx)) {
+            Some(Frame::Data(chunk)) => Poll::Ready(Some(Ok(chunk))),
+            Some(Frame::Trailers(trailers)) => {
+                self.trailers = Some(trailers);
+                Poll::Ready(None)
+            }
+            None => Poll::Ready(Some(Err(original_failed()))),
+        }
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        if let Some(trailers) = self.trailers.take() {
+            return Poll::Ready(Ok(trailers));
+        }
+        match futures_util::ready!(self.rx.poll_recv(cx)) {
+            Some(Frame::Trailers(trailers)) => Poll::Ready(Ok(trailers)),
+            _ => Poll::Ready(Err(original_failed())),
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        matches!(self.trailers, Some(None))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body::Full;
+
+    #[tokio::test]
+    async fn copies_body_to_shadow() {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let body = BoxBody::new(Full::new(Bytes::from_static(b"hello")).map_err(|e| match e {}));
+        let mut tee = TeeBody::new(body, tx);
+        let mut shadow = ShadowBody { rx, trailers: None };
+
+        // The body has ended after its data, so its trailers aren't polled.
+        assert_eq!(tee.data().await.unwrap().unwrap(), "hello");
+        assert!(tee.is_end_stream());
+        drop(tee);
+
+        assert_eq!(shadow.data().await.unwrap().unwrap(), "hello");
+        assert!(shadow.data().await.is_none());
+        assert!(shadow.is_end_stream());
+        assert!(shadow.trailers().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn copies_trailers_to_shadow() {
+        let mut trailers = HeaderMap::new();
+        trailers.insert("grpc-status", "0".parse().unwrap());
+        let (mut sender, body) = hyper::Body::channel();
+        sender
+            .send_data(Bytes::from_static(b"hello"))
+            .await
+            .unwrap();
+        sender.send_trailers(trailers.clone()).await.unwrap();
+        drop(sender);
+
+        let (tx, rx) = mpsc::unbounded_channel();
+        let body = BoxBody::new(body.map_err(|error| Status::internal(error.to_string())));
+        let mut tee = TeeBody::new(body, tx);
+        let mut shadow = ShadowBody { rx, trailers: None };
+
+        assert_eq!(tee.data().await.unwrap().unwrap(), "hello");
+        assert!(tee.data().await.is_none());
+        assert_eq!(tee.trailers().await.unwrap(), Some(trailers.clone()));
+        drop(tee);
+
+        assert_eq!(shadow.data().await.unwrap().unwrap(), "hello");
+        assert!(shadow.data().await.is_none());
+        assert_eq!(shadow.trailers().await.unwrap(), Some(trailers));
+    }
+
+    #[tokio::test]
+    async fn ends_shadow_of_empty_body() {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let _tee = TeeBody::new(BoxBody::default(), tx);
+        let mut shadow = ShadowBody { rx, trailers: None };
+
+        assert!(shadow.data().await.is_none());
+        assert!(shadow.trailers().await.unwrap().is_none());
+    }
+}
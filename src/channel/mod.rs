@@ -1,39 +1,82 @@
 //! Client implementation and builder.
 
+mod balance;
 mod endpoint;
-
+mod method_stats;
+mod mirror;
+mod pool;
+mod profile;
+mod resolver;
+mod retry;
+mod socket;
+mod stats;
+mod target;
+
+pub(crate) use self::balance::ReadyEndpoints;
+pub use self::balance::{
+    Affinity, BalanceBuilder, EndpointMetadata, Endpoints, Policy, Random, RoutingHint, SessionKey,
+    Sticky, ZoneAware,
+};
 pub use self::endpoint::ChannelBuilder;
-
-use crate::service::{Connection, DynamicServiceStream};
+pub use self::method_stats::MethodStats;
+use self::method_stats::MethodStatsMap;
+pub use self::mirror::{Mirror, MirrorLayer};
+pub use self::pool::{ChannelPool, PooledChannel};
+pub use self::profile::NetworkProfile;
+#[cfg(feature = "consul")]
+pub use self::resolver::ConsulResolver;
+pub use self::resolver::DnsResolver;
+#[cfg(feature = "etcd")]
+pub use self::resolver::EtcdResolver;
+pub use self::resolver::{FileResolver, ResolvedEndpoints, Resolver};
+pub use self::retry::RetryOnTransportError;
+pub(crate) use self::retry::{is_transport_error, RetryMethods};
+pub use self::socket::SocketOptions;
+pub use self::stats::ChannelStats;
+use self::stats::{Dequeue, QueueStats, WarmUp};
+pub use self::target::Target;
+
+use crate::server::inbound_deadline;
+use crate::service::{
+    grpc_timeout::{encode_grpc_timeout, try_parse_grpc_timeout, CallTimeout, GRPC_TIMEOUT_HEADER},
+    Balance, Connection, Deadline, PingRtt, ReplayBody, ResponseBody, Unready,
+};
 use crate::{BoxBody, BoxError, Error, Result};
 use bytes::Bytes;
 use http::{uri::Uri, Request, Response};
 use hyper::client::connect::Connection as HyperConnection;
 use std::{
+    collections::HashMap,
     fmt,
     future::Future,
     hash::Hash,
     pin::Pin,
+    sync::Arc,
     task::{Context, Poll},
+    time::Duration,
 };
 use tokio::{
     io::{AsyncRead, AsyncWrite},
-    sync::mpsc::{channel, Sender},
+    sync::mpsc::Sender,
+    time::Instant,
 };
 use tokio_native_tls::TlsConnector;
 
-use tower::balance::p2c::Balance;
 use tower::{
     buffer::{self, Buffer},
     discover::{Change, Discover},
     util::{BoxService, Either},
-    Service,
+    Service, ServiceExt,
 };
 
-type Svc = Either<Connection, BoxService<Request<BoxBody>, Response<hyper::Body>, BoxError>>;
+type Svc =
+    Dequeue<Either<Connection, BoxService<Request<BoxBody>, Response<ResponseBody>, BoxError>>>;
 
 const DEFAULT_BUFFER_SIZE: usize = 1024;
 
+// The maximum size of request body which is kept so that the request can be retried.
+const MAX_REPLAY_LEN: usize = 64 * 1024;
+
 /// A default batteries included `transport` channel.
 ///
 /// This provides a fully featured http2 gRPC client based on [`hyper::Client`]
@@ -57,20 +100,82 @@ const DEFAULT_BUFFER_SIZE: usize = 1024;
 /// the channel is backed by a `tower_buffer::Buffer` which runs the connection
 /// in a background task and provides a `mpsc` channel interface. Due to this
 /// cloning the `Channel` type is cheap and encouraged.
+///
+/// # Retries
+///
+/// Requests which are marked with [`RetryOnTransportError`], or whose method is configured with
+/// [`ChannelBuilder::retry_transport_errors`], are sent again, once, on a new connection if the
+/// server refuses them without processing them, because it sent GOAWAY (for example, during a
+/// graceful restart) before receiving them or reset them with REFUSED_STREAM, or if the
+/// connection fails before a response is received. Requests whose body is larger than 64 KiB are
+/// not retried.
+///
+/// A request which is sent again keeps its method, URI, headers, and the extensions used by the
+/// channel, such as [`RoutingHint`], [`SessionKey`] and its deadline, but not other extensions.
+/// Layers added with [`ChannelBuilder::layer`] are applied to it again.
 #[derive(Clone)]
 pub struct Channel {
     svc: Buffer<Svc, Request<BoxBody>>,
+    // Fail requests, rather than wait, when the buffer is full.
+    shed_load: bool,
+    overloaded: bool,
+    retry_methods: RetryMethods,
+    stats: Arc<QueueStats>,
+    // The PING round-trip time of the channel's connection, if it has only one.
+    ping_rtt: Option<Arc<PingRtt>>,
+    method_stats: Option<Arc<MethodStatsMap>>,
+    mode: Mode,
+}
+
+// What a channel sends its requests to, for its `Debug` output.
+#[derive(Clone)]
+enum Mode {
+    Endpoint(Uri),
+    Balanced {
+        policy: &'static str,
+        unready: Unready,
+    },
 }
 
 /// A future that resolves to an HTTP response.
 ///
 /// This is returned by the `Service::call` on [`Channel`].
 pub struct ResponseFuture {
-    inner: buffer::future::ResponseFuture<<Svc as Service<Request<BoxBody>>>::Future>,
+    state: ResponseState,
+    // Set if the request may be retried.
+    retry: Option<Retry>,
+    // Where to record the request's statistics, its path and when it was sent.
+    method_stats: Option<(Arc<MethodStatsMap>, String, Instant)>,
+}
+
+// What is needed to send a request again if the first attempt fails because of its connection.
+struct Retry {
+    svc: Buffer<Svc, Request<BoxBody>>,
+    stats: Arc<QueueStats>,
+    request: Request<()>,
+    body: ReplayBody,
+}
+
+type BufferFuture = buffer::future::ResponseFuture<<Svc as Service<Request<BoxBody>>>::Future>;
+
+enum ResponseState {
+    // The request was rejected because the buffer was full.
+    Overloaded,
+    Called(BufferFuture),
+    // Waiting for the channel to be ready to retry the request with the given body.
+    Retrying(Option<ReplayBody>),
 }
 
 pub trait IntoUri {
     fn into_uri(self) -> Result<Uri>;
+
+    /// Convert into a gRPC [`Target`], strings are parsed using the gRPC naming syntax.
+    fn into_target(self) -> Result<Target>
+    where
+        Self: Sized,
+    {
+        self.into_uri().map(Target::Dns)
+    }
 }
 
 impl IntoUri for Uri {
@@ -83,6 +188,10 @@ impl IntoUri for &'static str {
     fn into_uri(self) -> Result<Uri> {
         Ok(Uri::from_static(self))
     }
+
+    fn into_target(self) -> Result<Target> {
+        self.parse()
+    }
 }
 
 impl IntoUri for String {
@@ -90,32 +199,44 @@ impl IntoUri for String {
         let bytes: Bytes = self.into_bytes().into();
         bytes.into_uri()
     }
+
+    fn into_target(self) -> Result<Target> {
+        self.parse()
+    }
 }
 
 impl IntoUri for Bytes {
     fn into_uri(self) -> Result<Uri> {
         Uri::from_maybe_shared(self).map_err(|e| Error::new_invalid_uri(e.to_string()))
     }
+
+    fn into_target(self) -> Result<Target> {
+        std::str::from_utf8(&self)
+            .map_err(|e| Error::new_invalid_uri(e.to_string()))?
+            .parse()
+    }
 }
 
 impl Channel {
     /// Create an [`Endpoint`] builder that can create [`Channel`]s.
+    ///
+    /// `uri` may be a URI or a gRPC [`Target`] string.
     pub fn builder(uri: impl IntoUri, tls: TlsConnector) -> Result<ChannelBuilder> {
         ChannelBuilder::new(uri, tls)
     }
 
+    /// Create an [`Endpoint`] builder for channels whose connections don't use TLS, see
+    /// [`ChannelBuilder::new_plaintext`].
+    pub fn builder_plaintext(uri: impl IntoUri) -> Result<ChannelBuilder> {
+        ChannelBuilder::new_plaintext(uri)
+    }
+
     /// Balance a list of [`Endpoint`]'s.
     ///
     /// This creates a [`Channel`] that will load balance across all the
-    /// provided endpoints.
+    /// provided endpoints. Use [`BalanceBuilder`] to configure balancing.
     pub fn balance_list(list: impl Iterator<Item = ChannelBuilder>) -> Self {
-        let (channel, tx) = Self::balance_channel(DEFAULT_BUFFER_SIZE);
-        list.for_each(|endpoint| {
-            tx.try_send(Change::Insert(endpoint.uri.clone(), endpoint))
-                .unwrap();
-        });
-
-        channel
+        BalanceBuilder::new().list(list)
     }
 
     /// Balance a list of [`Endpoint`]'s.
@@ -125,9 +246,40 @@ impl Channel {
     where
         K: Hash + Eq + Send + Clone + 'static,
     {
-        let (tx, rx) = channel(capacity);
-        let list = DynamicServiceStream::new(rx);
-        (Self::balance(list, DEFAULT_BUFFER_SIZE), tx)
+        BalanceBuilder::new().channel(capacity)
+    }
+
+    /// Balance across the endpoints found by `resolver`, each configured by `template`.
+    ///
+    /// Use [`BalanceBuilder::resolver`] to configure balancing.
+    pub fn balance_resolver<R: Resolver>(resolver: R, template: ChannelBuilder) -> Self {
+        BalanceBuilder::new().resolver(resolver, template)
+    }
+
+    /// Balance across the addresses of `endpoint`'s host, resolving it with DNS every
+    /// `refresh_interval` so that the channel follows rolling deployments.
+    ///
+    /// Each address's endpoint is configured by `endpoint`. This is a shorthand for a
+    /// [`DnsResolver`], which has more options, such as resolving again when a connection
+    /// fails. Fails if `endpoint`'s target isn't a host name.
+    ///
+    /// ```no_run
+    /// # use tonic_transport::{Channel, ChannelBuilder};
+    /// # use std::time::Duration;
+    /// # fn example(endpoint: ChannelBuilder) -> Result<(), tonic_transport::Error> {
+    /// let channel = Channel::balance_dns(endpoint, Duration::from_secs(30))?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn balance_dns(endpoint: ChannelBuilder, refresh_interval: Duration) -> Result<Self> {
+        let Target::Dns(uri) = &endpoint.target else {
+            return Err(Error::new_invalid_uri(
+                "DNS balancing requires a host name".to_owned(),
+            ));
+        };
+        let host = uri.host().unwrap_or_default().to_owned();
+        let resolver = DnsResolver::new(host, endpoint.port()).refresh_interval(refresh_interval);
+        Ok(resolver.channel(BalanceBuilder::new(), endpoint))
     }
 
     pub(crate) fn new<C>(connector: C, endpoint: ChannelBuilder) -> Self
@@ -138,12 +290,17 @@ impl Channel {
         C::Response: AsyncRead + AsyncWrite + HyperConnection + Unpin + Send + 'static,
     {
         let buffer_size = endpoint.buffer_size.unwrap_or(DEFAULT_BUFFER_SIZE);
+        let retry_methods = RetryMethods::new(endpoint.retry_methods.clone());
+        let method_stats = endpoint.method_stats;
+        let mode = Mode::Endpoint(endpoint.uri.clone());
 
         let svc = Connection::lazy(connector, endpoint);
-        let (svc, worker) = Buffer::pair(Either::A(svc), buffer_size);
+        let ping_rtt = svc.ping_rtt().clone();
+        let (svc, worker) = Buffer::pair(Dequeue::new(Either::A(svc)), buffer_size);
         tokio::spawn(Box::pin(worker));
 
-        Channel { svc }
+        Channel::from_buffer(svc, mode, retry_methods, Some(ping_rtt))
+            .with_method_stats(method_stats)
     }
 
     pub(crate) async fn connect<C>(connector: C, endpoint: ChannelBuilder) -> Result<Self>
@@ -154,61 +311,346 @@ impl Channel {
         C::Response: AsyncRead + AsyncWrite + HyperConnection + Unpin + Send + 'static,
     {
         let buffer_size = endpoint.buffer_size.unwrap_or(DEFAULT_BUFFER_SIZE);
+        let retry_methods = RetryMethods::new(endpoint.retry_methods.clone());
+        let method_stats = endpoint.method_stats;
+        let mode = Mode::Endpoint(endpoint.uri.clone());
 
         let svc = Connection::connect(connector, endpoint)
             .await
             .map_err(super::Error::from_source)?;
-        let (svc, worker) = Buffer::pair(Either::A(svc), buffer_size);
+        let ping_rtt = svc.ping_rtt().clone();
+        let (svc, worker) = Buffer::pair(Dequeue::new(Either::A(svc)), buffer_size);
         tokio::spawn(Box::pin(worker));
 
-        Ok(Channel { svc })
+        Ok(
+            Channel::from_buffer(svc, mode, retry_methods, Some(ping_rtt))
+                .with_method_stats(method_stats),
+        )
     }
 
-    pub(crate) fn balance<D>(discover: D, buffer_size: usize) -> Self
+    pub(crate) fn balance<D>(
+        discover: D,
+        buffer_size: usize,
+        policy: Box<dyn Policy>,
+        policy_name: &'static str,
+        unready: Unready,
+        retry_methods: RetryMethods,
+    ) -> Self
     where
         D: Discover<Service = Connection> + Unpin + Send + 'static,
         D::Error: Into<BoxError>,
         D::Key: Hash + Send + Clone,
     {
-        let svc = Balance::new(discover);
+        let svc = Balance::new(discover, policy, unready);
 
         let svc = BoxService::new(svc);
-        let (svc, worker) = Buffer::pair(Either::B(svc), buffer_size);
+        let (svc, worker) = Buffer::pair(Dequeue::new(Either::B(svc)), buffer_size);
         tokio::spawn(Box::pin(worker));
 
-        Channel { svc }
+        let mode = Mode::Balanced {
+            policy: policy_name,
+            unready,
+        };
+        Channel::from_buffer(svc, mode, retry_methods, None)
+    }
+
+    fn from_buffer(
+        svc: Buffer<Svc, Request<BoxBody>>,
+        mode: Mode,
+        retry_methods: RetryMethods,
+        ping_rtt: Option<Arc<PingRtt>>,
+    ) -> Self {
+        Channel {
+            svc,
+            // Fail requests, rather than wait, when the buffer is full and the balancer doesn't
+            // wait for endpoints.
+            shed_load: !matches!(
+                mode,
+                Mode::Endpoint(_)
+                    | Mode::Balanced {
+                        unready: Unready::Wait,
+                        ..
+                    }
+            ),
+            overloaded: false,
+            retry_methods,
+            stats: Arc::default(),
+            ping_rtt,
+            method_stats: None,
+            mode,
+        }
+    }
+
+    pub(crate) fn with_method_stats(self, enabled: bool) -> Self {
+        Channel {
+            method_stats: enabled.then(Arc::default),
+            ..self
+        }
+    }
+
+    /// Get the state of the channel's request queue.
+    ///
+    /// Requests wait in the queue until the channel's connection, or for a balanced channel one of
+    /// its endpoints, is ready for them. The statistics are shared by all clones of the channel.
+    pub fn stats(&self) -> ChannelStats {
+        let ping_rtt = self.ping_rtt.as_ref().and_then(|rtt| rtt.get());
+        self.stats.snapshot(ping_rtt)
+    }
+
+    /// Send an HTTP/2 PING on the channel's connection and wait for the server to acknowledge
+    /// it, returning the round-trip time, to check that the connection is alive without making
+    /// a call.
+    ///
+    /// The PING is sent as soon as the connection is between frames, even while it is busy.
+    /// This fails with [`Error::ConnectionClosed`] if the channel isn't connected or the
+    /// connection closes before the acknowledgement, and with [`Error::PingUnavailable`] for a
+    /// balanced channel. A server which doesn't answer is only detected with a timeout:
+    ///
+    /// ```no_run
+    /// # use tonic_transport::Channel;
+    /// # use std::time::Duration;
+    /// # async fn example(channel: Channel) {
+    /// match tokio::time::timeout(Duration::from_secs(5), channel.ping()).await {
+    ///     Ok(Ok(rtt)) => println!("alive, {rtt:?} round trip"),
+    ///     Ok(Err(error)) => println!("not connected: {error}"),
+    ///     Err(_) => println!("no answer"),
+    /// }
+    /// # }
+    /// ```
+    pub async fn ping(&self) -> Result<Duration> {
+        match &self.ping_rtt {
+            Some(rtt) => rtt.ping().await,
+            None => Err(Error::PingUnavailable),
+        }
+    }
+
+    fn record_method_stats<B>(
+        &self,
+        request: &Request<B>,
+    ) -> Option<(Arc<MethodStatsMap>, String, Instant)> {
+        let stats = self.method_stats.clone()?;
+        Some((stats, request.uri().path().to_owned(), Instant::now()))
+    }
+
+    /// Get the statistics of the calls made on the channel, keyed by method path such as
+    /// `/helloworld.Greeter/SayHello`.
+    ///
+    /// Statistics are only recorded if enabled with [`ChannelBuilder::method_stats`] or
+    /// [`BalanceBuilder::method_stats`], otherwise this is empty. They are shared by all clones of
+    /// the channel.
+    pub fn method_stats(&self) -> HashMap<String, MethodStats> {
+        match &self.method_stats {
+            Some(stats) => stats.snapshot(),
+            None => HashMap::new(),
+        }
+    }
+
+    /// Start connecting, without waiting for the first request.
+    ///
+    /// Channels created by [`connect_lazy`](ChannelBuilder::connect_lazy) otherwise only connect
+    /// when the first request is sent, which makes that request wait for name resolution and the
+    /// TCP, TLS and HTTP/2 handshakes. The connection is made in the background, even if the
+    /// returned future is dropped; the future resolves once the channel is ready for requests.
+    /// For a balanced channel, that is when one of its endpoints is ready.
+    ///
+    /// ```no_run
+    /// # use tonic_transport::ChannelBuilder;
+    /// # async fn example(builder: ChannelBuilder) -> Result<(), tonic_transport::Error> {
+    /// let channel = builder.connect_lazy()?;
+    /// channel.warm_up().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn warm_up(&self) -> impl Future<Output = Result<()>> + Send + 'static {
+        let mut request = Request::new(tonic::body::empty_body());
+        request.extensions_mut().insert(WarmUp);
+        let warm_up = tokio::spawn(self.svc.clone().oneshot(request));
+        async move {
+            match warm_up.await {
+                Ok(result) => result.map(drop).map_err(Error::from_source),
+                Err(error) => Err(Error::from_source(error.into())),
+            }
+        }
     }
 }
 
 impl Service<http::Request<BoxBody>> for Channel {
-    type Response = http::Response<crate::Body>;
+    type Response = http::Response<ResponseBody>;
     type Error = Error;
     type Future = ResponseFuture;
 
     fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<()>> {
-        Service::poll_ready(&mut self.svc, cx).map_err(Error::from_source)
+        match Service::poll_ready(&mut self.svc, cx) {
+            Poll::Pending if self.shed_load => {
+                self.overloaded = true;
+                Poll::Ready(Ok(()))
+            }
+            Poll::Pending => Poll::Pending,
+            Poll::Ready(result) => {
+                self.overloaded = false;
+                Poll::Ready(result.map_err(Error::from_source))
+            }
+        }
     }
 
-    fn call(&mut self, request: http::Request<BoxBody>) -> Self::Future {
+    fn call(&mut self, mut request: http::Request<BoxBody>) -> Self::Future {
+        if self.overloaded {
+            self.overloaded = false;
+            return ResponseFuture {
+                state: ResponseState::Overloaded,
+                retry: None,
+                method_stats: self.record_method_stats(&request),
+            };
+        }
+
+        // Calls made while handling a request, see `PropagateDeadline`, must finish before its
+        // deadline.
+        if let Some(deadline) = inbound_deadline() {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match try_parse_grpc_timeout(request.headers()) {
+                Ok(Some(timeout)) if timeout <= remaining => {}
+                _ => {
+                    request
+                        .headers_mut()
+                        .insert(GRPC_TIMEOUT_HEADER, encode_grpc_timeout(remaining));
+                }
+            }
+        }
+
+        // The `grpc-timeout` header is refreshed when the request is sent on a connection, so that
+        // time spent queueing counts against the deadline.
+        if let Ok(Some(timeout)) = try_parse_grpc_timeout(request.headers()) {
+            request
+                .extensions_mut()
+                .insert(Deadline(Instant::now() + timeout));
+        }
+
+        let method_stats = self.record_method_stats(&request);
+        if !self.retry_methods.matches(&request) {
+            // The request keeps all of its extensions, since it won't need to be copied.
+            self.stats.enqueue(&mut request);
+            return ResponseFuture {
+                state: ResponseState::Called(Service::call(&mut self.svc, request)),
+                retry: None,
+                method_stats,
+            };
+        }
+
+        // Extensions can't be cloned, so the copy of the request for a retry has only the
+        // channel's own.
+        let (parts, body) = request.into_parts();
+        let mut template = Request::new(());
+        *template.method_mut() = parts.method.clone();
+        *template.uri_mut() = parts.uri.clone();
+        *template.version_mut() = parts.version;
+        *template.headers_mut() = parts.headers.clone();
+        copy_extension::<RoutingHint>(&parts.extensions, template.extensions_mut());
+        copy_extension::<SessionKey>(&parts.extensions, template.extensions_mut());
+        copy_extension::<Deadline>(&parts.extensions, template.extensions_mut());
+        copy_extension::<CallTimeout>(&parts.extensions, template.extensions_mut());
+
+        let replay = ReplayBody::new(body, MAX_REPLAY_LEN);
+        let body = replay.try_clone().expect("no data has been read");
+        let mut request = Request::from_parts(parts, BoxBody::new(body));
+        self.stats.enqueue(&mut request);
         let inner = Service::call(&mut self.svc, request);
 
-        ResponseFuture { inner }
+        ResponseFuture {
+            state: ResponseState::Called(inner),
+            retry: Some(Retry {
+                svc: self.svc.clone(),
+                stats: self.stats.clone(),
+                request: template,
+                body: replay,
+            }),
+            method_stats,
+        }
     }
 }
 
 impl Future for ResponseFuture {
-    type Output = Result<Response<hyper::Body>>;
+    type Output = Result<Response<ResponseBody>>;
 
     fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        let val =
-            futures_util::ready!(Pin::new(&mut self.inner).poll(cx)).map_err(Error::from_source)?;
-        Ok(val).into()
+        let result = futures_util::ready!(self.poll_response(cx));
+        if let Some((stats, path, started)) = self.method_stats.take() {
+            stats.record(&path, started, result.as_ref());
+        }
+        Poll::Ready(result)
+    }
+}
+
+impl ResponseFuture {
+    fn poll_response(&mut self, cx: &mut Context<'_>) -> Poll<Result<Response<ResponseBody>>> {
+        let this = self;
+        loop {
+            match &mut this.state {
+                ResponseState::Overloaded => {
+                    let status = tonic::Status::unavailable("request queue is full");
+                    return Poll::Ready(Err(Error::from_source(status.into())));
+                }
+                ResponseState::Called(inner) => {
+                    let error = match futures_util::ready!(Pin::new(inner).poll(cx)) {
+                        Err(error) if this.retry.is_some() && retry::is_transport_error(&error) => {
+                            error
+                        }
+                        result => return Poll::Ready(result.map_err(Error::from_source)),
+                    };
+                    match this.retry.as_ref().and_then(|retry| retry.body.try_clone()) {
+                        Some(body) if retry::is_refused(&error) => {
+                            // The server never processed the request.
+                            tracing::debug!("retrying request refused by the server");
+                            this.state = ResponseState::Retrying(Some(body));
+                        }
+                        Some(body) => {
+                            tracing::debug!(%error, "retrying request after transport error");
+                            this.state = ResponseState::Retrying(Some(body));
+                        }
+                        None => return Poll::Ready(Err(Error::from_source(error))),
+                    }
+                }
+                ResponseState::Retrying(body) => {
+                    let retry = this.retry.as_mut().expect("retrying without a request");
+                    futures_util::ready!(Service::poll_ready(&mut retry.svc, cx))
+                        .map_err(Error::from_source)?;
+
+                    let body = body.take().expect("polled after ready");
+                    let Retry {
+                        mut svc,
+                        stats,
+                        request,
+                        ..
+                    } = this.retry.take().expect("retrying without a request");
+                    let mut request = request.map(|()| BoxBody::new(body));
+                    stats.enqueue(&mut request);
+                    this.state = ResponseState::Called(Service::call(&mut svc, request));
+                }
+            }
+        }
+    }
+}
+
+fn copy_extension<T: Clone + Send + Sync + 'static>(
+    from: &http::Extensions,
+    to: &mut http::Extensions,
+) {
+    if let Some(value) = from.get::<T>() {
+        to.insert(value.clone());
     }
 }
 
 impl fmt::Debug for Channel {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.debug_struct("Channel").finish()
+        let mut f = f.debug_struct("Channel");
+        match &self.mode {
+            Mode::Endpoint(uri) => f.field("uri", uri),
+            Mode::Balanced { policy, unready } => {
+                f.field("policy", policy).field("unready", unready)
+            }
+        };
+        f.field("retry_methods", &self.retry_methods)
+            .field("method_stats", &self.method_stats.is_some())
+            .finish_non_exhaustive()
     }
 }
 
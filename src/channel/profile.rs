@@ -0,0 +1,76 @@
+use super::ChannelBuilder;
+use crate::ConnectBackoff;
+
+use std::time::Duration;
+
+/// Settings for the network between a client and its servers, see
+/// [`ChannelBuilder::with_profile`].
+///
+/// A profile only sets a starting point: each setting can still be changed with the builder's
+/// other methods, which override the profile.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NetworkProfile {
+    /// For servers in the same datacenter, with low latency and reliable links.
+    ///
+    /// Connection attempts time out after 2 seconds, and keepalive PINGs are sent every 60
+    /// seconds while calls are in progress.
+    Datacenter,
+    /// For servers in another region, or clients on mobile networks, where latency is high and
+    /// connections are silently dropped by NATs and network changes.
+    ///
+    /// Keepalive PINGs are sent every 20 seconds while calls are in progress, so that dropped
+    /// connections are noticed, and TCP keepalives every 30 seconds keep idle connections open.
+    /// PINGs are not sent while the connection is idle, since standard servers, such as grpc-go
+    /// and gRPC C-core, close connections which do that. Flow control windows adapt to
+    /// the link's bandwidth-delay product. Connection attempts time out after 10 seconds, and
+    /// reconnection backs off to at most 30 seconds so that clients recover quickly once the
+    /// network does.
+    Wan,
+}
+
+impl NetworkProfile {
+    pub(crate) fn apply(self, builder: ChannelBuilder) -> ChannelBuilder {
+        match self {
+            NetworkProfile::Datacenter => builder
+                .tcp_nodelay(true)
+                .connect_timeout(Duration::from_secs(2))
+                .http2_keep_alive_interval(Duration::from_secs(60))
+                .keep_alive_timeout(Duration::from_secs(20))
+                .keep_alive_while_idle(false),
+            NetworkProfile::Wan => builder
+                .tcp_nodelay(true)
+                .tcp_keepalive(Some(Duration::from_secs(30)))
+                .connect_timeout(Duration::from_secs(10))
+                .connect_backoff(ConnectBackoff::new().max(Duration::from_secs(30)))
+                .http2_keep_alive_interval(Duration::from_secs(20))
+                .keep_alive_timeout(Duration::from_secs(10))
+                .keep_alive_while_idle(false)
+                .http2_adaptive_window(true),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio_native_tls::TlsConnector;
+
+    #[test]
+    fn later_settings_override_profile() {
+        let tls = TlsConnector::from(native_tls::TlsConnector::new().unwrap());
+        let builder = ChannelBuilder::new("https://example.com", tls)
+            .unwrap()
+            .with_profile(NetworkProfile::Wan)
+            .http2_keep_alive_interval(Duration::from_secs(5));
+        assert_eq!(
+            builder.http2_keep_alive_interval,
+            Some(Duration::from_secs(5))
+        );
+        assert_eq!(
+            builder.http2_keep_alive_timeout,
+            Some(Duration::from_secs(10))
+        );
+        assert_eq!(builder.http2_adaptive_window, Some(true));
+    }
+}
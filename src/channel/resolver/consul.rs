@@ -0,0 +1,381 @@
+use super::{authority, BoxResolved, ResolvedEndpoints, Resolver};
+use crate::service::backoff::{Backoff, ConnectBackoff};
+use crate::{BalanceBuilder, BoxError, Channel, ChannelBuilder, Error, Result};
+
+use http::uri::{Authority, Uri};
+use hyper::{client::HttpConnector, Client};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use serde_json::Value;
+use std::{collections::HashSet, time::Duration};
+use tokio::time::{sleep, sleep_until, Instant};
+
+const INDEX_HEADER: &str = "x-consul-index";
+
+/// A resolver which watches the healthy instances of a Consul service.
+///
+/// Instances are fetched from the agent's health API with blocking queries, so changes are
+/// seen as soon as Consul reports them, limited by
+/// [`min_interval`](ConsulResolver::min_interval). An instance is used while all of its health checks are
+/// passing; see [`allow_warning`](ConsulResolver::allow_warning). If the agent can't be
+/// reached, the last known instances are kept. Requires the `consul` feature.
+///
+/// ```no_run
+/// # use tonic_transport::{BalanceBuilder, ChannelBuilder, ConsulResolver};
+/// # fn example(tls: tokio_native_tls::TlsConnector) -> Result<(), tonic_transport::Error> {
+/// // Each instance's certificate is verified for `greeter.service.consul`.
+/// let template = ChannelBuilder::new("https://greeter.service.consul", tls)?;
+/// let channel = ConsulResolver::new("http://127.0.0.1:8500".parse().unwrap(), "greeter")
+///     .tag("grpc")
+///     .channel(BalanceBuilder::new(), template);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct ConsulResolver {
+    addr: Uri,
+    service: String,
+    tag: Option<String>,
+    datacenter: Option<String>,
+    allow_warning: bool,
+    wait: Duration,
+    min_interval: Duration,
+}
+
+impl ConsulResolver {
+    /// Create a resolver for `service`, using the Consul agent's HTTP API at `addr`, such as
+    /// `http://127.0.0.1:8500`.
+    pub fn new(addr: Uri, service: impl Into<String>) -> Self {
+        ConsulResolver {
+            addr,
+            service: service.into(),
+            tag: None,
+            datacenter: None,
+            allow_warning: false,
+            wait: Duration::from_secs(300),
+            min_interval: Duration::from_secs(1),
+        }
+    }
+
+    /// Only use instances with `tag`.
+    pub fn tag(self, tag: impl Into<String>) -> Self {
+        ConsulResolver {
+            tag: Some(tag.into()),
+            ..self
+        }
+    }
+
+    /// Use instances in `datacenter`, instead of the agent's datacenter.
+    pub fn datacenter(self, datacenter: impl Into<String>) -> Self {
+        ConsulResolver {
+            datacenter: Some(datacenter.into()),
+            ..self
+        }
+    }
+
+    /// Also use instances whose health checks are in the `warning` state.
+    ///
+    /// Instances with a `critical` check, including those in maintenance, are never used.
+    pub fn allow_warning(self, allow_warning: bool) -> Self {
+        ConsulResolver {
+            allow_warning,
+            ..self
+        }
+    }
+
+    /// Set how long each blocking query waits for a change before it is repeated.
+    ///
+    /// Default is five minutes.
+    pub fn wait(self, wait: Duration) -> Self {
+        ConsulResolver { wait, ..self }
+    }
+
+    /// Set the shortest time between the start of one query and the next, so that a service
+    /// whose instances change often doesn't overload Consul.
+    ///
+    /// Default is one second.
+    pub fn min_interval(self, min_interval: Duration) -> Self {
+        ConsulResolver {
+            min_interval,
+            ..self
+        }
+    }
+
+    /// Create a [`Channel`] balancing across the service's instances, which are kept up to date
+    /// until the channel is dropped.
+    ///
+    /// Each instance's endpoint is configured by `template`, with the instance's address in
+    /// place of the template's. Unless the template has a
+    /// [`tls_verify_domain`](ChannelBuilder::tls_verify_domain), instances are verified for
+    /// the template's host.
+    pub fn channel(self, balance: BalanceBuilder, template: ChannelBuilder) -> Channel {
+        balance.resolver(self, template)
+    }
+
+    /// Wait for the instances to change after `index`, and return the new index and the
+    /// healthy instances.
+    async fn fetch(
+        &self,
+        client: &Client<HttpConnector>,
+        index: Option<u64>,
+    ) -> Result<(u64, HashSet<Authority>)> {
+        let uri = self.query_uri(index)?;
+        let response = client
+            .get(uri)
+            .await
+            .map_err(|e| Error::from_source(e.into()))?;
+        if !response.status().is_success() {
+            let error = format!("Consul responded with {}", response.status());
+            return Err(Error::from_source(error.into()));
+        }
+
+        // Without an index, the next query wouldn't block.
+        let new_index = response
+            .headers()
+            .get(INDEX_HEADER)
+            .and_then(|index| index.to_str().ok())
+            .and_then(|index| index.parse().ok())
+            .ok_or_else(|| Error::from_source("Consul response has no valid index".into()))?;
+        let body = hyper::body::to_bytes(response.into_body())
+            .await
+            .map_err(|e| Error::from_source(e.into()))?;
+        let instances = parse_instances(&body, self.allow_warning).map_err(Error::from_source)?;
+        Ok((new_index, instances))
+    }
+
+    fn query_uri(&self, index: Option<u64>) -> Result<Uri> {
+        let mut uri = format!(
+            "{}/v1/health/service/{}?wait={}s",
+            self.addr.to_string().trim_end_matches('/'),
+            utf8_percent_encode(&self.service, NON_ALPHANUMERIC),
+            self.wait.as_secs().max(1),
+        );
+        if let Some(index) = index {
+            uri.push_str(&format!("&index={}", index));
+        }
+        if let Some(tag) = &self.tag {
+            uri.push_str(&format!(
+                "&tag={}",
+                utf8_percent_encode(tag, NON_ALPHANUMERIC)
+            ));
+        }
+        if let Some(datacenter) = &self.datacenter {
+            uri.push_str(&format!(
+                "&dc={}",
+                utf8_percent_encode(datacenter, NON_ALPHANUMERIC)
+            ));
+        }
+        uri.parse().map_err(|_| Error::new_invalid_uri(uri))
+    }
+}
+
+impl Resolver for ConsulResolver {
+    type Endpoints = BoxResolved;
+
+    fn resolve(self, _template: &mut ChannelBuilder) -> Self::Endpoints {
+        Box::pin(async_stream::stream! {
+            let client = Client::new();
+            let mut backoff = Backoff::new(ConnectBackoff::default());
+            let mut index = None;
+
+            loop {
+                let started = Instant::now();
+                match self.fetch(&client, index).await {
+                    Ok((new_index, instances)) => {
+                        yield Ok(ResolvedEndpoints::from(instances));
+                        index = next_index(index, new_index);
+                        if index.is_some() {
+                            backoff.reset();
+                            sleep_until(started + self.min_interval).await;
+                        } else {
+                            // Such as after Consul restores a snapshot, so wait in case it is
+                            // still recovering.
+                            tracing::debug!(service = %self.service, "Consul index went backwards");
+                            sleep(backoff.next_delay().max(self.min_interval)).await;
+                        }
+                    }
+                    Err(error) => {
+                        index = None;
+                        let service = &self.service;
+                        let error = format!("failed to query Consul for {}: {}", service, error);
+                        yield Err(error.into());
+                        sleep(backoff.next_delay().max(self.min_interval)).await;
+                    }
+                }
+            }
+        })
+    }
+}
+
+/// The index for the query after one which returned `new_index`, following Consul's advice for
+/// blocking queries: the index is reset if it goes backwards, and is never zero, since a query
+/// with index zero returns immediately.
+fn next_index(index: Option<u64>, new_index: u64) -> Option<u64> {
+    match index {
+        Some(index) if new_index < index => None,
+        _ => Some(new_index.max(1)),
+    }
+}
+
+/// Parse the response of the health API, returning the addresses of the instances whose checks
+/// are all passing, or warning if `allow_warning`.
+fn parse_instances(
+    body: &[u8],
+    allow_warning: bool,
+) -> std::result::Result<HashSet<Authority>, BoxError> {
+    let entries: Value = serde_json::from_slice(body)?;
+    let entries = entries
+        .as_array()
+        .ok_or("expected an array of service entries")?;
+
+    let mut instances = HashSet::new();
+    for entry in entries {
+        let healthy = entry["Checks"]
+            .as_array()
+            .map(|checks| {
+                checks.iter().all(|check| match check["Status"].as_str() {
+                    Some("passing") => true,
+                    Some("warning") => allow_warning,
+                    _ => false,
+                })
+            })
+            .unwrap_or(true);
+        if !healthy {
+            continue;
+        }
+
+        let service = &entry["Service"];
+        let host = match service["Address"].as_str() {
+            Some(address) if !address.is_empty() => address,
+            _ => entry["Node"]["Address"].as_str().unwrap_or_default(),
+        };
+        let port = service["Port"]
+            .as_u64()
+            .and_then(|port| u16::try_from(port).ok());
+        match port.and_then(|port| authority(host, port)) {
+            Some(authority) => {
+                instances.insert(authority);
+            }
+            None => tracing::debug!(%host, ?port, "ignoring Consul instance with invalid address"),
+        }
+    }
+    Ok(instances)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::StreamExt;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+        sync::mpsc,
+    };
+    use tokio_native_tls::TlsConnector;
+
+    fn parse(body: &str, allow_warning: bool) -> Vec<String> {
+        let mut instances: Vec<_> = parse_instances(body.as_bytes(), allow_warning)
+            .unwrap()
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        instances.sort();
+        instances
+    }
+
+    #[test]
+    fn parses_healthy_instances() {
+        let body = r#"[
+            {
+                "Node": {"Address": "10.0.0.1"},
+                "Service": {"Address": "", "Port": 50051},
+                "Checks": [{"Status": "passing"}, {"Status": "passing"}]
+            },
+            {
+                "Node": {"Address": "10.0.0.2"},
+                "Service": {"Address": "10.1.0.2", "Port": 50051},
+                "Checks": [{"Status": "passing"}, {"Status": "warning"}]
+            },
+            {
+                "Node": {"Address": "10.0.0.3"},
+                "Service": {"Address": "10.1.0.3", "Port": 50051},
+                "Checks": [{"Status": "critical"}]
+            },
+            {
+                "Node": {"Address": "10.0.0.4"},
+                "Service": {"Address": "10.1.0.4", "Port": 70000},
+                "Checks": []
+            }
+        ]"#;
+        assert_eq!(parse(body, false), ["10.0.0.1:50051"]);
+        assert_eq!(parse(body, true), ["10.0.0.1:50051", "10.1.0.2:50051"]);
+        assert_eq!(parse("[]", false), Vec::<String>::new());
+        assert!(parse_instances(br#"{"Service": {}}"#, false).is_err());
+    }
+
+    #[test]
+    fn resets_the_index() {
+        assert_eq!(next_index(None, 5), Some(5));
+        assert_eq!(next_index(Some(5), 7), Some(7));
+        assert_eq!(next_index(Some(5), 5), Some(5));
+        assert_eq!(next_index(Some(5), 3), None);
+        assert_eq!(next_index(None, 0), Some(1));
+    }
+
+    /// Answer a request for each of `indexes` with no instances and the index, if any, sending
+    /// the target of each request and when it was received.
+    async fn agent(indexes: Vec<Option<u64>>) -> (Uri, mpsc::UnboundedReceiver<(String, Instant)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let uri = format!("http://{}", listener.local_addr().unwrap());
+        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            for index in indexes {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+                while !request.ends_with(b"\r\n\r\n") {
+                    request.push(stream.read_u8().await.unwrap());
+                }
+                let request = String::from_utf8(request).unwrap();
+                let target = request.split(' ').nth(1).unwrap().to_owned();
+                tx.send((target, Instant::now())).unwrap();
+
+                let header = index
+                    .map(|index| format!("{}: {}\r\n", INDEX_HEADER, index))
+                    .unwrap_or_default();
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\n{}content-length: 2\r\nconnection: close\r\n\r\n[]",
+                    header
+                );
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        (uri.parse().unwrap(), rx)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn waits_between_queries() {
+        let (uri, mut requests) = agent(vec![Some(5), Some(3), None, Some(4)]).await;
+        let resolver = ConsulResolver::new(uri, "greeter").min_interval(Duration::from_secs(2));
+        let tls = TlsConnector::from(native_tls::TlsConnector::new().unwrap());
+        let mut template = ChannelBuilder::new("http://greeter", tls).unwrap();
+        let mut resolved = resolver.resolve(&mut template);
+
+        // The index going backwards and a missing index both reset it.
+        assert!(resolved.next().await.unwrap().is_ok());
+        assert!(resolved.next().await.unwrap().is_ok());
+        assert!(resolved.next().await.unwrap().is_err());
+        assert!(resolved.next().await.unwrap().is_ok());
+
+        let mut previous: Option<Instant> = None;
+        for index in ["", "&index=5", "", ""] {
+            let (target, at) = requests.recv().await.unwrap();
+            assert_eq!(
+                target,
+                format!("/v1/health/service/greeter?wait=300s{}", index)
+            );
+            if let Some(previous) = previous {
+                assert!(at - previous >= Duration::from_secs(2));
+            }
+            previous = Some(at);
+        }
+    }
+}
@@ -0,0 +1,845 @@
+use super::{authority, BoxResolved, ResolvedEndpoints, Resolver};
+use crate::service::backoff::{Backoff, ConnectBackoff};
+use crate::{BalanceBuilder, Channel, ChannelBuilder};
+
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpStream, UdpSocket},
+    sync::Notify,
+    time::{sleep, sleep_until, timeout, Instant},
+};
+
+const RESOLV_CONF: &str = "/etc/resolv.conf";
+const HOSTS: &str = "/etc/hosts";
+const QUERY_TIMEOUT: Duration = Duration::from_secs(2);
+// The UDP payload size advertised with EDNS, so that large answers are not truncated.
+const UDP_PAYLOAD_SIZE: u16 = 4096;
+
+const TYPE_A: u16 = 1;
+const TYPE_AAAA: u16 = 28;
+const TYPE_SRV: u16 = 33;
+const TYPE_OPT: u16 = 41;
+const CLASS_IN: u16 = 1;
+const RCODE_NOERROR: u16 = 0;
+const RCODE_NXDOMAIN: u16 = 3;
+
+/// A resolver which looks up every address of a host name with DNS, and keeps them up to date.
+///
+/// This is intended for Kubernetes headless services, whose DNS name resolves to the IP address
+/// of each ready pod: each address becomes an endpoint of the balanced channel. The name is
+/// resolved again when the shortest TTL of its records expires, or periodically, see
+/// [`refresh_interval`](DnsResolver::refresh_interval). Like other gRPC implementations, it is
+/// also resolved soon after a connection to an endpoint fails or is lost, or an endpoint sends
+/// GOAWAY, so that pods which have been removed by a scale-down or a rollout are noticed
+/// without waiting for the TTL. Resolutions caused by these events are limited by
+/// [`min_interval`](DnsResolver::min_interval).
+///
+/// Queries are sent to the name servers in `/etc/resolv.conf`, using its search domains, so a
+/// service can be named relative to the pod's namespace; resolving fails if the file can't be
+/// read. Addresses in `/etc/hosts` are used instead of querying for a host name listed there,
+/// as with the usual `hosts: files dns` in `/etc/nsswitch.conf`, which isn't read.
+///
+/// To balance across the targets of SRV records instead, create the resolver with
+/// [`srv`](DnsResolver::srv).
+///
+/// ```no_run
+/// # use tonic_transport::{BalanceBuilder, ChannelBuilder, DnsResolver};
+/// # fn example(tls: tokio_native_tls::TlsConnector) -> Result<(), tonic_transport::Error> {
+/// let template = ChannelBuilder::new("https://greeter.default.svc.cluster.local", tls)?;
+/// let channel = DnsResolver::new("greeter.default.svc.cluster.local", 50051)
+///     .channel(BalanceBuilder::new(), template);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct DnsResolver {
+    host: String,
+    lookup: Lookup,
+    min_ttl: Duration,
+    max_ttl: Duration,
+    refresh_interval: Option<Duration>,
+    min_interval: Duration,
+    on_connection_failure: bool,
+    on_go_away: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Lookup {
+    /// The addresses of the host, with a port.
+    Host(u16),
+    /// The targets of the SRV records of the name.
+    Srv,
+}
+
+impl DnsResolver {
+    /// Create a resolver for `host`, whose endpoints are its addresses with `port`.
+    pub fn new(host: impl Into<String>, port: u16) -> Self {
+        DnsResolver::with_lookup(host.into(), Lookup::Host(port))
+    }
+
+    /// Create a resolver for the SRV records of `name`, whose endpoints are the records' targets
+    /// and ports.
+    ///
+    /// This suits Consul DNS (`_greeter._tcp.service.consul`) and the named ports of
+    /// Kubernetes headless services (`_grpc._tcp.greeter.default.svc.cluster.local`). Only the
+    /// records with the lowest priority are used, and each endpoint's
+    /// [`weight`](crate::EndpointMetadata::weight) is its record's weight, which is used by
+    /// weighted balancing [`Policy`](crate::Policy)s such as [`ZoneAware`](crate::ZoneAware).
+    /// The targets' host names are resolved when connecting to them.
+    ///
+    /// Channels created from an `srv:` [`Target`](crate::Target) use this resolver.
+    ///
+    /// ```no_run
+    /// # use tonic_transport::{BalanceBuilder, ChannelBuilder, DnsResolver};
+    /// # fn example(tls: tokio_native_tls::TlsConnector) -> Result<(), tonic_transport::Error> {
+    /// let template = ChannelBuilder::new("https://greeter.service.consul", tls)?;
+    /// let channel = DnsResolver::srv("_greeter._tcp.service.consul")
+    ///     .channel(BalanceBuilder::new(), template);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn srv(name: impl Into<String>) -> Self {
+        DnsResolver::with_lookup(name.into(), Lookup::Srv)
+    }
+
+    fn with_lookup(host: String, lookup: Lookup) -> Self {
+        DnsResolver {
+            host,
+            lookup,
+            min_ttl: Duration::from_secs(1),
+            max_ttl: Duration::from_secs(300),
+            refresh_interval: None,
+            min_interval: Duration::from_secs(1),
+            on_connection_failure: true,
+            on_go_away: true,
+        }
+    }
+
+    /// Resolve the name at least every `min_ttl`, even if its records have a shorter TTL.
+    ///
+    /// Default is one second.
+    pub fn min_ttl(self, min_ttl: Duration) -> Self {
+        DnsResolver { min_ttl, ..self }
+    }
+
+    /// Resolve the name at most every `max_ttl`, even if its records have a longer TTL. This
+    /// takes precedence over [`min_ttl`](DnsResolver::min_ttl) if it is shorter.
+    ///
+    /// Default is five minutes.
+    pub fn max_ttl(self, max_ttl: Duration) -> Self {
+        DnsResolver { max_ttl, ..self }
+    }
+
+    /// Resolve the name every `refresh_interval`, instead of when the TTL of its records
+    /// expires.
+    ///
+    /// By default, the TTL is used, limited by [`min_ttl`](DnsResolver::min_ttl) and
+    /// [`max_ttl`](DnsResolver::max_ttl).
+    pub fn refresh_interval(self, refresh_interval: Duration) -> Self {
+        DnsResolver {
+            refresh_interval: Some(refresh_interval),
+            ..self
+        }
+    }
+
+    /// Set whether to resolve the name when a connection attempt to an endpoint fails, or an
+    /// established connection is lost.
+    ///
+    /// Default is `true`.
+    pub fn resolve_on_connection_failure(self, enabled: bool) -> Self {
+        DnsResolver {
+            on_connection_failure: enabled,
+            ..self
+        }
+    }
+
+    /// Set whether to resolve the name when an endpoint sends GOAWAY, which servers usually do
+    /// before they shut down.
+    ///
+    /// Default is `true`.
+    pub fn resolve_on_go_away(self, enabled: bool) -> Self {
+        DnsResolver {
+            on_go_away: enabled,
+            ..self
+        }
+    }
+
+    /// Set the shortest time between resolving the name because of a connection failure or
+    /// GOAWAY and the previous resolution, so that failing endpoints don't overload the name
+    /// servers.
+    ///
+    /// Default is one second.
+    pub fn min_interval(self, min_interval: Duration) -> Self {
+        DnsResolver {
+            min_interval,
+            ..self
+        }
+    }
+
+    /// Create a [`Channel`] balancing across the name's addresses, which are kept up to date
+    /// until the channel is dropped.
+    ///
+    /// Each address's endpoint is configured by `template`, with the address in place of the
+    /// template's. Unless the template has a
+    /// [`tls_verify_domain`](ChannelBuilder::tls_verify_domain), endpoints are verified for the
+    /// template's host.
+    pub fn channel(self, balance: BalanceBuilder, template: ChannelBuilder) -> Channel {
+        balance.resolver(self, template)
+    }
+
+    /// Resolve the endpoints, with their weights, and the shortest TTL of their records.
+    async fn lookup_endpoints(&self) -> io::Result<(ResolvedEndpoints, u32)> {
+        match self.lookup {
+            Lookup::Host(port) => {
+                let records = lookup(&self.host).await?;
+                let ttl = records.iter().map(|(_, ttl)| *ttl).min();
+                let resolved = records
+                    .iter()
+                    .filter_map(|(ip, _)| authority(&ip.to_string(), port))
+                    .collect();
+                Ok((resolved, ttl.unwrap_or_default()))
+            }
+            Lookup::Srv => {
+                let records = lookup_srv(&self.host).await?;
+                let ttl = records.iter().map(|(_, ttl)| *ttl).min();
+                // Records with a higher priority value are only for when those with the lowest
+                // are unavailable.
+                let priority = records.iter().map(|(srv, _)| srv.priority).min();
+                let mut resolved = ResolvedEndpoints::new();
+                for (srv, _) in &records {
+                    if Some(srv.priority) != priority {
+                        continue;
+                    }
+                    if let Some(authority) = authority(&srv.target, srv.port) {
+                        resolved.insert_weighted(authority, srv.weight.into());
+                    }
+                }
+                Ok((resolved, ttl.unwrap_or_default()))
+            }
+        }
+    }
+}
+
+impl Resolver for DnsResolver {
+    type Endpoints = BoxResolved;
+
+    fn resolve(self, template: &mut ChannelBuilder) -> Self::Endpoints {
+        // Both events are reported with the same notification, which holds at most one pending
+        // event, so that a burst of events, such as a GOAWAY followed by losing the
+        // connection, causes few resolutions.
+        let events = Arc::new(Notify::new());
+        if self.on_connection_failure {
+            template.on_connection_failure = Some(events.clone());
+        }
+        if self.on_go_away {
+            template.on_go_away = Some(events.clone());
+        }
+
+        Box::pin(async_stream::stream! {
+            let mut backoff = Backoff::new(ConnectBackoff::default());
+
+            loop {
+                let result = self.lookup_endpoints().await;
+                let resolved_at = Instant::now();
+
+                let wait = match result {
+                    Ok((resolved, ttl)) if !resolved.is_empty() => {
+                        backoff.reset();
+                        yield Ok(resolved);
+                        self.refresh_interval.unwrap_or_else(|| {
+                            Duration::from_secs(ttl.into())
+                                .max(self.min_ttl)
+                                .min(self.max_ttl)
+                        })
+                    }
+                    Ok((resolved, _)) => {
+                        tracing::debug!(host = %self.host, "name has no addresses");
+                        yield Ok(resolved);
+                        backoff.next_delay()
+                    }
+                    Err(error) => {
+                        yield Err(format!("failed to resolve {}: {}", self.host, error).into());
+                        backoff.next_delay()
+                    }
+                };
+
+                tokio::select! {
+                    () = sleep(wait) => {}
+                    () = events.notified() => {
+                        sleep_until(resolved_at + self.min_interval).await;
+                        let host = &self.host;
+                        tracing::debug!(%host, "resolving again after a connection event");
+                    }
+                }
+            }
+        })
+    }
+}
+
+/// The parts of `/etc/resolv.conf` used to resolve names.
+#[derive(Debug, PartialEq, Eq)]
+struct ResolvConf {
+    nameservers: Vec<SocketAddr>,
+    search: Vec<String>,
+    ndots: usize,
+}
+
+impl Default for ResolvConf {
+    fn default() -> Self {
+        ResolvConf {
+            nameservers: vec![SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 53)],
+            search: Vec::new(),
+            ndots: 1,
+        }
+    }
+}
+
+impl ResolvConf {
+    fn parse(conf: &str) -> Self {
+        let mut nameservers = Vec::new();
+        let mut search = None;
+        let mut domain = None;
+        let mut ndots = 1;
+
+        for line in conf.lines() {
+            let mut words = line.split_whitespace();
+            match words.next() {
+                Some("nameserver") => {
+                    if let Some(ip) = words.next().and_then(|ip| ip.parse::<IpAddr>().ok()) {
+                        nameservers.push(SocketAddr::new(ip, 53));
+                    }
+                }
+                Some("search") => search = Some(words.map(str::to_owned).collect()),
+                Some("domain") => domain = words.next().map(str::to_owned),
+                Some("options") => {
+                    for option in words {
+                        if let Some(n) = option.strip_prefix("ndots:").and_then(|n| n.parse().ok())
+                        {
+                            ndots = n;
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let mut conf = ResolvConf::default();
+        if !nameservers.is_empty() {
+            conf.nameservers = nameservers;
+        }
+        conf.search = search
+            .or_else(|| domain.map(|domain| vec![domain]))
+            .unwrap_or_default();
+        conf.ndots = ndots;
+        conf
+    }
+
+    /// The names to try when resolving `host`, in order.
+    fn candidates(&self, host: &str) -> Vec<String> {
+        if host.ends_with('.') {
+            return vec![host.to_owned()];
+        }
+        let searched = self
+            .search
+            .iter()
+            .map(|domain| format!("{}.{}", host, domain));
+        if host.matches('.').count() >= self.ndots {
+            std::iter::once(host.to_owned()).chain(searched).collect()
+        } else {
+            searched.chain(std::iter::once(host.to_owned())).collect()
+        }
+    }
+}
+
+/// Resolve the IPv4 and IPv6 addresses of `host`, with the TTL of each record in seconds.
+async fn lookup(host: &str) -> io::Result<Vec<(IpAddr, u32)>> {
+    if let Ok(ip) = host.parse::<IpAddr>() {
+        return Ok(vec![(ip, u32::MAX)]);
+    }
+
+    // The file is small, so reading it only blocks briefly.
+    match std::fs::read_to_string(HOSTS) {
+        Ok(hosts) => {
+            let ips = parse_hosts(&hosts, host);
+            if !ips.is_empty() {
+                return Ok(ips.into_iter().map(|ip| (ip, u32::MAX)).collect());
+            }
+        }
+        Err(error) => tracing::debug!(%error, "failed to read {}", HOSTS),
+    }
+
+    let records = lookup_records(host, &[TYPE_A, TYPE_AAAA]).await?;
+    Ok(records
+        .into_iter()
+        .filter_map(|(record, ttl)| match record {
+            Record::Addr(ip) => Some((ip, ttl)),
+            Record::Srv(_) => None,
+        })
+        .collect())
+}
+
+/// Resolve the SRV records of `name`, with the TTL of each record in seconds.
+async fn lookup_srv(name: &str) -> io::Result<Vec<(Srv, u32)>> {
+    let records = lookup_records(name, &[TYPE_SRV]).await?;
+    Ok(records
+        .into_iter()
+        .filter_map(|(record, ttl)| match record {
+            Record::Srv(srv) => Some((srv, ttl)),
+            Record::Addr(_) => None,
+        })
+        .collect())
+}
+
+/// The addresses of `host` in the hosts file `hosts`.
+fn parse_hosts(hosts: &str, host: &str) -> Vec<IpAddr> {
+    let host = host.trim_end_matches('.');
+    hosts
+        .lines()
+        .filter_map(|line| {
+            let mut words = line.split('#').next()?.split_whitespace();
+            let ip = words.next()?.parse().ok()?;
+            words
+                .any(|name| name.trim_end_matches('.').eq_ignore_ascii_case(host))
+                .then_some(ip)
+        })
+        .collect()
+}
+
+/// Resolve the records of `host` with each of `qtypes`, trying the candidate names from the
+/// search domains until one has records.
+async fn lookup_records(host: &str, qtypes: &[u16]) -> io::Result<Vec<(Record, u32)>> {
+    // The file is small, so reading it only blocks briefly.
+    let conf = std::fs::read_to_string(RESOLV_CONF).map_err(|error| {
+        io::Error::new(
+            error.kind(),
+            format!("failed to read {}: {}", RESOLV_CONF, error),
+        )
+    })?;
+    let conf = ResolvConf::parse(&conf);
+
+    for name in conf.candidates(host) {
+        let mut records = Vec::new();
+        let mut failed = None;
+        for &qtype in qtypes {
+            match query_nameservers(&conf.nameservers, &name, qtype).await {
+                Ok(Some(answers)) => records.extend(answers),
+                Ok(None) => break,
+                // Keep the records of the other types, such as the A records of a name whose
+                // AAAA query failed.
+                Err(error) => {
+                    tracing::debug!(%error, %name, qtype, "DNS lookup failed");
+                    failed = Some(error);
+                }
+            }
+        }
+        if !records.is_empty() {
+            return Ok(records);
+        }
+        // The name may exist, so don't try the next candidate.
+        if let Some(error) = failed {
+            return Err(error);
+        }
+    }
+    Ok(Vec::new())
+}
+
+/// Query each name server in turn until one answers, skipping those which fail or respond with
+/// an error, such as SERVFAIL. Returns `None` if the name doesn't exist.
+async fn query_nameservers(
+    nameservers: &[SocketAddr],
+    name: &str,
+    qtype: u16,
+) -> io::Result<Option<Vec<(Record, u32)>>> {
+    let mut last_error = None;
+    for nameserver in nameservers {
+        match query(*nameserver, name, qtype).await {
+            Ok(response) if response.rcode == RCODE_NXDOMAIN => return Ok(None),
+            Ok(response) if response.rcode == RCODE_NOERROR => return Ok(Some(response.records)),
+            Ok(response) => {
+                let error = io::Error::other(format!(
+                    "DNS server responded with error code {}",
+                    response.rcode
+                ));
+                tracing::debug!(%error, %nameserver, "DNS query failed");
+                last_error = Some(error);
+            }
+            Err(error) => {
+                tracing::debug!(%error, %nameserver, "DNS query failed");
+                last_error = Some(error);
+            }
+        }
+    }
+    Err(last_error.unwrap_or_else(|| io::Error::other("no name servers")))
+}
+
+/// Send a query over UDP, and again over TCP if the response is truncated.
+async fn query(nameserver: SocketAddr, name: &str, qtype: u16) -> io::Result<Response> {
+    let id = rand::random();
+    let message = encode_query(id, name, qtype)?;
+
+    let bind: SocketAddr = match nameserver {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let socket = UdpSocket::bind(bind).await?;
+    socket.connect(nameserver).await?;
+    socket.send(&message).await?;
+
+    let response = timeout(QUERY_TIMEOUT, async {
+        let mut buf = vec![0; UDP_PAYLOAD_SIZE.into()];
+        loop {
+            let len = socket.recv(&mut buf).await?;
+            // Ignore stray datagrams which don't answer this query.
+            if let Some(response) = parse_response(id, name, qtype, &buf[..len]) {
+                return Ok::<_, io::Error>(response);
+            }
+        }
+    })
+    .await
+    .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "DNS query timed out"))??;
+    if !response.truncated {
+        return Ok(response);
+    }
+
+    timeout(QUERY_TIMEOUT, async {
+        let mut stream = TcpStream::connect(nameserver).await?;
+        let len = u16::try_from(message.len()).expect("query is short");
+        stream.write_all(&len.to_be_bytes()).await?;
+        stream.write_all(&message).await?;
+        let len = stream.read_u16().await?;
+        let mut buf = vec![0; len.into()];
+        stream.read_exact(&mut buf).await?;
+        parse_response(id, name, qtype, &buf)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid DNS response"))
+    })
+    .await
+    .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "DNS query timed out"))?
+}
+
+fn encode_query(id: u16, name: &str, qtype: u16) -> io::Result<Vec<u8>> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidInput, "invalid host name");
+
+    let mut message = Vec::with_capacity(name.len() + 30);
+    message.extend_from_slice(&id.to_be_bytes());
+    // Recursion desired.
+    message.extend_from_slice(&0x0100u16.to_be_bytes());
+    // One question and one additional record, the EDNS OPT record.
+    for count in [1u16, 0, 0, 1] {
+        message.extend_from_slice(&count.to_be_bytes());
+    }
+
+    for label in name.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(invalid());
+        }
+        message.push(label.len() as u8);
+        message.extend_from_slice(label.as_bytes());
+    }
+    message.push(0);
+    if message.len() > 12 + 255 {
+        return Err(invalid());
+    }
+    message.extend_from_slice(&qtype.to_be_bytes());
+    message.extend_from_slice(&CLASS_IN.to_be_bytes());
+
+    // OPT record: root name, type, UDP payload size as the class, zero TTL and no data.
+    message.push(0);
+    message.extend_from_slice(&TYPE_OPT.to_be_bytes());
+    message.extend_from_slice(&UDP_PAYLOAD_SIZE.to_be_bytes());
+    message.extend_from_slice(&[0; 6]);
+    Ok(message)
+}
+
+#[derive(Debug)]
+struct Response {
+    rcode: u16,
+    truncated: bool,
+    // The address and SRV records in the answer section, with their TTLs.
+    records: Vec<(Record, u32)>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Record {
+    Addr(IpAddr),
+    Srv(Srv),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Srv {
+    priority: u16,
+    weight: u16,
+    port: u16,
+    target: String,
+}
+
+/// Parse the response to the query with `id` for `name` and `qtype`, returning `None` if it is
+/// malformed or answers a different query.
+fn parse_response(id: u16, name: &str, qtype: u16, message: &[u8]) -> Option<Response> {
+    let u16_at = |pos: usize| {
+        Some(u16::from_be_bytes(
+            message.get(pos..pos + 2)?.try_into().ok()?,
+        ))
+    };
+    let u32_at = |pos: usize| {
+        Some(u32::from_be_bytes(
+            message.get(pos..pos + 4)?.try_into().ok()?,
+        ))
+    };
+
+    let flags = u16_at(2)?;
+    if u16_at(0)? != id || flags & 0x8000 == 0 {
+        return None;
+    }
+    let mut response = Response {
+        rcode: flags & 0x000f,
+        truncated: flags & 0x0200 != 0,
+        records: Vec::new(),
+    };
+
+    // The question must be the one which was asked, so that a spoofed or misdirected
+    // response with a matching id isn't accepted.
+    if u16_at(4)? != 1 || !read_name(message, 12)?.eq_ignore_ascii_case(name.trim_end_matches('.'))
+    {
+        return None;
+    }
+    let mut pos = skip_name(message, 12)?;
+    if u16_at(pos)? != qtype || u16_at(pos + 2)? != CLASS_IN {
+        return None;
+    }
+    pos += 4;
+    for _ in 0..u16_at(6)? {
+        pos = skip_name(message, pos)?;
+        let rtype = u16_at(pos)?;
+        let class = u16_at(pos + 2)?;
+        let ttl = u32_at(pos + 4)?;
+        let len = usize::from(u16_at(pos + 8)?);
+        let data = message.get(pos + 10..pos + 10 + len)?;
+        pos += 10 + len;
+
+        if class != CLASS_IN {
+            continue;
+        }
+        let record = match rtype {
+            TYPE_A => Record::Addr(IpAddr::from(<[u8; 4]>::try_from(data).ok()?)),
+            TYPE_AAAA => Record::Addr(IpAddr::from(<[u8; 16]>::try_from(data).ok()?)),
+            TYPE_SRV => {
+                let start = pos - len;
+                Record::Srv(Srv {
+                    priority: u16_at(start)?,
+                    weight: u16_at(start + 2)?,
+                    port: u16_at(start + 4)?,
+                    target: read_name(message, start + 6)?,
+                })
+            }
+            // Such as the CNAME records leading to the addresses.
+            _ => continue,
+        };
+        response.records.push((record, ttl));
+    }
+    Some(response)
+}
+
+/// Returns the position after the (possibly compressed) name at `pos`.
+fn skip_name(message: &[u8], mut pos: usize) -> Option<usize> {
+    loop {
+        let len = *message.get(pos)?;
+        match len {
+            0 => return Some(pos + 1),
+            len if len & 0xc0 == 0xc0 => return Some(pos + 2),
+            len => pos += 1 + usize::from(len),
+        }
+    }
+}
+
+/// Read the (possibly compressed) name at `pos`, without the trailing dot.
+fn read_name(message: &[u8], mut pos: usize) -> Option<String> {
+    let mut name = String::new();
+    // Names have at most 127 labels, so more steps means the pointers form a loop.
+    for _ in 0..256 {
+        let len = *message.get(pos)?;
+        match len {
+            0 => return Some(name),
+            len if len & 0xc0 == 0xc0 => {
+                pos = usize::from(u16::from_be_bytes([len & 0x3f, *message.get(pos + 1)?]));
+            }
+            len => {
+                let label = message.get(pos + 1..pos + 1 + usize::from(len))?;
+                if !name.is_empty() {
+                    name.push('.');
+                }
+                name.push_str(std::str::from_utf8(label).ok()?);
+                pos += 1 + usize::from(len);
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_resolv_conf() {
+        let conf = ResolvConf::parse(
+            "# Kubernetes\n\
+             nameserver 10.96.0.10\n\
+             search default.svc.cluster.local svc.cluster.local cluster.local\n\
+             options ndots:5\n",
+        );
+        assert_eq!(conf.nameservers, vec!["10.96.0.10:53".parse().unwrap()]);
+        assert_eq!(conf.ndots, 5);
+        assert_eq!(
+            conf.candidates("greeter"),
+            vec![
+                "greeter.default.svc.cluster.local",
+                "greeter.svc.cluster.local",
+                "greeter.cluster.local",
+                "greeter",
+            ]
+        );
+        assert_eq!(
+            conf.candidates("greeter.example.com."),
+            vec!["greeter.example.com."]
+        );
+
+        let conf = ResolvConf::parse("domain example.com\n");
+        assert_eq!(
+            conf,
+            ResolvConf {
+                search: vec!["example.com".to_owned()],
+                ..ResolvConf::default()
+            }
+        );
+        assert_eq!(conf.candidates("a.b"), vec!["a.b", "a.b.example.com"]);
+    }
+
+    #[test]
+    fn parses_answers() {
+        let query = encode_query(7, "greeter.default", TYPE_A).unwrap();
+        // The response repeats the question, without the OPT record.
+        let question_end = query.len() - 11;
+        let mut response = query[..question_end].to_vec();
+        response[2] = 0x81;
+        response[3] = 0x80;
+        response[6..8].copy_from_slice(&3u16.to_be_bytes());
+        response[10..12].copy_from_slice(&0u16.to_be_bytes());
+        // A CNAME record pointing to the question's name, and two A records.
+        response.extend_from_slice(&[0xc0, 12, 0, 5, 0, 1, 0, 0, 0, 30, 0, 2, 0xc0, 12]);
+        response.extend_from_slice(&[0xc0, 12, 0, 1, 0, 1, 0, 0, 0, 5, 0, 4, 10, 0, 0, 1]);
+        response.extend_from_slice(&[0xc0, 12, 0, 1, 0, 1, 0, 0, 0, 9, 0, 4, 10, 0, 0, 2]);
+
+        let parsed = parse_response(7, "greeter.default", TYPE_A, &response).unwrap();
+        assert_eq!(parsed.rcode, 0);
+        assert!(!parsed.truncated);
+        assert_eq!(
+            parsed.records,
+            vec![
+                (Record::Addr(IpAddr::from([10, 0, 0, 1])), 5),
+                (Record::Addr(IpAddr::from([10, 0, 0, 2])), 9)
+            ]
+        );
+
+        assert!(parse_response(8, "greeter.default", TYPE_A, &response).is_none());
+        let truncated = &response[..response.len() - 1];
+        assert!(parse_response(7, "greeter.default", TYPE_A, truncated).is_none());
+        // Responses to other questions are ignored.
+        assert!(parse_response(7, "greeter.other", TYPE_A, &response).is_none());
+        assert!(parse_response(7, "greeter.default", TYPE_AAAA, &response).is_none());
+        assert!(parse_response(7, "Greeter.Default.", TYPE_A, &response).is_some());
+    }
+
+    #[test]
+    fn parses_srv_answers() {
+        let query = encode_query(7, "_grpc._tcp.greeter", TYPE_SRV).unwrap();
+        let question_end = query.len() - 11;
+        let mut response = query[..question_end].to_vec();
+        response[2] = 0x81;
+        response[3] = 0x80;
+        response[6..8].copy_from_slice(&1u16.to_be_bytes());
+        response[10..12].copy_from_slice(&0u16.to_be_bytes());
+        // Priority 1, weight 5 and port 50051, with the target `a` followed by a pointer to the
+        // question's name.
+        response.extend_from_slice(&[0xc0, 12, 0, 33, 0, 1, 0, 0, 0, 30, 0, 10]);
+        response.extend_from_slice(&[0, 1, 0, 5, 0xc3, 0x83, 1, b'a', 0xc0, 12]);
+
+        let parsed = parse_response(7, "_grpc._tcp.greeter", TYPE_SRV, &response).unwrap();
+        assert_eq!(
+            parsed.records,
+            vec![(
+                Record::Srv(Srv {
+                    priority: 1,
+                    weight: 5,
+                    port: 50051,
+                    target: "a._grpc._tcp.greeter".to_owned(),
+                }),
+                30
+            )]
+        );
+    }
+
+    #[test]
+    fn parses_hosts() {
+        let hosts = "127.0.0.1 localhost\n\
+                     # 10.0.0.9 greeter\n\
+                     10.0.0.1 greeter.local greeter # pod\n\
+                     fd00::1\tGREETER\n";
+        assert_eq!(
+            parse_hosts(hosts, "greeter."),
+            vec![
+                IpAddr::from([10, 0, 0, 1]),
+                "fd00::1".parse::<IpAddr>().unwrap()
+            ]
+        );
+        assert!(parse_hosts(hosts, "other").is_empty());
+    }
+
+    /// Answer one query on a local UDP socket with `rcode`, and an A record if it is NOERROR.
+    async fn nameserver(rcode: u8) -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = [0; 512];
+            let (len, peer) = socket.recv_from(&mut buf).await.unwrap();
+            // Repeat the question, without the OPT record.
+            let mut response = buf[..len - 11].to_vec();
+            response[2] = 0x81;
+            response[3] = 0x80 | rcode;
+            response[10..12].copy_from_slice(&0u16.to_be_bytes());
+            if u16::from(rcode) == RCODE_NOERROR {
+                response[6..8].copy_from_slice(&1u16.to_be_bytes());
+                response.extend_from_slice(&[0xc0, 12, 0, 1, 0, 1, 0, 0, 0, 5, 0, 4, 10, 0, 0, 1]);
+            }
+            socket.send_to(&response, peer).await.unwrap();
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn skips_failing_nameservers() {
+        let failing = nameserver(2).await;
+        let answering = nameserver(0).await;
+        let records = query_nameservers(&[failing, answering], "greeter", TYPE_A)
+            .await
+            .unwrap();
+        assert_eq!(
+            records,
+            Some(vec![(Record::Addr(IpAddr::from([10, 0, 0, 1])), 5)])
+        );
+
+        let failing = nameserver(2).await;
+        assert!(query_nameservers(&[failing], "greeter", TYPE_A)
+            .await
+            .is_err());
+
+        let missing = nameserver(3).await;
+        let records = query_nameservers(&[missing], "greeter", TYPE_A).await;
+        assert_eq!(records.unwrap(), None);
+    }
+}
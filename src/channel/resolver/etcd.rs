@@ -0,0 +1,230 @@
+use super::{BoxResolved, ResolvedEndpoints, Resolver};
+use crate::service::backoff::{Backoff, ConnectBackoff};
+use crate::{BalanceBuilder, BoxError, Channel, ChannelBuilder, Error, Result};
+
+use bytes::{Buf, BytesMut};
+use http::{
+    header::CONTENT_TYPE,
+    uri::{Authority, Uri},
+    Method, Request,
+};
+use http_body::Body as _;
+use hyper::{client::HttpConnector, Body, Client};
+use serde_json::Value;
+use std::{collections::HashSet, str::FromStr};
+
+/// A resolver which watches the endpoints registered under a prefix in etcd.
+///
+/// The value of each key under the prefix is an endpoint's address, either as `host:port` or
+/// as a JSON object with an `Addr` field, the format used by the etcd naming resolver of other
+/// gRPC implementations. Endpoints usually register their key with a lease which they keep
+/// alive while they are healthy, so that unhealthy endpoints are removed when their lease
+/// expires.
+///
+/// The resolver uses etcd's v3 JSON gateway: it reads the keys and then watches the prefix,
+/// reading the keys again whenever they change. If etcd can't be reached, the last known
+/// endpoints are kept. Requires the `etcd` feature.
+///
+/// ```no_run
+/// # use tonic_transport::{BalanceBuilder, ChannelBuilder, EtcdResolver};
+/// # fn example(tls: tokio_native_tls::TlsConnector) -> Result<(), tonic_transport::Error> {
+/// let template = ChannelBuilder::new("https://greeter.internal", tls)?;
+/// let channel = EtcdResolver::new("http://127.0.0.1:2379".parse().unwrap(), "/services/greeter/")
+///     .channel(BalanceBuilder::new(), template);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct EtcdResolver {
+    addr: Uri,
+    prefix: String,
+}
+
+impl EtcdResolver {
+    /// Create a resolver for the keys starting with `prefix`, using the etcd server at `addr`,
+    /// such as `http://127.0.0.1:2379`.
+    pub fn new(addr: Uri, prefix: impl Into<String>) -> Self {
+        EtcdResolver {
+            addr,
+            prefix: prefix.into(),
+        }
+    }
+
+    /// Create a [`Channel`] balancing across the registered endpoints, which are kept up to
+    /// date until the channel is dropped.
+    ///
+    /// Each endpoint is configured by `template`, with the registered address in place of the
+    /// template's. Unless the template has a
+    /// [`tls_verify_domain`](ChannelBuilder::tls_verify_domain), endpoints are verified for the
+    /// template's host.
+    pub fn channel(self, balance: BalanceBuilder, template: ChannelBuilder) -> Channel {
+        balance.resolver(self, template)
+    }
+
+    /// Read the endpoints under the prefix, returning the revision they were read at.
+    async fn range(&self, client: &Client<HttpConnector>) -> Result<(i64, HashSet<Authority>)> {
+        let body = format!(
+            r#"{{"key":"{}","range_end":"{}"}}"#,
+            base64::encode(&self.prefix),
+            base64::encode(prefix_end(self.prefix.as_bytes())),
+        );
+        let response = self.post(client, "/v3/kv/range", body).await?;
+        let body = hyper::body::to_bytes(response.into_body())
+            .await
+            .map_err(|e| Error::from_source(e.into()))?;
+        parse_range(&body).map_err(Error::from_source)
+    }
+
+    /// Wait until a key under the prefix changes after `revision`.
+    async fn wait_for_change(&self, client: &Client<HttpConnector>, revision: i64) -> Result<()> {
+        let body = format!(
+            r#"{{"create_request":{{"key":"{}","range_end":"{}","start_revision":"{}"}}}}"#,
+            base64::encode(&self.prefix),
+            base64::encode(prefix_end(self.prefix.as_bytes())),
+            revision + 1,
+        );
+        let mut body = self.post(client, "/v3/watch", body).await?.into_body();
+
+        // The response is a stream of JSON messages, one per line.
+        let mut buf = BytesMut::new();
+        while let Some(chunk) = body.data().await {
+            buf.extend_from_slice(&chunk.map_err(|e| Error::from_source(e.into()))?);
+            while let Some(end) = buf.iter().position(|b| *b == b'\n') {
+                let line = buf.split_to(end + 1);
+                if is_change(&line[..end]).map_err(Error::from_source)? {
+                    return Ok(());
+                }
+            }
+        }
+        if buf.has_remaining() && is_change(&buf).map_err(Error::from_source)? {
+            return Ok(());
+        }
+        Err(Error::from_source("etcd closed the watch".into()))
+    }
+
+    async fn post(
+        &self,
+        client: &Client<HttpConnector>,
+        path: &str,
+        body: String,
+    ) -> Result<http::Response<Body>> {
+        let uri = format!("{}{}", self.addr.to_string().trim_end_matches('/'), path);
+        let uri = Uri::from_str(&uri).map_err(|_| Error::new_invalid_uri(uri))?;
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri(uri)
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(body))
+            .map_err(|e| Error::from_source(e.into()))?;
+        let response = client
+            .request(request)
+            .await
+            .map_err(|e| Error::from_source(e.into()))?;
+        if !response.status().is_success() {
+            let error = format!("etcd responded with {}", response.status());
+            return Err(Error::from_source(error.into()));
+        }
+        Ok(response)
+    }
+}
+
+impl Resolver for EtcdResolver {
+    type Endpoints = BoxResolved;
+
+    fn resolve(self, _template: &mut ChannelBuilder) -> Self::Endpoints {
+        Box::pin(async_stream::stream! {
+            let client = Client::new();
+            let mut backoff = Backoff::new(ConnectBackoff::default());
+
+            loop {
+                let result = match self.range(&client).await {
+                    Ok((revision, resolved)) => {
+                        yield Ok(ResolvedEndpoints::from(resolved));
+                        self.wait_for_change(&client, revision).await
+                    }
+                    Err(error) => Err(error),
+                };
+
+                match result {
+                    Ok(()) => backoff.reset(),
+                    Err(error) => {
+                        let prefix = &self.prefix;
+                        let error = format!("failed to watch etcd prefix {}: {}", prefix, error);
+                        yield Err(error.into());
+                        tokio::time::sleep(backoff.next_delay()).await;
+                    }
+                }
+            }
+        })
+    }
+}
+
+/// The end of the range of keys starting with `prefix`.
+fn prefix_end(prefix: &[u8]) -> Vec<u8> {
+    let mut end = prefix.to_vec();
+    while let Some(last) = end.pop() {
+        if last < 0xff {
+            end.push(last + 1);
+            return end;
+        }
+    }
+    // Every key is greater than the prefix.
+    vec![0]
+}
+
+fn parse_range(body: &[u8]) -> std::result::Result<(i64, HashSet<Authority>), BoxError> {
+    let response: Value = serde_json::from_slice(body)?;
+    // The gateway encodes 64 bit integers as strings.
+    let revision = response["header"]["revision"]
+        .as_str()
+        .and_then(|revision| revision.parse().ok())
+        .ok_or("missing revision")?;
+
+    let mut endpoints = HashSet::new();
+    for kv in response["kvs"].as_array().into_iter().flatten() {
+        let value = kv["value"]
+            .as_str()
+            .and_then(|value| base64::decode(value).ok())
+            .unwrap_or_default();
+        match parse_addr(&value) {
+            Some(authority) => {
+                endpoints.insert(authority);
+            }
+            None => tracing::debug!(key = ?kv["key"], "ignoring etcd key with invalid address"),
+        }
+    }
+    Ok((revision, endpoints))
+}
+
+/// Parse an endpoint's address from `host:port`, or a JSON object with an `Addr` field.
+fn parse_addr(value: &[u8]) -> Option<Authority> {
+    if let Ok(Value::Object(object)) = serde_json::from_slice(value) {
+        return object.get("Addr")?.as_str()?.parse().ok();
+    }
+    Authority::try_from(std::str::from_utf8(value).ok()?.trim()).ok()
+}
+
+/// Whether a message from a watch reports a change, rather than the creation of the watch.
+fn is_change(line: &[u8]) -> std::result::Result<bool, BoxError> {
+    let message: Value = serde_json::from_slice(line)?;
+    let result = &message["result"];
+    let created = result["created"].as_bool().unwrap_or(false);
+    let has_events = result["events"]
+        .as_array()
+        .is_some_and(|events| !events.is_empty());
+    // Anything else, such as the watch being cancelled because the revision was compacted,
+    // means the keys should be read again.
+    Ok(has_events || !created)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prefix_ranges() {
+        assert_eq!(prefix_end(b"/services/"), b"/services0");
+        assert_eq!(prefix_end(b"a\xff"), b"b");
+        assert_eq!(prefix_end(b"\xff\xff"), b"\0");
+    }
+}
@@ -0,0 +1,180 @@
+use super::{BoxResolved, ResolvedEndpoints, Resolver};
+use crate::{BalanceBuilder, BoxError, Channel, ChannelBuilder};
+
+use http::uri::Authority;
+use serde_json::Value;
+use std::{collections::HashSet, path::PathBuf, time::Duration};
+use tokio::time::sleep;
+
+/// A resolver which reads the endpoints from a file, and watches it for changes.
+///
+/// The file lists the endpoints' addresses as `host:port`, either one per line, or as a JSON
+/// array of strings. In the line format, blank lines and lines starting with `#` are ignored.
+/// For example:
+///
+/// ```text
+/// # Managed by configuration management.
+/// 10.0.0.1:50051
+/// 10.0.0.2:50051
+/// ```
+///
+/// The file is checked for changes periodically, see [`interval`](FileResolver::interval). If
+/// it can't be read or parsed, for example while it is being written, the last known endpoints
+/// are kept. An empty file is treated as incomplete; to remove every endpoint, write `[]`, or
+/// only comments.
+///
+/// ```no_run
+/// # use tonic_transport::{BalanceBuilder, ChannelBuilder, FileResolver};
+/// # fn example(tls: tokio_native_tls::TlsConnector) -> Result<(), tonic_transport::Error> {
+/// let template = ChannelBuilder::new("https://greeter.internal", tls)?;
+/// let channel = FileResolver::new("/etc/greeter/endpoints")
+///     .channel(BalanceBuilder::new(), template);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct FileResolver {
+    path: PathBuf,
+    interval: Duration,
+}
+
+impl FileResolver {
+    /// Create a resolver for the endpoints listed in the file at `path`.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        FileResolver {
+            path: path.into(),
+            interval: Duration::from_secs(1),
+        }
+    }
+
+    /// Set how often the file is checked for changes.
+    ///
+    /// Default is one second.
+    pub fn interval(self, interval: Duration) -> Self {
+        FileResolver { interval, ..self }
+    }
+
+    /// Create a [`Channel`] balancing across the listed endpoints, which are kept up to date
+    /// until the channel is dropped.
+    ///
+    /// Each endpoint is configured by `template`, with the listed address in place of the
+    /// template's. Unless the template has a
+    /// [`tls_verify_domain`](ChannelBuilder::tls_verify_domain), endpoints are verified for the
+    /// template's host.
+    pub fn channel(self, balance: BalanceBuilder, template: ChannelBuilder) -> Channel {
+        balance.resolver(self, template)
+    }
+}
+
+impl Resolver for FileResolver {
+    type Endpoints = BoxResolved;
+
+    fn resolve(self, _template: &mut ChannelBuilder) -> Self::Endpoints {
+        Box::pin(async_stream::stream! {
+            let mut last = None;
+            let mut failing = false;
+
+            loop {
+                // The file is small, so reading it only blocks briefly.
+                match std::fs::read_to_string(&self.path) {
+                    Ok(contents) => {
+                        failing = false;
+                        if last.as_ref() != Some(&contents) {
+                            let path = self.path.display();
+                            tracing::debug!(%path, "endpoint file changed");
+                            yield match parse_endpoints(&contents) {
+                                Ok(endpoints) => Ok(ResolvedEndpoints::from(endpoints)),
+                                Err(error) => {
+                                    Err(format!("invalid endpoint file {}: {}", path, error).into())
+                                }
+                            };
+                            // A file which can't be parsed is read again, but only reported
+                            // once, until it changes.
+                            last = Some(contents);
+                        }
+                    }
+                    Err(error) => {
+                        // Only report the first failure, the file may be missing for a while.
+                        if !failing {
+                            failing = true;
+                            let path = self.path.display();
+                            let error = format!("failed to read endpoint file {}: {}", path, error);
+                            yield Err(error.into());
+                        }
+                    }
+                }
+                sleep(self.interval).await;
+            }
+        })
+    }
+}
+
+/// Parse a list of endpoints, either one per line or as a JSON array of strings.
+fn parse_endpoints(contents: &str) -> Result<HashSet<Authority>, BoxError> {
+    let contents = contents.trim();
+    if contents.is_empty() {
+        return Err("the file is empty".into());
+    }
+
+    let addrs: Vec<String> = if contents.starts_with('[') {
+        let list: Value = serde_json::from_str(contents)?;
+        list.as_array()
+            .ok_or("expected a JSON array")?
+            .iter()
+            .map(|addr| addr.as_str().map(str::to_owned))
+            .collect::<Option<_>>()
+            .ok_or("expected a JSON array of strings")?
+    } else {
+        contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(str::to_owned)
+            .collect()
+    };
+
+    addrs
+        .iter()
+        .map(|addr| {
+            addr.parse()
+                .map_err(|_| format!("invalid endpoint address `{}`", addr).into())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(contents: &str) -> Vec<String> {
+        let mut endpoints: Vec<_> = parse_endpoints(contents)
+            .unwrap()
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        endpoints.sort();
+        endpoints
+    }
+
+    #[test]
+    fn parses_endpoint_lists() {
+        assert_eq!(
+            parse("# endpoints\n10.0.0.1:80\n\n  [::1]:80  \n"),
+            ["10.0.0.1:80", "[::1]:80"]
+        );
+        assert_eq!(
+            parse(r#"[ "10.0.0.1:80", "host.internal:443" ]"#),
+            ["10.0.0.1:80", "host.internal:443"]
+        );
+        assert_eq!(parse("[]"), Vec::<String>::new());
+        assert_eq!(parse("# no endpoints\n"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn rejects_incomplete_files() {
+        assert!(parse_endpoints("").is_err());
+        assert!(parse_endpoints(r#"[ "10.0.0.1:80", "host.int"#).is_err());
+        assert!(parse_endpoints("10.0.0.1:80\nnot an address\n").is_err());
+        assert!(parse_endpoints("[1, 2]").is_err());
+    }
+}
@@ -0,0 +1,295 @@
+//! Resolvers which keep the endpoints of a balanced [`Channel`](crate::Channel) up to date.
+
+#[cfg(feature = "consul")]
+mod consul;
+mod dns;
+#[cfg(feature = "etcd")]
+mod etcd;
+mod file;
+
+#[cfg(feature = "consul")]
+pub use self::consul::ConsulResolver;
+pub use self::dns::DnsResolver;
+#[cfg(feature = "etcd")]
+pub use self::etcd::EtcdResolver;
+pub use self::file::FileResolver;
+
+use crate::{BalanceBuilder, BoxError, Channel, ChannelBuilder};
+
+use futures_core::Stream;
+use futures_util::StreamExt;
+use http::uri::Authority;
+use std::{
+    collections::{HashMap, HashSet},
+    iter::FromIterator,
+    net::Ipv6Addr,
+    pin::Pin,
+    str::FromStr,
+};
+use tokio::sync::mpsc::Sender;
+use tower::discover::Change;
+
+/// The stream of endpoints of the built-in resolvers.
+type BoxResolved = Pin<Box<dyn Stream<Item = Result<ResolvedEndpoints, BoxError>> + Send>>;
+
+/// A source of the endpoints of a balanced [`Channel`], such as a service discovery system.
+///
+/// Implement this to balance across endpoints found by a custom resolver, without handling
+/// the channel's changes yourself; the channel adds and removes endpoints as the resolved set
+/// changes. See [`BalanceBuilder::resolver`].
+///
+/// The built-in resolvers, such as [`DnsResolver`], implement this trait too, so they can be
+/// passed to [`BalanceBuilder::resolver`] as well as used with their own `channel` methods.
+///
+/// ```no_run
+/// # use tonic_transport::{BalanceBuilder, BoxError, ChannelBuilder, ResolvedEndpoints, Resolver};
+/// # use futures_util::stream::{self, BoxStream, StreamExt};
+/// # use http::uri::Authority;
+/// # use std::collections::HashSet;
+/// struct Fixed(HashSet<Authority>);
+///
+/// impl Resolver for Fixed {
+///     type Endpoints = BoxStream<'static, Result<ResolvedEndpoints, BoxError>>;
+///
+///     fn resolve(self, _template: &mut ChannelBuilder) -> Self::Endpoints {
+///         stream::iter([Ok(self.0.into())]).boxed()
+///     }
+/// }
+///
+/// # fn example(template: ChannelBuilder, endpoints: HashSet<Authority>) {
+/// let channel = BalanceBuilder::new().resolver(Fixed(endpoints), template);
+/// # }
+/// ```
+pub trait Resolver {
+    /// The endpoints found by the resolver.
+    ///
+    /// Each set replaces the previous one. After an error, the previous endpoints are kept
+    /// until the next set; when the stream ends, the endpoints are kept for the life of the
+    /// channel.
+    type Endpoints: Stream<Item = Result<ResolvedEndpoints, BoxError>> + Send + 'static;
+
+    /// Start resolving the endpoints configured by `template`, whose URI is usually the name
+    /// being resolved.
+    ///
+    /// The resolver may adjust `template` before any endpoints are created from it, for example
+    /// to be notified of connection failures.
+    fn resolve(self, template: &mut ChannelBuilder) -> Self::Endpoints;
+}
+
+/// A set of endpoints found by a [`Resolver`], each of which may have a weight.
+///
+/// An endpoint's weight replaces the template's
+/// [`weight`](crate::EndpointMetadata::weight), for weighted balancing
+/// [`Policy`](crate::Policy)s.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ResolvedEndpoints {
+    // The weight of each endpoint, `None` for the template's weight.
+    endpoints: HashMap<Authority, Option<u32>>,
+}
+
+impl ResolvedEndpoints {
+    /// Create an empty set of endpoints.
+    pub fn new() -> Self {
+        ResolvedEndpoints::default()
+    }
+
+    /// Add an endpoint with the template's weight.
+    pub fn insert(&mut self, authority: Authority) {
+        self.endpoints.insert(authority, None);
+    }
+
+    /// Add an endpoint with `weight`.
+    pub fn insert_weighted(&mut self, authority: Authority, weight: u32) {
+        self.endpoints.insert(authority, Some(weight));
+    }
+
+    /// The number of endpoints.
+    pub fn len(&self) -> usize {
+        self.endpoints.len()
+    }
+
+    /// Returns `true` if there are no endpoints.
+    pub fn is_empty(&self) -> bool {
+        self.endpoints.is_empty()
+    }
+}
+
+impl From<HashSet<Authority>> for ResolvedEndpoints {
+    fn from(endpoints: HashSet<Authority>) -> Self {
+        endpoints.into_iter().collect()
+    }
+}
+
+impl FromIterator<Authority> for ResolvedEndpoints {
+    fn from_iter<I: IntoIterator<Item = Authority>>(iter: I) -> Self {
+        ResolvedEndpoints {
+            endpoints: iter
+                .into_iter()
+                .map(|authority| (authority, None))
+                .collect(),
+        }
+    }
+}
+
+/// Create a channel balancing across the endpoints from `resolver`.
+pub(crate) fn channel<R: Resolver>(
+    resolver: R,
+    balance: BalanceBuilder,
+    mut template: ChannelBuilder,
+) -> Channel {
+    let (channel, changes) = balance.channel(1024);
+    // Every endpoint would be invalid, so don't resolve any.
+    if let Err(error) = template.validate() {
+        tracing::error!(%error, uri = %template.uri, "invalid resolver template");
+        return channel;
+    }
+    let resolved = resolver.resolve(&mut template);
+    let endpoints = EndpointUpdates::new(template, changes);
+    tokio::spawn(watch(resolved, endpoints));
+    channel
+}
+
+/// Apply the endpoints from `resolved` to a channel until either ends. The previous endpoints
+/// are kept after an error.
+async fn watch<S>(resolved: S, mut endpoints: EndpointUpdates)
+where
+    S: Stream<Item = Result<ResolvedEndpoints, BoxError>>,
+{
+    futures_util::pin_mut!(resolved);
+    loop {
+        let next = tokio::select! {
+            next = resolved.next() => next,
+            () = endpoints.closed() => return,
+        };
+        match next {
+            Some(Ok(resolved)) => {
+                if !endpoints.update(resolved).await {
+                    return;
+                }
+            }
+            Some(Err(error)) => tracing::warn!(%error, "failed to resolve endpoints"),
+            None => return,
+        }
+    }
+}
+
+/// The endpoints last reported by a resolver, which sends the differences to a balanced channel
+/// when they change.
+struct EndpointUpdates {
+    template: ChannelBuilder,
+    // The weight of each endpoint, `None` for the template's weight.
+    current: HashMap<Authority, Option<u32>>,
+    changes: Sender<Change<Authority, ChannelBuilder>>,
+}
+
+impl EndpointUpdates {
+    /// `template` configures the endpoints, which connect to the resolved addresses instead of
+    /// the template's.
+    fn new(template: ChannelBuilder, changes: Sender<Change<Authority, ChannelBuilder>>) -> Self {
+        EndpointUpdates {
+            template,
+            current: HashMap::new(),
+            changes,
+        }
+    }
+
+    /// Replace the endpoints with `resolved`. An endpoint whose weight changes is replaced.
+    /// Returns `false` if the channel has been dropped.
+    async fn update(&mut self, resolved: ResolvedEndpoints) -> bool {
+        let resolved = resolved.endpoints;
+        for (removed, weight) in &self.current {
+            if resolved.get(removed) == Some(weight) {
+                continue;
+            }
+            if self
+                .changes
+                .send(Change::Remove(removed.clone()))
+                .await
+                .is_err()
+            {
+                return false;
+            }
+        }
+
+        let mut current = HashMap::with_capacity(resolved.len());
+        for (authority, weight) in resolved {
+            if self.current.get(&authority) != Some(&weight) {
+                let mut endpoint = match self.template.with_authority(&authority) {
+                    Ok(endpoint) => endpoint,
+                    Err(error) => {
+                        tracing::debug!(%error, %authority, "ignoring resolved endpoint");
+                        continue;
+                    }
+                };
+                if let Some(weight) = weight {
+                    endpoint.metadata.weight = weight;
+                }
+                tracing::debug!(%authority, ?weight, "adding resolved endpoint");
+                if self
+                    .changes
+                    .send(Change::Insert(authority.clone(), endpoint))
+                    .await
+                    .is_err()
+                {
+                    return false;
+                }
+            }
+            current.insert(authority, weight);
+        }
+        self.current = current;
+        true
+    }
+
+    /// Completes when the channel has been dropped, so the resolver can stop.
+    async fn closed(&self) {
+        self.changes.closed().await
+    }
+}
+
+/// The authority for `host` and `port`, adding brackets to IPv6 addresses.
+pub(crate) fn authority(host: &str, port: u16) -> Option<Authority> {
+    let authority = if Ipv6Addr::from_str(host).is_ok() {
+        format!("[{}]:{}", host, port)
+    } else {
+        format!("{}:{}", host, port)
+    };
+    Authority::from_str(&authority).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_authorities() {
+        assert_eq!(authority("10.0.0.1", 80).unwrap(), "10.0.0.1:80");
+        assert_eq!(authority("::1", 80).unwrap(), "[::1]:80");
+        assert_eq!(authority("pod.local", 443).unwrap(), "pod.local:443");
+        assert!(authority("not a host", 80).is_none());
+    }
+
+    #[tokio::test]
+    async fn keeps_endpoints_after_errors() {
+        let a: Authority = "10.0.0.1:80".parse().unwrap();
+        let b: Authority = "10.0.0.2:80".parse().unwrap();
+        let resolved = futures_util::stream::iter([
+            Ok(ResolvedEndpoints::from_iter([a.clone(), b.clone()])),
+            Err("name server unavailable".into()),
+            Ok(ResolvedEndpoints::from_iter([a.clone()])),
+        ]);
+        let template = ChannelBuilder::new_plaintext("http://example.com").unwrap();
+        let (tx, mut rx) = tokio::sync::mpsc::channel(16);
+        watch(resolved, EndpointUpdates::new(template, tx)).await;
+
+        let mut inserted = HashSet::new();
+        let mut removed = Vec::new();
+        while let Ok(change) = rx.try_recv() {
+            match change {
+                Change::Insert(authority, _) => assert!(inserted.insert(authority)),
+                Change::Remove(authority) => removed.push(authority),
+            }
+        }
+        assert_eq!(inserted, HashSet::from([a, b.clone()]));
+        assert_eq!(removed, [b]);
+    }
+}
@@ -0,0 +1,179 @@
+use crate::BoxError;
+
+use http::Request;
+use std::{error::Error as StdError, io, sync::Arc};
+
+/// A request extension which marks a request as safe to retry after a transport error.
+///
+/// Requests are only retried if they fail before any part of the response is received, because
+/// the connection was reset, the stream was refused, or the connection could not be
+/// established. Only mark requests which are idempotent, since the server may have processed a
+/// request before its connection was reset. See also
+/// [`ChannelBuilder::retry_transport_errors`](super::ChannelBuilder::retry_transport_errors).
+///
+/// ```no_run
+/// # use tonic_transport::RetryOnTransportError;
+/// let mut request = tonic::Request::new(());
+/// request.extensions_mut().insert(RetryOnTransportError);
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RetryOnTransportError;
+
+/// The methods which are retried after a transport error.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct RetryMethods(Arc<[String]>);
+
+impl RetryMethods {
+    pub(crate) fn new(methods: Vec<String>) -> Self {
+        RetryMethods(methods.into())
+    }
+
+    /// Returns `true` if `request` may be retried after a transport error.
+    pub(crate) fn matches<B>(&self, request: &Request<B>) -> bool {
+        if request
+            .extensions()
+            .get::<RetryOnTransportError>()
+            .is_some()
+        {
+            return true;
+        }
+        let path = request.uri().path();
+        self.0.iter().any(|method| {
+            path == method || (method.ends_with('/') && path.starts_with(method.as_str()))
+        })
+    }
+}
+
+/// Returns `true` if the server refused the request without processing it.
+///
+/// That is the case if the server reset the stream with REFUSED_STREAM, or sent GOAWAY with a
+/// last stream ID below the stream's. h2 only fails a stream with the server's GOAWAY in the
+/// latter case, or if the stream was opened after the GOAWAY was received; streams which the
+/// server may have processed fail with a broken pipe once the connection is closed.
+pub(crate) fn is_refused(error: &BoxError) -> bool {
+    sources(error).any(|error| match error.downcast_ref::<h2::Error>() {
+        Some(h2) if h2.is_remote() => {
+            h2.is_go_away() || h2.reason() == Some(h2::Reason::REFUSED_STREAM)
+        }
+        _ => false,
+    })
+}
+
+/// Returns `true` if `error` was caused by a failure of the connection, rather than by the server
+/// responding.
+pub(crate) fn is_transport_error(error: &BoxError) -> bool {
+    sources(error).any(|error| {
+        if let Some(h2) = error.downcast_ref::<h2::Error>() {
+            return h2.is_io()
+                || h2.reason() == Some(h2::Reason::REFUSED_STREAM)
+                || (h2.is_go_away() && h2.is_remote());
+        }
+        if let Some(hyper) = error.downcast_ref::<hyper::Error>() {
+            return hyper.is_connect() || hyper.is_closed() || hyper.is_incomplete_message();
+        }
+        if let Some(io) = error.downcast_ref::<io::Error>() {
+            return matches!(
+                io.kind(),
+                io::ErrorKind::ConnectionRefused
+                    | io::ErrorKind::ConnectionReset
+                    | io::ErrorKind::ConnectionAborted
+                    | io::ErrorKind::BrokenPipe
+            );
+        }
+        false
+    })
+}
+
+fn sources(error: &BoxError) -> impl Iterator<Item = &(dyn StdError + 'static)> {
+    let error: &(dyn StdError + 'static) = &**error;
+    std::iter::successors(Some(error), |&error| error.source())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+
+    fn request(path: &'static str) -> Request<()> {
+        Request::builder().uri(path).body(()).unwrap()
+    }
+
+    #[test]
+    fn matches_methods() {
+        let methods = RetryMethods::new(vec![
+            "/pkg.Greeter/SayHello".to_owned(),
+            "/pkg.Store/".to_owned(),
+        ]);
+        assert!(methods.matches(&request("/pkg.Greeter/SayHello")));
+        assert!(!methods.matches(&request("/pkg.Greeter/SayGoodbye")));
+        assert!(methods.matches(&request("/pkg.Store/Get")));
+        assert!(!methods.matches(&request("/pkg.StoreAdmin/Get")));
+
+        let mut marked = request("/pkg.Greeter/SayGoodbye");
+        marked.extensions_mut().insert(RetryOnTransportError);
+        assert!(methods.matches(&marked));
+    }
+
+    #[test]
+    fn classifies_errors() {
+        let reset: BoxError = Box::new(io::Error::from(io::ErrorKind::ConnectionReset));
+        assert!(is_transport_error(&reset));
+        assert!(!is_refused(&reset));
+
+        let refused: BoxError = Box::new(h2::Error::from(h2::Reason::REFUSED_STREAM));
+        assert!(is_transport_error(&refused));
+        // Not sent by the server.
+        assert!(!is_refused(&refused));
+
+        let status: BoxError = Box::new(tonic::Status::internal("oops"));
+        assert!(!is_transport_error(&status));
+    }
+
+    #[tokio::test]
+    async fn only_unprocessed_streams_are_refused() {
+        let (client_io, server_io) = tokio::io::duplex(64 * 1024);
+        let server = tokio::spawn(async move {
+            let mut conn = h2::server::handshake(server_io).await.unwrap();
+            let (_, mut respond) = conn.accept().await.unwrap().unwrap();
+            respond.send_reset(h2::Reason::REFUSED_STREAM);
+            let (_, mut respond) = conn.accept().await.unwrap().unwrap();
+            respond.send_reset(h2::Reason::CANCEL);
+            // The next stream has been received, so it may have been processed.
+            let _stream = conn.accept().await.unwrap().unwrap();
+            conn.abrupt_shutdown(h2::Reason::NO_ERROR);
+            let _ = futures_util::future::poll_fn(|cx| conn.poll_closed(cx)).await;
+        });
+        let (mut client, conn) = h2::client::handshake(client_io).await.unwrap();
+        tokio::spawn(conn);
+
+        fn send(
+            client: &mut h2::client::SendRequest<Bytes>,
+        ) -> Result<h2::client::ResponseFuture, h2::Error> {
+            let request = Request::builder()
+                .uri("http://example.com/")
+                .body(())
+                .unwrap();
+            client
+                .send_request(request, true)
+                .map(|(response, _)| response)
+        }
+        fn error<T>(result: Result<T, h2::Error>) -> BoxError {
+            Box::new(result.err().expect("request succeeded"))
+        }
+
+        let refused = send(&mut client).unwrap().await;
+        assert!(is_refused(&error(refused)));
+        let cancelled = send(&mut client).unwrap().await;
+        assert!(!is_refused(&error(cancelled)));
+        let processed = send(&mut client).unwrap().await;
+        assert!(!is_refused(&error(processed)));
+        server.await.unwrap();
+
+        // Sent after the GOAWAY, so never received.
+        let after = match send(&mut client) {
+            Ok(response) => response.await.map(|_| ()),
+            Err(error) => Err(error),
+        };
+        assert!(is_refused(&error(after)));
+    }
+}
@@ -0,0 +1,69 @@
+use std::time::Duration;
+
+/// Options for the TCP sockets of a channel's connections, see
+/// [`ChannelBuilder::socket_options`](crate::ChannelBuilder::socket_options).
+///
+/// Options which aren't set are left at the operating system's defaults.
+///
+/// ```no_run
+/// # use tonic_transport::SocketOptions;
+/// # use std::time::Duration;
+/// // For a link with a high bandwidth-delay product.
+/// let options = SocketOptions::new()
+///     .send_buffer_size(4 << 20)
+///     .recv_buffer_size(4 << 20)
+///     .user_timeout(Duration::from_secs(30));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SocketOptions {
+    pub(crate) send_buffer_size: Option<usize>,
+    pub(crate) recv_buffer_size: Option<usize>,
+    pub(crate) reuse_address: bool,
+    pub(crate) user_timeout: Option<Duration>,
+}
+
+impl SocketOptions {
+    /// Create options which leave every option at its default.
+    pub fn new() -> Self {
+        SocketOptions::default()
+    }
+
+    /// Set the size of the socket's send buffer (`SO_SNDBUF`).
+    pub fn send_buffer_size(self, size: usize) -> Self {
+        SocketOptions {
+            send_buffer_size: Some(size),
+            ..self
+        }
+    }
+
+    /// Set the size of the socket's receive buffer (`SO_RCVBUF`).
+    pub fn recv_buffer_size(self, size: usize) -> Self {
+        SocketOptions {
+            recv_buffer_size: Some(size),
+            ..self
+        }
+    }
+
+    /// Set whether the socket may bind to a local address which is in use (`SO_REUSEADDR`),
+    /// which only matters with a [`local_address`](crate::ChannelBuilder::local_address).
+    ///
+    /// Default is `false`.
+    pub fn reuse_address(self, enabled: bool) -> Self {
+        SocketOptions {
+            reuse_address: enabled,
+            ..self
+        }
+    }
+
+    /// Close the connection when data sent on it has not been acknowledged for `timeout`
+    /// (`TCP_USER_TIMEOUT`).
+    ///
+    /// This detects a dead peer sooner than the kernel's retransmission limit, which can take
+    /// many minutes. It is only supported on Linux and Android, and ignored elsewhere.
+    pub fn user_timeout(self, timeout: Duration) -> Self {
+        SocketOptions {
+            user_timeout: Some(timeout),
+            ..self
+        }
+    }
+}
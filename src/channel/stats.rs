@@ -0,0 +1,200 @@
+use futures_util::future::{self, Either, Ready};
+use http::Request;
+use std::{
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::time::Instant;
+use tower_service::Service;
+
+/// A snapshot of the state of a [`Channel`](super::Channel)'s request queue.
+///
+/// Requests wait in the queue until the channel's connection, or one of its balanced endpoints,
+/// is ready for them. A deep queue or a long time in the queue means that requests are sent
+/// faster than the channel can handle them.
+///
+/// See [`Channel::stats`](super::Channel::stats).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChannelStats {
+    queue_depth: usize,
+    dequeued: u64,
+    total_queue_time: Duration,
+    last_queue_time: Duration,
+    ping_rtt: Option<Duration>,
+}
+
+impl ChannelStats {
+    /// The number of requests waiting in the queue.
+    pub fn queue_depth(&self) -> usize {
+        self.queue_depth
+    }
+
+    /// The number of requests which have left the queue to be sent.
+    pub fn dequeued(&self) -> u64 {
+        self.dequeued
+    }
+
+    /// The total time spent in the queue by the requests counted in
+    /// [`dequeued`](ChannelStats::dequeued).
+    ///
+    /// The mean time in the queue over an interval can be computed from the differences of this
+    /// and `dequeued` between two snapshots.
+    pub fn total_queue_time(&self) -> Duration {
+        self.total_queue_time
+    }
+
+    /// The time spent in the queue by the request which most recently left it.
+    pub fn last_queue_time(&self) -> Duration {
+        self.last_queue_time
+    }
+
+    /// The round-trip time of the most recently acknowledged HTTP/2 PING on the channel's
+    /// connection.
+    ///
+    /// This is `None` if no PING has been acknowledged, and for balanced channels, which have a
+    /// connection per endpoint, see [`Endpoints::ping_rtt`](super::Endpoints::ping_rtt). PINGs
+    /// are sent when keepalive or an adaptive window is enabled.
+    pub fn ping_rtt(&self) -> Option<Duration> {
+        self.ping_rtt
+    }
+}
+
+/// The queue statistics shared by all clones of a channel.
+#[derive(Debug, Default)]
+pub(crate) struct QueueStats {
+    depth: AtomicUsize,
+    dequeued: AtomicU64,
+    total_nanos: AtomicU64,
+    last_nanos: AtomicU64,
+}
+
+impl QueueStats {
+    /// Count `request` as queued until it is passed to a [`Dequeue`] service or dropped.
+    pub(crate) fn enqueue<B>(self: &Arc<Self>, request: &mut Request<B>) {
+        self.depth.fetch_add(1, Ordering::Relaxed);
+        request.extensions_mut().insert(Queued {
+            stats: self.clone(),
+            since: Instant::now(),
+        });
+    }
+
+    pub(crate) fn snapshot(&self, ping_rtt: Option<Duration>) -> ChannelStats {
+        ChannelStats {
+            queue_depth: self.depth.load(Ordering::Relaxed),
+            dequeued: self.dequeued.load(Ordering::Relaxed),
+            total_queue_time: Duration::from_nanos(self.total_nanos.load(Ordering::Relaxed)),
+            last_queue_time: Duration::from_nanos(self.last_nanos.load(Ordering::Relaxed)),
+            ping_rtt,
+        }
+    }
+}
+
+// A request extension for a request in the queue. The queue depth is decremented when it is
+// dropped, so that requests which are dropped while queued are not counted forever.
+struct Queued {
+    stats: Arc<QueueStats>,
+    since: Instant,
+}
+
+impl Queued {
+    fn dequeue(self) {
+        let nanos = self
+            .since
+            .elapsed()
+            .as_nanos()
+            .try_into()
+            .unwrap_or(u64::MAX);
+        self.stats.dequeued.fetch_add(1, Ordering::Relaxed);
+        self.stats.total_nanos.fetch_add(nanos, Ordering::Relaxed);
+        self.stats.last_nanos.store(nanos, Ordering::Relaxed);
+    }
+}
+
+impl Drop for Queued {
+    fn drop(&mut self) {
+        self.stats.depth.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Marks a request sent by [`Channel::warm_up`](super::Channel::warm_up), which is answered as
+/// soon as the channel is ready rather than being sent.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct WarmUp;
+
+/// The service behind a channel's buffer, which records when requests leave the queue.
+#[derive(Debug)]
+pub(crate) struct Dequeue<S> {
+    inner: S,
+}
+
+impl<S> Dequeue<S> {
+    pub(crate) fn new(inner: S) -> Self {
+        Dequeue { inner }
+    }
+}
+
+impl<S, B> Service<Request<B>> for Dequeue<S>
+where
+    S: Service<Request<B>>,
+    S::Response: Default,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Either<Ready<Result<S::Response, S::Error>>, S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request<B>) -> Self::Future {
+        if let Some(queued) = request.extensions_mut().remove::<Queued>() {
+            queued.dequeue();
+        }
+        // The buffer only calls the service once it is ready, so there is nothing more to do.
+        if request.extensions().get::<WarmUp>().is_some() {
+            return Either::Left(future::ready(Ok(S::Response::default())));
+        }
+        Either::Right(self.inner.call(request))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn counts_queued_requests() {
+        let stats = Arc::new(QueueStats::default());
+        let mut first = Request::new(());
+        let mut second = Request::new(());
+        stats.enqueue(&mut first);
+        stats.enqueue(&mut second);
+        assert_eq!(stats.snapshot(None).queue_depth(), 2);
+
+        tokio::time::advance(Duration::from_millis(10)).await;
+        first.extensions_mut().remove::<Queued>().unwrap().dequeue();
+        drop(second);
+
+        let snapshot = stats.snapshot(None);
+        assert_eq!(snapshot.queue_depth(), 0);
+        assert_eq!(snapshot.dequeued(), 1);
+        assert_eq!(snapshot.last_queue_time(), Duration::from_millis(10));
+    }
+
+    #[tokio::test]
+    async fn answers_warm_up_requests() {
+        let svc = tower::service_fn(|_: Request<()>| async {
+            panic!("warm-up request was sent");
+            #[allow(unreachable_code)]
+            Ok::<_, std::convert::Infallible>(http::Response::new(()))
+        });
+        let mut request = Request::new(());
+        request.extensions_mut().insert(WarmUp);
+        let response = tower::ServiceExt::oneshot(Dequeue::new(svc), request).await;
+        assert!(response.is_ok());
+    }
+}
@@ -0,0 +1,273 @@
+use crate::{Error, Result};
+
+use http::uri::{Authority, Uri};
+use std::{
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    str::FromStr,
+};
+
+const DEFAULT_GRPC_PORT: u16 = 443;
+
+/// A gRPC name-resolution target.
+///
+/// Supports the [naming syntax][naming] shared with other gRPC runtimes, as well as plain
+/// `http`/`https` URIs:
+///
+/// * `dns:[//authority/]host[:port]`, the authority (DNS server) is ignored and the system
+///   resolver is used,
+/// * `ipv4:address[:port][,address[:port],...]` and `ipv6:...`, a list of addresses which is
+///   load balanced if there is more than one,
+/// * `unix:path` or `unix:///absolute_path`, a Unix domain socket,
+/// * `unix-abstract:name`, a Unix domain socket in the abstract namespace, which is only
+///   supported on Linux,
+/// * `npipe://server/pipe/name` or `npipe:////server/pipe/name`, the Windows named pipe
+///   `\\server\pipe\name`, where `server` is usually `.` for the local host,
+/// * `srv:name` or `srv://name`, a DNS SRV record such as `_grpc._tcp.greeter.example.com`,
+///   whose targets are load balanced, see [`DnsResolver::srv`](crate::DnsResolver::srv).
+///
+/// If no port is given, the gRPC default of 443 is used.
+///
+/// [naming]: https://github.com/grpc/grpc/blob/master/doc/naming.md
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Target {
+    /// A host name to be resolved by DNS, or a plain URI.
+    Dns(Uri),
+    /// A list of socket addresses.
+    Addrs(Vec<SocketAddr>),
+    /// The path of a Unix domain socket.
+    Unix(PathBuf),
+    /// The name of a Unix domain socket in the abstract namespace, without the leading NUL byte.
+    UnixAbstract(String),
+    /// The name of a DNS SRV record.
+    Srv(String),
+    /// The name of a Windows named pipe, such as `\\.\pipe\grpc`.
+    NamedPipe(String),
+}
+
+impl FromStr for Target {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Target> {
+        if let Some(rest) = s.strip_prefix("dns:") {
+            let host_port = match rest.strip_prefix("//") {
+                Some(rest) => match rest.split_once('/') {
+                    Some((_, host_port)) => host_port,
+                    None => return Err(Error::new_invalid_uri(s.to_owned())),
+                },
+                None => rest,
+            };
+            return dns_uri(host_port).map(Target::Dns);
+        }
+
+        if let Some(rest) = s.strip_prefix("ipv4:") {
+            return parse_addrs(rest, true).map(Target::Addrs);
+        }
+
+        if let Some(rest) = s.strip_prefix("ipv6:") {
+            return parse_addrs(rest, false).map(Target::Addrs);
+        }
+
+        if let Some(name) = s.strip_prefix("unix-abstract:") {
+            return Ok(Target::UnixAbstract(name.to_owned()));
+        }
+
+        if let Some(rest) = s.strip_prefix("srv:") {
+            let name = rest.strip_prefix("//").unwrap_or(rest);
+            if name.is_empty() {
+                return Err(Error::new_invalid_uri(s.to_owned()));
+            }
+            return Ok(Target::Srv(name.to_owned()));
+        }
+
+        if let Some(rest) = s.strip_prefix("unix:") {
+            let path = match rest.strip_prefix("//") {
+                Some(path) if path.starts_with('/') => path,
+                Some(_) => return Err(Error::new_invalid_uri(s.to_owned())),
+                None => rest,
+            };
+            if path.is_empty() {
+                return Err(Error::new_invalid_uri(s.to_owned()));
+            }
+            return Ok(Target::Unix(PathBuf::from(path)));
+        }
+
+        if let Some(rest) = s.strip_prefix("npipe:") {
+            return pipe_name(rest)
+                .map(Target::NamedPipe)
+                .ok_or_else(|| Error::new_invalid_uri(s.to_owned()));
+        }
+
+        Uri::from_str(s)
+            .map(Target::Dns)
+            .map_err(|e| Error::new_invalid_uri(e.to_string()))
+    }
+}
+
+/// Convert the path of an `npipe:` target, such as `//./pipe/name`, to the pipe's name, such as
+/// `\\.\pipe\name`.
+fn pipe_name(path: &str) -> Option<String> {
+    let mut segments = path.trim_start_matches('/').splitn(3, '/');
+    let server = segments.next().filter(|server| !server.is_empty())?;
+    segments
+        .next()
+        .filter(|pipe| pipe.eq_ignore_ascii_case("pipe"))?;
+    let name = segments.next().filter(|name| !name.is_empty())?;
+    Some(format!(r"\\{}\pipe\{}", server, name.replace('/', "\\")))
+}
+
+fn dns_uri(host_port: &str) -> Result<Uri> {
+    let authority = Authority::from_str(host_port)
+        .map_err(|_| Error::new_invalid_uri(host_port.to_owned()))?;
+    let authority = match authority.port_u16() {
+        Some(_) => authority,
+        None => Authority::from_str(&format!("{}:{}", authority, DEFAULT_GRPC_PORT))
+            .map_err(|_| Error::new_invalid_uri(host_port.to_owned()))?,
+    };
+
+    Uri::builder()
+        .scheme("https")
+        .authority(authority)
+        .path_and_query("/")
+        .build()
+        .map_err(|e| Error::new_invalid_uri(e.to_string()))
+}
+
+fn parse_addrs(list: &str, ipv4: bool) -> Result<Vec<SocketAddr>> {
+    list.split(',')
+        .map(|addr| {
+            let addr = SocketAddr::from_str(addr)
+                .or_else(|_| {
+                    IpAddr::from_str(addr).map(|ip| SocketAddr::new(ip, DEFAULT_GRPC_PORT))
+                })
+                .map_err(|_| Error::new_invalid_uri(addr.to_owned()))?;
+            if addr.is_ipv4() != ipv4 {
+                return Err(Error::new_invalid_uri(addr.to_string()));
+            }
+            Ok(addr)
+        })
+        .collect()
+}
+
+/// Create an `https` URI for the domain of the SRV record `name`, without its leading service
+/// and protocol labels, such as `https://greeter.example.com:443/` for
+/// `_grpc._tcp.greeter.example.com`.
+pub(crate) fn srv_uri(name: &str) -> Result<Uri> {
+    let mut domain = name;
+    while let Some((label, rest)) = domain.split_once('.') {
+        if !label.starts_with('_') {
+            break;
+        }
+        domain = rest;
+    }
+    dns_uri(domain.trim_end_matches('.'))
+}
+
+/// Create an `https` URI addressing `addr` directly.
+pub(crate) fn addr_uri(addr: &SocketAddr) -> Uri {
+    Uri::builder()
+        .scheme("https")
+        .authority(addr.to_string().as_str())
+        .path_and_query("/")
+        .build()
+        .expect("socket address is a valid authority")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> Target {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn dns_target() {
+        assert_eq!(
+            parse("dns:///example.com:50051"),
+            Target::Dns(Uri::from_static("https://example.com:50051/"))
+        );
+        assert_eq!(
+            parse("dns://8.8.8.8/example.com"),
+            Target::Dns(Uri::from_static("https://example.com:443/"))
+        );
+        assert_eq!(
+            parse("dns:example.com"),
+            Target::Dns(Uri::from_static("https://example.com:443/"))
+        );
+    }
+
+    #[test]
+    fn ip_targets() {
+        assert_eq!(
+            parse("ipv4:1.2.3.4:443,5.6.7.8"),
+            Target::Addrs(vec![
+                "1.2.3.4:443".parse().unwrap(),
+                "5.6.7.8:443".parse().unwrap()
+            ])
+        );
+        assert_eq!(
+            parse("ipv6:[::1]:50051,::2"),
+            Target::Addrs(vec![
+                "[::1]:50051".parse().unwrap(),
+                "[::2]:443".parse().unwrap()
+            ])
+        );
+        assert!("ipv4:[::1]:50051".parse::<Target>().is_err());
+        assert!("ipv6:1.2.3.4".parse::<Target>().is_err());
+    }
+
+    #[test]
+    fn unix_target() {
+        assert_eq!(
+            parse("unix:///tmp/grpc.sock"),
+            Target::Unix(PathBuf::from("/tmp/grpc.sock"))
+        );
+        assert_eq!(
+            parse("unix:relative.sock"),
+            Target::Unix(PathBuf::from("relative.sock"))
+        );
+        assert!("unix://relative.sock".parse::<Target>().is_err());
+        assert!("unix:".parse::<Target>().is_err());
+        assert_eq!(
+            parse("unix-abstract:grpc/test"),
+            Target::UnixAbstract("grpc/test".to_owned())
+        );
+    }
+
+    #[test]
+    fn named_pipe_target() {
+        assert_eq!(
+            parse("npipe://./pipe/grpc"),
+            Target::NamedPipe(r"\\.\pipe\grpc".to_owned())
+        );
+        assert_eq!(
+            parse("npipe:////host/pipe/my/grpc"),
+            Target::NamedPipe(r"\\host\pipe\my\grpc".to_owned())
+        );
+        assert!("npipe://./grpc".parse::<Target>().is_err());
+        assert!("npipe://./pipe/".parse::<Target>().is_err());
+    }
+
+    #[test]
+    fn srv_target() {
+        assert_eq!(
+            parse("srv://_grpc._tcp.greeter.example.com"),
+            Target::Srv("_grpc._tcp.greeter.example.com".to_owned())
+        );
+        assert_eq!(parse("srv:greeter"), Target::Srv("greeter".to_owned()));
+        assert!("srv:".parse::<Target>().is_err());
+        assert_eq!(
+            srv_uri("_grpc._tcp.greeter.example.com.").unwrap(),
+            Uri::from_static("https://greeter.example.com:443/")
+        );
+    }
+
+    #[test]
+    fn uri_target() {
+        assert_eq!(
+            parse("https://example.com"),
+            Target::Dns(Uri::from_static("https://example.com"))
+        );
+    }
+}
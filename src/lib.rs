@@ -1,9 +1,45 @@
+#[cfg(feature = "consul")]
 #[doc(inline)]
-pub use crate::channel::{Channel, ChannelBuilder};
+pub use crate::channel::ConsulResolver;
+#[cfg(feature = "etcd")]
 #[doc(inline)]
-pub use crate::server::{Router, Server};
+pub use crate::channel::EtcdResolver;
 #[doc(inline)]
-pub use crate::service::grpc_timeout::TimeoutExpired;
+pub use crate::channel::{
+    Affinity, BalanceBuilder, Channel, ChannelBuilder, ChannelPool, ChannelStats, DnsResolver,
+    EndpointMetadata, Endpoints, FileResolver, MethodStats, Mirror, MirrorLayer, NetworkProfile,
+    Policy, PooledChannel, Random, ResolvedEndpoints, Resolver, RetryOnTransportError, RoutingHint,
+    SessionKey, SocketOptions, Sticky, Target, ZoneAware,
+};
+#[cfg(windows)]
+#[doc(inline)]
+pub use crate::server::NamedPipeConnectInfo;
+#[cfg(feature = "x509")]
+#[doc(inline)]
+pub use crate::server::PeerCertificate;
+#[doc(inline)]
+pub use crate::server::{
+    code_from_h2_reason, ClientIdentity, ConnectInfoFailure, ConnectionInfo, ConnectionStack,
+    ConnectionStats, H2Reason, MaybeEmptyBody, NegotiatedEncoding, NonGrpcResponse, PeerIdentity,
+    PeerRateLimit, Profile, PropagateDeadline, PropagateDeadlineLayer, ReapedConnections,
+    RecoverError, RecoverErrorLayer, Router, Server, TcpConnectInfo, TcpIncoming, TcpOptions,
+    TlsConnectInfo,
+};
+#[cfg(unix)]
+#[doc(inline)]
+pub use crate::server::{UdsConnectInfo, UnixIncoming, UnixIncomingBuilder};
+#[doc(inline)]
+pub use crate::service::grpc_timeout::{CallTimeout, TimeoutExpired};
+#[doc(inline)]
+pub use crate::service::{
+    ConnectBackoff, EndpointService, Fault, FaultInjection, FaultInjectionLayer, GoAway,
+    ResponseBody, Routes, Throttle,
+};
+#[cfg(feature = "spiffe")]
+#[doc(inline)]
+pub use crate::spiffe::{X509Source, X509Svid};
+#[doc(inline)]
+pub use crate::tls::{ReloadableTls, TlsHandshakeError};
 pub use hyper::{Body, Uri};
 
 use pin_project::pin_project;
@@ -13,6 +49,8 @@ use tonic::body::BoxBody;
 mod channel;
 mod server;
 mod service;
+#[cfg(feature = "spiffe")]
+mod spiffe;
 mod tls;
 
 type BoxFuture<T, E> = std::pin::Pin<
@@ -26,12 +64,76 @@ pub enum Error {
     InvalidUri(String),
     #[error("Invalid user agent")]
     InvalidUserAgent,
+    /// Kept for compatibility; a server which doesn't choose HTTP/2 is reported with
+    /// [`Error::H2NotChosen`], which has the handshake's details.
     #[error("HTTP/2 was not negotiated")]
     H2NotNegotiated,
+    #[error("The server did not choose HTTP/2 with ALPN")]
+    H2NotChosen(#[source] Box<TlsHandshakeError>),
+    #[error("TLS handshake failed")]
+    TlsHandshake(#[source] Box<TlsHandshakeError>),
+    #[error("The TLS handshake did not complete within {0:?}")]
+    TlsHandshakeTimeout(std::time::Duration),
+    #[error(
+        "The server's TLS acceptor chose `{}` rather than `h2` with ALPN, it must be configured to \
+         choose `h2`",
+        String::from_utf8_lossy(.0)
+    )]
+    H2NotAccepted(Vec<u8>),
+    #[error("The peer's SPIFFE ID was not accepted")]
+    SpiffeIdRejected,
+    #[error("The connection was rejected: {0}")]
+    ConnectionRejected(#[source] BoxError),
+    #[error("The connection is closed")]
+    ConnectionClosed,
+    #[error("PINGs can only be sent on a channel with a single connection")]
+    PingUnavailable,
+    #[error("The peer's certificate is unavailable")]
+    PeerCertificateUnavailable(#[source] Option<BoxError>),
+    #[error("Invalid configuration: {0}")]
+    InvalidConfig(ConfigError),
     #[error("Unknown error {0}")]
     Other(#[from] BoxError),
 }
 
+/// A combination of [`ChannelBuilder`] settings which would produce a channel that misbehaves,
+/// see [`Error::InvalidConfig`].
+#[non_exhaustive]
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ConfigError {
+    /// A keepalive timeout was set, or keepalive while idle enabled, without a keepalive
+    /// interval, so no keepalive PINGs would be sent.
+    #[error("HTTP/2 keepalive settings have no effect without `http2_keep_alive_interval`")]
+    KeepAliveWithoutInterval,
+    /// The concurrency limit is zero, so no requests would be sent.
+    #[error("the concurrency limit is zero")]
+    ZeroConcurrencyLimit,
+    /// The rate limit allows no requests, or has a zero period.
+    #[error("the rate limit must allow at least one request in a non-zero period")]
+    ZeroRateLimit,
+    /// The URI's scheme is `http`, but connections use TLS, see
+    /// [`ChannelBuilder::force_tls`].
+    #[error("the URI's scheme is `http` but connections use TLS, use `https` or `force_tls`")]
+    HttpWithTls,
+    /// The URI's scheme is neither `http` nor `https`.
+    #[error("unsupported URI scheme `{0}`")]
+    UnsupportedScheme(String),
+    /// The connect timeout is zero, so every connection attempt would time out.
+    #[error("the connect timeout is zero")]
+    ZeroConnectTimeout,
+    /// An initial flow control window is larger than HTTP/2 allows.
+    #[error("initial window sizes must be at most 2^31 - 1")]
+    WindowSizeTooLarge,
+    /// Connections should be bound to a network device, which isn't supported on this platform,
+    /// see [`ChannelBuilder::bind_device`].
+    #[error("binding to a network device is not supported on this platform")]
+    BindDeviceUnsupported,
+    /// A server name other than the verified domain was set with
+    /// [`ChannelBuilder::sni_override`], which requires the `x509` feature.
+    #[error("sending a server name other than the verified domain requires the `x509` feature")]
+    SniOverrideWithoutX509,
+}
+
 impl Error {
     fn new_invalid_uri(detail: String) -> Error {
         Error::InvalidUri(detail)
@@ -0,0 +1,207 @@
+use crate::BoxError;
+
+use http::{HeaderMap, HeaderValue, Request, Response};
+use pin_project::pin_project;
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+use tonic::{metadata::MetadataMap, Code, Status};
+use tower::Service;
+
+const GRPC_ENCODING: &str = "grpc-encoding";
+const GRPC_ACCEPT_ENCODING: &str = "grpc-accept-encoding";
+
+/// The compression encoding a [`Server`](crate::Server) and a client have agreed to use for the
+/// response, such as `gzip`.
+///
+/// When the server is configured with
+/// [`compression_encodings`](crate::Server::compression_encodings), this is added to the
+/// extensions of requests whose `grpc-accept-encoding` includes one of the server's encodings,
+/// so that services can compress their responses with it. It is the first of the server's
+/// encodings, in order of preference, which the client accepts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NegotiatedEncoding(Arc<str>);
+
+impl NegotiatedEncoding {
+    /// The name of the encoding.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+/// The compression encodings supported by a server, in order of preference.
+#[derive(Debug, Clone)]
+pub(crate) struct Encodings {
+    names: Arc<[Arc<str>]>,
+    // The value of `grpc-accept-encoding` in responses.
+    header: HeaderValue,
+}
+
+impl Encodings {
+    pub(crate) fn new(names: impl IntoIterator<Item = String>) -> Self {
+        let names: Vec<Arc<str>> = names
+            .into_iter()
+            .filter(|name| {
+                let valid = HeaderValue::from_str(name).is_ok() && !name.contains(',');
+                if !valid {
+                    tracing::warn!(%name, "ignoring invalid compression encoding");
+                }
+                valid && name != "identity"
+            })
+            .map(Arc::from)
+            .collect();
+        let header = names
+            .iter()
+            .map(|name| &**name)
+            .chain(["identity"])
+            .collect::<Vec<_>>()
+            .join(",");
+        Encodings {
+            names: names.into(),
+            header: HeaderValue::try_from(header).expect("encodings are valid header values"),
+        }
+    }
+
+    fn supports(&self, name: &str) -> bool {
+        name == "identity" || self.names.iter().any(|n| n.eq_ignore_ascii_case(name))
+    }
+
+    // The first of the server's encodings which the client accepts.
+    fn negotiate(&self, headers: &HeaderMap) -> Option<NegotiatedEncoding> {
+        let accepted: Vec<&str> = headers
+            .get_all(GRPC_ACCEPT_ENCODING)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .collect();
+        self.names
+            .iter()
+            .find(|name| accepted.iter().any(|a| a.eq_ignore_ascii_case(name)))
+            .map(|name| NegotiatedEncoding(name.clone()))
+    }
+}
+
+/// Middleware that negotiates the compression encoding of responses, rejects requests compressed
+/// with an unsupported encoding, and advertises the supported encodings in responses.
+#[derive(Debug, Clone)]
+pub(crate) struct NegotiateEncoding<S> {
+    inner: S,
+    encodings: Encodings,
+}
+
+impl<S> NegotiateEncoding<S> {
+    pub(crate) fn new(inner: S, encodings: Encodings) -> Self {
+        Self { inner, encodings }
+    }
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for NegotiateEncoding<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+    S::Error: Into<BoxError>,
+{
+    type Response = S::Response;
+    type Error = BoxError;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, mut req: Request<ReqBody>) -> Self::Future {
+        let encoding = req
+            .headers()
+            .get(GRPC_ENCODING)
+            .map(|value| value.to_str().unwrap_or_default());
+        if let Some(encoding) = encoding.filter(|&encoding| !self.encodings.supports(encoding)) {
+            tracing::debug!(%encoding, "rejecting request with unsupported compression");
+            // The supported encodings are sent so that the client can retry with one of them.
+            let mut headers = HeaderMap::new();
+            headers.insert(GRPC_ACCEPT_ENCODING, self.encodings.header.clone());
+            return ResponseFuture::Rejected(Some(Status::with_metadata(
+                Code::Unimplemented,
+                format!("message compression `{encoding}` is not supported"),
+                MetadataMap::from_headers(headers),
+            )));
+        }
+
+        if let Some(negotiated) = self.encodings.negotiate(req.headers()) {
+            req.extensions_mut().insert(negotiated);
+        }
+        ResponseFuture::Inner {
+            inner: self.inner.call(req),
+            header: Some(self.encodings.header.clone()),
+        }
+    }
+}
+
+#[pin_project(project = ResponseFutureProj)]
+pub(crate) enum ResponseFuture<F> {
+    Inner {
+        #[pin]
+        inner: F,
+        header: Option<HeaderValue>,
+    },
+    Rejected(Option<Status>),
+}
+
+impl<F, ResBody, E> Future for ResponseFuture<F>
+where
+    F: Future<Output = Result<Response<ResBody>, E>>,
+    E: Into<BoxError>,
+{
+    type Output = Result<Response<ResBody>, BoxError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match self.project() {
+            ResponseFutureProj::Inner { inner, header } => {
+                let mut response = futures_util::ready!(inner.poll(cx)).map_err(Into::into)?;
+                let header = header.take().expect("polled after ready");
+                // Services which compress their responses themselves may already advertise the
+                // encodings they support.
+                response
+                    .headers_mut()
+                    .entry(GRPC_ACCEPT_ENCODING)
+                    .or_insert(header);
+                Poll::Ready(Ok(response))
+            }
+            ResponseFutureProj::Rejected(status) => {
+                let status = status.take().expect("polled after ready");
+                Poll::Ready(Err(status.into()))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(accept_encoding: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            GRPC_ACCEPT_ENCODING,
+            HeaderValue::from_static(accept_encoding),
+        );
+        headers
+    }
+
+    #[test]
+    fn negotiates_preferred_encoding() {
+        let encodings = Encodings::new(["zstd".to_owned(), "gzip".to_owned()]);
+        assert_eq!(encodings.header, "zstd,gzip,identity");
+        assert!(encodings.supports("gzip"));
+        assert!(encodings.supports("identity"));
+        assert!(!encodings.supports("deflate"));
+
+        let negotiated = |accept| encodings.negotiate(&headers(accept));
+        assert_eq!(negotiated("gzip, zstd").unwrap().as_str(), "zstd");
+        assert_eq!(negotiated("deflate,gzip").unwrap().as_str(), "gzip");
+        assert_eq!(negotiated("identity"), None);
+        assert_eq!(encodings.negotiate(&HeaderMap::new()), None);
+    }
+}
@@ -4,8 +4,8 @@ use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::net::TcpStream;
 use tokio_native_tls::TlsStream;
 
-use crate::{tls::Certificate, Result};
-use std::sync::Arc;
+use crate::{tls::Certificate, Error, Result};
+use std::{any::Any, sync::Arc};
 
 /// Trait that connected IO resources implement and use to produce info about the connection.
 ///
@@ -67,9 +67,15 @@ pub trait Connected {
 #[derive(Debug, Clone)]
 pub struct TcpConnectInfo {
     remote_addr: Option<SocketAddr>,
+    connection_id: u64,
 }
 
 impl TcpConnectInfo {
+    /// The id of the connection, see [`ConnectionInfo::id`](crate::ConnectionInfo::id).
+    pub fn connection_id(&self) -> u64 {
+        self.connection_id
+    }
+
     /// Return the remote address the IO resource is connected too.
     pub fn remote_addr(&self) -> Option<SocketAddr> {
         self.remote_addr
@@ -82,6 +88,7 @@ impl Connected for AddrStream {
     fn connect_info(&self) -> Result<Self::ConnectInfo> {
         Ok(TcpConnectInfo {
             remote_addr: Some(self.remote_addr()),
+            connection_id: 0,
         })
     }
 }
@@ -92,6 +99,7 @@ impl Connected for TcpStream {
     fn connect_info(&self) -> Result<Self::ConnectInfo> {
         Ok(TcpConnectInfo {
             remote_addr: self.peer_addr().ok(),
+            connection_id: 0,
         })
     }
 }
@@ -111,10 +119,62 @@ where
             None
         };
 
-        Ok(TlsConnectInfo { inner, cert })
+        Ok(TlsConnectInfo {
+            inner,
+            cert,
+            connection_id: 0,
+        })
+    }
+}
+
+/// Check that the peer of a TLS stream presented a certificate which can be read.
+pub(crate) fn require_peer_certificate<T>(stream: &TlsStream<T>) -> Result<()>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    match stream.get_ref().peer_certificate() {
+        Ok(Some(cert)) => match cert.to_der() {
+            Ok(_) => Ok(()),
+            Err(error) => Err(Error::PeerCertificateUnavailable(Some(error.into()))),
+        },
+        Ok(None) => Err(Error::PeerCertificateUnavailable(None)),
+        Err(error) => Err(Error::PeerCertificateUnavailable(Some(error.into()))),
+    }
+}
+
+/// Check the protocol chosen with ALPN by the server's TLS acceptor, returning `false` if none was
+/// chosen, which happens if either the client didn't offer any or the acceptor isn't configured
+/// to choose one.
+///
+/// Clients which offer `h2` fail to connect unless it is chosen, but a misconfigured acceptor
+/// can't be detected before a client connects.
+pub(crate) fn accepted_h2<T>(stream: &TlsStream<T>) -> Result<bool>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    match stream.get_ref().negotiated_alpn()? {
+        Some(protocol) if protocol == b"h2" => Ok(true),
+        Some(protocol) => Err(Error::H2NotAccepted(protocol)),
+        None => Ok(false),
     }
 }
 
+/// Connection info for a TLS stream without the peer's certificate, for when reading the
+/// certificate fails.
+pub(crate) fn connect_info_without_cert<T>(
+    stream: &TlsStream<T>,
+) -> Result<TlsConnectInfo<T::ConnectInfo>>
+where
+    T: Connected + AsyncRead + AsyncWrite + Unpin,
+{
+    let inner = stream.get_ref().get_ref().get_ref().connect_info()?;
+    Ok(TlsConnectInfo {
+        inner,
+        cert: None,
+        connection_id: 0,
+    })
+}
+
 /// Connection info for TLS streams.
 ///
 /// This type will be accessible through [request extensions][ext] if you're using a TLS connector.
@@ -126,6 +186,7 @@ where
 pub struct TlsConnectInfo<T> {
     inner: T,
     cert: Option<Arc<Certificate>>,
+    connection_id: u64,
 }
 
 impl<T> TlsConnectInfo<T> {
@@ -143,4 +204,28 @@ impl<T> TlsConnectInfo<T> {
     pub fn peer_cert(&self) -> Option<Arc<Certificate>> {
         self.cert.clone()
     }
+
+    /// The id of the connection, see [`ConnectionInfo::id`](crate::ConnectionInfo::id).
+    pub fn connection_id(&self) -> u64 {
+        self.connection_id
+    }
+}
+
+impl<T: 'static> TlsConnectInfo<T> {
+    /// Set the id of the connection, here and in the underlying connection info.
+    pub(crate) fn set_connection_id(&mut self, id: u64) {
+        self.connection_id = id;
+        let inner = &mut self.inner as &mut dyn Any;
+        if let Some(tcp) = inner.downcast_mut::<TcpConnectInfo>() {
+            tcp.connection_id = id;
+        }
+        #[cfg(unix)]
+        if let Some(uds) = inner.downcast_mut::<super::UdsConnectInfo>() {
+            uds.connection_id = id;
+        }
+        #[cfg(windows)]
+        if let Some(pipe) = inner.downcast_mut::<super::NamedPipeConnectInfo>() {
+            pipe.connection_id = id;
+        }
+    }
 }
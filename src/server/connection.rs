@@ -0,0 +1,506 @@
+use super::drain::{ActiveRequests, TrackedFuture};
+use super::{BoxHttpBody, BoxService, StreamActivity, TcpConnectInfo, TlsConnectInfo};
+use crate::service::PingRtt;
+use crate::{tls::Certificate, BoxError, BoxFuture, Error};
+
+use bytes::Bytes;
+use http::{Request, Response};
+use http_body::Body as _;
+use hyper::{server::conn, Body};
+use pin_project::pin_project;
+use std::{
+    any::Any,
+    fmt,
+    future::Future,
+    net::SocketAddr,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    sync::{watch, Notify},
+    time::{interval, Interval, MissedTickBehavior, Sleep},
+};
+use tower::{Layer, Service, ServiceExt};
+
+static NEXT_CONNECTION_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Allocate an id for a new connection, unique within the process.
+pub(crate) fn next_connection_id() -> u64 {
+    NEXT_CONNECTION_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+type EstablishedHook = Arc<dyn Fn(&ConnectionInfo) + Send + Sync + 'static>;
+type ClosedHook = Arc<dyn Fn(&ConnectionInfo, &ConnectionStats) + Send + Sync + 'static>;
+type HandshakeErrorHook = Arc<dyn Fn(&Error) + Send + Sync + 'static>;
+type WrapHook = Arc<
+    dyn Fn(&ConnectionInfo, ConnectionStack) -> Result<ConnectionStack, BoxError>
+        + Send
+        + Sync
+        + 'static,
+>;
+type DataHook = Arc<dyn Fn(&ConnectionInfo) -> Option<InsertData> + Send + Sync + 'static>;
+type InsertData = Box<dyn Fn(&mut http::Extensions) + Send + Sync + 'static>;
+
+/// Callbacks for the lifecycle of each connection.
+#[derive(Clone, Default)]
+pub(crate) struct ConnectionHooks {
+    pub(crate) established: Option<EstablishedHook>,
+    pub(crate) closed: Option<ClosedHook>,
+    pub(crate) handshake_error: Option<HandshakeErrorHook>,
+    pub(crate) wrap: Option<WrapHook>,
+    pub(crate) data: Vec<DataHook>,
+}
+
+impl ConnectionHooks {
+    pub(crate) fn handshake_error(&self, error: &Error) {
+        if let Some(hook) = &self.handshake_error {
+            hook(error);
+        }
+    }
+
+    /// Add a hook computing data for each connection, which is cloned into the extensions of the
+    /// connection's requests.
+    pub(crate) fn add_data<T, F>(&mut self, f: F)
+    where
+        F: Fn(&ConnectionInfo) -> Option<T> + Send + Sync + 'static,
+        T: Clone + Send + Sync + 'static,
+    {
+        self.data.push(Arc::new(move |info| {
+            let data = f(info)?;
+            Some(Box::new(move |extensions: &mut http::Extensions| {
+                extensions.insert(data.clone());
+            }))
+        }));
+    }
+
+    /// Wrap the service for a connection, failing with [`Error::ConnectionRejected`] if the hook
+    /// rejects the connection.
+    ///
+    /// The connection's data is inserted into requests before the wrapping layers see them.
+    pub(crate) fn wrap(
+        &self,
+        info: &ConnectionInfo,
+        inner: BoxService,
+    ) -> crate::Result<BoxService> {
+        let inner = match &self.wrap {
+            Some(hook) => hook(info, ConnectionStack { inner })
+                .map(|stack| stack.inner)
+                .map_err(Error::ConnectionRejected)?,
+            None => inner,
+        };
+
+        let data: Vec<_> = self.data.iter().filter_map(|hook| hook(info)).collect();
+        if data.is_empty() {
+            return Ok(inner);
+        }
+        Ok(BoxService::new(inner.map_request(
+            move |mut request: Request<Body>| {
+                for insert in &data {
+                    insert(request.extensions_mut());
+                }
+                request
+            },
+        )))
+    }
+}
+
+/// The services handling the requests on a connection, which the hook set with
+/// [`Server::wrap_connection`](crate::Server::wrap_connection) can wrap with layers.
+pub struct ConnectionStack {
+    inner: BoxService,
+}
+
+impl ConnectionStack {
+    /// Wrap the services with `layer`, whose service may return any response body.
+    pub fn layer<L, B>(self, layer: L) -> Self
+    where
+        L: Layer<ConnectionStack>,
+        L::Service: Service<Request<Body>, Response = Response<B>> + Send + 'static,
+        <L::Service as Service<Request<Body>>>::Future: Send + 'static,
+        <L::Service as Service<Request<Body>>>::Error: Into<BoxError>,
+        B: http_body::Body<Data = Bytes> + Send + 'static,
+        B::Error: Into<BoxError>,
+    {
+        let inner = layer
+            .layer(self)
+            .map_response(|response| response.map(|body| body.map_err(Into::into).boxed_unsync()))
+            .map_err(Into::into);
+        ConnectionStack {
+            inner: BoxService::new(inner),
+        }
+    }
+}
+
+impl Service<Request<Body>> for ConnectionStack {
+    type Response = Response<BoxHttpBody>;
+    type Error = BoxError;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        self.inner.call(request)
+    }
+}
+
+impl fmt::Debug for ConnectionStack {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConnectionStack").finish()
+    }
+}
+
+/// What a [`Server`](crate::Server) does with a connection when it fails to get the
+/// connection's info, for example because the client's certificate can't be read.
+///
+/// See [`Server::connect_info_failure`](crate::Server::connect_info_failure).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConnectInfoFailure {
+    /// Close the connection.
+    #[default]
+    Reject,
+    /// Serve the connection with whatever info is available. Requests on the connection may be
+    /// missing the peer certificate, or all connection info.
+    Degrade,
+}
+
+/// Information about a connection accepted by a [`Server`](crate::Server).
+///
+/// See [`Server::on_connection_established`](crate::Server::on_connection_established).
+#[derive(Debug, Clone)]
+pub struct ConnectionInfo {
+    id: u64,
+    remote_addr: Option<SocketAddr>,
+    peer_cert: Option<Arc<Certificate>>,
+    ping_rtt: Arc<PingRtt>,
+}
+
+impl ConnectionInfo {
+    pub(crate) fn new<T: 'static>(
+        id: u64,
+        conn_info: Option<&TlsConnectInfo<T>>,
+        ping_rtt: Arc<PingRtt>,
+    ) -> Self {
+        let inner = conn_info.map(|conn_info| conn_info.get_ref() as &dyn Any);
+        ConnectionInfo {
+            id,
+            remote_addr: inner
+                .and_then(|inner| inner.downcast_ref::<TcpConnectInfo>())
+                .and_then(TcpConnectInfo::remote_addr),
+            peer_cert: conn_info.and_then(TlsConnectInfo::peer_cert),
+            ping_rtt,
+        }
+    }
+
+    /// An identifier for the connection, unique among the connections accepted in the process.
+    ///
+    /// Ids increase in the order connections are accepted. The id is also available from the
+    /// connection info in request extensions, such as
+    /// [`TlsConnectInfo::connection_id`](crate::TlsConnectInfo::connection_id), so that
+    /// the logs of requests and connections can be correlated.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// The address of the client, if the connection is over TCP.
+    pub fn remote_addr(&self) -> Option<SocketAddr> {
+        self.remote_addr
+    }
+
+    /// The client's leaf TLS certificate, if it sent one.
+    pub fn peer_cert(&self) -> Option<Arc<Certificate>> {
+        self.peer_cert.clone()
+    }
+
+    /// The round-trip time of the most recently acknowledged HTTP/2 PING sent by the server on
+    /// the connection, or `None` if no PING has been acknowledged.
+    ///
+    /// This is updated while the connection is open. PINGs are sent when
+    /// [`Server::http2_keepalive_interval`](crate::Server::http2_keepalive_interval) or an
+    /// adaptive window is enabled.
+    pub fn ping_rtt(&self) -> Option<Duration> {
+        self.ping_rtt.get()
+    }
+
+    /// Send an HTTP/2 PING on the connection and wait for the client to acknowledge it,
+    /// returning the round-trip time, to check that the client is alive without a request.
+    ///
+    /// This fails with [`Error::ConnectionClosed`] if the connection closes before the
+    /// acknowledgement. A client which doesn't answer is only detected with a timeout.
+    pub async fn ping(&self) -> crate::Result<Duration> {
+        self.ping_rtt.ping().await
+    }
+}
+
+/// Statistics about a connection which has closed.
+///
+/// See [`Server::on_connection_closed`](crate::Server::on_connection_closed).
+#[derive(Debug, Clone)]
+pub struct ConnectionStats {
+    requests: usize,
+    duration: Duration,
+    ping_rtt: Option<Duration>,
+}
+
+impl ConnectionStats {
+    /// The number of requests received on the connection.
+    pub fn requests(&self) -> usize {
+        self.requests
+    }
+
+    /// How long the connection was open.
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+
+    /// The round-trip time of the last HTTP/2 PING acknowledged before the connection closed,
+    /// see [`ConnectionInfo::ping_rtt`].
+    pub fn ping_rtt(&self) -> Option<Duration> {
+        self.ping_rtt
+    }
+}
+
+/// The number of connections a [`Server`](crate::Server) has closed because their client
+/// stopped acknowledging keepalive PINGs.
+///
+/// See [`Server::client_liveness`](crate::Server::client_liveness).
+#[derive(Debug, Clone, Default)]
+pub struct ReapedConnections(Arc<AtomicU64>);
+
+impl ReapedConnections {
+    /// The number of connections closed so far.
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    fn increment(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Closes connections whose client has not acknowledged a PING for `max_unacked`, checking
+/// every `check_interval`.
+#[derive(Debug, Clone)]
+pub(crate) struct Liveness {
+    pub(crate) check_interval: Duration,
+    pub(crate) max_unacked: Duration,
+    pub(crate) reaped: ReapedConnections,
+}
+
+/// Counts the requests received on a connection, to close it after `max` requests.
+pub(crate) struct RequestCount {
+    max: Option<usize>,
+    count: AtomicUsize,
+    limit_reached: Notify,
+}
+
+impl RequestCount {
+    pub(crate) fn new(max: Option<usize>) -> Self {
+        RequestCount {
+            max,
+            count: AtomicUsize::new(0),
+            limit_reached: Notify::new(),
+        }
+    }
+
+    fn record(&self) {
+        let count = self.count.fetch_add(1, Ordering::Relaxed) + 1;
+        if Some(count) == self.max {
+            self.limit_reached.notify_one();
+        }
+    }
+}
+
+/// The service for a single connection, which records each request in the connection's
+/// [`RequestCount`] and the server's [`ActiveRequests`].
+pub(crate) struct ConnectionService {
+    inner: BoxService,
+    id: u64,
+    requests: Arc<RequestCount>,
+    active: Arc<ActiveRequests>,
+}
+
+impl ConnectionService {
+    pub(crate) fn new(
+        inner: BoxService,
+        id: u64,
+        requests: Arc<RequestCount>,
+        active: Arc<ActiveRequests>,
+    ) -> Self {
+        ConnectionService {
+            inner,
+            id,
+            requests,
+            active,
+        }
+    }
+}
+
+impl Service<Request<Body>> for ConnectionService {
+    type Response = Response<BoxHttpBody>;
+    type Error = BoxError;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        self.requests.record();
+        let guard = self.active.register(self.id, request.uri().path());
+        Box::pin(TrackedFuture::new(self.inner.call(request), guard))
+    }
+}
+
+/// Serves a connection until it closes, shutting it down gracefully when it reaches its request
+/// limit or the server shuts down, and closing it when its client stops responding.
+#[pin_project]
+pub(crate) struct ServeConnection<IO> {
+    #[pin]
+    conn: conn::Connection<IO, ConnectionService>,
+    // Completes when the connection should be closed, `None` once it is closing.
+    close: Option<Pin<Box<dyn Future<Output = ()> + Send>>>,
+    info: ConnectionInfo,
+    requests: Arc<RequestCount>,
+    established: Instant,
+    on_closed: Option<ClosedHook>,
+    liveness: Option<(Liveness, Interval)>,
+    inactivity: Option<(Arc<StreamActivity>, Pin<Box<Sleep>>)>,
+    // Held until the connection has closed, so that the server waits for it to drain.
+    _shutdown: watch::Receiver<()>,
+}
+
+impl<IO> ServeConnection<IO> {
+    pub(crate) fn new(
+        conn: conn::Connection<IO, ConnectionService>,
+        info: ConnectionInfo,
+        requests: Arc<RequestCount>,
+        hooks: &ConnectionHooks,
+        shutdown: watch::Receiver<()>,
+        liveness: Option<Liveness>,
+        activity: Option<Arc<StreamActivity>>,
+    ) -> Self {
+        if let Some(hook) = &hooks.established {
+            hook(&info);
+        }
+
+        let count = requests.clone();
+        let mut signal = shutdown.clone();
+        let close = async move {
+            let limit_reached = async {
+                match count.max {
+                    Some(_) => count.limit_reached.notified().await,
+                    None => futures_util::future::pending().await,
+                }
+            };
+            let shutdown = async {
+                // The sender is dropped without sending if the server stops without shutting down
+                // its connections.
+                if signal.changed().await.is_err() {
+                    futures_util::future::pending().await
+                }
+            };
+            tokio::select! {
+                () = limit_reached => {
+                    tracing::debug!("closing connection which reached its request limit");
+                }
+                () = shutdown => {}
+            }
+        };
+
+        ServeConnection {
+            conn,
+            close: Some(Box::pin(close)),
+            info,
+            requests,
+            established: Instant::now(),
+            on_closed: hooks.closed.clone(),
+            liveness: liveness.map(|liveness| {
+                let mut check = interval(liveness.check_interval);
+                check.set_missed_tick_behavior(MissedTickBehavior::Delay);
+                (liveness, check)
+            }),
+            inactivity: activity.map(|activity| {
+                let timer = activity.timer();
+                (activity, timer)
+            }),
+            _shutdown: shutdown,
+        }
+    }
+}
+
+impl<IO> Future for ServeConnection<IO>
+where
+    IO: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut this = self.project();
+
+        if let Some(close) = this.close {
+            if close.as_mut().poll(cx).is_ready() {
+                *this.close = None;
+                this.conn.as_mut().graceful_shutdown();
+            }
+        }
+
+        if let Some((liveness, check)) = this.liveness {
+            while check.poll_tick(cx).is_ready() {
+                let unacked = this.info.ping_rtt.unacked_for();
+                if unacked.is_some_and(|unacked| unacked >= liveness.max_unacked) {
+                    tracing::debug!(
+                        remote_addr = ?this.info.remote_addr,
+                        "closing connection whose client stopped acknowledging PINGs"
+                    );
+                    liveness.reaped.increment();
+                    // Completing drops the connection, which closes it without waiting for
+                    // the client.
+                    closed(this.info, this.requests, *this.established, this.on_closed);
+                    return Poll::Ready(());
+                }
+            }
+        }
+
+        if let Some((activity, timer)) = this.inactivity {
+            if activity.poll_expired(timer, cx) {
+                tracing::debug!(
+                    remote_addr = ?this.info.remote_addr,
+                    "closing connection whose streams are inactive"
+                );
+                closed(this.info, this.requests, *this.established, this.on_closed);
+                return Poll::Ready(());
+            }
+        }
+
+        if let Err(error) = futures_util::ready!(this.conn.poll(cx)) {
+            tracing::debug!(%error, "connection error");
+        }
+
+        closed(this.info, this.requests, *this.established, this.on_closed);
+        Poll::Ready(())
+    }
+}
+
+fn closed(
+    info: &ConnectionInfo,
+    requests: &RequestCount,
+    established: Instant,
+    on_closed: &Option<ClosedHook>,
+) {
+    if let Some(hook) = on_closed {
+        let stats = ConnectionStats {
+            requests: requests.count.load(Ordering::Relaxed),
+            duration: established.elapsed(),
+            ping_rtt: info.ping_rtt(),
+        };
+        hook(info, &stats);
+    }
+}
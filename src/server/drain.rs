@@ -0,0 +1,219 @@
+use super::BoxHttpBody;
+use crate::BoxError;
+
+use bytes::Bytes;
+use futures_util::task::AtomicWaker;
+use http::{HeaderMap, Response};
+use http_body::Body;
+use pin_project::pin_project;
+use std::{
+    collections::HashMap,
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll},
+};
+use tokio::time::Instant;
+use tonic::Status;
+
+/// The requests being handled by a server, so that they can be reported and aborted when the
+/// server shuts down.
+#[derive(Debug, Default)]
+pub(crate) struct ActiveRequests {
+    next_id: AtomicU64,
+    requests: Mutex<HashMap<u64, Arc<ActiveRequest>>>,
+}
+
+#[derive(Debug)]
+struct ActiveRequest {
+    connection_id: u64,
+    path: String,
+    started: Instant,
+    aborted: AtomicBool,
+    waker: AtomicWaker,
+}
+
+impl ActiveRequest {
+    /// Returns `true` if the request has been aborted, otherwise wakes the task when it is.
+    fn poll_aborted(&self, cx: &mut Context<'_>) -> bool {
+        self.waker.register(cx.waker());
+        self.aborted.load(Ordering::Acquire)
+    }
+}
+
+impl ActiveRequests {
+    /// Register a request, which is active until the returned guard is dropped.
+    pub(crate) fn register(self: &Arc<Self>, connection_id: u64, path: &str) -> RequestGuard {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let request = Arc::new(ActiveRequest {
+            connection_id,
+            path: path.to_owned(),
+            started: Instant::now(),
+            aborted: AtomicBool::new(false),
+            waker: AtomicWaker::new(),
+        });
+        self.requests.lock().unwrap().insert(id, request.clone());
+        RequestGuard {
+            registry: self.clone(),
+            id,
+            request,
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.requests.lock().unwrap().len()
+    }
+
+    /// Log the requests which are still active.
+    pub(crate) fn report(&self) {
+        let requests = self.requests.lock().unwrap();
+        tracing::info!(
+            requests = requests.len(),
+            "waiting for requests to complete"
+        );
+        for request in requests.values() {
+            tracing::debug!(
+                connection_id = request.connection_id,
+                path = %request.path,
+                elapsed = ?request.started.elapsed(),
+                "request still active"
+            );
+        }
+    }
+
+    /// Abort the active requests, which fail or have their response streams reset. Returns the
+    /// number of requests aborted.
+    pub(crate) fn abort(&self) -> usize {
+        let requests = self.requests.lock().unwrap();
+        let mut aborted = 0;
+        for request in requests.values() {
+            if !request.aborted.swap(true, Ordering::AcqRel) {
+                tracing::debug!(
+                    connection_id = request.connection_id,
+                    path = %request.path,
+                    "aborting request which did not complete before shutdown"
+                );
+                request.waker.wake();
+                aborted += 1;
+            }
+        }
+        aborted
+    }
+}
+
+/// Keeps a request registered in [`ActiveRequests`] until it and its response body are dropped.
+pub(crate) struct RequestGuard {
+    registry: Arc<ActiveRequests>,
+    id: u64,
+    request: Arc<ActiveRequest>,
+}
+
+impl Drop for RequestGuard {
+    fn drop(&mut self) {
+        self.registry.requests.lock().unwrap().remove(&self.id);
+    }
+}
+
+fn aborted() -> Status {
+    Status::unavailable("server is shutting down")
+}
+
+/// A response future which can be aborted, and whose response body can be aborted.
+#[pin_project]
+pub(crate) struct TrackedFuture<F> {
+    #[pin]
+    inner: F,
+    guard: Option<RequestGuard>,
+}
+
+impl<F> TrackedFuture<F> {
+    pub(crate) fn new(inner: F, guard: RequestGuard) -> Self {
+        TrackedFuture {
+            inner,
+            guard: Some(guard),
+        }
+    }
+}
+
+impl<F> Future for TrackedFuture<F>
+where
+    F: Future<Output = Result<Response<BoxHttpBody>, BoxError>>,
+{
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let guard = this.guard.as_ref().expect("polled after ready");
+        if guard.request.poll_aborted(cx) {
+            return Poll::Ready(Err(aborted().into()));
+        }
+
+        let response = futures_util::ready!(this.inner.poll(cx))?;
+        let guard = this.guard.take().expect("polled after ready");
+        Poll::Ready(Ok(
+            response.map(|body| TrackedBody { inner: body, guard }.boxed_unsync())
+        ))
+    }
+}
+
+/// A response body which fails when its request is aborted, which resets the stream.
+struct TrackedBody {
+    inner: BoxHttpBody,
+    guard: RequestGuard,
+}
+
+impl Body for TrackedBody {
+    type Data = Bytes;
+    type Error = BoxError;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        if self.guard.request.poll_aborted(cx) {
+            return Poll::Ready(Some(Err(aborted().into())));
+        }
+        Pin::new(&mut self.inner).poll_data(cx)
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        if self.guard.request.poll_aborted(cx) {
+            return Poll::Ready(Err(aborted().into()));
+        }
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> http_body::SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tracks_and_aborts_requests() {
+        let registry = Arc::new(ActiveRequests::default());
+        let first = registry.register(1, "/test.Service/First");
+        let second = registry.register(2, "/test.Service/Second");
+        assert_eq!(registry.len(), 2);
+
+        drop(first);
+        assert_eq!(registry.len(), 1);
+
+        assert_eq!(registry.abort(), 1);
+        assert!(second.request.aborted.load(Ordering::Acquire));
+        assert_eq!(registry.abort(), 0);
+    }
+}
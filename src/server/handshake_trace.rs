@@ -0,0 +1,78 @@
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Chooses one in every `one_in` accepted connections to trace.
+#[derive(Debug)]
+pub(crate) struct Sampler {
+    one_in: u32,
+    // The number of connections to skip before the next sampled connection.
+    skip: u32,
+}
+
+impl Sampler {
+    pub(crate) fn new(one_in: u32) -> Self {
+        Sampler { one_in, skip: 0 }
+    }
+
+    /// Start a trace if the connection which was just accepted is sampled.
+    pub(crate) fn sample(&mut self) -> Option<HandshakeTrace> {
+        if self.one_in == 0 {
+            return None;
+        }
+        if self.skip > 0 {
+            self.skip -= 1;
+            return None;
+        }
+        self.skip = self.one_in - 1;
+        Some(HandshakeTrace {
+            accepted: Instant::now(),
+            handshake: None,
+        })
+    }
+}
+
+/// The timings of a sampled connection, from being accepted to its first request.
+#[derive(Debug)]
+pub(crate) struct HandshakeTrace {
+    accepted: Instant,
+    // How long the TLS handshake took, once it has completed.
+    handshake: Option<Duration>,
+}
+
+impl HandshakeTrace {
+    pub(crate) fn handshake_done(&mut self) {
+        self.handshake = Some(self.accepted.elapsed());
+    }
+
+    pub(crate) fn handshake_failed(self, error: &dyn std::fmt::Display) {
+        tracing::info!(
+            elapsed = ?self.accepted.elapsed(),
+            %error,
+            "sampled connection failed its TLS handshake"
+        );
+    }
+
+    pub(crate) fn first_request(self, connection_id: u64) {
+        tracing::info!(
+            connection_id,
+            handshake = ?self.handshake,
+            first_request = ?self.accepted.elapsed(),
+            "sampled connection received its first request"
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn samples_one_in_n() {
+        let mut sampler = Sampler::new(3);
+        let sampled: Vec<_> = (0..7).map(|_| sampler.sample().is_some()).collect();
+        assert_eq!(sampled, [true, false, false, true, false, false, true]);
+
+        let mut sampler = Sampler::new(0);
+        assert!((0..3).all(|_| sampler.sample().is_none()));
+    }
+}
@@ -0,0 +1,78 @@
+#[cfg(feature = "x509")]
+use super::PeerCertificate;
+use super::{TcpConnectInfo, TlsConnectInfo};
+
+#[cfg(feature = "x509")]
+use std::sync::Arc;
+use std::{any::Any, fmt, net::IpAddr};
+#[cfg(unix)]
+use tokio::net::unix::UCred;
+
+/// Who is calling a [`Server`](crate::Server), so that authorization can be written once for all
+/// the ways clients connect.
+///
+/// This is inserted into the extensions of every request on a connection whose client could be
+/// identified. Clients are identified by, in order of preference:
+///
+/// * their TLS certificate, when the `x509` feature is enabled and the client sent one,
+/// * the credentials of their process, when serving on a [`UnixIncoming`](crate::UnixIncoming),
+/// * their IP address.
+///
+/// ```no_run
+/// # use tonic_transport::ClientIdentity;
+/// fn check(request: &tonic::Request<()>) -> Result<(), tonic::Status> {
+///     match request.extensions().get::<ClientIdentity>() {
+///         Some(ClientIdentity::Ip(ip)) if ip.is_loopback() => Ok(()),
+///         Some(identity) => Err(tonic::Status::permission_denied(format!(
+///             "{identity} may not call this service"
+///         ))),
+///         None => Err(tonic::Status::unauthenticated("unknown client")),
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ClientIdentity {
+    /// The client's TLS certificate.
+    #[cfg(feature = "x509")]
+    Certificate(Arc<PeerCertificate>),
+    /// The credentials of the client process, on a Unix domain socket.
+    #[cfg(unix)]
+    Unix(UCred),
+    /// The client's IP address.
+    Ip(IpAddr),
+}
+
+impl ClientIdentity {
+    /// Identify a client from its connection, without its certificate.
+    pub(crate) fn from_connect_info<T: 'static>(conn_info: &TlsConnectInfo<T>) -> Option<Self> {
+        let inner = conn_info.get_ref() as &dyn Any;
+        #[cfg(unix)]
+        if let Some(cred) = inner
+            .downcast_ref::<super::UdsConnectInfo>()
+            .and_then(super::UdsConnectInfo::peer_cred)
+        {
+            return Some(ClientIdentity::Unix(cred));
+        }
+        inner
+            .downcast_ref::<TcpConnectInfo>()
+            .and_then(TcpConnectInfo::remote_addr)
+            .map(|addr| ClientIdentity::Ip(addr.ip()))
+    }
+}
+
+/// Formats the identity for logs: a certificate's SPIFFE ID or subject, `uid=<uid>` for a Unix
+/// client, or an IP address.
+impl fmt::Display for ClientIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            #[cfg(feature = "x509")]
+            ClientIdentity::Certificate(cert) => {
+                f.write_str(cert.spiffe_id().unwrap_or_else(|| cert.subject()))
+            }
+            #[cfg(unix)]
+            ClientIdentity::Unix(cred) => write!(f, "uid={}", cred.uid()),
+            ClientIdentity::Ip(ip) => ip.fmt(f),
+        }
+    }
+}
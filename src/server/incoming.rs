@@ -1,5 +1,10 @@
+use crate::server::handshake_trace::{HandshakeTrace, Sampler};
 use crate::server::{Connected, Server};
-use crate::BoxError;
+use crate::service::{
+    backoff::{Backoff, ConnectBackoff},
+    ThrottledIo,
+};
+use crate::{BoxError, Error};
 
 use futures_core::Stream;
 use futures_util::stream::TryStreamExt;
@@ -8,22 +13,38 @@ use hyper::server::{
     conn::{AddrIncoming, AddrStream},
 };
 use std::{
+    io,
     net::SocketAddr,
     pin::Pin,
+    sync::{Arc, Mutex},
     task::{Context, Poll},
     time::Duration,
 };
 use tokio::io::{AsyncRead, AsyncWrite};
 use tokio_native_tls::TlsStream;
 
+/// A connection whose TLS handshake has completed, with its trace if it was sampled.
+type Accepted<IO> = (TlsStream<IO>, Option<HandshakeTrace>);
+
 pub(crate) fn tcp_incoming<IO, IE, L>(
     incoming: impl Stream<Item = Result<IO, IE>>,
     server: Server<L>,
-) -> impl Stream<Item = Result<TlsStream<IO>, BoxError>>
+) -> impl Stream<Item = Result<Accepted<ThrottledIo<IO>>, BoxError>>
 where
     IO: AsyncRead + AsyncWrite + Connected + Unpin + Send + 'static,
     IE: Into<BoxError>,
 {
+    let throttle = server.throttle.clone();
+    let abort_on_fatal = server.abort_on_fatal_accept_error;
+    let mut sampler = Sampler::new(server.trace_handshakes);
+    let mut backoff = Backoff::new(
+        ConnectBackoff::new()
+            .initial(Duration::from_millis(5))
+            .multiplier(2.0)
+            .max(Duration::from_secs(1)),
+    );
+    let incoming = incoming.map_ok(move |io| ThrottledIo::new(io, throttle.as_ref()));
+
     async_stream::try_stream! {
         futures_util::pin_mut!(incoming);
 
@@ -32,10 +53,25 @@ where
         loop {
             match select(&mut incoming, &mut tasks).await {
                 SelectOutput::Incoming(stream) => {
+                    backoff.reset();
                     let tls = server.tls.clone();
+                    let mut trace = sampler.sample();
 
                     let accept = tokio::spawn(async move {
-                        Ok(tls.accept(stream).await?)
+                        match tls.accept(stream).await {
+                            Ok(io) => {
+                                if let Some(trace) = &mut trace {
+                                    trace.handshake_done();
+                                }
+                                Ok((io, trace))
+                            }
+                            Err(e) => {
+                                if let Some(trace) = trace {
+                                    trace.handshake_failed(&e);
+                                }
+                                Err(e.into())
+                            }
+                        }
                     });
 
                     tasks.push(accept);
@@ -45,8 +81,28 @@ where
                     yield io;
                 }
 
-                SelectOutput::Err(e) => {
-                    tracing::debug!(message = "Accept loop error.", error = %e);
+                SelectOutput::Err(e) => match classify(&e) {
+                    AcceptError::Connection => {
+                        tracing::debug!(message = "Accept loop error.", error = %e);
+                    }
+                    AcceptError::Fatal if abort_on_fatal => {
+                        tracing::error!(message = "Fatal accept error, stopping.", error = %e);
+                        Err(e)?;
+                    }
+                    kind => {
+                        let delay = backoff.next_delay();
+                        if kind == AcceptError::Fatal {
+                            tracing::error!(message = "Fatal accept error.", error = %e, ?delay);
+                        } else {
+                            tracing::warn!(message = "Accept error, backing off.", error = %e, ?delay);
+                        }
+                        tokio::time::sleep(delay).await;
+                    }
+                },
+
+                SelectOutput::Handshake(e) => {
+                    tracing::debug!(message = "TLS handshake error.", error = %e);
+                    server.connection_hooks.handshake_error(&Error::from_source(e));
                 }
 
                 SelectOutput::Done => {
@@ -60,7 +116,7 @@ where
 async fn select<IO, IE>(
     incoming: &mut (impl Stream<Item = Result<IO, IE>> + Unpin),
     tasks: &mut futures_util::stream::futures_unordered::FuturesUnordered<
-        tokio::task::JoinHandle<Result<TlsStream<IO>, BoxError>>,
+        tokio::task::JoinHandle<Result<Accepted<IO>, BoxError>>,
     >,
 ) -> SelectOutput<IO>
 where
@@ -88,17 +144,49 @@ where
         accept = tasks.next() => {
             match accept.expect("FuturesUnordered stream should never end") {
                 Ok(Ok(io)) => SelectOutput::Io(io),
-                Ok(Err(e)) => SelectOutput::Err(e),
-                Err(e) => SelectOutput::Err(e.into()),
+                Ok(Err(e)) => SelectOutput::Handshake(e),
+                // The handshake task panicked, which doesn't affect the listener.
+                Err(e) => SelectOutput::Handshake(e.into()),
             }
         }
     }
 }
 
+/// The kinds of error from accepting connections.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AcceptError {
+    /// A connection failed before it was accepted; the listener is unaffected.
+    Connection,
+    /// The listener may recover, for example once file descriptors are closed.
+    Transient,
+    /// The listener can't accept any more connections.
+    Fatal,
+}
+
+fn classify(error: &BoxError) -> AcceptError {
+    let Some(error) = error.downcast_ref::<io::Error>() else {
+        return AcceptError::Transient;
+    };
+    match error.kind() {
+        io::ErrorKind::ConnectionRefused
+        | io::ErrorKind::ConnectionAborted
+        | io::ErrorKind::ConnectionReset
+        | io::ErrorKind::Interrupted
+        | io::ErrorKind::WouldBlock
+        | io::ErrorKind::TimedOut => AcceptError::Connection,
+        // For example, the socket isn't listening.
+        io::ErrorKind::InvalidInput | io::ErrorKind::Unsupported => AcceptError::Fatal,
+        // EBADF, the socket has been closed.
+        _ if cfg!(unix) && error.raw_os_error() == Some(9) => AcceptError::Fatal,
+        _ => AcceptError::Transient,
+    }
+}
+
 enum SelectOutput<A> {
     Incoming(A),
-    Io(TlsStream<A>),
+    Io(Accepted<A>),
     Err(BoxError),
+    Handshake(BoxError),
     Done,
 }
 
@@ -106,9 +194,56 @@ enum SelectOutput<A> {
 ///
 /// An incoming stream, usable with [Router::serve_with_incoming](super::Router::serve_with_incoming),
 /// of `AsyncRead + AsyncWrite` that communicate with clients that connect to a socket address.
+///
+/// # Handing off the listener
+///
+/// A new version of a server can take over from a running one without refusing connections:
+///
+/// * With `SO_REUSEPORT`, on Unix, both versions bind the address with
+///   [`bind_reuse_port`](TcpIncoming::bind_reuse_port), so the kernel distributes connections
+///   between them while both are serving.
+/// * Or the old version passes its listening socket to the new one, for example as an
+///   inherited file descriptor, using [`try_clone_listener`](TcpIncoming::try_clone_listener),
+///   and the new version serves it with [`from_std`](TcpIncoming::from_std).
+///
+/// Once the new version is serving, the old one completes the shutdown signal given to
+/// [`serve_with_incoming_shutdown`](super::Router::serve_with_incoming_shutdown). It stops
+/// accepting connections, sends GOAWAY so that clients move to the new version, and the
+/// returned future completes once its requests have drained, see
+/// [`Server::max_drain_duration`](super::Server::max_drain_duration).
 #[derive(Debug)]
 pub struct TcpIncoming {
     inner: AddrIncoming,
+    options: TcpOptions,
+    // A handle to the listening socket, which can be handed to another process.
+    listener: std::net::TcpListener,
+}
+
+/// A handle to change the socket options of a [`TcpIncoming`] while it is accepting
+/// connections, for example from an admin API, without rebinding its address.
+///
+/// Changes apply to connections accepted afterwards; existing connections keep their options.
+///
+/// ```no_run
+/// # use tonic_transport::TcpIncoming;
+/// # use std::time::Duration;
+/// # fn example() -> Result<(), tonic_transport::BoxError> {
+/// let incoming = TcpIncoming::new("[::1]:50051".parse().unwrap(), true, None)?;
+/// let options = incoming.options();
+/// // Serve `incoming`, then later:
+/// options.set_keepalive(Some(Duration::from_secs(60)));
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct TcpOptions {
+    inner: Arc<Mutex<Options>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Options {
+    nodelay: bool,
+    keepalive: Option<Duration>,
 }
 
 impl TcpIncoming {
@@ -148,10 +283,96 @@ impl TcpIncoming {
         nodelay: bool,
         keepalive: Option<Duration>,
     ) -> Result<Self, BoxError> {
-        let mut inner = AddrIncoming::bind(&addr)?;
+        TcpIncoming::from_std(std::net::TcpListener::bind(addr)?, nodelay, keepalive)
+    }
+
+    /// Creates an instance from a socket which is already listening, for example one inherited
+    /// from a previous version of the server.
+    ///
+    /// Must be called from within a Tokio runtime.
+    pub fn from_std(
+        listener: std::net::TcpListener,
+        nodelay: bool,
+        keepalive: Option<Duration>,
+    ) -> Result<Self, BoxError> {
+        listener.set_nonblocking(true)?;
+        let handle = listener.try_clone()?;
+        let mut inner = AddrIncoming::from_listener(tokio::net::TcpListener::from_std(listener)?)?;
         inner.set_nodelay(nodelay);
         inner.set_keepalive(keepalive);
-        Ok(TcpIncoming { inner })
+        // Return accept errors, rather than hyper logging them and sleeping, so that they are
+        // handled by `tcp_incoming`.
+        inner.set_sleep_on_errors(false);
+        let options = TcpOptions {
+            inner: Arc::new(Mutex::new(Options { nodelay, keepalive })),
+        };
+        Ok(TcpIncoming {
+            inner,
+            options,
+            listener: handle,
+        })
+    }
+
+    /// Binds `addr` with `SO_REUSEPORT`, so that other processes, such as the next version of
+    /// this server, can bind and serve the same address at the same time.
+    ///
+    /// Must be called from within a Tokio runtime.
+    #[cfg(unix)]
+    pub fn bind_reuse_port(
+        addr: SocketAddr,
+        nodelay: bool,
+        keepalive: Option<Duration>,
+    ) -> Result<Self, BoxError> {
+        let socket = match addr {
+            SocketAddr::V4(_) => tokio::net::TcpSocket::new_v4()?,
+            SocketAddr::V6(_) => tokio::net::TcpSocket::new_v6()?,
+        };
+        socket.set_reuseaddr(true)?;
+        socket.set_reuseport(true)?;
+        socket.bind(addr)?;
+        let listener = socket.listen(1024)?.into_std()?;
+        TcpIncoming::from_std(listener, nodelay, keepalive)
+    }
+
+    /// The address the socket is bound to.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.inner.local_addr()
+    }
+
+    /// A new handle to the listening socket, which can be passed to another process and served
+    /// there with [`from_std`](TcpIncoming::from_std).
+    ///
+    /// The socket stays open while any handle to it does, so it keeps accepting connections
+    /// after this instance is dropped.
+    pub fn try_clone_listener(&self) -> io::Result<std::net::TcpListener> {
+        self.listener.try_clone()
+    }
+
+    /// A handle to change the options of connections accepted from now on.
+    pub fn options(&self) -> TcpOptions {
+        self.options.clone()
+    }
+}
+
+impl TcpOptions {
+    /// Whether `TCP_NODELAY` is set on accepted connections.
+    pub fn nodelay(&self) -> bool {
+        self.inner.lock().unwrap().nodelay
+    }
+
+    /// Set whether to set `TCP_NODELAY` on accepted connections.
+    pub fn set_nodelay(&self, nodelay: bool) {
+        self.inner.lock().unwrap().nodelay = nodelay;
+    }
+
+    /// The TCP keepalive interval of accepted connections.
+    pub fn keepalive(&self) -> Option<Duration> {
+        self.inner.lock().unwrap().keepalive
+    }
+
+    /// Set the TCP keepalive interval of accepted connections, `None` disables keepalive.
+    pub fn set_keepalive(&self, keepalive: Option<Duration>) {
+        self.inner.lock().unwrap().keepalive = keepalive;
     }
 }
 
@@ -159,13 +380,36 @@ impl Stream for TcpIncoming {
     type Item = Result<AddrStream, std::io::Error>;
 
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let Options { nodelay, keepalive } = self.options.inner.lock().unwrap().clone();
+        self.inner.set_nodelay(nodelay);
+        self.inner.set_keepalive(keepalive);
         Pin::new(&mut self.inner).poll_accept(cx)
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use super::*;
     use crate::transport::server::TcpIncoming;
+
+    #[test]
+    fn classifies_accept_errors() {
+        let classify_io = |error: io::Error| classify(&error.into());
+        assert_eq!(
+            classify_io(io::ErrorKind::ConnectionAborted.into()),
+            AcceptError::Connection
+        );
+        assert_eq!(
+            classify_io(io::ErrorKind::InvalidInput.into()),
+            AcceptError::Fatal
+        );
+        assert_eq!(
+            classify_io(io::Error::from_raw_os_error(24)),
+            AcceptError::Transient
+        );
+        assert_eq!(classify(&"other".into()), AcceptError::Transient);
+    }
+
     #[tokio::test]
     async fn one_tcpincoming_at_a_time() {
         let addr = "127.0.0.1:1322".parse().unwrap();
@@ -175,4 +419,22 @@ mod tests {
         }
         let _t3 = TcpIncoming::new(addr, true, None).unwrap();
     }
+
+    #[tokio::test]
+    async fn hands_off_listener() {
+        let old = TcpIncoming::new("127.0.0.1:0".parse().unwrap(), true, None).unwrap();
+        let addr = old.local_addr();
+        let listener = old.try_clone_listener().unwrap();
+        drop(old);
+        let new = TcpIncoming::from_std(listener, true, None).unwrap();
+        assert_eq!(new.local_addr(), addr);
+
+        #[cfg(unix)]
+        {
+            let any = "127.0.0.1:0".parse().unwrap();
+            let first = TcpIncoming::bind_reuse_port(any, true, None).unwrap();
+            let second = TcpIncoming::bind_reuse_port(first.local_addr(), true, None);
+            assert!(second.is_ok());
+        }
+    }
 }
@@ -1,44 +1,95 @@
+pub use self::compression::NegotiatedEncoding;
 pub use self::conn::Connected;
-pub use self::incoming::TcpIncoming;
+pub use self::conn::{TcpConnectInfo, TlsConnectInfo};
+pub use self::connection::{
+    ConnectInfoFailure, ConnectionInfo, ConnectionStack, ConnectionStats, ReapedConnections,
+};
+pub use self::identity::ClientIdentity;
+pub use self::incoming::{TcpIncoming, TcpOptions};
+#[cfg(windows)]
+pub use self::named_pipe::NamedPipeConnectInfo;
+pub use self::peer_rate_limit::{PeerIdentity, PeerRateLimit};
+pub use self::profile::Profile;
+pub(crate) use self::propagate_deadline::inbound_deadline;
+pub use self::propagate_deadline::{PropagateDeadline, PropagateDeadlineLayer};
+pub use self::recover_error::{
+    code_from_h2_reason, H2Reason, MaybeEmptyBody, RecoverError, RecoverErrorLayer,
+};
+pub use self::require_grpc::NonGrpcResponse;
+pub(crate) use self::stream_timeout::StreamActivity;
+#[cfg(unix)]
+pub use self::unix::{UdsConnectInfo, UnixIncoming, UnixIncomingBuilder};
+#[cfg(feature = "x509")]
+pub use self::x509::PeerCertificate;
 pub use crate::service::Routes;
 
 use std::{
     convert::Infallible,
     fmt,
     future::Future,
-    marker::PhantomData,
     net::SocketAddr,
     pin::Pin,
-    sync::Arc,
+    sync::{Arc, Mutex},
     task::{Context, Poll},
     time::Duration,
 };
 
-use self::recover_error::RecoverError;
-use crate::service::GrpcTimeout;
-use crate::tls::TlsAcceptor;
+use self::compression::{Encodings, NegotiateEncoding};
+use self::connection::{
+    ConnectionHooks, ConnectionService, Liveness, RequestCount, ServeConnection,
+};
+use self::drain::ActiveRequests;
+use self::peer_rate_limit::PeerLimits;
+use self::require_grpc::RequireGrpc;
+use self::shed_deadline::{Arrival, QueueInCall, ShedDeadline};
+use self::slow_request::SlowRequests;
+use self::stream_timeout::StreamInactivityTimeout;
+use crate::service::{GrpcTimeout, PingIo, PingRtt, Throttle};
+use crate::tls::{ReloadableTls, TlsAcceptor};
 use crate::{BoxError, Error};
 use bytes::Bytes;
 use futures_core::Stream;
-use futures_util::{future, ready};
-use http::{Request, Response};
+use futures_util::{future, ready, TryStreamExt};
+use http::{HeaderName, HeaderValue, Request, Response};
 use http_body::Body as _;
-use hyper::{server::accept, Body};
+use hyper::{server::conn::Http, Body};
 use pin_project::pin_project;
-use tokio::io::{AsyncRead, AsyncWrite};
-use tokio_native_tls::TlsStream;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    sync::watch,
+};
 use tonic::body::BoxBody;
 use tonic::server::NamedService;
 use tower::{
     layer::util::{Identity, Stack},
-    layer::Layer,
+    layer::{layer_fn, Layer},
     limit::concurrency::ConcurrencyLimitLayer,
-    Service, ServiceBuilder,
+    Service, ServiceBuilder, ServiceExt,
 };
 
+mod compression;
 mod conn;
+mod connection;
+mod drain;
+mod handshake_trace;
+mod identity;
 mod incoming;
+#[cfg(windows)]
+mod named_pipe;
+mod peer_rate_limit;
+mod profile;
+mod propagate_deadline;
 mod recover_error;
+mod require_grpc;
+mod shed_deadline;
+mod slow_request;
+mod stream_timeout;
+#[cfg(unix)]
+mod unix;
+#[cfg(feature = "x509")]
+mod x509;
+#[cfg(feature = "x509")]
+use self::x509::SpiffeIdVerifier;
 
 type BoxHttpBody = http_body::combinators::UnsyncBoxBody<Bytes, BoxError>;
 type BoxService = tower::util::BoxService<Request<Body>, Response<BoxHttpBody>, BoxError>;
@@ -48,7 +99,7 @@ const DEFAULT_HTTP2_KEEPALIVE_TIMEOUT_SECS: u64 = 20;
 
 /// A default batteries included `transport` server.
 ///
-/// This is a wrapper around [`hyper::Server`] and provides an easy builder
+/// This is built on [`hyper`]'s HTTP/2 server connections and provides an easy builder
 /// pattern style builder [`Server`]. This builder exposes easy configuration parameters
 /// for providing a fully featured http2 based gRPC server. This should provide
 /// a very good out of the box http2 server for use with tonic but is also a
@@ -59,7 +110,15 @@ pub struct Server<L = Identity> {
     trace_interceptor: Option<TraceInterceptor>,
     concurrency_limit: Option<usize>,
     timeout: Option<Duration>,
+    max_deadline: Option<Duration>,
+    shed_deadline_margin: Option<Duration>,
+    stream_inactivity_timeout: Option<Duration>,
+    slow_request_threshold: Option<Duration>,
+    peer_rate_limit: Option<PeerRateLimit>,
+    non_grpc_response: NonGrpcResponse,
+    compression_encodings: Option<Encodings>,
     tls: TlsAcceptor,
+    throttle: Option<Throttle>,
     init_stream_window_size: Option<u32>,
     init_connection_window_size: Option<u32>,
     max_concurrent_streams: Option<u32>,
@@ -67,8 +126,20 @@ pub struct Server<L = Identity> {
     tcp_nodelay: bool,
     http2_keepalive_interval: Option<Duration>,
     http2_keepalive_timeout: Option<Duration>,
+    client_liveness: Option<(Duration, u32)>,
+    reaped_connections: ReapedConnections,
     http2_adaptive_window: Option<bool>,
     max_frame_size: Option<u32>,
+    http2_max_send_buf_size: Option<usize>,
+    max_requests_per_connection: Option<usize>,
+    max_drain_duration: Option<Duration>,
+    abort_on_fatal_accept_error: bool,
+    trace_handshakes: u32,
+    connection_hooks: ConnectionHooks,
+    connect_info_failure: ConnectInfoFailure,
+    require_peer_certificate: bool,
+    #[cfg(feature = "x509")]
+    verify_client_spiffe_id: Option<SpiffeIdVerifier>,
     service_builder: ServiceBuilder<L>,
 }
 
@@ -79,7 +150,22 @@ impl<L> fmt::Debug for Server<L> {
 }
 
 /// A stack based `Service` router.
-#[derive(Debug)]
+///
+/// A router can be built once and cloned to serve the same services from several listeners at
+/// once, such as a TCP port and a Unix domain socket:
+///
+/// ```no_run
+/// # use tonic_transport::{Router, UnixIncoming};
+/// # async fn example(router: Router) -> Result<(), tonic_transport::BoxError> {
+/// let uds = UnixIncoming::builder("/run/my-service/grpc.sock").bind()?;
+/// tokio::try_join!(
+///     router.clone().serve("[::]:50051".parse()?),
+///     router.serve_with_incoming(uds),
+/// )?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
 pub struct Router<L = Identity> {
     server: Server<L>,
     routes: Routes,
@@ -87,12 +173,43 @@ pub struct Router<L = Identity> {
 
 impl Server {
     /// Create a new server builder that can configure a [`Server`].
+    ///
+    /// `tls` must be configured to choose `h2` with ALPN. Connections for which it chooses
+    /// another protocol are closed with [`Error::H2NotAccepted`], and a warning is logged if a
+    /// client connects without a protocol being chosen.
     pub fn builder(tls: tokio_native_tls::TlsAcceptor) -> Self {
+        Server::builder_with_reloadable_tls(ReloadableTls::new(tls))
+    }
+
+    /// Create a new server builder with the settings of `profile`, which can be changed with
+    /// the builder's other methods.
+    ///
+    /// ```no_run
+    /// # use tonic_transport::{Profile, Server};
+    /// # fn example(tls: tokio_native_tls::TlsAcceptor) -> Server {
+    /// Server::builder_with_profile(tls, Profile::HighThroughput).concurrency_limit_per_connection(64)
+    /// # }
+    /// ```
+    pub fn builder_with_profile(tls: tokio_native_tls::TlsAcceptor, profile: Profile) -> Self {
+        profile.apply(Server::builder(tls))
+    }
+
+    /// Create a new server builder whose TLS configuration can be replaced while it is serving,
+    /// see [`ReloadableTls`].
+    pub fn builder_with_reloadable_tls(tls: ReloadableTls<tokio_native_tls::TlsAcceptor>) -> Self {
         Server {
             trace_interceptor: None,
             concurrency_limit: None,
             timeout: None,
-            tls: TlsAcceptor::new(Arc::new(tls)),
+            max_deadline: None,
+            shed_deadline_margin: None,
+            stream_inactivity_timeout: None,
+            slow_request_threshold: None,
+            peer_rate_limit: None,
+            non_grpc_response: NonGrpcResponse::default(),
+            compression_encodings: None,
+            tls: TlsAcceptor::new(tls),
+            throttle: None,
             init_stream_window_size: None,
             init_connection_window_size: None,
             max_concurrent_streams: None,
@@ -100,8 +217,20 @@ impl Server {
             tcp_nodelay: true,
             http2_keepalive_interval: None,
             http2_keepalive_timeout: None,
+            client_liveness: None,
+            reaped_connections: ReapedConnections::default(),
             http2_adaptive_window: None,
             max_frame_size: None,
+            http2_max_send_buf_size: None,
+            max_requests_per_connection: None,
+            max_drain_duration: None,
+            abort_on_fatal_accept_error: false,
+            trace_handshakes: 0,
+            connection_hooks: ConnectionHooks::default(),
+            connect_info_failure: ConnectInfoFailure::default(),
+            require_peer_certificate: false,
+            #[cfg(feature = "x509")]
+            verify_client_spiffe_id: None,
             service_builder: Default::default(),
         }
     }
@@ -145,6 +274,199 @@ impl<L> Server<L> {
         }
     }
 
+    /// Set the longest deadline accepted from clients.
+    ///
+    /// Requests whose `grpc-timeout` is longer than `max` are handled as if it were `max`, and
+    /// the header seen by the handler is reduced to `max`. Requests without a `grpc-timeout`
+    /// are not affected, use [`Server::timeout`] to limit those.
+    ///
+    /// Default is no limit (`None`).
+    #[must_use]
+    pub fn max_deadline(self, max: impl Into<Option<Duration>>) -> Self {
+        Server {
+            max_deadline: max.into(),
+            ..self
+        }
+    }
+
+    /// Reject requests whose deadline has expired, or will expire within `margin`.
+    ///
+    /// The deadline is read from the request's `grpc-timeout` header when the request is
+    /// dequeued, and is measured from when the request was received, so that time spent waiting
+    /// for [`concurrency_limit_per_connection`](Self::concurrency_limit_per_connection) counts
+    /// against it. Requests which can't complete in time fail with `DEADLINE_EXCEEDED` without
+    /// invoking the handler, which avoids wasting work when the server is overloaded.
+    ///
+    /// Default is to not reject requests (`None`).
+    #[must_use]
+    pub fn shed_expired_deadlines(self, margin: impl Into<Option<Duration>>) -> Self {
+        Server {
+            shed_deadline_margin: margin.into(),
+            ..self
+        }
+    }
+
+    /// Close connections with open streams where the client has neither sent request data nor
+    /// consumed response data for `timeout`, which frees the resources held by stalled clients.
+    ///
+    /// Activity is tracked for each connection, from the frames the client sends, so that a
+    /// client which has stopped reading is noticed even though responses are not polled while
+    /// its flow control window is full. Closing the connection resets its streams.
+    ///
+    /// Default is no timeout (`None`).
+    #[must_use]
+    pub fn stream_inactivity_timeout(self, timeout: impl Into<Option<Duration>>) -> Self {
+        Server {
+            stream_inactivity_timeout: timeout.into(),
+            ..self
+        }
+    }
+
+    /// Log requests which are still in flight after `threshold`, with their method, peer and
+    /// elapsed time, so that stuck handlers can be found while they are stuck.
+    ///
+    /// A request is in flight until its response has been sent, including the whole of a
+    /// streaming response, or until it is cancelled. Slow requests are logged as a warning once
+    /// they reach the threshold, and again when they finish.
+    ///
+    /// Default is not to log slow requests (`None`).
+    #[must_use]
+    pub fn slow_request_threshold(self, threshold: impl Into<Option<Duration>>) -> Self {
+        Server {
+            slow_request_threshold: threshold.into(),
+            ..self
+        }
+    }
+
+    /// Limit the rate of requests from each client.
+    ///
+    /// The limit is shared by all of a client's connections, so that one client can't starve
+    /// the others by opening more connections. Requests over the limit fail with
+    /// `RESOURCE_EXHAUSTED`, see [`PeerRateLimit`].
+    ///
+    /// Default is no limit (`None`).
+    #[must_use]
+    pub fn rate_limit_per_peer(self, limit: impl Into<Option<PeerRateLimit>>) -> Self {
+        Server {
+            peer_rate_limit: limit.into(),
+            ..self
+        }
+    }
+
+    /// Set how the server responds to requests which are not gRPC.
+    ///
+    /// Requests whose `content-type` is not `application/grpc` (or a subtype such as
+    /// `application/grpc+proto`) are not passed to the services. Default is to respond with
+    /// `415 Unsupported Media Type`.
+    #[must_use]
+    pub fn non_grpc_response(self, response: NonGrpcResponse) -> Self {
+        Server {
+            non_grpc_response: response,
+            ..self
+        }
+    }
+
+    /// Set the compression encodings the server supports, such as `gzip`, in order of preference.
+    ///
+    /// Responses advertise the encodings in `grpc-accept-encoding`, and requests compressed with
+    /// any other encoding are rejected with `UNIMPLEMENTED`. The first encoding which a request's
+    /// `grpc-accept-encoding` includes is added to its extensions as a [`NegotiatedEncoding`], for
+    /// the service to compress its response with. The services must still be able to compress
+    /// and decompress messages with the encodings, for example with tonic's `accept_compressed`
+    /// and `send_compressed`.
+    ///
+    /// ```no_run
+    /// # use tonic_transport::Server;
+    /// # fn example(server: Server) {
+    /// let server = server.compression_encodings(["gzip"]);
+    /// # }
+    /// ```
+    #[must_use]
+    pub fn compression_encodings<I>(self, encodings: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        Server {
+            compression_encodings: Some(Encodings::new(encodings.into_iter().map(Into::into))),
+            ..self
+        }
+    }
+
+    /// Close each connection after it has received `max` requests.
+    ///
+    /// Once a connection has received `max` requests, the server sends GOAWAY so that the client
+    /// opens a new connection, which may be to a different replica. Requests already in progress
+    /// are completed. This gradually rebalances long-lived clients across a fleet of servers.
+    ///
+    /// Default is no limit (`None`).
+    #[must_use]
+    pub fn max_requests_per_connection(self, max: impl Into<Option<usize>>) -> Self {
+        Server {
+            max_requests_per_connection: max.into(),
+            ..self
+        }
+    }
+
+    /// Limit how long graceful shutdown waits for requests in progress to complete.
+    ///
+    /// When the shutdown signal completes, the server stops accepting connections and sends
+    /// GOAWAY on each connection, then waits for the requests in progress. Requests which are
+    /// still in progress after `max` are aborted: those which have not responded fail with
+    /// `UNAVAILABLE`, and those which are streaming a response have their stream reset. Other
+    /// requests on the same connections are not affected.
+    ///
+    /// Default is to wait for all requests to complete (`None`).
+    #[must_use]
+    pub fn max_drain_duration(self, max: impl Into<Option<Duration>>) -> Self {
+        Server {
+            max_drain_duration: max.into(),
+            ..self
+        }
+    }
+
+    /// Stop serving, and return the error from `serve`, when accepting connections fails in a way
+    /// the listener can't recover from, for example because its socket was closed, so that a
+    /// supervisor can restart the process.
+    ///
+    /// Errors from accepting a single connection, such as a reset before it was accepted, are
+    /// ignored. Other errors, such as running out of file descriptors, pause accepting with a
+    /// backoff from 5 milliseconds up to 1 second. Default is to also back off after
+    /// unrecoverable errors and keep serving.
+    #[must_use]
+    pub fn abort_on_fatal_accept_error(self, abort: bool) -> Self {
+        Server {
+            abort_on_fatal_accept_error: abort,
+            ..self
+        }
+    }
+
+    /// Trace one in every `one_in` accepted connections, logging how long after being accepted
+    /// its TLS handshake completed and it received its first request, or that its handshake
+    /// failed.
+    ///
+    /// Sampling keeps the cost and volume of the events low enough to monitor handshake latency
+    /// on a busy server. The events are logged at `INFO` level. Default is `0`, which traces no
+    /// connections.
+    #[must_use]
+    pub fn trace_handshakes(self, one_in: u32) -> Self {
+        Server {
+            trace_handshakes: one_in,
+            ..self
+        }
+    }
+
+    /// Limit the throughput and add latency to accepted connections, to simulate a slow network.
+    ///
+    /// This is intended for testing, see [`Throttle`].
+    #[must_use]
+    pub fn throttle(self, throttle: Throttle) -> Self {
+        Server {
+            throttle: Some(throttle),
+            ..self
+        }
+    }
+
     /// Sets the [`SETTINGS_INITIAL_WINDOW_SIZE`][spec] option for HTTP2
     /// stream-level flow control.
     ///
@@ -216,6 +538,42 @@ impl<L> Server<L> {
         }
     }
 
+    /// Close connections whose client stops acknowledging keepalive PINGs, such as mobile
+    /// clients which disappeared without closing their connection.
+    ///
+    /// A PING is sent when nothing has been received from the client for `interval`. A client
+    /// misses a PING for each `interval` which passes without it acknowledging the outstanding
+    /// PING, and its connection is closed once it has missed `max_missed` in a row. The closed
+    /// connections are counted by [`reaped_connections`](Server::reaped_connections).
+    ///
+    /// This replaces [`http2_keepalive_interval`](Server::http2_keepalive_interval) and
+    /// [`http2_keepalive_timeout`](Server::http2_keepalive_timeout).
+    ///
+    /// ```no_run
+    /// # use std::time::Duration;
+    /// # use tonic_transport::Server;
+    /// # fn example(server: Server) {
+    /// let server = server.client_liveness(Duration::from_secs(30), 3);
+    /// let reaped = server.reaped_connections();
+    /// // Later, for metrics:
+    /// println!("reaped {} dead connections", reaped.get());
+    /// # }
+    /// ```
+    #[must_use]
+    pub fn client_liveness(self, interval: Duration, max_missed: u32) -> Self {
+        Server {
+            client_liveness: Some((interval, max_missed.max(1))),
+            ..self
+        }
+    }
+
+    /// A counter of the connections closed by [`client_liveness`](Server::client_liveness).
+    ///
+    /// The counter is shared by clones of the server, and keeps counting while it serves.
+    pub fn reaped_connections(&self) -> ReapedConnections {
+        self.reaped_connections.clone()
+    }
+
     /// Sets whether to use an adaptive flow control. Defaults to false.
     /// Enabling this will override the limits set in http2_initial_stream_window_size and
     /// http2_initial_connection_window_size.
@@ -265,6 +623,21 @@ impl<L> Server<L> {
         }
     }
 
+    /// Sets the maximum number of bytes buffered for sending on each HTTP/2 stream, before the
+    /// stream waits for its data to be written.
+    ///
+    /// A larger buffer can increase the throughput of streaming responses on links with a high
+    /// bandwidth-delay product. Values above `u32::MAX` are clamped.
+    ///
+    /// If not set, will default from underlying transport, currently 1 MiB.
+    #[must_use]
+    pub fn http2_max_send_buf_size(self, max: usize) -> Self {
+        Server {
+            http2_max_send_buf_size: Some(max),
+            ..self
+        }
+    }
+
     /// Intercept inbound headers and add a [`tracing::Span`] to each response future.
     #[must_use]
     pub fn trace_fn<F>(self, f: F) -> Self
@@ -277,6 +650,187 @@ impl<L> Server<L> {
         }
     }
 
+    /// Call `f` whenever a connection is established, after its TLS handshake.
+    ///
+    /// Together with [`on_connection_closed`](Server::on_connection_closed) this can be used to
+    /// keep track of the connected clients.
+    #[must_use]
+    pub fn on_connection_established<F>(self, f: F) -> Self
+    where
+        F: Fn(&ConnectionInfo) + Send + Sync + 'static,
+    {
+        Server {
+            connection_hooks: ConnectionHooks {
+                established: Some(Arc::new(f)),
+                ..self.connection_hooks
+            },
+            ..self
+        }
+    }
+
+    /// Call `f` whenever a connection is closed, with statistics about the connection.
+    #[must_use]
+    pub fn on_connection_closed<F>(self, f: F) -> Self
+    where
+        F: Fn(&ConnectionInfo, &ConnectionStats) + Send + Sync + 'static,
+    {
+        Server {
+            connection_hooks: ConnectionHooks {
+                closed: Some(Arc::new(f)),
+                ..self.connection_hooks
+            },
+            ..self
+        }
+    }
+
+    /// Call `f` whenever a connection fails during its handshake, either because the TLS
+    /// handshake fails or because the connection's info can't be read.
+    #[must_use]
+    pub fn on_handshake_error<F>(self, f: F) -> Self
+    where
+        F: Fn(&Error) + Send + Sync + 'static,
+    {
+        Server {
+            connection_hooks: ConnectionHooks {
+                handshake_error: Some(Arc::new(f)),
+                ..self.connection_hooks
+            },
+            ..self
+        }
+    }
+
+    /// Call `f` once for each connection, after its TLS handshake, to compute data which is
+    /// cloned into the extensions of every request on the connection.
+    ///
+    /// This avoids repeating work which only depends on the connection for every request, such
+    /// as authenticating the client's certificate. If `f` returns `None`, nothing is inserted.
+    /// Calling this again with another type adds to the data, calling it with the same type
+    /// replaces the value inserted by the earlier call.
+    ///
+    /// ```no_run
+    /// # use tonic_transport::{ConnectionInfo, Server};
+    /// #[derive(Clone)]
+    /// struct Tenant(String);
+    ///
+    /// // Looks up the tenant of the client's certificate.
+    /// # fn authenticate(info: &ConnectionInfo) -> Option<Tenant> { None }
+    /// # fn example(server: Server) -> Server {
+    /// server.connection_data(authenticate)
+    /// # }
+    ///
+    /// fn tenant(request: &tonic::Request<()>) -> Option<&Tenant> {
+    ///     request.extensions().get::<Tenant>()
+    /// }
+    /// ```
+    #[must_use]
+    pub fn connection_data<T, F>(self, f: F) -> Self
+    where
+        F: Fn(&ConnectionInfo) -> Option<T> + Send + Sync + 'static,
+        T: Clone + Send + Sync + 'static,
+    {
+        let mut connection_hooks = self.connection_hooks;
+        connection_hooks.add_data(f);
+        Server {
+            connection_hooks,
+            ..self
+        }
+    }
+
+    /// Call `f` once for each connection, after its TLS handshake, to wrap the services which
+    /// handle the connection's requests or to reject the connection.
+    ///
+    /// Layers added by `f` can keep state for the connection, such as a rate limit for the
+    /// tenant identified by the client's certificate, without looking it up for every request.
+    /// If `f` returns an error, the connection is closed and the error is passed to the
+    /// [`on_handshake_error`](Server::on_handshake_error) hook as
+    /// [`Error::ConnectionRejected`].
+    ///
+    /// ```no_run
+    /// # use tonic_transport::{ConnectionStack, Server};
+    /// # use tower::limit::ConcurrencyLimitLayer;
+    /// # fn example(server: Server) -> Server {
+    /// server.wrap_connection(|info, stack: ConnectionStack| {
+    ///     if info.peer_cert().is_none() {
+    ///         return Err("a client certificate is required".into());
+    ///     }
+    ///     Ok(stack.layer(ConcurrencyLimitLayer::new(8)))
+    /// })
+    /// # }
+    /// ```
+    #[must_use]
+    pub fn wrap_connection<F>(self, f: F) -> Self
+    where
+        F: Fn(&ConnectionInfo, ConnectionStack) -> Result<ConnectionStack, BoxError>
+            + Send
+            + Sync
+            + 'static,
+    {
+        Server {
+            connection_hooks: ConnectionHooks {
+                wrap: Some(Arc::new(f)),
+                ..self.connection_hooks
+            },
+            ..self
+        }
+    }
+
+    /// Set what to do with a connection whose info can't be read, for example because the
+    /// client's certificate can't be decoded.
+    ///
+    /// The error is passed to the [`on_handshake_error`](Server::on_handshake_error) hook in
+    /// either case. Default is to close the connection ([`ConnectInfoFailure::Reject`]).
+    #[must_use]
+    pub fn connect_info_failure(self, policy: ConnectInfoFailure) -> Self {
+        Server {
+            connect_info_failure: policy,
+            ..self
+        }
+    }
+
+    /// Close connections whose client doesn't present a certificate, or whose certificate can't
+    /// be read, for deployments where every client must be identified by mutual TLS.
+    ///
+    /// By default such connections are served without a peer certificate. When this is enabled,
+    /// they are closed regardless of [`connect_info_failure`](Server::connect_info_failure), and
+    /// [`Error::PeerCertificateUnavailable`] is passed to the
+    /// [`on_handshake_error`](Server::on_handshake_error) hook.
+    #[must_use]
+    pub fn require_peer_certificate(self, require: bool) -> Self {
+        Server {
+            require_peer_certificate: require,
+            ..self
+        }
+    }
+
+    /// Only accept connections from clients whose certificate has a SPIFFE ID for which `f`
+    /// returns `true`.
+    ///
+    /// Other connections are closed, and [`Error::SpiffeIdRejected`] is passed to the
+    /// [`on_handshake_error`](Server::on_handshake_error) hook. The acceptor should be built to
+    /// request client certificates, verified against the trust bundle. The SPIFFE ID of a
+    /// connection is also available to services from its [`PeerCertificate`].
+    ///
+    /// Requires the `x509` feature.
+    ///
+    /// ```no_run
+    /// # use tonic_transport::Server;
+    /// # fn example(acceptor: tokio_native_tls::TlsAcceptor) {
+    /// let server = Server::builder(acceptor)
+    ///     .verify_client_spiffe_id(|id| id.starts_with("spiffe://cluster.local/ns/frontend/"));
+    /// # }
+    /// ```
+    #[cfg(feature = "x509")]
+    #[must_use]
+    pub fn verify_client_spiffe_id<F>(self, f: F) -> Self
+    where
+        F: Fn(&str) -> bool + Send + Sync + 'static,
+    {
+        Server {
+            verify_client_spiffe_id: Some(Arc::new(f)),
+            ..self
+        }
+    }
+
     /// Create a router with the `S` typed service as the first service.
     ///
     /// This will clone the `Server` builder and create a router that will
@@ -316,6 +870,26 @@ impl<L> Server<L> {
         Router::new(self.clone(), routes)
     }
 
+    /// Create a router with the `S` typed service as the first service, handling only requests
+    /// for the virtual host `host`.
+    ///
+    /// See [`Router::add_service_for_host`].
+    pub fn add_service_for_host<S>(&mut self, host: &str, svc: S) -> Router<L>
+    where
+        S: Service<Request<Body>, Response = Response<BoxBody>, Error = Infallible>
+            + NamedService
+            + Clone
+            + Send
+            + 'static,
+        S::Future: Send + 'static,
+        L: Clone,
+    {
+        Router::new(
+            self.clone(),
+            Routes::empty().add_service_for_host(host, svc),
+        )
+    }
+
     /// Set the [Tower] [`Layer`] all services will be wrapped in.
     ///
     /// This enables using middleware from the [Tower ecosystem][eco].
@@ -383,7 +957,15 @@ impl<L> Server<L> {
             trace_interceptor: self.trace_interceptor,
             concurrency_limit: self.concurrency_limit,
             timeout: self.timeout,
+            max_deadline: self.max_deadline,
+            shed_deadline_margin: self.shed_deadline_margin,
+            stream_inactivity_timeout: self.stream_inactivity_timeout,
+            slow_request_threshold: self.slow_request_threshold,
+            peer_rate_limit: self.peer_rate_limit,
+            non_grpc_response: self.non_grpc_response,
+            compression_encodings: self.compression_encodings,
             tls: self.tls,
+            throttle: self.throttle,
             init_stream_window_size: self.init_stream_window_size,
             init_connection_window_size: self.init_connection_window_size,
             max_concurrent_streams: self.max_concurrent_streams,
@@ -391,8 +973,20 @@ impl<L> Server<L> {
             tcp_nodelay: self.tcp_nodelay,
             http2_keepalive_interval: self.http2_keepalive_interval,
             http2_keepalive_timeout: self.http2_keepalive_timeout,
+            client_liveness: self.client_liveness,
+            reaped_connections: self.reaped_connections,
             http2_adaptive_window: self.http2_adaptive_window,
             max_frame_size: self.max_frame_size,
+            http2_max_send_buf_size: self.http2_max_send_buf_size,
+            max_requests_per_connection: self.max_requests_per_connection,
+            max_drain_duration: self.max_drain_duration,
+            abort_on_fatal_accept_error: self.abort_on_fatal_accept_error,
+            trace_handshakes: self.trace_handshakes,
+            connection_hooks: self.connection_hooks,
+            connect_info_failure: self.connect_info_failure,
+            require_peer_certificate: self.require_peer_certificate,
+            #[cfg(feature = "x509")]
+            verify_client_spiffe_id: self.verify_client_spiffe_id,
         }
     }
 
@@ -421,29 +1015,62 @@ impl<L> Server<L> {
         let init_stream_window_size = self.init_stream_window_size;
         let max_concurrent_streams = self.max_concurrent_streams;
         let timeout = self.timeout;
+        let max_deadline = self.max_deadline;
+        let shed_deadline_margin = self.shed_deadline_margin;
+        let stream_inactivity_timeout = self.stream_inactivity_timeout;
+        let slow_request_threshold = self.slow_request_threshold;
+        let peer_limits = self.peer_rate_limit.clone().map(PeerLimits::new);
+        let non_grpc_response = self.non_grpc_response.clone();
+        let compression_encodings = self.compression_encodings.clone();
         let max_frame_size = self.max_frame_size;
+        let http2_max_send_buf_size = self.http2_max_send_buf_size;
 
-        let http2_keepalive_interval = self.http2_keepalive_interval;
-        let http2_keepalive_timeout = self
+        let mut http2_keepalive_interval = self.http2_keepalive_interval;
+        let mut http2_keepalive_timeout = self
             .http2_keepalive_timeout
             .unwrap_or_else(|| Duration::new(DEFAULT_HTTP2_KEEPALIVE_TIMEOUT_SECS, 0));
+        let liveness = self.client_liveness.map(|(interval, max_missed)| {
+            let max_unacked = interval * max_missed;
+            http2_keepalive_interval = Some(interval);
+            // Connections are closed by `Liveness`, which counts them; hyper's timeout is only a
+            // fallback.
+            http2_keepalive_timeout = max_unacked + interval;
+            Liveness {
+                check_interval: interval,
+                max_unacked,
+                reaped: self.reaped_connections.clone(),
+            }
+        });
         let http2_adaptive_window = self.http2_adaptive_window;
 
         let svc = self.service_builder.service(svc);
 
+        let max_requests_per_connection = self.max_requests_per_connection;
+        let max_drain_duration = self.max_drain_duration;
+        let active = Arc::new(ActiveRequests::default());
+        let connection_hooks = self.connection_hooks.clone();
+        let connect_info_failure = self.connect_info_failure;
+        let require_peer_certificate = self.require_peer_certificate;
+        #[cfg(feature = "x509")]
+        let verify_client_spiffe_id = self.verify_client_spiffe_id.clone();
+
         let tcp = incoming::tcp_incoming(incoming, self);
-        let incoming = accept::from_stream::<_, _, BoxError>(tcp);
 
         let svc = MakeSvc {
             inner: svc,
             concurrency_limit,
             timeout,
+            max_deadline,
+            shed_deadline_margin,
+            slow_request_threshold,
+            peer_limits,
+            non_grpc_response,
+            compression_encodings,
             trace_interceptor,
-            _io: PhantomData,
         };
 
-        let server = hyper::Server::builder(incoming)
-            .http2_only(true)
+        let mut http = Http::new();
+        http.http2_only(true)
             .http2_initial_connection_window_size(init_connection_window_size)
             .http2_initial_stream_window_size(init_stream_window_size)
             .http2_max_concurrent_streams(max_concurrent_streams)
@@ -451,19 +1078,150 @@ impl<L> Server<L> {
             .http2_keep_alive_timeout(http2_keepalive_timeout)
             .http2_adaptive_window(http2_adaptive_window.unwrap_or_default())
             .http2_max_frame_size(max_frame_size);
+        if let Some(max) = http2_max_send_buf_size {
+            http.http2_max_send_buf_size(max.min(u32::MAX as usize));
+        }
 
-        if let Some(signal) = signal {
-            server
-                .serve(svc)
-                .with_graceful_shutdown(signal)
-                .await
-                .map_err(|e| Error::from_source(Box::new(e)))?
-        } else {
-            server
-                .serve(svc)
+        // Connections are shut down gracefully when `signal` completes, and hold a receiver so
+        // that the server can wait for them to close.
+        let (shutdown_tx, shutdown_rx) = watch::channel(());
+        let signal = async move {
+            match signal {
+                Some(signal) => signal.await,
+                None => future::pending().await,
+            }
+        };
+        let mut tcp = Box::pin(tcp);
+        futures_util::pin_mut!(signal);
+        let mut warned_no_alpn = false;
+
+        loop {
+            let (io, trace) = tokio::select! {
+                io = tcp.try_next() => match io.map_err(Error::from_source)? {
+                    Some(io) => io,
+                    None => return Ok(()),
+                },
+                () = &mut signal => break,
+            };
+
+            match conn::accepted_h2(&io) {
+                Ok(true) => {}
+                Ok(false) if !warned_no_alpn => {
+                    warned_no_alpn = true;
+                    tracing::warn!(
+                        "a client connected without choosing a protocol with ALPN; unless the TLS \
+                         acceptor is configured to choose `h2`, clients which require it will \
+                         fail with `H2NotChosen`"
+                    );
+                }
+                Ok(false) => {}
+                Err(error) => {
+                    tracing::warn!(%error, "rejecting connection");
+                    connection_hooks.handshake_error(&error);
+                    continue;
+                }
+            }
+
+            if require_peer_certificate {
+                if let Err(error) = conn::require_peer_certificate(&io) {
+                    tracing::debug!(%error, "rejecting connection");
+                    connection_hooks.handshake_error(&error);
+                    continue;
+                }
+            }
+
+            let id = connection::next_connection_id();
+            let mut conn_info = match io.connect_info() {
+                Ok(conn_info) => Some(conn_info),
+                Err(error) => {
+                    tracing::debug!(%error, "failed to get connection info");
+                    connection_hooks.handshake_error(&error);
+                    match connect_info_failure {
+                        ConnectInfoFailure::Reject => continue,
+                        ConnectInfoFailure::Degrade => conn::connect_info_without_cert(&io).ok(),
+                    }
+                }
+            };
+            if let Some(conn_info) = &mut conn_info {
+                conn_info.set_connection_id(id);
+            }
+            #[cfg(feature = "x509")]
+            if let Some(verify) = &verify_client_spiffe_id {
+                if !x509::accepts_spiffe_id(conn_info.as_ref(), verify) {
+                    let error = Error::SpiffeIdRejected;
+                    tracing::debug!(%error, "rejecting connection");
+                    connection_hooks.handshake_error(&error);
+                    continue;
+                }
+            }
+            let ping_rtt = Arc::new(PingRtt::default());
+            let info = ConnectionInfo::new(id, conn_info.as_ref(), ping_rtt.clone());
+
+            let inner = match connection_hooks.wrap(&info, svc.make_service(conn_info)) {
+                Ok(inner) => inner,
+                Err(error) => {
+                    tracing::debug!(%error, "rejecting connection");
+                    connection_hooks.handshake_error(&error);
+                    continue;
+                }
+            };
+            let inner = match trace {
+                Some(trace) => {
+                    let trace = Mutex::new(Some(trace));
+                    BoxService::new(inner.map_request(move |request| {
+                        if let Some(trace) = trace.lock().unwrap().take() {
+                            trace.first_request(id);
+                        }
+                        request
+                    }))
+                }
+                None => inner,
+            };
+            let activity =
+                stream_inactivity_timeout.map(|timeout| Arc::new(StreamActivity::new(timeout)));
+            let inner = match &activity {
+                Some(activity) => {
+                    BoxService::new(StreamInactivityTimeout::new(inner, activity.clone()))
+                }
+                None => inner,
+            };
+            let requests = Arc::new(RequestCount::new(max_requests_per_connection));
+            let svc = ConnectionService::new(inner, id, requests.clone(), active.clone());
+            let io = PingIo::server(io, ping_rtt, activity.clone());
+            let conn = http.serve_connection(io, svc);
+            tokio::spawn(ServeConnection::new(
+                conn,
+                info,
+                requests,
+                &connection_hooks,
+                shutdown_rx.clone(),
+                liveness.clone(),
+                activity,
+            ));
+        }
+
+        // Close the listener before draining, so that new connections are refused, or go to
+        // another server sharing the port, rather than waiting in the backlog.
+        drop(tcp);
+        drop(shutdown_rx);
+        let _ = shutdown_tx.send(());
+        if active.len() > 0 {
+            active.report();
+        }
+
+        if let Some(max) = max_drain_duration {
+            if tokio::time::timeout(max, shutdown_tx.closed())
                 .await
-                .map_err(|e| Error::from_source(Box::new(e)))?;
+                .is_err()
+            {
+                let aborted = active.abort();
+                tracing::warn!(
+                    requests = aborted,
+                    "aborting requests which did not complete before the drain deadline"
+                );
+            }
         }
+        shutdown_tx.closed().await;
 
         Ok(())
     }
@@ -474,6 +1232,16 @@ impl<L> Router<L> {
         Self { server, routes }
     }
 
+    /// The names of the services added to this router, such as `helloworld.Greeter`.
+    pub fn services(&self) -> &[String] {
+        self.routes.services()
+    }
+
+    /// The routes of the services added to this router.
+    pub fn routes(&self) -> &Routes {
+        &self.routes
+    }
+
     /// Add a new service to this router.
     pub fn add_service<S>(mut self, svc: S) -> Self
     where
@@ -508,6 +1276,45 @@ impl<L> Router<L> {
         self
     }
 
+    /// Add a new service to this router, handling only requests for the virtual host `host`.
+    ///
+    /// The host is matched, ignoring case and port, against the request's `:authority`. Once a
+    /// service is added for a host, requests for that host are only routed to the services
+    /// added for it; requests for other hosts are routed to the services added with
+    /// [`add_service`](Router::add_service).
+    pub fn add_service_for_host<S>(mut self, host: &str, svc: S) -> Self
+    where
+        S: Service<Request<Body>, Response = Response<BoxBody>, Error = Infallible>
+            + NamedService
+            + Clone
+            + Send
+            + 'static,
+        S::Future: Send + 'static,
+    {
+        self.routes = self.routes.add_service_for_host(host, svc);
+        self
+    }
+
+    /// Add a new service to this router, handling only requests which have the header `name`
+    /// set to `value`.
+    ///
+    /// This allows several implementations of the same service to be served side by side, for
+    /// example to roll out a new version to clients which send `x-api-version: v2`. Requests for
+    /// the service without a matching header are routed to the service added with
+    /// [`add_service`](Router::add_service).
+    pub fn add_service_for_header<S>(mut self, name: HeaderName, value: HeaderValue, svc: S) -> Self
+    where
+        S: Service<Request<Body>, Response = Response<BoxBody>, Error = Infallible>
+            + NamedService
+            + Clone
+            + Send
+            + 'static,
+        S::Future: Send + 'static,
+    {
+        self.routes = self.routes.add_service_for_header(name, value, svc);
+        self
+    }
+
     /// Consume this [`Server`] creating a future that will execute the server
     /// on [tokio]'s default executor.
     ///
@@ -705,61 +1512,302 @@ impl<S> fmt::Debug for Svc<S> {
     }
 }
 
-struct MakeSvc<S, IO> {
+struct MakeSvc<S> {
     concurrency_limit: Option<usize>,
     timeout: Option<Duration>,
+    max_deadline: Option<Duration>,
+    shed_deadline_margin: Option<Duration>,
+    slow_request_threshold: Option<Duration>,
+    peer_limits: Option<PeerLimits>,
+    non_grpc_response: NonGrpcResponse,
+    compression_encodings: Option<Encodings>,
     inner: S,
     trace_interceptor: Option<TraceInterceptor>,
-    _io: PhantomData<fn() -> IO>,
 }
 
-impl<S, ResBody, IO> Service<&TlsStream<IO>> for MakeSvc<S, IO>
+impl<S, ResBody> MakeSvc<S>
 where
-    IO: Connected + AsyncRead + AsyncWrite + Unpin,
     S: Service<Request<Body>, Response = Response<ResBody>> + Clone + Send + 'static,
     S::Future: Send + 'static,
     S::Error: Into<BoxError> + Send,
     ResBody: http_body::Body<Data = Bytes> + Send + 'static,
     ResBody::Error: Into<BoxError>,
 {
-    type Response = BoxService;
-    type Error = BoxError;
-    type Future = future::Ready<Result<Self::Response, Self::Error>>;
-
-    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
-        Ok(()).into()
-    }
-
-    fn call(&mut self, io: &TlsStream<IO>) -> Self::Future {
-        let conn_info = match io.connect_info() {
-            Ok(i) => i,
-            Err(e) => return future::err(Box::new(e)),
-        };
-
+    /// Create the service for a connection.
+    fn make_service<T>(&self, conn_info: Option<TlsConnectInfo<T>>) -> BoxService
+    where
+        T: Clone + Send + Sync + 'static,
+    {
         let svc = self.inner.clone();
         let concurrency_limit = self.concurrency_limit;
         let timeout = self.timeout;
+        let max_deadline = self.max_deadline;
+        let shed_deadline_margin = self.shed_deadline_margin;
+        let slow_request_threshold = self.slow_request_threshold;
         let trace_interceptor = self.trace_interceptor.clone();
+        let negotiate_encoding = self
+            .compression_encodings
+            .clone()
+            .map(|encodings| layer_fn(move |s| NegotiateEncoding::new(s, encodings.clone())));
+        let require_grpc = match &self.non_grpc_response {
+            NonGrpcResponse::Allow => None,
+            response => Some(response.clone()),
+        };
+
+        #[cfg(feature = "x509")]
+        let peer_cert = conn_info
+            .as_ref()
+            .and_then(TlsConnectInfo::peer_cert)
+            .and_then(|cert| {
+                let parsed = PeerCertificate::parse(&cert);
+                if parsed.is_none() {
+                    tracing::debug!("failed to parse peer certificate");
+                }
+                parsed.map(Arc::new)
+            });
+
+        let client_identity = conn_info
+            .as_ref()
+            .and_then(ClientIdentity::from_connect_info);
+        #[cfg(feature = "x509")]
+        let client_identity = peer_cert
+            .clone()
+            .map(ClientIdentity::Certificate)
+            .or(client_identity);
+
+        let peer_limits = self.peer_limits.as_ref().map(|limits| {
+            let conn_info = conn_info.as_ref();
+            layer_fn(move |s| limits.service(s, conn_info))
+        });
 
         let svc = ServiceBuilder::new()
             .layer_fn(RecoverError::new)
+            .option_layer(negotiate_encoding)
+            .option_layer(peer_limits)
+            .option_layer(
+                concurrency_limit
+                    .and(shed_deadline_margin)
+                    .map(|_| layer_fn(QueueInCall::new)),
+            )
             .option_layer(concurrency_limit.map(ConcurrencyLimitLayer::new))
-            .layer_fn(|s| GrpcTimeout::new(s, timeout))
+            .option_layer(
+                shed_deadline_margin.map(|margin| layer_fn(move |s| ShedDeadline::new(s, margin))),
+            )
+            .layer_fn(|s| GrpcTimeout::new(s, timeout).with_max_timeout(max_deadline))
             .service(svc);
 
         let svc = ServiceBuilder::new()
             .layer(BoxService::layer())
+            .option_layer(
+                require_grpc
+                    .map(|response| layer_fn(move |s| RequireGrpc::new(s, response.clone()))),
+            )
             .map_request(move |mut request: Request<Body>| {
-                request.extensions_mut().insert(conn_info.clone());
-                request.extensions_mut().insert(conn_info.get_ref().clone());
+                if shed_deadline_margin.is_some() {
+                    let arrival = Arrival(tokio::time::Instant::now());
+                    request.extensions_mut().insert(arrival);
+                }
+                if let Some(conn_info) = &conn_info {
+                    request.extensions_mut().insert(conn_info.clone());
+                    request.extensions_mut().insert(conn_info.get_ref().clone());
+                }
+                #[cfg(feature = "x509")]
+                if let Some(peer_cert) = &peer_cert {
+                    request.extensions_mut().insert(peer_cert.clone());
+                }
+                if let Some(client_identity) = &client_identity {
+                    request.extensions_mut().insert(client_identity.clone());
+                }
 
                 request
             })
+            .option_layer(
+                slow_request_threshold
+                    .map(|threshold| layer_fn(move |s| SlowRequests::new(s, threshold))),
+            )
             .service(Svc {
                 inner: svc,
                 trace_interceptor,
             });
 
-        future::ok(svc)
+        svc
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::{oneshot, Notify};
+
+    const CA: &[u8] = include_bytes!(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/examples/certs/ca.crt"
+    ));
+    const CERT: &[u8] = include_bytes!(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/examples/certs/server.crt"
+    ));
+    const KEY: &[u8] = include_bytes!(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/examples/certs/server.key"
+    ));
+
+    /// A service whose requests wait to be released.
+    #[derive(Clone)]
+    struct Held {
+        started: Arc<Notify>,
+        release: Arc<Notify>,
+    }
+
+    impl NamedService for Held {
+        const NAME: &'static str = "test.Held";
+    }
+
+    impl Service<Request<Body>> for Held {
+        type Response = Response<BoxBody>;
+        type Error = Infallible;
+        type Future = crate::BoxFuture<Self::Response, Self::Error>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, _: Request<Body>) -> Self::Future {
+            let Held { started, release } = self.clone();
+            Box::pin(async move {
+                started.notify_one();
+                release.notified().await;
+                Ok(Response::new(tonic::body::empty_body()))
+            })
+        }
+    }
+
+    /// Serve `held` with `server`, returning the server's task, a sender for its shutdown signal,
+    /// and a request in flight.
+    async fn serve_held(
+        mut server: Server,
+        held: Held,
+    ) -> (
+        tokio::task::JoinHandle<Result<(), Error>>,
+        oneshot::Sender<()>,
+        tokio::task::JoinHandle<hyper::Result<Response<Body>>>,
+    ) {
+        let incoming = TcpIncoming::new(([127, 0, 0, 1], 0).into(), true, None).unwrap();
+        let addr = incoming.local_addr();
+        let (signal, shutdown) = oneshot::channel();
+        let started = held.started.clone();
+        let router = server.add_service(held);
+        let serve = tokio::spawn(router.serve_with_incoming_shutdown(incoming, async {
+            let _ = shutdown.await;
+        }));
+
+        let tls = native_tls::TlsConnector::builder()
+            .add_root_certificate(native_tls::Certificate::from_pem(CA).unwrap())
+            .build()
+            .unwrap();
+        let tcp = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let io = tokio_native_tls::TlsConnector::from(tls)
+            .connect("localhost", tcp)
+            .await
+            .unwrap();
+        let (mut send, conn) = hyper::client::conn::Builder::new()
+            .http2_only(true)
+            .handshake::<_, Body>(io)
+            .await
+            .unwrap();
+        tokio::spawn(conn);
+        let request = Request::post("https://localhost/test.Held/Call")
+            .header("content-type", "application/grpc")
+            .body(Body::empty())
+            .unwrap();
+        let response = tokio::spawn(send.send_request(request));
+        started.notified().await;
+        (serve, signal, response)
+    }
+
+    fn server() -> Server {
+        let identity = native_tls::Identity::from_pkcs8(CERT, KEY).unwrap();
+        let acceptor = native_tls::TlsAcceptor::new(identity).unwrap();
+        Server::builder(acceptor.into())
+    }
+
+    #[tokio::test]
+    async fn stops_on_fatal_accept_error() {
+        // A socket which is bound but not listening, so accepting connections fails.
+        let socket =
+            socket2::Socket::new(socket2::Domain::IPV4, socket2::Type::STREAM, None).unwrap();
+        socket
+            .bind(&SocketAddr::from(([127, 0, 0, 1], 0)).into())
+            .unwrap();
+        let incoming = TcpIncoming::from_std(socket.into(), true, None).unwrap();
+
+        let held = Held {
+            started: Arc::new(Notify::new()),
+            release: Arc::new(Notify::new()),
+        };
+        let mut server = server().abort_on_fatal_accept_error(true);
+        let serve = server.add_service(held).serve_with_incoming(incoming);
+        let result = tokio::time::timeout(Duration::from_secs(5), serve)
+            .await
+            .expect("the accept error should stop the server");
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn shutdown_waits_for_requests_in_flight() {
+        let held = Held {
+            started: Arc::new(Notify::new()),
+            release: Arc::new(Notify::new()),
+        };
+        let release = held.release.clone();
+        let (mut serve, signal, response) = serve_held(server(), held).await;
+
+        signal.send(()).unwrap();
+        assert!(tokio::time::timeout(Duration::from_millis(200), &mut serve)
+            .await
+            .is_err());
+
+        release.notify_one();
+        let response = response.await.unwrap().unwrap();
+        assert_eq!(response.status(), http::StatusCode::OK);
+        serve.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn shutdown_aborts_requests_after_max_drain_duration() {
+        let held = Held {
+            started: Arc::new(Notify::new()),
+            release: Arc::new(Notify::new()),
+        };
+        let server = server().max_drain_duration(Duration::from_millis(100));
+        let (serve, signal, response) = serve_held(server, held).await;
+
+        signal.send(()).unwrap();
+        tokio::time::timeout(Duration::from_secs(5), serve)
+            .await
+            .expect("shutdown didn't complete after the drain duration")
+            .unwrap()
+            .unwrap();
+        let response = tokio::time::timeout(Duration::from_secs(5), response)
+            .await
+            .expect("the request wasn't aborted");
+        if let Ok(response) = response.unwrap() {
+            assert_eq!(response.headers()["grpc-status"], "14");
+        }
+    }
+
+    #[tokio::test]
+    async fn closes_connections_with_inactive_streams() {
+        let held = Held {
+            started: Arc::new(Notify::new()),
+            release: Arc::new(Notify::new()),
+        };
+        let server = server().stream_inactivity_timeout(Duration::from_millis(100));
+        let (_serve, _signal, response) = serve_held(server, held).await;
+
+        let response = tokio::time::timeout(Duration::from_secs(5), response)
+            .await
+            .expect("the inactive stream wasn't closed");
+        assert!(response.unwrap().is_err());
     }
 }
@@ -0,0 +1,47 @@
+use super::Connected;
+use crate::Result;
+
+use tokio::net::windows::named_pipe::{NamedPipeClient, NamedPipeServer, PipeInfo};
+
+/// Connection info for a Windows named pipe.
+///
+/// See [`Connected`] for more details.
+#[derive(Debug, Clone)]
+pub struct NamedPipeConnectInfo {
+    info: Option<PipeInfo>,
+    pub(super) connection_id: u64,
+}
+
+impl NamedPipeConnectInfo {
+    /// The id of the connection, see [`ConnectionInfo::id`](crate::ConnectionInfo::id).
+    pub fn connection_id(&self) -> u64 {
+        self.connection_id
+    }
+
+    /// The pipe's mode, end and buffer sizes.
+    pub fn pipe_info(&self) -> Option<&PipeInfo> {
+        self.info.as_ref()
+    }
+}
+
+impl Connected for NamedPipeClient {
+    type ConnectInfo = NamedPipeConnectInfo;
+
+    fn connect_info(&self) -> Result<Self::ConnectInfo> {
+        Ok(NamedPipeConnectInfo {
+            info: self.info().ok(),
+            connection_id: 0,
+        })
+    }
+}
+
+impl Connected for NamedPipeServer {
+    type ConnectInfo = NamedPipeConnectInfo;
+
+    fn connect_info(&self) -> Result<Self::ConnectInfo> {
+        Ok(NamedPipeConnectInfo {
+            info: self.info().ok(),
+            connection_id: 0,
+        })
+    }
+}
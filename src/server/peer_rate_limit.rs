@@ -0,0 +1,291 @@
+use super::{TcpConnectInfo, TlsConnectInfo};
+use crate::{tls::Certificate, BoxError, OptionPin, OptionPinProj};
+
+use http::Request;
+use pin_project::pin_project;
+use std::{
+    any::Any,
+    collections::HashMap,
+    future::Future,
+    net::IpAddr,
+    pin::Pin,
+    sync::{Arc, Mutex, Weak},
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::time::Instant;
+use tonic::Status;
+use tower::Service;
+
+// The most peers whose limits are tracked. When a new peer arrives after this is reached, an
+// arbitrary peer is forgotten, so that many clients can't exhaust the server's memory.
+const MAX_PEERS: usize = 65536;
+// The shortest interval between forgetting the peers whose limits have fully recovered.
+const MIN_PRUNE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How a [`PeerRateLimit`] identifies clients.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PeerIdentity {
+    /// Limit each client IP address separately.
+    #[default]
+    Ip,
+    /// Limit each client TLS certificate separately, falling back to the IP address for clients
+    /// which don't send a certificate.
+    ///
+    /// Certificates are compared byte for byte, so a client which presents a renewed certificate
+    /// gets a new limit.
+    Certificate,
+}
+
+/// A limit on the rate of requests from each client of a [`Server`](crate::Server).
+///
+/// Each client may make `requests` requests per `per`, with bursts of up to `requests` requests.
+/// Requests over the limit fail with `RESOURCE_EXHAUSTED` without being passed to the services.
+/// Clients which can't be identified, for example because the server is not listening on TCP,
+/// are not limited.
+///
+/// See [`Server::rate_limit_per_peer`](crate::Server::rate_limit_per_peer).
+///
+/// ```no_run
+/// # use tonic_transport::{PeerIdentity, PeerRateLimit};
+/// # use std::time::Duration;
+/// let limit = PeerRateLimit::new(100, Duration::from_secs(1)).identity(PeerIdentity::Certificate);
+/// ```
+#[derive(Debug, Clone)]
+pub struct PeerRateLimit {
+    requests: u64,
+    per: Duration,
+    identity: PeerIdentity,
+}
+
+impl PeerRateLimit {
+    fn capacity(&self) -> f64 {
+        self.requests as f64
+    }
+
+    /// The number of requests a client's limit recovers per second.
+    fn rate(&self) -> f64 {
+        self.capacity() / self.per.as_secs_f64().max(f64::MIN_POSITIVE)
+    }
+
+    /// Allow each client `requests` requests per `per`.
+    pub fn new(requests: u64, per: Duration) -> Self {
+        PeerRateLimit {
+            requests: requests.max(1),
+            per,
+            identity: PeerIdentity::default(),
+        }
+    }
+
+    /// Set how clients are identified. Default is [`PeerIdentity::Ip`].
+    pub fn identity(self, identity: PeerIdentity) -> Self {
+        PeerRateLimit { identity, ..self }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum PeerId {
+    Ip(IpAddr),
+    Certificate(Vec<u8>),
+}
+
+type Buckets = Mutex<HashMap<PeerId, Bucket>>;
+
+/// The state of a [`PeerRateLimit`], shared by all of a server's connections.
+#[derive(Debug, Clone)]
+pub(crate) struct PeerLimits {
+    limit: PeerRateLimit,
+    buckets: Arc<Buckets>,
+    max_peers: usize,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self, limit: &PeerRateLimit, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit.rate()).min(limit.capacity());
+        self.updated = now;
+    }
+}
+
+impl PeerLimits {
+    /// Must be called within a Tokio runtime, which prunes the limits in the background.
+    pub(crate) fn new(limit: PeerRateLimit) -> Self {
+        let buckets = Arc::default();
+        tokio::spawn(prune(Arc::downgrade(&buckets), limit.clone()));
+        PeerLimits {
+            limit,
+            buckets,
+            max_peers: MAX_PEERS,
+        }
+    }
+
+    fn peer_id<T: 'static>(&self, conn_info: Option<&TlsConnectInfo<T>>) -> Option<PeerId> {
+        let conn_info = conn_info?;
+        if self.limit.identity == PeerIdentity::Certificate {
+            if let Some(cert) = conn_info.peer_cert() {
+                let (Certificate::Pem(bytes) | Certificate::Der(bytes)) = &*cert;
+                return Some(PeerId::Certificate(bytes.clone()));
+            }
+        }
+        (conn_info.get_ref() as &dyn Any)
+            .downcast_ref::<TcpConnectInfo>()
+            .and_then(TcpConnectInfo::remote_addr)
+            .map(|addr| PeerId::Ip(addr.ip()))
+    }
+
+    /// Take a request from `peer`'s limit, returning `false` if it has none left.
+    fn acquire(&self, peer: &PeerId) -> bool {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= self.max_peers && !buckets.contains_key(peer) {
+            if let Some(evicted) = buckets.keys().next().cloned() {
+                buckets.remove(&evicted);
+            }
+        }
+        let bucket = buckets.entry(peer.clone()).or_insert(Bucket {
+            tokens: self.limit.capacity(),
+            updated: now,
+        });
+        bucket.refill(&self.limit, now);
+        if bucket.tokens < 1.0 {
+            return false;
+        }
+        bucket.tokens -= 1.0;
+        true
+    }
+
+    /// Wrap the service for a connection with the limit for its peer.
+    pub(crate) fn service<S, T: 'static>(
+        &self,
+        inner: S,
+        conn_info: Option<&TlsConnectInfo<T>>,
+    ) -> LimitPeer<S> {
+        LimitPeer {
+            inner,
+            limits: self.clone(),
+            peer: self.peer_id(conn_info),
+        }
+    }
+}
+
+/// Periodically forget the peers whose limits have fully recovered, until the limits are
+/// dropped with the server.
+async fn prune(buckets: Weak<Buckets>, limit: PeerRateLimit) {
+    let mut interval = tokio::time::interval(limit.per.max(MIN_PRUNE_INTERVAL));
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        let Some(buckets) = buckets.upgrade() else {
+            return;
+        };
+        let now = Instant::now();
+        buckets.lock().unwrap().retain(|_, bucket| {
+            bucket.refill(&limit, now);
+            bucket.tokens < limit.capacity()
+        });
+    }
+}
+
+/// Middleware which applies a [`PeerRateLimit`] to the requests on a connection.
+#[derive(Debug, Clone)]
+pub(crate) struct LimitPeer<S> {
+    inner: S,
+    limits: PeerLimits,
+    peer: Option<PeerId>,
+}
+
+impl<S, ReqBody> Service<Request<ReqBody>> for LimitPeer<S>
+where
+    S: Service<Request<ReqBody>>,
+    S::Error: Into<BoxError>,
+{
+    type Response = S::Response;
+    type Error = BoxError;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        match &self.peer {
+            Some(peer) if !self.limits.acquire(peer) => {
+                tracing::debug!(?peer, path = %req.uri().path(), "peer rate limit exceeded");
+                ResponseFuture {
+                    inner: OptionPin::None,
+                }
+            }
+            _ => ResponseFuture {
+                inner: OptionPin::Some(self.inner.call(req)),
+            },
+        }
+    }
+}
+
+#[pin_project]
+pub(crate) struct ResponseFuture<F> {
+    #[pin]
+    inner: OptionPin<F>,
+}
+
+impl<F, Res, E> Future for ResponseFuture<F>
+where
+    F: Future<Output = Result<Res, E>>,
+    E: Into<BoxError>,
+{
+    type Output = Result<Res, BoxError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match self.project().inner.project() {
+            OptionPinProj::Some(inner) => inner.poll(cx).map_err(Into::into),
+            OptionPinProj::None => Poll::Ready(Err(Status::resource_exhausted(
+                "too many requests from this client",
+            )
+            .into())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn limits_each_peer() {
+        let limits = PeerLimits::new(PeerRateLimit::new(2, Duration::from_secs(1)));
+        let a = PeerId::Ip([10, 0, 0, 1].into());
+        let b = PeerId::Ip([10, 0, 0, 2].into());
+
+        assert!(limits.acquire(&a));
+        assert!(limits.acquire(&a));
+        assert!(!limits.acquire(&a));
+        assert!(limits.acquire(&b));
+
+        tokio::time::advance(Duration::from_millis(500)).await;
+        assert!(limits.acquire(&a));
+        assert!(!limits.acquire(&a));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn forgets_peers() {
+        let mut limits = PeerLimits::new(PeerRateLimit::new(2, Duration::from_secs(1)));
+        limits.max_peers = 2;
+        let peers: Vec<_> = (1..=3).map(|i| PeerId::Ip([10, 0, 0, i].into())).collect();
+
+        for peer in &peers {
+            assert!(limits.acquire(peer));
+        }
+        assert_eq!(limits.buckets.lock().unwrap().len(), 2);
+
+        // Peers are forgotten in the background once their limits recover.
+        assert!(limits.acquire(&peers[2]));
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        assert!(limits.buckets.lock().unwrap().is_empty());
+    }
+}
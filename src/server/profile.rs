@@ -0,0 +1,67 @@
+use super::Server;
+
+use std::time::Duration;
+
+/// A coherent set of connection settings for a common kind of deployment, see
+/// [`Server::builder_with_profile`].
+///
+/// A profile only sets a starting point: each setting can still be changed with the server's
+/// other methods, which override the profile.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Profile {
+    /// For unary calls which should complete as quickly as possible.
+    ///
+    /// Flow control windows adapt to the connection so that responses are never held back, and
+    /// little data is buffered per stream so that a stream can't delay the others. Keepalive
+    /// PINGs every 20 seconds find dead connections quickly.
+    LowLatency,
+    /// For large messages and long streams.
+    ///
+    /// Windows of 4 MiB per stream and 16 MiB per connection, 1 MiB frames and 4 MiB send
+    /// buffers keep links with a high bandwidth-delay product full. Keepalive PINGs are sent
+    /// every 60 seconds.
+    HighThroughput,
+    /// For servers with little memory, or many mostly idle clients.
+    ///
+    /// Windows of 64 KiB per stream and 256 KiB per connection, 64 KiB send buffers, and at
+    /// most 32 concurrent streams and 16 requests in progress per connection bound the memory
+    /// used by each client. Connections of clients which stop acknowledging keepalive PINGs for
+    /// three minutes are closed, see [`Server::client_liveness`].
+    ResourceConstrained,
+}
+
+impl Profile {
+    pub(crate) fn apply<L>(self, server: Server<L>) -> Server<L> {
+        const KIB: u32 = 1024;
+        const MIB: u32 = 1024 * KIB;
+
+        match self {
+            Profile::LowLatency => server
+                .tcp_nodelay(true)
+                .http2_adaptive_window(Some(true))
+                .http2_max_send_buf_size(256 * KIB as usize)
+                .http2_keepalive_interval(Some(Duration::from_secs(20)))
+                .http2_keepalive_timeout(Some(Duration::from_secs(10)))
+                .tcp_keepalive(Some(Duration::from_secs(60))),
+            Profile::HighThroughput => server
+                .tcp_nodelay(true)
+                .initial_stream_window_size(4 * MIB)
+                .initial_connection_window_size(16 * MIB)
+                .max_frame_size(MIB)
+                .http2_max_send_buf_size(4 * MIB as usize)
+                .http2_keepalive_interval(Some(Duration::from_secs(60)))
+                .http2_keepalive_timeout(Some(Duration::from_secs(20)))
+                .tcp_keepalive(Some(Duration::from_secs(60))),
+            Profile::ResourceConstrained => server
+                .tcp_nodelay(true)
+                .initial_stream_window_size(64 * KIB)
+                .initial_connection_window_size(256 * KIB)
+                .max_concurrent_streams(32)
+                .concurrency_limit_per_connection(16)
+                .http2_max_send_buf_size(64 * KIB as usize)
+                .client_liveness(Duration::from_secs(60), 3)
+                .tcp_keepalive(Some(Duration::from_secs(60))),
+        }
+    }
+}
@@ -0,0 +1,139 @@
+use crate::service::Deadline;
+
+use http::Request;
+use pin_project::pin_project;
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::time::Instant;
+use tower::{Layer, Service};
+
+tokio::task_local! {
+    // The deadline of the request being handled, while its handler is polled.
+    static INBOUND_DEADLINE: Instant;
+}
+
+/// The deadline of the request whose handler is running, if it was wrapped by
+/// [`PropagateDeadline`].
+pub(crate) fn inbound_deadline() -> Option<Instant> {
+    INBOUND_DEADLINE.try_with(|deadline| *deadline).ok()
+}
+
+/// A layer which propagates the deadlines of requests to the calls their handlers make, see
+/// [`PropagateDeadline`].
+///
+/// This is useful for servers which call other servers to handle requests, so that those calls
+/// give up once the client has stopped waiting.
+///
+/// ```no_run
+/// # use tonic_transport::{PropagateDeadlineLayer, Server};
+/// # fn example(acceptor: tokio_native_tls::TlsAcceptor) {
+/// Server::builder(acceptor).layer(PropagateDeadlineLayer::new());
+/// # }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct PropagateDeadlineLayer {
+    _priv: (),
+}
+
+impl PropagateDeadlineLayer {
+    /// Create a layer which propagates deadlines.
+    pub fn new() -> Self {
+        PropagateDeadlineLayer::default()
+    }
+}
+
+impl<S> Layer<S> for PropagateDeadlineLayer {
+    type Service = PropagateDeadline<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        PropagateDeadline::new(inner)
+    }
+}
+
+/// Middleware that limits the calls made while handling a request to the request's deadline.
+///
+/// The deadline is the shorter of the request's `grpc-timeout` and the server's
+/// [`timeout`](crate::Server::timeout). While the inner service's response future is polled, any
+/// call on a [`Channel`](crate::Channel) is sent with a `grpc-timeout` of at most the time left
+/// before that deadline, and fails with `DEADLINE_EXCEEDED` if it has already passed.
+///
+/// Calls made from other tasks, such as ones spawned by the handler, or while a streaming
+/// response's body is produced, are not limited.
+#[derive(Debug, Clone)]
+pub struct PropagateDeadline<S> {
+    inner: S,
+}
+
+impl<S> PropagateDeadline<S> {
+    /// Wrap `inner`, propagating the deadlines of its requests.
+    pub fn new(inner: S) -> Self {
+        Self { inner }
+    }
+}
+
+impl<S, ReqBody> Service<Request<ReqBody>> for PropagateDeadline<S>
+where
+    S: Service<Request<ReqBody>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        let deadline = req
+            .extensions()
+            .get::<Deadline>()
+            .map(|&Deadline(deadline)| deadline);
+        ResponseFuture {
+            inner: self.inner.call(req),
+            deadline,
+        }
+    }
+}
+
+/// Response future for [`PropagateDeadline`].
+#[pin_project]
+pub struct ResponseFuture<F> {
+    #[pin]
+    inner: F,
+    deadline: Option<Instant>,
+}
+
+impl<F: Future> Future for ResponseFuture<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        match *this.deadline {
+            Some(deadline) => INBOUND_DEADLINE.sync_scope(deadline, || this.inner.poll(cx)),
+            None => this.inner.poll(cx),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn deadline_is_visible_to_handler() {
+        let mut svc = PropagateDeadline::new(tower::service_fn(|_: Request<()>| async {
+            Ok::<_, std::convert::Infallible>(inbound_deadline())
+        }));
+
+        let deadline = Instant::now() + Duration::from_secs(1);
+        let mut request = Request::new(());
+        request.extensions_mut().insert(Deadline(deadline));
+        assert_eq!(svc.call(request).await.unwrap(), Some(deadline));
+        assert_eq!(svc.call(Request::new(())).await.unwrap(), None);
+        assert_eq!(inbound_deadline(), None);
+    }
+}
@@ -1,26 +1,73 @@
 use crate::{BoxError, OptionPin, OptionPinProj};
 
+use bytes::Bytes;
 use futures_util::ready;
 use http::Response;
 use pin_project::pin_project;
 use std::{
+    error::Error as StdError,
     future::Future,
     pin::Pin,
     sync::Arc,
     task::{Context, Poll},
 };
-use tonic::{Code, Status};
-use tower::Service;
+use tonic::{
+    metadata::{MetadataMap, MetadataValue},
+    Code, Status,
+};
+use tower::{Layer, Service};
+
+/// A layer which recovers from a service's errors by turning them into gRPC responses, see
+/// [`RecoverError`].
+///
+/// The [`Server`](crate::Server) adds this to the stack of every connection; it is useful when
+/// serving a [`Router`](crate::Router) with a custom stack.
+///
+/// ```no_run
+/// # use tonic_transport::{RecoverErrorLayer, Router};
+/// # use tower::ServiceBuilder;
+/// # fn example(router: Router) {
+/// let service = ServiceBuilder::new()
+///     .layer(RecoverErrorLayer::new())
+///     .service(router.into_service());
+/// # }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct RecoverErrorLayer {
+    _priv: (),
+}
+
+impl RecoverErrorLayer {
+    /// Create a layer which recovers from errors.
+    pub fn new() -> Self {
+        RecoverErrorLayer::default()
+    }
+}
+
+impl<S> Layer<S> for RecoverErrorLayer {
+    type Service = RecoverError<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RecoverError::new(inner)
+    }
+}
 
 /// Middleware that attempts to recover from service errors by turning them into a response built
 /// from the `Status`.
+///
+/// An error which is, or was caused by, a [`Status`] becomes a trailers-only response with that
+/// status, including its details and metadata. An error caused by an [`h2::Error`], such as a
+/// reset stream, becomes a response whose code is chosen from its reason by
+/// [`code_from_h2_reason`], and whose reason is sent as an [`H2Reason`]. Other errors are
+/// returned as they are.
 #[derive(Debug, Clone)]
-pub(crate) struct RecoverError<S> {
+pub struct RecoverError<S> {
     inner: S,
 }
 
 impl<S> RecoverError<S> {
-    pub(crate) fn new(inner: S) -> Self {
+    /// Wrap `inner`, recovering from its errors.
+    pub fn new(inner: S) -> Self {
         Self { inner }
     }
 }
@@ -45,8 +92,9 @@ where
     }
 }
 
+/// Response future for [`RecoverError`].
 #[pin_project]
-pub(crate) struct ResponseFuture<F> {
+pub struct ResponseFuture<F> {
     #[pin]
     inner: F,
 }
@@ -71,6 +119,9 @@ where
                 Ok(status) => {
                     let mut res = Response::new(MaybeEmptyBody::empty());
                     status.add_header(res.headers_mut()).unwrap();
+                    if let Some(reason) = H2Reason::from_status(&status) {
+                        res.extensions_mut().insert(reason);
+                    }
                     Poll::Ready(Ok(res))
                 }
                 Err(err) => Poll::Ready(Err(err)),
@@ -84,36 +135,103 @@ fn try_status_from_error(err: BoxError) -> Result<Status, BoxError> {
 
     let err = match err.downcast::<h2::Error>() {
         Ok(h2) => {
-            // See https://github.com/grpc/grpc/blob/3977c30/doc/PROTOCOL-HTTP2.md#errors
-            let code = match h2.reason() {
-                Some(h2::Reason::NO_ERROR)
-                | Some(h2::Reason::PROTOCOL_ERROR)
-                | Some(h2::Reason::INTERNAL_ERROR)
-                | Some(h2::Reason::FLOW_CONTROL_ERROR)
-                | Some(h2::Reason::SETTINGS_TIMEOUT)
-                | Some(h2::Reason::COMPRESSION_ERROR)
-                | Some(h2::Reason::CONNECT_ERROR) => Code::Internal,
-                Some(h2::Reason::REFUSED_STREAM) => Code::Unavailable,
-                Some(h2::Reason::CANCEL) => Code::Cancelled,
-                Some(h2::Reason::ENHANCE_YOUR_CALM) => Code::ResourceExhausted,
-                Some(h2::Reason::INADEQUATE_SECURITY) => Code::PermissionDenied,
-
-                _ => Code::Unknown,
-            };
-
-            let mut status = Status::new(code, format!("h2 protocol error: {}", h2));
+            let mut status = status_from_h2(&h2);
             status.set_source(Arc::new(*h2));
-
             return Ok(status);
         }
         Err(err) => err,
     };
 
+    let err = match err.downcast::<Status>() {
+        Ok(status) => return Ok(*status),
+        Err(err) => err,
+    };
+
+    // Middleware often wraps a service's `Status` in its own error. Copy the status from the
+    // source chain with its details, so that `grpc-status-details-bin` is still sent.
+    if let Some(status) = find_source::<Status>(&*err) {
+        let mut status = Status::with_details_and_metadata(
+            status.code(),
+            status.message(),
+            Bytes::copy_from_slice(status.details()),
+            status.metadata().clone(),
+        );
+        status.set_source(Arc::from(err));
+        return Ok(status);
+    }
+
+    // For example, a `hyper::Error` reading the request body of a reset stream.
+    if let Some(h2) = find_source::<h2::Error>(&*err) {
+        let mut status = status_from_h2(h2);
+        status.set_source(Arc::from(err));
+        return Ok(status);
+    }
+
     Status::try_from_error(err)
 }
 
+fn status_from_h2(h2: &h2::Error) -> Status {
+    let code = h2.reason().map_or(Code::Unknown, code_from_h2_reason);
+    let mut metadata = MetadataMap::new();
+    if let Some(reason) = h2.reason() {
+        metadata.insert(H2_REASON, MetadataValue::from(u32::from(reason)));
+    }
+    Status::with_metadata(code, format!("h2 protocol error: {}", h2), metadata)
+}
+
+const H2_REASON: &str = "h2-reason";
+
+/// The HTTP/2 reason a call failed with, for example because its stream was reset, so that
+/// applications can tell a server shedding load (`ENHANCE_YOUR_CALM`) from a cancelled call
+/// (`CANCEL`), which have similar status codes.
+///
+/// [`RecoverError`] sends the reason in the `h2-reason` metadata of the status, as a decimal
+/// error code, and adds it to the extensions of the response. Clients read it from the status
+/// with [`from_status`](H2Reason::from_status).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct H2Reason(h2::Reason);
+
+impl H2Reason {
+    /// The reason.
+    pub fn reason(&self) -> h2::Reason {
+        self.0
+    }
+
+    /// The reason sent with `status`, if any.
+    pub fn from_status(status: &Status) -> Option<H2Reason> {
+        let value = status.metadata().get(H2_REASON)?.to_str().ok()?;
+        value.parse::<u32>().ok().map(|code| H2Reason(code.into()))
+    }
+}
+
+/// The first `T` in the source chain of `err`.
+fn find_source<'a, T: StdError + 'static>(err: &'a (dyn StdError + 'static)) -> Option<&'a T> {
+    std::iter::successors(Some(err), |&err| err.source()).find_map(|err| err.downcast_ref::<T>())
+}
+
+/// The gRPC status code for a stream or connection which was reset with `reason`, as specified
+/// by [gRPC over HTTP/2](https://github.com/grpc/grpc/blob/3977c30/doc/PROTOCOL-HTTP2.md#errors).
+pub fn code_from_h2_reason(reason: h2::Reason) -> Code {
+    match reason {
+        h2::Reason::NO_ERROR
+        | h2::Reason::PROTOCOL_ERROR
+        | h2::Reason::INTERNAL_ERROR
+        | h2::Reason::FLOW_CONTROL_ERROR
+        | h2::Reason::SETTINGS_TIMEOUT
+        | h2::Reason::COMPRESSION_ERROR
+        | h2::Reason::CONNECT_ERROR => Code::Internal,
+        h2::Reason::REFUSED_STREAM => Code::Unavailable,
+        h2::Reason::CANCEL => Code::Cancelled,
+        h2::Reason::ENHANCE_YOUR_CALM => Code::ResourceExhausted,
+        h2::Reason::INADEQUATE_SECURITY => Code::PermissionDenied,
+        _ => Code::Unknown,
+    }
+}
+
+/// The body of a response from [`RecoverError`], which is empty if the response was made from
+/// an error.
 #[pin_project]
-pub(crate) struct MaybeEmptyBody<B> {
+pub struct MaybeEmptyBody<B> {
     #[pin]
     inner: OptionPin<B>,
 }
@@ -166,3 +284,86 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recovers_status_from_h2_errors() {
+        let err: BoxError = Box::new(h2::Error::from(h2::Reason::REFUSED_STREAM));
+        let status = try_status_from_error(err).unwrap();
+        assert_eq!(status.code(), Code::Unavailable);
+        assert_eq!(
+            H2Reason::from_status(&status).map(|r| r.reason()),
+            Some(h2::Reason::REFUSED_STREAM)
+        );
+
+        let err: BoxError = Box::new(Status::not_found("missing"));
+        assert_eq!(try_status_from_error(err).unwrap().code(), Code::NotFound);
+
+        let err: BoxError = "other".into();
+        assert!(try_status_from_error(err).is_err());
+    }
+
+    #[derive(Debug)]
+    struct Wrapped(Status);
+
+    impl std::fmt::Display for Wrapped {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "middleware failed")
+        }
+    }
+
+    impl StdError for Wrapped {
+        fn source(&self) -> Option<&(dyn StdError + 'static)> {
+            Some(&self.0)
+        }
+    }
+
+    #[derive(Debug)]
+    struct Reset(h2::Error);
+
+    impl std::fmt::Display for Reset {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "stream reset")
+        }
+    }
+
+    impl StdError for Reset {
+        fn source(&self) -> Option<&(dyn StdError + 'static)> {
+            Some(&self.0)
+        }
+    }
+
+    #[tokio::test]
+    async fn sends_h2_reason() {
+        let service = tower::service_fn(|_: ()| async {
+            let reset = Reset(h2::Error::from(h2::Reason::ENHANCE_YOUR_CALM));
+            Err::<Response<()>, _>(reset)
+        });
+
+        let response = RecoverError::new(service).call(()).await.unwrap();
+        assert_eq!(response.headers()["grpc-status"], "8");
+        assert_eq!(response.headers()["h2-reason"], "11");
+        assert_eq!(
+            response.extensions().get::<H2Reason>().unwrap().reason(),
+            h2::Reason::ENHANCE_YOUR_CALM
+        );
+    }
+
+    #[tokio::test]
+    async fn preserves_status_details() {
+        let service = tower::service_fn(|_: ()| async {
+            let status = Status::with_details(Code::Aborted, "conflict", Bytes::from("details"));
+            Err::<Response<()>, _>(Wrapped(status))
+        });
+
+        let response = RecoverError::new(service).call(()).await.unwrap();
+        let headers = response.headers();
+        assert_eq!(headers["grpc-status"], "10");
+        assert_eq!(headers["grpc-message"], "conflict");
+        // The details are base64 encoded without padding.
+        assert_eq!(headers["grpc-status-details-bin"], "ZGV0YWlscw");
+    }
+}
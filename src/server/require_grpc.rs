@@ -0,0 +1,164 @@
+use super::BoxHttpBody;
+use crate::BoxError;
+
+use bytes::Bytes;
+use http::{header, HeaderValue, Request, Response, StatusCode, Uri};
+use http_body::{Body as _, Empty, Full};
+use pin_project::pin_project;
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tower::Service;
+
+/// How a [`Server`](crate::Server) responds to requests which are not gRPC, that is, requests
+/// whose `content-type` is not `application/grpc` or one of its subtypes.
+///
+/// See [`Server::non_grpc_response`](crate::Server::non_grpc_response).
+#[derive(Debug, Clone, Default)]
+pub enum NonGrpcResponse {
+    /// Respond with `415 Unsupported Media Type`.
+    #[default]
+    UnsupportedMediaType,
+    /// Respond with `415 Unsupported Media Type` and an HTML page, for example to explain what
+    /// the server is to someone who opens it in a browser.
+    InfoPage(Bytes),
+    /// Redirect to a URI with `307 Temporary Redirect`.
+    Redirect(Uri),
+    /// Pass requests to the services as if they were gRPC requests.
+    Allow,
+}
+
+impl NonGrpcResponse {
+    fn to_response(&self) -> Response<BoxHttpBody> {
+        let empty = || Empty::new().map_err(|e| match e {}).boxed_unsync();
+        match self {
+            NonGrpcResponse::UnsupportedMediaType | NonGrpcResponse::Allow => {
+                let mut response = Response::new(empty());
+                *response.status_mut() = StatusCode::UNSUPPORTED_MEDIA_TYPE;
+                response
+            }
+            NonGrpcResponse::InfoPage(page) => {
+                let body = Full::new(page.clone()).map_err(|e| match e {});
+                let mut response = Response::new(body.boxed_unsync());
+                *response.status_mut() = StatusCode::UNSUPPORTED_MEDIA_TYPE;
+                response.headers_mut().insert(
+                    header::CONTENT_TYPE,
+                    HeaderValue::from_static("text/html; charset=utf-8"),
+                );
+                response
+            }
+            NonGrpcResponse::Redirect(location) => {
+                let mut response = Response::new(empty());
+                *response.status_mut() = StatusCode::TEMPORARY_REDIRECT;
+                if let Ok(location) = HeaderValue::try_from(location.to_string()) {
+                    response.headers_mut().insert(header::LOCATION, location);
+                }
+                response
+            }
+        }
+    }
+}
+
+/// Middleware that responds to requests which are not gRPC with a [`NonGrpcResponse`], instead
+/// of passing them to the services.
+#[derive(Debug, Clone)]
+pub(crate) struct RequireGrpc<S> {
+    inner: S,
+    response: NonGrpcResponse,
+}
+
+impl<S> RequireGrpc<S> {
+    pub(crate) fn new(inner: S, response: NonGrpcResponse) -> Self {
+        Self { inner, response }
+    }
+}
+
+impl<S, ReqBody> Service<Request<ReqBody>> for RequireGrpc<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<BoxHttpBody>, Error = BoxError>,
+{
+    type Response = S::Response;
+    type Error = BoxError;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        if is_grpc(&req) {
+            return ResponseFuture::Inner(self.inner.call(req));
+        }
+
+        tracing::debug!(
+            path = %req.uri().path(),
+            content_type = ?req.headers().get(header::CONTENT_TYPE),
+            "rejecting request which is not gRPC"
+        );
+        ResponseFuture::Rejected(Some(self.response.to_response()))
+    }
+}
+
+fn is_grpc<B>(req: &Request<B>) -> bool {
+    const GRPC: &[u8] = b"application/grpc";
+    let Some(content_type) = req.headers().get(header::CONTENT_TYPE) else {
+        return false;
+    };
+    let content_type = content_type.as_bytes();
+    if content_type.len() < GRPC.len() || !content_type[..GRPC.len()].eq_ignore_ascii_case(GRPC) {
+        return false;
+    }
+    // Allow a subtype such as `+proto` or parameters, but not another type such as grpc-web.
+    matches!(content_type.get(GRPC.len()), None | Some(b'+' | b';'))
+}
+
+#[pin_project(project = ResponseFutureProj)]
+pub(crate) enum ResponseFuture<F> {
+    Inner(#[pin] F),
+    Rejected(Option<Response<BoxHttpBody>>),
+}
+
+impl<F> Future for ResponseFuture<F>
+where
+    F: Future<Output = Result<Response<BoxHttpBody>, BoxError>>,
+{
+    type Output = Result<Response<BoxHttpBody>, BoxError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match self.project() {
+            ResponseFutureProj::Inner(inner) => inner.poll(cx),
+            ResponseFutureProj::Rejected(response) => {
+                Poll::Ready(Ok(response.take().expect("polled after ready")))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(content_type: Option<&'static str>) -> Request<()> {
+        let mut builder = Request::builder();
+        if let Some(content_type) = content_type {
+            builder = builder.header(header::CONTENT_TYPE, content_type);
+        }
+        builder.body(()).unwrap()
+    }
+
+    #[test]
+    fn detects_grpc_content_types() {
+        assert!(is_grpc(&request(Some("application/grpc"))));
+        assert!(is_grpc(&request(Some("application/grpc+proto"))));
+        assert!(is_grpc(&request(Some("Application/GRPC"))));
+        assert!(is_grpc(&request(Some("application/grpc;charset=utf-8"))));
+        assert!(!is_grpc(&request(Some("application/grpc-web"))));
+        assert!(!is_grpc(&request(Some("application/grpc-web+proto"))));
+        assert!(!is_grpc(&request(Some("application/grpcx"))));
+        assert!(!is_grpc(&request(Some("application/json"))));
+        assert!(!is_grpc(&request(Some("text/html"))));
+        assert!(!is_grpc(&request(None)));
+    }
+}
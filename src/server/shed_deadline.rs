@@ -0,0 +1,174 @@
+use crate::service::grpc_timeout::try_parse_grpc_timeout;
+use crate::BoxError;
+
+use http::Request;
+use pin_project::pin_project;
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::time::Instant;
+use tonic::Status;
+use tower::{util::Oneshot, Service, ServiceExt};
+
+/// A request extension recording when the server received the request, before it was queued.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Arrival(pub(crate) Instant);
+
+/// Middleware that rejects requests whose `grpc-timeout` deadline has expired, or will expire
+/// within `margin`, with `DEADLINE_EXCEEDED` instead of calling the inner service.
+///
+/// The deadline is measured from the request's [`Arrival`], so that the time it spent queued
+/// counts against it.
+#[derive(Debug, Clone)]
+pub(crate) struct ShedDeadline<S> {
+    inner: S,
+    margin: Duration,
+}
+
+impl<S> ShedDeadline<S> {
+    pub(crate) fn new(inner: S, margin: Duration) -> Self {
+        Self { inner, margin }
+    }
+}
+
+impl<S, ReqBody> Service<Request<ReqBody>> for ShedDeadline<S>
+where
+    S: Service<Request<ReqBody>>,
+    S::Error: Into<BoxError>,
+{
+    type Response = S::Response;
+    type Error = BoxError;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        let queued = req
+            .extensions()
+            .get::<Arrival>()
+            .map_or(Duration::ZERO, |arrival| arrival.0.elapsed());
+        match try_parse_grpc_timeout(req.headers()) {
+            Ok(Some(timeout)) if timeout <= self.margin + queued => {
+                tracing::debug!(?timeout, "shedding request whose deadline has expired");
+                ResponseFuture::Shed(Some(Status::deadline_exceeded(
+                    "deadline expired before the request was handled",
+                )))
+            }
+            _ => ResponseFuture::Inner(self.inner.call(req)),
+        }
+    }
+}
+
+/// Middleware which waits for the inner service to be ready in the response future, rather than
+/// in `poll_ready`.
+///
+/// Requests waiting for a concurrency limit are then accepted first, so that they have an
+/// [`Arrival`] and the time they wait is measured.
+#[derive(Debug, Clone)]
+pub(crate) struct QueueInCall<S> {
+    inner: S,
+}
+
+impl<S> QueueInCall<S> {
+    pub(crate) fn new(inner: S) -> Self {
+        Self { inner }
+    }
+}
+
+impl<S, Req> Service<Req> for QueueInCall<S>
+where
+    S: Service<Req> + Clone,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Oneshot<S, Req>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Req) -> Self::Future {
+        self.inner.clone().oneshot(req)
+    }
+}
+
+#[pin_project(project = ResponseFutureProj)]
+pub(crate) enum ResponseFuture<F> {
+    Inner(#[pin] F),
+    Shed(Option<Status>),
+}
+
+impl<F, Res, E> Future for ResponseFuture<F>
+where
+    F: Future<Output = Result<Res, E>>,
+    E: Into<BoxError>,
+{
+    type Output = Result<Res, BoxError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match self.project() {
+            ResponseFutureProj::Inner(inner) => inner.poll(cx).map_err(Into::into),
+            ResponseFutureProj::Shed(status) => {
+                let status = status.take().expect("polled after ready");
+                Poll::Ready(Err(status.into()))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::grpc_timeout::{encode_grpc_timeout, GRPC_TIMEOUT_HEADER};
+    use std::{convert::Infallible, sync::Arc};
+    use tokio::sync::Semaphore;
+    use tower::limit::ConcurrencyLimit;
+
+    fn request() -> Request<()> {
+        let mut request = Request::new(());
+        request.headers_mut().insert(
+            GRPC_TIMEOUT_HEADER,
+            encode_grpc_timeout(Duration::from_secs(5)),
+        );
+        request.extensions_mut().insert(Arrival(Instant::now()));
+        request
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn counts_time_spent_queued() {
+        // Requests are handled once permits are released.
+        let released = Arc::new(Semaphore::new(0));
+        let inner = tower::service_fn({
+            let released = released.clone();
+            move |_: Request<()>| {
+                let released = released.clone();
+                async move {
+                    released.acquire().await.unwrap().forget();
+                    Ok::<_, Infallible>(())
+                }
+            }
+        });
+        let limited = ConcurrencyLimit::new(ShedDeadline::new(inner, Duration::ZERO), 1);
+        let mut svc = QueueInCall::new(limited);
+
+        let first = tokio::spawn(svc.ready().await.unwrap().call(request()));
+        let queued = tokio::spawn(svc.ready().await.unwrap().call(request()));
+        tokio::time::sleep(Duration::from_secs(6)).await;
+        released.add_permits(2);
+        first.await.unwrap().unwrap();
+
+        let status = queued
+            .await
+            .unwrap()
+            .unwrap_err()
+            .downcast::<Status>()
+            .unwrap();
+        assert_eq!(status.code(), tonic::Code::DeadlineExceeded);
+        svc.ready().await.unwrap().call(request()).await.unwrap();
+    }
+}
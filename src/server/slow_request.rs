@@ -0,0 +1,214 @@
+use super::{BoxHttpBody, TcpConnectInfo};
+use crate::BoxError;
+
+use bytes::Bytes;
+use http::{HeaderMap, Request, Response};
+use http_body::Body as _;
+use hyper::Body;
+use pin_project::pin_project;
+use std::{
+    future::Future,
+    net::SocketAddr,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::time::{sleep, Instant, Sleep};
+use tower::Service;
+
+/// Middleware that logs requests which are still in flight after `threshold`, including while
+/// their response is streamed, and logs them again when they finish.
+#[derive(Debug, Clone)]
+pub(crate) struct SlowRequests<S> {
+    inner: S,
+    threshold: Duration,
+}
+
+impl<S> SlowRequests<S> {
+    pub(crate) fn new(inner: S, threshold: Duration) -> Self {
+        Self { inner, threshold }
+    }
+}
+
+impl<S> Service<Request<Body>> for SlowRequests<S>
+where
+    S: Service<Request<Body>, Response = Response<BoxHttpBody>, Error = BoxError>,
+{
+    type Response = Response<BoxHttpBody>;
+    type Error = BoxError;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        let in_flight = InFlight {
+            method: request.uri().path().to_owned(),
+            peer: request
+                .extensions()
+                .get::<TcpConnectInfo>()
+                .and_then(TcpConnectInfo::remote_addr),
+            start: Instant::now(),
+            timer: Some(Box::pin(sleep(self.threshold))),
+        };
+        ResponseFuture {
+            inner: self.inner.call(request),
+            in_flight: Some(in_flight),
+        }
+    }
+}
+
+/// A request which is in flight, logged once it has been for the threshold.
+struct InFlight {
+    method: String,
+    peer: Option<SocketAddr>,
+    start: Instant,
+    // `None` once the request has been logged as slow.
+    timer: Option<Pin<Box<Sleep>>>,
+}
+
+impl InFlight {
+    fn poll_slow(&mut self, cx: &mut Context<'_>) {
+        if let Some(timer) = &mut self.timer {
+            if timer.as_mut().poll(cx).is_ready() {
+                self.timer = None;
+                tracing::warn!(
+                    method = %self.method,
+                    peer = ?self.peer,
+                    elapsed = ?self.start.elapsed(),
+                    "slow request is still in flight"
+                );
+            }
+        }
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        if self.timer.is_none() {
+            tracing::info!(
+                method = %self.method,
+                peer = ?self.peer,
+                elapsed = ?self.start.elapsed(),
+                "slow request finished"
+            );
+        }
+    }
+}
+
+#[pin_project]
+pub(crate) struct ResponseFuture<F> {
+    #[pin]
+    inner: F,
+    in_flight: Option<InFlight>,
+}
+
+impl<F> Future for ResponseFuture<F>
+where
+    F: Future<Output = Result<Response<BoxHttpBody>, BoxError>>,
+{
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let in_flight = this.in_flight.as_mut().expect("polled after ready");
+        in_flight.poll_slow(cx);
+
+        let response = futures_util::ready!(this.inner.poll(cx))?;
+        let in_flight = this.in_flight.take().expect("polled after ready");
+        if response.body().is_end_stream() {
+            return Poll::Ready(Ok(response));
+        }
+        Poll::Ready(Ok(response.map(|body| {
+            ResponseBody {
+                inner: body,
+                in_flight,
+            }
+            .boxed_unsync()
+        })))
+    }
+}
+
+/// A response body which keeps its request in flight until it is dropped.
+struct ResponseBody {
+    inner: BoxHttpBody,
+    in_flight: InFlight,
+}
+
+impl http_body::Body for ResponseBody {
+    type Data = Bytes;
+    type Error = BoxError;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let this = &mut *self;
+        this.in_flight.poll_slow(cx);
+        Pin::new(&mut this.inner).poll_data(cx)
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        let this = &mut *self;
+        this.in_flight.poll_slow(cx);
+        Pin::new(&mut this.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> http_body::SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::future::poll_fn;
+
+    async fn is_pending<F: Future>(mut future: Pin<&mut F>) -> bool {
+        poll_fn(|cx| Poll::Ready(future.as_mut().poll(cx).is_pending())).await
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn warns_while_response_is_pending() {
+        let inner = tower::service_fn(|_: Request<Body>| async {
+            sleep(Duration::from_secs(2)).await;
+            Ok::<_, BoxError>(Response::new(BoxHttpBody::default()))
+        });
+        let mut svc = SlowRequests::new(inner, Duration::from_secs(1));
+        let mut future = Box::pin(svc.call(Request::new(Body::empty())));
+
+        assert!(is_pending(future.as_mut()).await);
+        assert!(future.in_flight.as_ref().unwrap().timer.is_some());
+        tokio::time::advance(Duration::from_millis(1500)).await;
+        assert!(is_pending(future.as_mut()).await);
+        assert!(future.in_flight.as_ref().unwrap().timer.is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn warns_while_body_is_pending() {
+        let (_tx, body) = Body::channel();
+        let mut body = ResponseBody {
+            inner: body.map_err(Into::into).boxed_unsync(),
+            in_flight: InFlight {
+                method: "/test.Test/Stream".to_owned(),
+                peer: None,
+                start: Instant::now(),
+                timer: Some(Box::pin(sleep(Duration::from_secs(1)))),
+            },
+        };
+
+        assert!(is_pending(Pin::new(&mut body.data())).await);
+        assert!(body.in_flight.timer.is_some());
+        tokio::time::advance(Duration::from_millis(1500)).await;
+        assert!(is_pending(Pin::new(&mut body.data())).await);
+        assert!(body.in_flight.timer.is_none());
+    }
+}
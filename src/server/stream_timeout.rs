@@ -0,0 +1,238 @@
+use super::BoxHttpBody;
+use crate::BoxError;
+
+use bytes::Bytes;
+use http::{HeaderMap, Request, Response};
+use http_body::Body as _;
+use hyper::Body;
+use pin_project::pin_project;
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::time::{sleep_until, Instant, Sleep};
+use tower::Service;
+
+/// When the client of a connection was last active, and how many of its streams are open.
+///
+/// The client is active when it sends the frames of a stream, HEADERS or DATA, or consumes
+/// response data, which it acknowledges with WINDOW_UPDATE. These are followed by the
+/// connection's [`PingIo`](crate::service::PingIo), so that a client which has stopped reading
+/// is noticed even though hyper doesn't poll responses while the client's flow control window is
+/// full. The connection is closed once its streams have been inactive for `timeout`, which
+/// resets them.
+#[derive(Debug)]
+pub(crate) struct StreamActivity {
+    last: Mutex<Instant>,
+    open: AtomicUsize,
+    timeout: Duration,
+}
+
+impl StreamActivity {
+    pub(crate) fn new(timeout: Duration) -> Self {
+        StreamActivity {
+            last: Mutex::new(Instant::now()),
+            open: AtomicUsize::new(0),
+            timeout,
+        }
+    }
+
+    pub(crate) fn record(&self) {
+        *self.last.lock().unwrap() = Instant::now();
+    }
+
+    fn deadline(&self) -> Instant {
+        *self.last.lock().unwrap() + self.timeout
+    }
+
+    pub(crate) fn timer(&self) -> Pin<Box<Sleep>> {
+        Box::pin(sleep_until(self.deadline()))
+    }
+
+    /// Returns `true` if streams are open and the client has been inactive for the timeout,
+    /// otherwise wakes the task when that might be the case.
+    pub(crate) fn poll_expired(&self, timer: &mut Pin<Box<Sleep>>, cx: &mut Context<'_>) -> bool {
+        loop {
+            if timer.as_mut().poll(cx).is_pending() {
+                return false;
+            }
+            let now = Instant::now();
+            let deadline = self.deadline();
+            if deadline <= now && self.open.load(Ordering::Acquire) > 0 {
+                return true;
+            }
+            if deadline > now {
+                timer.as_mut().reset(deadline);
+            } else {
+                // Without open streams, check again once a stream could have become inactive.
+                timer.as_mut().reset(now + self.timeout);
+            }
+        }
+    }
+
+    fn open_stream(self: &Arc<Self>) -> StreamGuard {
+        self.record();
+        self.open.fetch_add(1, Ordering::AcqRel);
+        StreamGuard {
+            activity: self.clone(),
+        }
+    }
+}
+
+/// Keeps a stream open in its connection's [`StreamActivity`] until the stream and its response
+/// body are dropped.
+struct StreamGuard {
+    activity: Arc<StreamActivity>,
+}
+
+impl Drop for StreamGuard {
+    fn drop(&mut self) {
+        self.activity.open.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Middleware that counts the open streams of a connection in its [`StreamActivity`].
+pub(crate) struct StreamInactivityTimeout<S> {
+    inner: S,
+    activity: Arc<StreamActivity>,
+}
+
+impl<S> StreamInactivityTimeout<S> {
+    pub(crate) fn new(inner: S, activity: Arc<StreamActivity>) -> Self {
+        Self { inner, activity }
+    }
+}
+
+impl<S> Service<Request<Body>> for StreamInactivityTimeout<S>
+where
+    S: Service<Request<Body>, Response = Response<BoxHttpBody>, Error = BoxError>,
+{
+    type Response = Response<BoxHttpBody>;
+    type Error = BoxError;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        ResponseFuture {
+            inner: self.inner.call(request),
+            guard: Some(self.activity.open_stream()),
+        }
+    }
+}
+
+#[pin_project]
+pub(crate) struct ResponseFuture<F> {
+    #[pin]
+    inner: F,
+    guard: Option<StreamGuard>,
+}
+
+impl<F> Future for ResponseFuture<F>
+where
+    F: Future<Output = Result<Response<BoxHttpBody>, BoxError>>,
+{
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let response = futures_util::ready!(this.inner.poll(cx))?;
+        let guard = this.guard.take().expect("polled after ready");
+        Poll::Ready(Ok(response.map(|body| {
+            ResponseBody {
+                inner: body,
+                _guard: guard,
+            }
+            .boxed_unsync()
+        })))
+    }
+}
+
+/// A response body which keeps its stream open.
+struct ResponseBody {
+    inner: BoxHttpBody,
+    _guard: StreamGuard,
+}
+
+impl http_body::Body for ResponseBody {
+    type Data = Bytes;
+    type Error = BoxError;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        Pin::new(&mut self.inner).poll_data(cx)
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> http_body::SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::future::poll_fn;
+
+    async fn expired(activity: &StreamActivity, timer: &mut Pin<Box<Sleep>>) -> bool {
+        tokio::select! {
+            () = poll_fn(|cx| {
+                if activity.poll_expired(timer, cx) {
+                    Poll::Ready(())
+                } else {
+                    Poll::Pending
+                }
+            }) => true,
+            () = tokio::time::sleep(Duration::from_secs(1)) => false,
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn expires_while_streams_are_open() {
+        let activity = Arc::new(StreamActivity::new(Duration::from_secs(10)));
+        let mut timer = activity.timer();
+        let inner = tower::service_fn(|_: Request<Body>| async {
+            Ok::<_, BoxError>(Response::new(BoxHttpBody::default()))
+        });
+        let mut svc = StreamInactivityTimeout::new(inner, activity.clone());
+
+        // Without open streams the connection is idle, not inactive.
+        tokio::time::sleep(Duration::from_secs(20)).await;
+        assert!(!expired(&activity, &mut timer).await);
+
+        let response = svc.call(Request::new(Body::empty())).await.unwrap();
+        tokio::time::sleep(Duration::from_secs(5)).await;
+        // The client sent a frame.
+        activity.record();
+        tokio::time::sleep(Duration::from_secs(8)).await;
+        assert!(!expired(&activity, &mut timer).await);
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        assert!(expired(&activity, &mut timer).await);
+
+        // The stream stays open until its response body is dropped.
+        let body = response.into_body();
+        assert!(body.is_end_stream());
+        drop(body);
+        assert!(!expired(&activity, &mut timer).await);
+    }
+}
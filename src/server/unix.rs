@@ -0,0 +1,340 @@
+use super::Connected;
+use crate::{BoxError, Result};
+
+use futures_core::Stream;
+use std::{
+    fmt, fs, io,
+    os::unix::fs::{FileTypeExt, MetadataExt, PermissionsExt},
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+use tokio::net::{
+    unix::{SocketAddr, UCred},
+    UnixListener, UnixStream,
+};
+
+/// Connection info for Unix domain socket streams.
+///
+/// This type will be accessible through [request extensions][ext] if you're serving with a
+/// [`UnixIncoming`].
+///
+/// [ext]: crate::Request::extensions
+#[derive(Debug, Clone)]
+pub struct UdsConnectInfo {
+    peer_addr: Option<Arc<SocketAddr>>,
+    peer_cred: Option<UCred>,
+    pub(super) connection_id: u64,
+}
+
+impl UdsConnectInfo {
+    /// The id of the connection, see [`ConnectionInfo::id`](crate::ConnectionInfo::id).
+    pub fn connection_id(&self) -> u64 {
+        self.connection_id
+    }
+
+    /// The address of the client's socket, which is usually unnamed.
+    pub fn peer_addr(&self) -> Option<&SocketAddr> {
+        self.peer_addr.as_deref()
+    }
+
+    /// The credentials of the client process.
+    pub fn peer_cred(&self) -> Option<UCred> {
+        self.peer_cred
+    }
+}
+
+impl Connected for UnixStream {
+    type ConnectInfo = UdsConnectInfo;
+
+    fn connect_info(&self) -> Result<Self::ConnectInfo> {
+        Ok(UdsConnectInfo {
+            peer_addr: self.peer_addr().ok().map(Arc::new),
+            peer_cred: self.peer_cred().ok(),
+            connection_id: 0,
+        })
+    }
+}
+
+/// A builder for a [`UnixIncoming`], which sets up the socket file.
+///
+/// ```no_run
+/// # use tonic_transport::UnixIncoming;
+/// # fn example() -> Result<(), tonic_transport::BoxError> {
+/// let incoming = UnixIncoming::builder("/run/my-service/grpc.sock")
+///     .mode(0o660)
+///     .remove_stale(true)
+///     .remove_on_drop(true)
+///     .bind()?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct UnixIncomingBuilder {
+    path: PathBuf,
+    mode: Option<u32>,
+    owner: Option<(Option<u32>, Option<u32>)>,
+    remove_stale: bool,
+    remove_on_drop: bool,
+}
+
+impl UnixIncomingBuilder {
+    /// Set the permissions of the socket file after it is bound, for example `0o660`.
+    ///
+    /// Clients need write permission to connect. The socket is created with permissions from
+    /// the process's umask, and clients which connect before the mode is set are accepted.
+    pub fn mode(self, mode: u32) -> Self {
+        UnixIncomingBuilder {
+            mode: Some(mode),
+            ..self
+        }
+    }
+
+    /// Set the owning user and group of the socket file after it is bound. `None` leaves the
+    /// user or group unchanged.
+    pub fn owner(self, uid: Option<u32>, gid: Option<u32>) -> Self {
+        UnixIncomingBuilder {
+            owner: Some((uid, gid)),
+            ..self
+        }
+    }
+
+    /// Remove a socket file left at the path by a previous process before binding.
+    ///
+    /// The file is only removed if it is a socket which no process is listening on, so this
+    /// can't take over the socket of a running server. Default is `false`, in which case binding
+    /// fails if the file exists.
+    pub fn remove_stale(self, enabled: bool) -> Self {
+        UnixIncomingBuilder {
+            remove_stale: enabled,
+            ..self
+        }
+    }
+
+    /// Remove the socket file when the [`UnixIncoming`] is dropped, which happens when the server
+    /// using it stops.
+    ///
+    /// The file is only removed if it is still the socket which was bound, and not, for example,
+    /// the socket of a newer server bound to the same path. Default is `false`.
+    pub fn remove_on_drop(self, enabled: bool) -> Self {
+        UnixIncomingBuilder {
+            remove_on_drop: enabled,
+            ..self
+        }
+    }
+
+    /// Bind the socket.
+    ///
+    /// If the mode or owner can't be set, the socket file is removed and an error is returned.
+    /// This must be called from within a tokio runtime.
+    pub fn bind(self) -> std::result::Result<UnixIncoming, BoxError> {
+        if self.remove_stale {
+            remove_stale(&self.path)?;
+        }
+
+        let listener = UnixListener::bind(&self.path)?;
+        let incoming = UnixIncoming {
+            listener,
+            cleanup: None,
+        };
+
+        let set_up = || -> io::Result<()> {
+            if let Some(mode) = self.mode {
+                fs::set_permissions(&self.path, fs::Permissions::from_mode(mode))?;
+            }
+            if let Some((uid, gid)) = self.owner {
+                std::os::unix::fs::chown(&self.path, uid, gid)?;
+            }
+            Ok(())
+        };
+        if let Err(error) = set_up() {
+            // Don't leave a socket behind which nothing will accept connections on.
+            let _ = fs::remove_file(&self.path);
+            return Err(error.into());
+        }
+
+        let cleanup = match self.remove_on_drop {
+            true => Some(Cleanup::new(self.path)?),
+            false => None,
+        };
+        Ok(UnixIncoming {
+            cleanup,
+            ..incoming
+        })
+    }
+}
+
+/// Remove the socket file at `path` if no process is listening on it.
+fn remove_stale(path: &Path) -> io::Result<()> {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(error) => return Err(error),
+    };
+    if !metadata.file_type().is_socket() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} exists and is not a socket", path.display()),
+        ));
+    }
+
+    match std::os::unix::net::UnixStream::connect(path) {
+        Ok(_) => Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            format!("{} is in use by another process", path.display()),
+        )),
+        Err(error) if error.kind() == io::ErrorKind::ConnectionRefused => {
+            tracing::debug!(path = %path.display(), "removing stale socket");
+            fs::remove_file(path)
+        }
+        Err(error) => Err(error),
+    }
+}
+
+// Identifies the socket file which was bound, so that only it is removed.
+#[derive(Debug)]
+struct Cleanup {
+    path: PathBuf,
+    dev: u64,
+    ino: u64,
+}
+
+impl Cleanup {
+    fn new(path: PathBuf) -> io::Result<Self> {
+        let metadata = fs::symlink_metadata(&path)?;
+        Ok(Cleanup {
+            path,
+            dev: metadata.dev(),
+            ino: metadata.ino(),
+        })
+    }
+}
+
+impl Drop for Cleanup {
+    fn drop(&mut self) {
+        match fs::symlink_metadata(&self.path) {
+            Ok(metadata) if metadata.dev() == self.dev && metadata.ino() == self.ino => {
+                if let Err(error) = fs::remove_file(&self.path) {
+                    tracing::debug!(%error, path = %self.path.display(), "failed to remove socket");
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Binds a Unix domain socket and provides a stream of its connections, for use with
+/// [`Router::serve_with_incoming`](crate::Router::serve_with_incoming).
+pub struct UnixIncoming {
+    listener: UnixListener,
+    cleanup: Option<Cleanup>,
+}
+
+impl UnixIncoming {
+    /// Bind a socket at `path` with the default options.
+    ///
+    /// This must be called from within a tokio runtime.
+    pub fn bind(path: impl AsRef<Path>) -> std::result::Result<Self, BoxError> {
+        UnixIncoming::builder(path).bind()
+    }
+
+    /// Bind a socket called `name` in the abstract namespace.
+    ///
+    /// Abstract sockets have no file, so they are not subject to file permissions and don't
+    /// need to be cleaned up. `name` does not include the leading NUL byte. This must be called
+    /// from within a tokio runtime.
+    #[cfg(target_os = "linux")]
+    pub fn bind_abstract(name: impl AsRef<[u8]>) -> std::result::Result<Self, BoxError> {
+        use std::os::linux::net::SocketAddrExt;
+
+        let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+        let listener = std::os::unix::net::UnixListener::bind_addr(&addr)?;
+        listener.set_nonblocking(true)?;
+        Ok(UnixIncoming {
+            listener: UnixListener::from_std(listener)?,
+            cleanup: None,
+        })
+    }
+
+    /// Create a builder to bind a socket at `path`.
+    pub fn builder(path: impl AsRef<Path>) -> UnixIncomingBuilder {
+        UnixIncomingBuilder {
+            path: path.as_ref().to_owned(),
+            mode: None,
+            owner: None,
+            remove_stale: false,
+            remove_on_drop: false,
+        }
+    }
+}
+
+impl fmt::Debug for UnixIncoming {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UnixIncoming")
+            .field("local_addr", &self.listener.local_addr().ok())
+            .field("remove_on_drop", &self.cleanup.is_some())
+            .finish()
+    }
+}
+
+impl Stream for UnixIncoming {
+    type Item = io::Result<UnixStream>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.listener
+            .poll_accept(cx)
+            .map(|result| Some(result.map(|(stream, _)| stream)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn sets_up_and_removes_socket_file() {
+        let path = std::env::temp_dir().join(format!("tonic-unix-{}.sock", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        // A socket which nothing is listening on.
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+        assert!(UnixIncoming::bind(&path).is_err());
+
+        let incoming = UnixIncoming::builder(&path)
+            .mode(0o600)
+            .remove_stale(true)
+            .remove_on_drop(true)
+            .bind()
+            .unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        // The socket is in use, so it isn't stale.
+        assert!(UnixIncoming::builder(&path)
+            .remove_stale(true)
+            .bind()
+            .is_err());
+
+        drop(incoming);
+        assert!(!path.exists());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn connects_to_abstract_socket() {
+        use crate::service::UnixConnector;
+        use futures_util::StreamExt;
+        use tower_service::Service;
+
+        let name = format!("tonic-unix-abstract-{}", std::process::id());
+        let mut incoming = UnixIncoming::bind_abstract(&name).unwrap();
+        let mut connector = UnixConnector::new_abstract(name);
+        let (client, server) = tokio::join!(
+            connector.call(http::Uri::from_static("http://localhost")),
+            incoming.next()
+        );
+        client.unwrap();
+        server.unwrap().unwrap();
+    }
+}
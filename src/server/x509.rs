@@ -0,0 +1,177 @@
+use super::TlsConnectInfo;
+use crate::tls::Certificate;
+
+use std::{net::IpAddr, sync::Arc};
+use x509_parser::{extensions::GeneralName, pem::parse_x509_pem, prelude::X509Certificate};
+
+pub(crate) type SpiffeIdVerifier = Arc<dyn Fn(&str) -> bool + Send + Sync + 'static>;
+
+/// A summary of a client's TLS certificate, parsed once per connection.
+///
+/// When the `x509` feature is enabled, this is inserted into the extensions of requests on
+/// connections whose client sent a certificate, so that interceptors can authorize requests
+/// without parsing the certificate themselves.
+///
+/// ```no_run
+/// # use tonic_transport::PeerCertificate;
+/// fn check(request: &tonic::Request<()>) -> Result<(), tonic::Status> {
+///     let cert = request
+///         .extensions()
+///         .get::<std::sync::Arc<PeerCertificate>>()
+///         .ok_or_else(|| tonic::Status::unauthenticated("no client certificate"))?;
+///     match cert.spiffe_id() {
+///         Some("spiffe://example.org/frontend") => Ok(()),
+///         _ => Err(tonic::Status::permission_denied("unknown client")),
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerCertificate {
+    subject: String,
+    dns_names: Vec<String>,
+    uris: Vec<String>,
+    ip_addrs: Vec<IpAddr>,
+}
+
+impl PeerCertificate {
+    /// Parse a DER encoded certificate.
+    pub fn from_der(der: &[u8]) -> Option<Self> {
+        let (_, cert) = x509_parser::parse_x509_certificate(der).ok()?;
+        Some(PeerCertificate::new(&cert))
+    }
+
+    pub(crate) fn parse(cert: &Certificate) -> Option<Self> {
+        match cert {
+            Certificate::Der(der) => PeerCertificate::from_der(der),
+            Certificate::Pem(pem) => {
+                let (_, pem) = parse_x509_pem(pem).ok()?;
+                PeerCertificate::from_der(&pem.contents)
+            }
+        }
+    }
+
+    fn new(cert: &X509Certificate<'_>) -> Self {
+        let mut summary = PeerCertificate {
+            subject: cert.subject().to_string(),
+            dns_names: Vec::new(),
+            uris: Vec::new(),
+            ip_addrs: Vec::new(),
+        };
+
+        if let Ok(Some(san)) = cert.subject_alternative_name() {
+            for name in &san.value.general_names {
+                match name {
+                    GeneralName::DNSName(name) => summary.dns_names.push(name.to_string()),
+                    GeneralName::URI(uri) => summary.uris.push(uri.to_string()),
+                    GeneralName::IPAddress(bytes) => {
+                        if let Ok(octets) = <[u8; 4]>::try_from(*bytes) {
+                            summary.ip_addrs.push(octets.into());
+                        } else if let Ok(octets) = <[u8; 16]>::try_from(*bytes) {
+                            summary.ip_addrs.push(octets.into());
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        summary
+    }
+
+    /// The certificate's subject, as a string such as `CN=client, O=Example`.
+    pub fn subject(&self) -> &str {
+        &self.subject
+    }
+
+    /// The DNS names in the certificate's subject alternative names.
+    pub fn dns_names(&self) -> &[String] {
+        &self.dns_names
+    }
+
+    /// The URIs in the certificate's subject alternative names.
+    pub fn uris(&self) -> &[String] {
+        &self.uris
+    }
+
+    /// The IP addresses in the certificate's subject alternative names.
+    pub fn ip_addrs(&self) -> &[IpAddr] {
+        &self.ip_addrs
+    }
+
+    /// Whether the certificate is valid for `name`, a DNS name or an IP address, by its subject
+    /// alternative names. A DNS name may match a wildcard which is the whole leftmost label of a
+    /// name with at least three labels, such as `*.example.com`, following RFC 6125.
+    pub(crate) fn matches_name(&self, name: &str) -> bool {
+        let name = name.trim_end_matches('.');
+        if let Ok(addr) = name
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .parse::<IpAddr>()
+        {
+            return self.ip_addrs.contains(&addr);
+        }
+        self.dns_names.iter().any(|pattern| {
+            let pattern = pattern.trim_end_matches('.');
+            match pattern.strip_prefix("*.") {
+                // A wildcard doesn't match names under a top-level domain, such as `*.com`.
+                Some(suffix) if !suffix.contains('.') => false,
+                Some(suffix) => name.split_once('.').is_some_and(|(label, rest)| {
+                    !label.is_empty() && rest.eq_ignore_ascii_case(suffix)
+                }),
+                None => pattern.eq_ignore_ascii_case(name),
+            }
+        })
+    }
+
+    /// The certificate's SPIFFE ID, the first URI subject alternative name with the `spiffe`
+    /// scheme.
+    pub fn spiffe_id(&self) -> Option<&str> {
+        self.uris
+            .iter()
+            .map(String::as_str)
+            .find(|uri| uri.starts_with("spiffe://"))
+    }
+}
+
+/// Whether the client's certificate has a SPIFFE ID accepted by `verify`.
+pub(crate) fn accepts_spiffe_id<T>(
+    conn_info: Option<&TlsConnectInfo<T>>,
+    verify: &SpiffeIdVerifier,
+) -> bool {
+    let cert = conn_info
+        .and_then(TlsConnectInfo::peer_cert)
+        .and_then(|cert| PeerCertificate::parse(&cert));
+    match cert.as_ref().and_then(PeerCertificate::spiffe_id) {
+        Some(id) => verify(id),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_name() {
+        let cert = PeerCertificate {
+            subject: "CN=server".to_owned(),
+            dns_names: vec![
+                "api.example.com".to_owned(),
+                "*.internal.example".to_owned(),
+                "*.com".to_owned(),
+            ],
+            uris: Vec::new(),
+            ip_addrs: vec!["10.0.0.1".parse().unwrap(), "::1".parse().unwrap()],
+        };
+
+        assert!(cert.matches_name("api.example.com"));
+        assert!(cert.matches_name("API.example.com."));
+        assert!(cert.matches_name("db.internal.example"));
+        assert!(cert.matches_name("10.0.0.1"));
+        assert!(cert.matches_name("[::1]"));
+        assert!(!cert.matches_name("example.com"));
+        assert!(!cert.matches_name("internal.example"));
+        assert!(!cert.matches_name("a.db.internal.example"));
+        assert!(!cert.matches_name("example.com."));
+        assert!(!cert.matches_name("10.0.0.2"));
+    }
+}
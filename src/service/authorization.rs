@@ -0,0 +1,42 @@
+use http::{header::AUTHORIZATION, HeaderValue, Request};
+use std::task::{Context, Poll};
+use tower_service::Service;
+
+/// Adds a default `authorization` header to requests which do not already have one.
+#[derive(Debug)]
+pub(crate) struct AddAuthorization<T> {
+    inner: T,
+    authorization: Option<HeaderValue>,
+}
+
+impl<T> AddAuthorization<T> {
+    pub(crate) fn new(inner: T, authorization: Option<HeaderValue>) -> Self {
+        Self {
+            inner,
+            authorization,
+        }
+    }
+}
+
+impl<T, ReqBody> Service<Request<ReqBody>> for AddAuthorization<T>
+where
+    T: Service<Request<ReqBody>>,
+{
+    type Response = T::Response;
+    type Error = T::Error;
+    type Future = T::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<ReqBody>) -> Self::Future {
+        if let Some(authorization) = &self.authorization {
+            req.headers_mut()
+                .entry(AUTHORIZATION)
+                .or_insert_with(|| authorization.clone());
+        }
+
+        self.inner.call(req)
+    }
+}
@@ -0,0 +1,152 @@
+use rand::Rng;
+use std::time::Duration;
+
+/// Configuration of the backoff between connection attempts.
+///
+/// The defaults follow the [gRPC connection backoff protocol][spec]: the first attempt may take
+/// up to the initial backoff, and each later attempt is delayed by the previous backoff times
+/// the multiplier (up to the maximum backoff), randomized by the jitter. Each attempt is allowed
+/// at least the minimum connect timeout to complete.
+///
+/// [spec]: https://github.com/grpc/grpc/blob/master/doc/connection-backoff.md
+#[derive(Debug, Clone)]
+pub struct ConnectBackoff {
+    initial: Duration,
+    multiplier: f64,
+    jitter: f64,
+    max: Duration,
+    min_connect_timeout: Duration,
+}
+
+impl ConnectBackoff {
+    /// Create a backoff configuration with the gRPC defaults.
+    pub fn new() -> Self {
+        ConnectBackoff {
+            initial: Duration::from_secs(1),
+            multiplier: 1.6,
+            jitter: 0.2,
+            max: Duration::from_secs(120),
+            min_connect_timeout: Duration::from_secs(20),
+        }
+    }
+
+    /// Set the backoff after the first failed attempt, defaults to 1 second.
+    pub fn initial(self, initial: Duration) -> Self {
+        ConnectBackoff { initial, ..self }
+    }
+
+    /// Set the factor by which the backoff increases after each failed attempt, defaults to 1.6.
+    pub fn multiplier(self, multiplier: f64) -> Self {
+        ConnectBackoff { multiplier, ..self }
+    }
+
+    /// Set the proportion by which each backoff is randomly increased or decreased, defaults to
+    /// 0.2. The jitter is clamped to between 0 and 1.
+    pub fn jitter(self, jitter: f64) -> Self {
+        ConnectBackoff {
+            jitter: jitter.clamp(0.0, 1.0),
+            ..self
+        }
+    }
+
+    /// Set the maximum backoff, defaults to 120 seconds.
+    pub fn max(self, max: Duration) -> Self {
+        ConnectBackoff { max, ..self }
+    }
+
+    /// Set the minimum time allowed for a connection attempt, defaults to 20 seconds.
+    pub fn min_connect_timeout(self, min_connect_timeout: Duration) -> Self {
+        ConnectBackoff {
+            min_connect_timeout,
+            ..self
+        }
+    }
+}
+
+impl Default for ConnectBackoff {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The backoff state of a reconnecting service.
+#[derive(Debug, Clone)]
+pub(crate) struct Backoff {
+    config: ConnectBackoff,
+    current: Option<Duration>,
+}
+
+impl Backoff {
+    pub(crate) fn new(config: ConnectBackoff) -> Self {
+        Backoff {
+            config,
+            current: None,
+        }
+    }
+
+    /// Returns the delay between the start of the next attempt and the start of the attempt
+    /// after it, and increases the delay for later attempts.
+    pub(crate) fn next_delay(&mut self) -> Duration {
+        let current = match self.current {
+            None => {
+                self.current = Some(self.config.initial);
+                return self.config.initial;
+            }
+            Some(current) => current.mul_f64(self.config.multiplier).min(self.config.max),
+        };
+        self.current = Some(current);
+
+        if self.config.jitter == 0.0 {
+            return current;
+        }
+        let jitter = rand::thread_rng().gen_range(-self.config.jitter..=self.config.jitter);
+        current.mul_f64(1.0 + jitter)
+    }
+
+    /// The minimum time allowed for a connection attempt.
+    pub(crate) fn min_connect_timeout(&self) -> Duration {
+        self.config.min_connect_timeout
+    }
+
+    /// Start again from the initial delay.
+    pub(crate) fn reset(&mut self) {
+        self.current = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grows_to_max() {
+        let mut backoff = Backoff::new(ConnectBackoff::new().jitter(0.0));
+        assert_eq!(backoff.next_delay(), Duration::from_secs(1));
+        assert_eq!(backoff.next_delay(), Duration::from_millis(1600));
+        assert_eq!(backoff.next_delay(), Duration::from_millis(2560));
+        for _ in 0..20 {
+            backoff.next_delay();
+        }
+        assert_eq!(backoff.next_delay(), Duration::from_secs(120));
+    }
+
+    #[test]
+    fn jitter() {
+        let mut backoff = Backoff::new(ConnectBackoff::new());
+        assert_eq!(backoff.next_delay(), Duration::from_secs(1));
+        for _ in 0..100 {
+            let delay = backoff.next_delay();
+            let current = backoff.current.unwrap();
+            assert!(delay >= current.mul_f64(0.8) && delay <= current.mul_f64(1.2));
+        }
+    }
+
+    #[test]
+    fn reset() {
+        let mut backoff = Backoff::new(ConnectBackoff::new());
+        backoff.next_delay();
+        backoff.next_delay();
+        backoff.reset();
+        assert_eq!(backoff.next_delay(), Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,205 @@
+use super::connection::{Connection, Request, Response};
+use crate::channel::{Endpoints, Policy, ReadyEndpoints};
+use crate::{BoxError, BoxFuture};
+
+use futures_util::ready;
+use std::{
+    fmt,
+    future::Future,
+    hash::Hash,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::time::{sleep, Sleep};
+use tonic::Status;
+use tower::{
+    discover::{Change, Discover},
+    ready_cache::{error::Failed, ReadyCache},
+};
+use tower_service::Service;
+use tracing::{debug, trace};
+
+/// What to do with requests when no connection is ready.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Unready {
+    /// Wait until a connection becomes ready.
+    Wait,
+    /// Fail requests immediately.
+    FailFast,
+    /// Wait for up to the given duration, then fail requests until a connection is ready.
+    Queue(Duration),
+}
+
+/// Balances requests over the connections produced by `discover`, using a [`Policy`] to pick
+/// between ready connections.
+pub(crate) struct Balance<D>
+where
+    D: Discover<Service = Connection>,
+    D::Key: Hash,
+{
+    discover: D,
+    services: ReadyCache<D::Key, Connection, Request>,
+    policy: Box<dyn Policy>,
+    unready: Unready,
+    deadline: Option<Pin<Box<Sleep>>>,
+    failing: bool,
+}
+
+impl<D> Balance<D>
+where
+    D: Discover<Service = Connection> + Unpin,
+    D::Key: Hash + Clone,
+    D::Error: Into<BoxError>,
+{
+    pub(crate) fn new(discover: D, policy: Box<dyn Policy>, unready: Unready) -> Self {
+        Balance {
+            discover,
+            services: ReadyCache::default(),
+            policy,
+            unready,
+            deadline: None,
+            failing: false,
+        }
+    }
+
+    /// Polls `discover` for updates, adding new connections to the pending set.
+    fn update_pending_from_discover(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), BoxError>> {
+        loop {
+            match ready!(Pin::new(&mut self.discover).poll_discover(cx)) {
+                None => return Poll::Ready(Ok(())),
+                Some(Err(e)) => return Poll::Ready(Err(e.into())),
+                Some(Ok(Change::Remove(key))) => {
+                    trace!("remove");
+                    self.services.evict(&key);
+                }
+                Some(Ok(Change::Insert(key, svc))) => {
+                    trace!("insert");
+                    self.services.push(key, svc);
+                }
+            }
+        }
+    }
+
+    /// Moves ready connections which are no longer ready back to the pending set.
+    fn demote_unready(&mut self, cx: &mut Context<'_>) {
+        let mut index = 0;
+        while index < self.services.ready_len() {
+            match self.services.check_ready_index(cx, index) {
+                Ok(true) => index += 1,
+                // The connection at `index` was swapped out, so check the new one at `index`.
+                Ok(false) => {}
+                Err(Failed(_, error)) => debug!(%error, "dropping failed endpoint"),
+            }
+        }
+    }
+
+    /// Returns `true` if requests should fail, rather than wait, while no connection is ready.
+    fn should_fail(&mut self, cx: &mut Context<'_>) -> bool {
+        match self.unready {
+            Unready::Wait => false,
+            Unready::FailFast => true,
+            Unready::Queue(_) if self.failing => true,
+            Unready::Queue(timeout) => {
+                let deadline = self
+                    .deadline
+                    .get_or_insert_with(|| Box::pin(sleep(timeout)));
+                if deadline.as_mut().poll(cx).is_pending() {
+                    return false;
+                }
+                debug!("no endpoint became ready before the queue timeout");
+                self.deadline = None;
+                self.failing = true;
+                true
+            }
+        }
+    }
+
+    fn promote_pending_to_ready(&mut self, cx: &mut Context<'_>) {
+        loop {
+            match self.services.poll_pending(cx) {
+                Poll::Ready(Ok(())) | Poll::Pending => break,
+                Poll::Ready(Err(error)) => debug!(%error, "dropping failed endpoint"),
+            }
+        }
+        trace!(
+            ready = %self.services.ready_len(),
+            pending = %self.services.pending_len(),
+            "poll_unready"
+        );
+    }
+}
+
+impl<D> Service<Request> for Balance<D>
+where
+    D: Discover<Service = Connection> + Unpin,
+    D::Key: Hash + Clone,
+    D::Error: Into<BoxError>,
+{
+    type Response = Response;
+    type Error = BoxError;
+    type Future = BoxFuture<Response, BoxError>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        if let Poll::Ready(Err(e)) = self.update_pending_from_discover(cx) {
+            return Poll::Ready(Err(e));
+        }
+        self.demote_unready(cx);
+        self.promote_pending_to_ready(cx);
+
+        if self.services.ready_len() == 0 {
+            if self.should_fail(cx) {
+                // Accept the request so that `call` can fail it.
+                return Poll::Ready(Ok(()));
+            }
+            return Poll::Pending;
+        }
+
+        self.deadline = None;
+        self.failing = false;
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        let len = self.services.ready_len();
+        if len == 0 {
+            assert!(
+                !matches!(self.unready, Unready::Wait),
+                "called before ready"
+            );
+            let status = Status::unavailable("no ready endpoints");
+            return Box::pin(async move { Err::<Response, _>(status.into()) });
+        }
+
+        let index = self
+            .policy
+            .pick(&request, &Endpoints::new(&ReadySet(&self.services)));
+        self.services.call_ready_index(index.min(len - 1), request)
+    }
+}
+
+struct ReadySet<'a, K: Hash + Eq>(&'a ReadyCache<K, Connection, Request>);
+
+impl<K: Hash + Eq> ReadyEndpoints for ReadySet<'_, K> {
+    fn len(&self) -> usize {
+        self.0.ready_len()
+    }
+
+    fn get(&self, index: usize) -> &Connection {
+        let (_, connection) = self.0.get_ready_index(index).expect("invalid index");
+        connection
+    }
+}
+
+impl<D> fmt::Debug for Balance<D>
+where
+    D: Discover<Service = Connection>,
+    D::Key: Hash,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Balance")
+            .field("ready", &self.services.ready_len())
+            .field("pending", &self.services.pending_len())
+            .finish()
+    }
+}
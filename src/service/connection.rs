@@ -1,4 +1,12 @@
-use crate::service::{grpc_timeout::GrpcTimeout, reconnect::Reconnect, AddOrigin, UserAgent};
+use crate::channel::is_transport_error;
+use crate::channel::EndpointMetadata;
+use crate::service::GoAway;
+use crate::service::{
+    grpc_timeout::GrpcTimeout,
+    reconnect::{ErrorHook, Reconnect},
+    AddAuthorization, AddOrigin, EndpointService, PingIo, PingRtt, RefreshTimeout, ResponseBody,
+    StreamTimeout, ThrottledIo, UserAgent,
+};
 use crate::{BoxError, BoxFuture, ChannelBuilder};
 
 use http::Uri;
@@ -7,11 +15,15 @@ use hyper::client::connect::Connection as HyperConnection;
 use hyper::client::service::Connect as HyperConnect;
 use std::{
     fmt,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
     task::{Context, Poll},
+    time::Duration,
 };
 use tokio::io::{AsyncRead, AsyncWrite};
-use tonic::body::BoxBody;
-use tower::load::Load;
+use tonic::{body::BoxBody, Status};
 use tower::{
     layer::Layer,
     limit::{concurrency::ConcurrencyLimitLayer, rate::RateLimitLayer},
@@ -20,11 +32,19 @@ use tower::{
 };
 use tower_service::Service;
 
+const DEFAULT_RECONNECT_BACKOFF_RESET: Duration = Duration::from_secs(60);
+
 pub(crate) type Request = http::Request<BoxBody>;
-pub(crate) type Response = http::Response<hyper::Body>;
+pub(crate) type Response = http::Response<ResponseBody>;
 
 pub(crate) struct Connection {
     inner: BoxService<Request, Response, BoxError>,
+    uri: Uri,
+    metadata: Arc<EndpointMetadata>,
+    in_flight: Arc<AtomicUsize>,
+    ping_rtt: Arc<PingRtt>,
+    // The GOAWAY received on the current connection, if any.
+    go_away: Arc<Mutex<Option<GoAway>>>,
 }
 
 impl Connection {
@@ -54,25 +74,101 @@ impl Connection {
             settings.http2_adaptive_window(val);
         }
 
-        let stack = ServiceBuilder::new()
-            .layer_fn(|s| {
-                let origin = endpoint.origin.as_ref().unwrap_or(&endpoint.uri).clone();
+        if let Some(val) = endpoint.http2_max_send_buf_size {
+            settings.http2_max_send_buf_size(val.min(u32::MAX as usize));
+        }
 
-                AddOrigin::new(s, origin)
-            })
+        let stack = ServiceBuilder::new()
+            .layer_fn(|s| AddOrigin::new(s, endpoint.origin_uri()))
             .layer_fn(|s| UserAgent::new(s, endpoint.user_agent.clone()))
+            .layer_fn(|s| AddAuthorization::new(s, endpoint.basic_authorization()))
             .layer_fn(|s| GrpcTimeout::new(s, endpoint.timeout))
+            .layer_fn(|s| {
+                StreamTimeout::new(
+                    s,
+                    endpoint.response_headers_timeout,
+                    endpoint.first_byte_timeout,
+                    endpoint.stream_inactivity_timeout,
+                )
+            })
             .option_layer(endpoint.concurrency_limit.map(ConcurrencyLimitLayer::new))
             .option_layer(endpoint.rate_limit.map(|(l, d)| RateLimitLayer::new(l, d)))
+            .layer_fn(RefreshTimeout::new)
             .into_inner();
 
+        let throttle = endpoint.throttle.clone();
+        let ping_rtt = Arc::new(PingRtt::default());
+        let rtt = ping_rtt.clone();
+        let go_away = Arc::new(Mutex::new(None));
+        let on_go_away = {
+            let uri = endpoint.uri.clone();
+            let notify = endpoint.on_go_away.clone();
+            let hook = endpoint.go_away_hook.clone();
+            let go_away = go_away.clone();
+            Arc::new(move |received: GoAway| {
+                tracing::debug!(%uri, go_away = %received, "connection is closing");
+                if let Some(hook) = &hook {
+                    hook(&uri, &received);
+                }
+                if let Some(notify) = &notify {
+                    notify.notify_one();
+                }
+                *go_away.lock().unwrap() = Some(received);
+            })
+        };
+        let last_go_away = go_away.clone();
+        let connector = connector.map_response(move |io| {
+            // A new connection, which the previous connection's GOAWAY doesn't apply to.
+            *last_go_away.lock().unwrap() = None;
+            PingIo::client(
+                ThrottledIo::new(io, throttle.as_ref()),
+                rtt.clone(),
+                Some(on_go_away.clone()),
+            )
+        });
         let connector = HyperConnect::new(connector, settings);
-        let conn = Reconnect::new(connector, endpoint.uri.clone(), is_lazy);
+        let reset_backoff_after = endpoint
+            .reconnect_backoff_reset
+            .unwrap_or(DEFAULT_RECONNECT_BACKOFF_RESET);
+        let on_connect_error = endpoint.connect_error_hook.clone().map(|hook| {
+            let uri = endpoint.uri.clone();
+            Arc::new(move |error: &BoxError| hook(&uri, &**error)) as ErrorHook
+        });
+        let conn = Reconnect::new(
+            connector,
+            endpoint.connect_uri(),
+            is_lazy,
+            endpoint.connect_backoff.clone(),
+            reset_backoff_after,
+            endpoint.on_connection_failure.clone(),
+            on_connect_error,
+        );
+        let conn = match endpoint.connect_retries.clone() {
+            Some((retries, backoff)) => conn.connect_retries(retries, backoff),
+            None => conn,
+        };
+        let conn = conn.connection_deadline(endpoint.connection_deadline);
 
         let inner = stack.layer(conn);
+        let inner = if endpoint.layers.is_empty() {
+            BoxService::new(inner)
+        } else {
+            // The first layer added is the outermost.
+            let inner = endpoint
+                .layers
+                .iter()
+                .rev()
+                .fold(EndpointService::new(inner), |inner, layer| layer(inner));
+            BoxService::new(inner)
+        };
 
         Self {
-            inner: BoxService::new(inner),
+            inner,
+            uri: endpoint.uri,
+            metadata: Arc::new(endpoint.metadata),
+            in_flight: Arc::new(AtomicUsize::new(0)),
+            ping_rtt,
+            go_away,
         }
     }
 
@@ -95,6 +191,48 @@ impl Connection {
     {
         Self::new(connector, endpoint, true)
     }
+
+    pub(crate) fn uri(&self) -> &Uri {
+        &self.uri
+    }
+
+    pub(crate) fn metadata(&self) -> &EndpointMetadata {
+        &self.metadata
+    }
+
+    /// The number of requests which have been sent and are awaiting a response.
+    pub(crate) fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+
+    /// The round-trip time of the connection's most recently acknowledged PING.
+    pub(crate) fn ping_rtt(&self) -> &Arc<PingRtt> {
+        &self.ping_rtt
+    }
+}
+
+/// Explain that a request failed because the server sent `go_away`, keeping `error` as the
+/// source so that it can still be inspected.
+fn with_go_away(error: BoxError, go_away: &GoAway) -> BoxError {
+    let mut status = Status::unavailable(format!("{}; {}", error, go_away));
+    status.set_source(Arc::from(error));
+    Box::new(status)
+}
+
+/// Decrements a connection's in-flight count when dropped.
+struct InFlight(Arc<AtomicUsize>);
+
+impl InFlight {
+    fn new(count: Arc<AtomicUsize>) -> Self {
+        count.fetch_add(1, Ordering::Relaxed);
+        InFlight(count)
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
 }
 
 impl Service<Request> for Connection {
@@ -107,15 +245,19 @@ impl Service<Request> for Connection {
     }
 
     fn call(&mut self, req: Request) -> Self::Future {
-        self.inner.call(req)
-    }
-}
-
-impl Load for Connection {
-    type Metric = usize;
-
-    fn load(&self) -> Self::Metric {
-        0
+        let in_flight = InFlight::new(self.in_flight.clone());
+        let go_away = self.go_away.clone();
+        let fut = self.inner.call(req);
+        Box::pin(async move {
+            let _in_flight = in_flight;
+            fut.await.map_err(|error| {
+                let go_away = go_away.lock().unwrap().clone();
+                match go_away {
+                    Some(go_away) if is_transport_error(&error) => with_go_away(error, &go_away),
+                    _ => error,
+                }
+            })
+        })
     }
 }
 
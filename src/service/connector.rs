@@ -7,17 +7,24 @@ use std::task::{Context, Poll};
 use tower::make::MakeConnection;
 use tower_service::Service;
 
-pub(crate) fn connector<C>(inner: C, tls: TlsConnector) -> Connector<C> {
+/// A connector which negotiates TLS over the connections made by `inner`, unless `tls` is
+/// `None`.
+pub(crate) fn connector<C>(inner: C, tls: Option<TlsConnector>) -> Connector<C> {
     Connector::new(inner, tls)
 }
 
+/// A connector which uses the connections made by `inner` without TLS.
+pub(crate) fn raw_connector<C>(inner: C) -> Connector<C> {
+    Connector::new(inner, None)
+}
+
 pub(crate) struct Connector<C> {
     inner: C,
-    tls: TlsConnector,
+    tls: Option<TlsConnector>,
 }
 
 impl<C> Connector<C> {
-    fn new(inner: C, tls: TlsConnector) -> Self {
+    fn new(inner: C, tls: Option<TlsConnector>) -> Self {
         Self { inner, tls }
     }
 }
@@ -45,8 +52,10 @@ where
         Box::pin(async move {
             let io = connect.await?;
 
-            let conn = tls.connect(io).await?;
-            Ok(BoxedIo::new(conn))
+            match tls {
+                Some(tls) => Ok(tls.connect(io).await?),
+                None => Ok(BoxedIo::new(io)),
+            }
         })
     }
 }
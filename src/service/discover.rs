@@ -2,9 +2,11 @@ use super::connection::Connection;
 use crate::{service, BoxError, ChannelBuilder};
 
 use std::{
-    hash::Hash,
+    collections::{hash_map::DefaultHasher, HashMap, HashSet, VecDeque},
+    hash::{Hash, Hasher},
     pin::Pin,
     task::{Context, Poll},
+    time::Duration,
 };
 use tokio::sync::mpsc::Receiver;
 
@@ -15,11 +17,24 @@ type DiscoverResult<K, S, E> = Result<Change<K, S>, E>;
 
 pub(crate) struct DynamicServiceStream<K: Hash + Eq + Clone> {
     changes: Receiver<Change<K, ChannelBuilder>>,
+    subset: Option<Subset<K, ChannelBuilder>>,
+    queue: VecDeque<Change<K, ChannelBuilder>>,
+    // The keepalive ping interval and timeout applied to every endpoint.
+    ping_health: Option<(Duration, Duration)>,
 }
 
 impl<K: Hash + Eq + Clone> DynamicServiceStream<K> {
-    pub(crate) fn new(changes: Receiver<Change<K, ChannelBuilder>>) -> Self {
-        Self { changes }
+    pub(crate) fn new(
+        changes: Receiver<Change<K, ChannelBuilder>>,
+        subset: Option<Subset<K, ChannelBuilder>>,
+        ping_health: Option<(Duration, Duration)>,
+    ) -> Self {
+        Self {
+            changes,
+            subset,
+            queue: VecDeque::new(),
+            ping_health,
+        }
     }
 }
 
@@ -27,25 +42,183 @@ impl<K: Hash + Eq + Clone> Stream for DynamicServiceStream<K> {
     type Item = DiscoverResult<K, Connection, BoxError>;
 
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        let c = &mut self.changes;
-        match Pin::new(&mut *c).poll_recv(cx) {
-            Poll::Pending | Poll::Ready(None) => Poll::Pending,
-            Poll::Ready(Some(change)) => match change {
-                Change::Insert(k, endpoint) => {
-                    let mut http = hyper::client::connect::HttpConnector::new();
-                    http.set_nodelay(endpoint.tcp_nodelay);
-                    http.set_keepalive(endpoint.tcp_keepalive);
-                    http.enforce_http(false);
-                    // TODO unwrap
-                    let connector = service::connector(http, endpoint.tls_connector().unwrap());
-                    let connection = Connection::lazy(connector, endpoint);
-                    let change = Ok(Change::Insert(k, connection));
-                    Poll::Ready(Some(change))
+        let change = loop {
+            if let Some(change) = self.queue.pop_front() {
+                if let Change::Insert(_, endpoint) = &change {
+                    // Balanced channels are created without connecting, so invalid endpoints
+                    // are only noticed here.
+                    if let Err(error) = endpoint.validate() {
+                        tracing::warn!(%error, uri = %endpoint.uri, "ignoring invalid endpoint");
+                        continue;
+                    }
                 }
-                Change::Remove(k) => Poll::Ready(Some(Ok(Change::Remove(k)))),
-            },
+                break change;
+            }
+
+            let change = match Pin::new(&mut self.changes).poll_recv(cx) {
+                Poll::Pending | Poll::Ready(None) => return Poll::Pending,
+                Poll::Ready(Some(change)) => change,
+            };
+            let this = &mut *self;
+            match &mut this.subset {
+                Some(subset) => subset.apply(change, &mut this.queue),
+                None => this.queue.push_back(change),
+            }
+        };
+
+        match change {
+            Change::Insert(k, mut endpoint) => {
+                if let Some((interval, timeout)) = self.ping_health {
+                    endpoint = endpoint
+                        .http2_keep_alive_interval(interval)
+                        .keep_alive_timeout(timeout)
+                        .keep_alive_while_idle(true);
+                }
+                let http = endpoint.tcp_connector();
+                // TODO unwrap
+                let connector = service::connector(http, endpoint.tls_connector().unwrap());
+                let connection = Connection::lazy(connector, endpoint);
+                let change = Ok(Change::Insert(k, connection));
+                Poll::Ready(Some(change))
+            }
+            Change::Remove(k) => Poll::Ready(Some(Ok(Change::Remove(k)))),
         }
     }
 }
 
 impl<K: Hash + Eq + Clone> Unpin for DynamicServiceStream<K> {}
+
+/// Deterministically selects a subset of endpoints using rendezvous hashing.
+///
+/// Each endpoint is scored by hashing its key with the client's seed, and the `size` endpoints
+/// with the highest scores are selected. Clients with different seeds select different subsets,
+/// and adding or removing an endpoint changes the selected subset by at most one endpoint.
+pub(crate) struct Subset<K, V> {
+    size: usize,
+    seed: u64,
+    endpoints: HashMap<K, (u64, V)>,
+    selected: HashSet<K>,
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> Subset<K, V> {
+    pub(crate) fn new(size: usize, seed: u64) -> Self {
+        Subset {
+            size,
+            seed,
+            endpoints: HashMap::new(),
+            selected: HashSet::new(),
+        }
+    }
+
+    /// Record `change`, pushing the resulting changes to the selected subset onto `out`.
+    fn apply(&mut self, change: Change<K, V>, out: &mut VecDeque<Change<K, V>>) {
+        match change {
+            Change::Insert(key, value) => {
+                let mut hasher = DefaultHasher::new();
+                self.seed.hash(&mut hasher);
+                key.hash(&mut hasher);
+                let score = hasher.finish();
+
+                // An updated endpoint which is already selected must be replaced.
+                if self.selected.contains(&key) {
+                    out.push_back(Change::Insert(key.clone(), value.clone()));
+                }
+                self.endpoints.insert(key, (score, value));
+            }
+            Change::Remove(key) => {
+                self.endpoints.remove(&key);
+            }
+        }
+
+        let mut ranked: Vec<_> = self.endpoints.iter().collect();
+        ranked.sort_unstable_by(|(_, (a, _)), (_, (b, _))| b.cmp(a));
+        let wanted: HashSet<K> = ranked
+            .into_iter()
+            .take(self.size)
+            .map(|(key, _)| key.clone())
+            .collect();
+
+        for key in self.selected.difference(&wanted) {
+            out.push_back(Change::Remove(key.clone()));
+        }
+        for key in wanted.difference(&self.selected) {
+            let (_, value) = &self.endpoints[key];
+            out.push_back(Change::Insert(key.clone(), value.clone()));
+        }
+        self.selected = wanted;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio_stream::StreamExt;
+
+    fn apply(subset: &mut Subset<u32, ()>, change: Change<u32, ()>) -> Vec<Change<u32, ()>> {
+        let mut out = VecDeque::new();
+        subset.apply(change, &mut out);
+        out.into_iter().collect()
+    }
+
+    fn selected(subset: &Subset<u32, ()>) -> Vec<u32> {
+        let mut selected: Vec<_> = subset.selected.iter().copied().collect();
+        selected.sort_unstable();
+        selected
+    }
+
+    #[test]
+    fn selects_deterministic_subset() {
+        let mut a = Subset::new(3, 42);
+        let mut b = Subset::new(3, 42);
+        for key in 0..20 {
+            apply(&mut a, Change::Insert(key, ()));
+            apply(&mut b, Change::Insert(19 - key, ()));
+        }
+        assert_eq!(selected(&a).len(), 3);
+        assert_eq!(selected(&a), selected(&b));
+    }
+
+    #[test]
+    fn removal_replaces_one_endpoint() {
+        let mut subset = Subset::new(3, 7);
+        for key in 0..20 {
+            apply(&mut subset, Change::Insert(key, ()));
+        }
+        let before = selected(&subset);
+
+        let changes = apply(&mut subset, Change::Remove(before[0]));
+        let after = selected(&subset);
+        assert_eq!(after.len(), 3);
+        assert_eq!(changes.len(), 2);
+        assert_eq!(
+            after.iter().filter(|key| before.contains(key)).count(),
+            2,
+            "only the removed endpoint should be replaced"
+        );
+    }
+
+    #[test]
+    fn removing_unselected_endpoint_is_silent() {
+        let mut subset = Subset::new(1, 7);
+        for key in 0..5 {
+            apply(&mut subset, Change::Insert(key, ()));
+        }
+        let unselected = (0..5).find(|key| !subset.selected.contains(key)).unwrap();
+        assert!(apply(&mut subset, Change::Remove(unselected)).is_empty());
+    }
+
+    #[tokio::test]
+    async fn ignores_invalid_endpoints() {
+        let valid = ChannelBuilder::new_plaintext("http://10.0.0.1:80").unwrap();
+        let invalid = valid.clone().keep_alive_timeout(Duration::from_secs(1));
+        let (tx, rx) = tokio::sync::mpsc::channel(2);
+        tx.try_send(Change::Insert(1, invalid)).unwrap();
+        tx.try_send(Change::Insert(2, valid)).unwrap();
+
+        let mut stream = DynamicServiceStream::new(rx, None, None);
+        match stream.next().await {
+            Some(Ok(Change::Insert(key, _))) => assert_eq!(key, 2),
+            _ => panic!("expected the valid endpoint"),
+        }
+    }
+}
@@ -0,0 +1,83 @@
+use super::ResponseBody;
+use crate::{BoxError, BoxFuture};
+
+use bytes::Bytes;
+use http::{Request, Response};
+use http_body::Body as HttpBody;
+use std::{
+    any::Any,
+    fmt,
+    sync::Arc,
+    task::{Context, Poll},
+};
+use tonic::{body::BoxBody, Status};
+use tower::{util::BoxService, ServiceExt};
+use tower_service::Service;
+
+pub(crate) type EndpointLayer = Arc<dyn Fn(EndpointService) -> EndpointService + Send + Sync>;
+
+/// The service wrapped by a layer added with
+/// [`ChannelBuilder::layer`](crate::ChannelBuilder::layer), which sends requests to an endpoint.
+///
+/// It accepts requests with any body, so layers which wrap the request body can be used, and the
+/// response bodies of the layer's service are converted to a [`ResponseBody`], keeping their
+/// trailers.
+pub struct EndpointService {
+    inner: BoxService<Request<BoxBody>, Response<ResponseBody>, BoxError>,
+}
+
+impl EndpointService {
+    pub(crate) fn new<S, B>(inner: S) -> Self
+    where
+        S: Service<Request<BoxBody>, Response = Response<B>> + Send + 'static,
+        S::Future: Send + 'static,
+        S::Error: Into<BoxError>,
+        B: HttpBody<Data = Bytes> + Send + 'static,
+        B::Error: Into<BoxError>,
+    {
+        let inner = inner
+            .map_response(|response| response.map(ResponseBody::new))
+            .map_err(Into::into);
+        EndpointService {
+            inner: BoxService::new(inner),
+        }
+    }
+}
+
+impl<B> Service<Request<B>> for EndpointService
+where
+    B: HttpBody<Data = Bytes> + Send + 'static,
+    B::Error: Into<BoxError>,
+{
+    type Response = Response<ResponseBody>;
+    type Error = BoxError;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<B>) -> Self::Future {
+        self.inner.call(request.map(into_box_body))
+    }
+}
+
+impl fmt::Debug for EndpointService {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EndpointService").finish()
+    }
+}
+
+fn into_box_body<B>(body: B) -> BoxBody
+where
+    B: HttpBody<Data = Bytes> + Send + 'static,
+    B::Error: Into<BoxError>,
+{
+    let mut body = Some(body);
+    if let Some(body) = (&mut body as &mut dyn Any).downcast_mut::<Option<BoxBody>>() {
+        return body.take().expect("body is present");
+    }
+    body.expect("body is present")
+        .map_err(|error| Status::from_error(error.into()))
+        .boxed_unsync()
+}
@@ -0,0 +1,345 @@
+use crate::BoxError;
+
+use http::Request;
+use pin_project::pin_project;
+use rand::Rng;
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::time::{sleep, Sleep};
+use tonic::Status;
+use tower::Layer;
+use tower_service::Service;
+
+/// A fault to inject into a percentage of requests, see [`FaultInjectionLayer`].
+#[derive(Debug, Clone)]
+pub struct Fault {
+    method: Option<String>,
+    percentage: f64,
+    delay: Option<Duration>,
+    status: Option<Status>,
+}
+
+impl Fault {
+    /// Create a fault which applies to `percentage` (between 0 and 100) of requests.
+    ///
+    /// The fault does nothing until a [`delay`](Fault::delay) or [`status`](Fault::status) is set.
+    pub fn new(percentage: f64) -> Self {
+        Fault {
+            method: None,
+            percentage: percentage.clamp(0.0, 100.0),
+            delay: None,
+            status: None,
+        }
+    }
+
+    /// Only apply the fault to requests for `method`.
+    ///
+    /// `method` is a gRPC method path such as `/helloworld.Greeter/SayHello`, or a service path
+    /// ending in `/` such as `/helloworld.Greeter/` to match all of the service's methods.
+    pub fn method(self, method: impl Into<String>) -> Self {
+        Fault {
+            method: Some(method.into()),
+            ..self
+        }
+    }
+
+    /// Delay affected requests by `delay` before sending them to the inner service.
+    pub fn delay(self, delay: Duration) -> Self {
+        Fault {
+            delay: Some(delay),
+            ..self
+        }
+    }
+
+    /// Fail affected requests with `status`, without sending them to the inner service.
+    pub fn status(self, status: Status) -> Self {
+        Fault {
+            status: Some(status),
+            ..self
+        }
+    }
+
+    fn matches(&self, path: &str) -> bool {
+        match &self.method {
+            None => true,
+            Some(method) if method.ends_with('/') => path.starts_with(method.as_str()),
+            Some(method) => path == method,
+        }
+    }
+}
+
+/// A layer which injects latency and errors into requests, for testing how services behave
+/// under failure.
+///
+/// The layer can be used on the client, by wrapping a [`Channel`](crate::Channel), or on the
+/// server with [`Server::layer`](crate::Server::layer). For each request, the first [`Fault`]
+/// whose method matches is applied with its percentage probability.
+///
+/// ```no_run
+/// # use tonic_transport::{Fault, FaultInjectionLayer};
+/// # use std::time::Duration;
+/// let layer = FaultInjectionLayer::new([
+///     Fault::new(10.0)
+///         .method("/helloworld.Greeter/SayHello")
+///         .status(tonic::Status::unavailable("injected fault")),
+///     Fault::new(50.0).delay(Duration::from_millis(200)),
+/// ]);
+/// ```
+#[derive(Debug, Clone)]
+pub struct FaultInjectionLayer {
+    faults: Arc<[Fault]>,
+}
+
+impl FaultInjectionLayer {
+    /// Create a layer which injects `faults`.
+    pub fn new(faults: impl IntoIterator<Item = Fault>) -> Self {
+        FaultInjectionLayer {
+            faults: faults.into_iter().collect(),
+        }
+    }
+}
+
+impl<S> Layer<S> for FaultInjectionLayer {
+    type Service = FaultInjection<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        FaultInjection {
+            inner,
+            faults: self.faults.clone(),
+        }
+    }
+}
+
+/// Middleware which injects faults, see [`FaultInjectionLayer`].
+#[derive(Clone)]
+pub struct FaultInjection<S> {
+    inner: S,
+    faults: Arc<[Fault]>,
+}
+
+impl<S, ReqBody> Service<Request<ReqBody>> for FaultInjection<S>
+where
+    S: Service<Request<ReqBody>> + Clone,
+    S::Error: Into<BoxError>,
+{
+    type Response = S::Response;
+    type Error = BoxError;
+    type Future = ResponseFuture<S, Request<ReqBody>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        let path = req.uri().path();
+        let fault = self
+            .faults
+            .iter()
+            .find(|fault| fault.matches(path))
+            .filter(|fault| rand::thread_rng().gen_range(0.0..100.0) < fault.percentage);
+
+        let (delay, status) = match fault {
+            Some(fault) => {
+                tracing::trace!(?fault, %path, "injecting fault");
+                (fault.delay, fault.status.clone())
+            }
+            None => (None, None),
+        };
+
+        let state = match (delay, status) {
+            (None, None) => State::Called(self.inner.call(req)),
+            (None, Some(status)) => State::Failed(Some(status)),
+            (Some(delay), status) => {
+                // The request is sent once the delay has passed, so the service which is ready
+                // is kept for it, as in `tower::ServiceExt::oneshot`.
+                let call = status.is_none().then(|| {
+                    let clone = self.inner.clone();
+                    (std::mem::replace(&mut self.inner, clone), req)
+                });
+                State::Delayed {
+                    delay: sleep(delay),
+                    call,
+                    status,
+                }
+            }
+        };
+        ResponseFuture { state }
+    }
+}
+
+impl<S> fmt::Debug for FaultInjection<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FaultInjection")
+            .field("faults", &self.faults)
+            .finish()
+    }
+}
+
+/// Response future for [`FaultInjection`].
+#[pin_project]
+pub struct ResponseFuture<S, Req>
+where
+    S: Service<Req>,
+{
+    #[pin]
+    state: State<S, Req>,
+}
+
+#[pin_project(project = StateProj)]
+enum State<S, Req>
+where
+    S: Service<Req>,
+{
+    /// Waiting for an injected delay, before sending the request or failing with `status`.
+    Delayed {
+        #[pin]
+        delay: Sleep,
+        call: Option<(S, Req)>,
+        status: Option<Status>,
+    },
+    Called(#[pin] S::Future),
+    Failed(Option<Status>),
+}
+
+impl<S, Req> Future for ResponseFuture<S, Req>
+where
+    S: Service<Req>,
+    S::Error: Into<BoxError>,
+{
+    type Output = Result<S::Response, BoxError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.project().state;
+        loop {
+            match state.as_mut().project() {
+                StateProj::Delayed {
+                    delay,
+                    call,
+                    status,
+                } => {
+                    futures_util::ready!(delay.poll(cx));
+                    let next = match status.take() {
+                        Some(status) => State::Failed(Some(status)),
+                        None => {
+                            let (mut inner, req) = call.take().expect("polled after ready");
+                            State::Called(inner.call(req))
+                        }
+                    };
+                    state.set(next);
+                }
+                StateProj::Called(inner) => return inner.poll(cx).map_err(Into::into),
+                StateProj::Failed(status) => {
+                    let status = status.take().expect("polled after ready");
+                    return Poll::Ready(Err(status.into()));
+                }
+            }
+        }
+    }
+}
+
+impl<S, Req> fmt::Debug for ResponseFuture<S, Req>
+where
+    S: Service<Req>,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ResponseFuture").finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        convert::Infallible,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+    use tokio::time::Instant;
+    use tower::ServiceExt;
+
+    /// A service which counts its calls.
+    fn counting(
+        calls: &Arc<AtomicUsize>,
+    ) -> impl Service<Request<()>, Response = Instant, Error = Infallible> + Clone {
+        let calls = calls.clone();
+        tower::service_fn(move |_: Request<()>| {
+            calls.fetch_add(1, Ordering::SeqCst);
+            async { Ok(Instant::now()) }
+        })
+    }
+
+    fn request(path: &str) -> Request<()> {
+        Request::builder().uri(path).body(()).unwrap()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn delays_requests_before_sending_them() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let layer = FaultInjectionLayer::new([Fault::new(100.0).delay(Duration::from_secs(3))]);
+        let mut svc = layer.layer(counting(&calls));
+
+        let start = Instant::now();
+        let response = svc.ready().await.unwrap().call(request("/a.A/B"));
+        tokio::task::yield_now().await;
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+
+        let called_at = response.await.unwrap();
+        assert_eq!(called_at - start, Duration::from_secs(3));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn aborts_requests_with_the_status() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let layer = FaultInjectionLayer::new([
+            Fault::new(100.0)
+                .method("/a.A/")
+                .delay(Duration::from_secs(1))
+                .status(Status::unavailable("injected")),
+            Fault::new(100.0).status(Status::aborted("injected")),
+        ]);
+        let svc = layer.layer(counting(&calls));
+
+        let start = Instant::now();
+        let error = svc.clone().oneshot(request("/a.A/B")).await.unwrap_err();
+        let status = error.downcast::<Status>().unwrap();
+        assert_eq!(status.code(), tonic::Code::Unavailable);
+        assert_eq!(start.elapsed(), Duration::from_secs(1));
+
+        let error = svc.oneshot(request("/b.B/C")).await.unwrap_err();
+        let status = error.downcast::<Status>().unwrap();
+        assert_eq!(status.code(), tonic::Code::Aborted);
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn samples_the_percentage_of_requests() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let layer = FaultInjectionLayer::new([
+            Fault::new(0.0)
+                .method("/a.A/B")
+                .status(Status::unavailable("injected")),
+            Fault::new(50.0).status(Status::unavailable("injected")),
+        ]);
+        let svc = layer.layer(counting(&calls));
+
+        for _ in 0..100 {
+            svc.clone().oneshot(request("/a.A/B")).await.unwrap();
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 100);
+
+        let mut failed = 0;
+        for _ in 0..1000 {
+            if svc.clone().oneshot(request("/a.A/C")).await.is_err() {
+                failed += 1;
+            }
+        }
+        assert!((350..650).contains(&failed), "{} requests failed", failed);
+        assert_eq!(calls.load(Ordering::SeqCst), 1100 - failed);
+    }
+}
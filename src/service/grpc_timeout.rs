@@ -1,3 +1,4 @@
+use crate::service::Deadline;
 use crate::{BoxError, OptionPin, OptionPinProj};
 use http::{HeaderMap, HeaderValue, Request};
 use pin_project::pin_project;
@@ -8,15 +9,48 @@ use std::{
     task::{Context, Poll},
     time::Duration,
 };
-use tokio::time::Sleep;
+use tokio::time::{Instant, Sleep};
+use tonic::Status;
 use tower_service::Service;
 
-const GRPC_TIMEOUT_HEADER: &str = "grpc-timeout";
+pub(crate) const GRPC_TIMEOUT_HEADER: &str = "grpc-timeout";
+
+/// A request extension which sets the timeout of a single call, overriding
+/// [`ChannelBuilder::timeout`](crate::ChannelBuilder::timeout).
+///
+/// This is useful when one channel is used for both short unary calls and long streaming calls.
+/// Like the channel's timeout, it is not sent to the server; a `grpc-timeout` header on the
+/// request still applies if it is shorter.
+///
+/// ```no_run
+/// # use tonic_transport::CallTimeout;
+/// # use std::time::Duration;
+/// let mut request = tonic::Request::new(());
+/// request
+///     .extensions_mut()
+///     .insert(CallTimeout::new(Duration::from_secs(600)));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CallTimeout(Duration);
+
+impl CallTimeout {
+    /// Create a timeout for a call.
+    pub fn new(timeout: Duration) -> Self {
+        CallTimeout(timeout)
+    }
+
+    /// The timeout.
+    pub fn get(&self) -> Duration {
+        self.0
+    }
+}
 
 #[derive(Debug, Clone)]
 pub(crate) struct GrpcTimeout<S> {
     inner: S,
     server_timeout: Option<Duration>,
+    // The longest `grpc-timeout` accepted from a client, longer timeouts are reduced to this.
+    max_timeout: Option<Duration>,
 }
 
 impl<S> GrpcTimeout<S> {
@@ -24,6 +58,14 @@ impl<S> GrpcTimeout<S> {
         Self {
             inner,
             server_timeout,
+            max_timeout: None,
+        }
+    }
+
+    pub(crate) fn with_max_timeout(self, max_timeout: Option<Duration>) -> Self {
+        Self {
+            max_timeout,
+            ..self
         }
     }
 }
@@ -41,14 +83,37 @@ where
         self.inner.poll_ready(cx).map_err(Into::into)
     }
 
-    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
-        let client_timeout = try_parse_grpc_timeout(req.headers()).unwrap_or_else(|e| {
-            tracing::trace!("Error parsing `grpc-timeout` header {:?}", e);
-            None
-        });
+    fn call(&mut self, mut req: Request<ReqBody>) -> Self::Future {
+        let mut client_timeout = match try_parse_grpc_timeout(req.headers()) {
+            Ok(timeout) => timeout,
+            Err(e) => {
+                tracing::debug!("Error parsing `grpc-timeout` header {:?}", e);
+                return ResponseFuture {
+                    inner: OptionPin::None,
+                    sleep: OptionPin::None,
+                    error: Some(Status::internal("malformed grpc-timeout header")),
+                };
+            }
+        };
+
+        // Clamp the client's timeout, and tell the handler about the shorter deadline so that it
+        // is propagated to any requests the handler makes.
+        if let (Some(client), Some(max)) = (client_timeout, self.max_timeout) {
+            if client > max {
+                req.headers_mut()
+                    .insert(GRPC_TIMEOUT_HEADER, encode_grpc_timeout(max));
+                client_timeout = Some(max);
+            }
+        }
+
+        let server_timeout = req
+            .extensions()
+            .get::<CallTimeout>()
+            .map(CallTimeout::get)
+            .or(self.server_timeout);
 
         // Use the shorter of the two durations, if either are set
-        let timeout_duration = match (client_timeout, self.server_timeout) {
+        let timeout_duration = match (client_timeout, server_timeout) {
             (None, None) => None,
             (Some(dur), None) => Some(dur),
             (None, Some(dur)) => Some(dur),
@@ -57,13 +122,18 @@ where
                 Some(shorter_duration)
             }
         };
+        if let Some(timeout) = timeout_duration {
+            req.extensions_mut()
+                .insert(Deadline(Instant::now() + timeout));
+        }
 
         ResponseFuture {
-            inner: self.inner.call(req),
+            inner: OptionPin::Some(self.inner.call(req)),
             sleep: timeout_duration
                 .map(tokio::time::sleep)
                 .map(OptionPin::Some)
                 .unwrap_or(OptionPin::None),
+            error: None,
         }
     }
 }
@@ -71,9 +141,11 @@ where
 #[pin_project]
 pub(crate) struct ResponseFuture<F> {
     #[pin]
-    inner: F,
+    inner: OptionPin<F>,
     #[pin]
     sleep: OptionPin<Sleep>,
+    // The request was rejected with this status, without calling the inner service.
+    error: Option<Status>,
 }
 
 impl<F, Res, E> Future for ResponseFuture<F>
@@ -86,7 +158,14 @@ where
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         let this = self.project();
 
-        if let Poll::Ready(result) = this.inner.poll(cx) {
+        let inner = match this.inner.project() {
+            OptionPinProj::Some(inner) => inner,
+            OptionPinProj::None => {
+                let status = this.error.take().expect("polled after ready");
+                return Poll::Ready(Err(status.into()));
+            }
+        };
+        if let Poll::Ready(result) = inner.poll(cx) {
             return Poll::Ready(result.map_err(Into::into));
         }
 
@@ -106,7 +185,7 @@ const SECONDS_IN_MINUTE: u64 = 60;
 /// the value we attempted to parse.
 ///
 /// Follows the [gRPC over HTTP2 spec](https://github.com/grpc/grpc/blob/master/doc/PROTOCOL-HTTP2.md).
-fn try_parse_grpc_timeout(
+pub(crate) fn try_parse_grpc_timeout(
     headers: &HeaderMap<HeaderValue>,
 ) -> Result<Option<Duration>, &HeaderValue> {
     match headers.get(GRPC_TIMEOUT_HEADER) {
@@ -128,6 +207,11 @@ fn try_parse_grpc_timeout(
                 return Err(val);
             }
 
+            // `parse` would accept a leading `+`, which the spec doesn't allow.
+            if !timeout_value.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(val);
+            }
+
             let timeout_value: u64 = timeout_value.parse().map_err(|_| val)?;
 
             let duration = match timeout_unit {
@@ -152,6 +236,29 @@ fn try_parse_grpc_timeout(
     }
 }
 
+/// Encodes `timeout` as a `grpc-timeout` header value, using the most precise unit which fits in
+/// the 8 digits allowed by the spec. The value is rounded down.
+pub(crate) fn encode_grpc_timeout(timeout: Duration) -> HeaderValue {
+    const MAX_VALUE: u128 = 99_999_999;
+
+    let nanos = timeout.as_nanos();
+    let units: [(u128, char); 6] = [
+        (1, 'n'),
+        (1_000, 'u'),
+        (1_000_000, 'm'),
+        (1_000_000_000, 'S'),
+        (SECONDS_IN_MINUTE as u128 * 1_000_000_000, 'M'),
+        (SECONDS_IN_HOUR as u128 * 1_000_000_000, 'H'),
+    ];
+    let (value, unit) = units
+        .iter()
+        .map(|&(nanos_per_unit, unit)| (nanos / nanos_per_unit, unit))
+        .find(|&(value, _)| value <= MAX_VALUE)
+        .unwrap_or((MAX_VALUE, 'H'));
+
+    HeaderValue::try_from(format!("{}{}", value, unit)).expect("timeout is a valid header value")
+}
+
 /// Error returned if a request didn't complete within the configured timeout.
 ///
 /// Timeouts can be configured either with [`Endpoint::timeout`], [`Server::timeout`], or by
@@ -245,6 +352,33 @@ mod tests {
         setup_map_try_parse(Some("123456789H")).unwrap().unwrap();
     }
 
+    #[test]
+    #[should_panic(expected = "+5S")]
+    fn test_sign() {
+        setup_map_try_parse(Some("+5S")).unwrap().unwrap();
+    }
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(encode_grpc_timeout(Duration::from_nanos(82)), "82n");
+        assert_eq!(encode_grpc_timeout(Duration::from_millis(1500)), "1500000u");
+        assert_eq!(encode_grpc_timeout(Duration::from_secs(3600)), "3600000m");
+        assert_eq!(
+            encode_grpc_timeout(Duration::from_secs(1_000_000)),
+            "1000000S"
+        );
+        assert_eq!(encode_grpc_timeout(Duration::MAX), "99999999H");
+
+        for timeout in [
+            Duration::from_millis(250),
+            Duration::from_secs(7 * 24 * 3600),
+        ] {
+            let mut headers = HeaderMap::new();
+            headers.insert(GRPC_TIMEOUT_HEADER, encode_grpc_timeout(timeout));
+            assert_eq!(try_parse_grpc_timeout(&headers).unwrap(), Some(timeout));
+        }
+    }
+
     #[test]
     #[should_panic(expected = "oneH")]
     fn test_invalid_digits() {
@@ -262,6 +396,23 @@ mod tests {
         true
     }
 
+    #[tokio::test(start_paused = true)]
+    async fn call_timeout_overrides_default() {
+        let pending = tower::service_fn(|_: Request<()>| {
+            futures_util::future::pending::<Result<(), BoxError>>()
+        });
+        let mut svc = GrpcTimeout::new(pending, Some(Duration::from_secs(1)));
+
+        let mut request = Request::new(());
+        request
+            .extensions_mut()
+            .insert(CallTimeout::new(Duration::from_secs(60)));
+        let start = tokio::time::Instant::now();
+        let error = svc.call(request).await.unwrap_err();
+        assert!(error.is::<TimeoutExpired>());
+        assert_eq!(start.elapsed(), Duration::from_secs(60));
+    }
+
     /// Newtype to implement `Arbitrary` for generating `String`s that are valid `HeaderValue`s.
     #[derive(Clone, Debug)]
     struct HeaderValueGen(String);
@@ -1,17 +1,54 @@
 pub(crate) use self::add_origin::AddOrigin;
+pub(crate) use self::authorization::AddAuthorization;
+pub use self::backoff::ConnectBackoff;
+pub(crate) use self::balance::{Balance, Unready};
 pub(crate) use self::connection::Connection;
-pub(crate) use self::connector::connector;
-pub(crate) use self::discover::DynamicServiceStream;
+pub(crate) use self::connector::{connector, raw_connector};
+pub(crate) use self::discover::{DynamicServiceStream, Subset};
+pub(crate) use self::endpoint_layer::EndpointLayer;
+pub use self::endpoint_layer::EndpointService;
+pub use self::fault::{Fault, FaultInjection, FaultInjectionLayer};
 pub(crate) use self::grpc_timeout::GrpcTimeout;
+#[cfg(windows)]
+pub(crate) use self::named_pipe::NamedPipeConnector;
+pub(crate) use self::pin_addr::PinAddr;
+pub use self::ping::GoAway;
+pub(crate) use self::ping::{PingIo, PingRtt};
+pub(crate) use self::refresh_timeout::{Deadline, RefreshTimeout};
+pub(crate) use self::replay::ReplayBody;
+pub use self::response_body::ResponseBody;
 pub use self::router::Routes;
+pub(crate) use self::stream_timeout::StreamTimeout;
+pub(crate) use self::tcp_connector::TcpConnector;
+pub use self::throttle::Throttle;
+pub(crate) use self::throttle::ThrottledIo;
+#[cfg(unix)]
+pub(crate) use self::unix::UnixConnector;
 pub(crate) use self::user_agent::UserAgent;
 
 mod add_origin;
+mod authorization;
+pub(crate) mod backoff;
+mod balance;
 mod connection;
 mod connector;
 mod discover;
+mod endpoint_layer;
+mod fault;
 pub(crate) mod grpc_timeout;
 pub(crate) mod io;
+#[cfg(windows)]
+mod named_pipe;
+mod pin_addr;
+mod ping;
 mod reconnect;
+mod refresh_timeout;
+mod replay;
+mod response_body;
 mod router;
+mod stream_timeout;
+mod tcp_connector;
+mod throttle;
+#[cfg(unix)]
+mod unix;
 mod user_agent;
@@ -0,0 +1,53 @@
+use crate::BoxFuture;
+
+use http::Uri;
+use std::{
+    io,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::net::windows::named_pipe::{ClientOptions, NamedPipeClient};
+use tower_service::Service;
+
+// The error opening a pipe whose instances are all connected to other clients.
+const ERROR_PIPE_BUSY: i32 = 231;
+// How long to wait before opening a busy pipe again.
+const BUSY_RETRY_DELAY: Duration = Duration::from_millis(50);
+
+/// Connects to a Windows named pipe, ignoring the URI it is called with.
+#[derive(Debug, Clone)]
+pub(crate) struct NamedPipeConnector {
+    name: Arc<str>,
+}
+
+impl NamedPipeConnector {
+    /// Connect to the pipe called `name`, such as `\\.\pipe\grpc`.
+    pub(crate) fn new(name: String) -> Self {
+        Self { name: name.into() }
+    }
+}
+
+impl Service<Uri> for NamedPipeConnector {
+    type Response = NamedPipeClient;
+    type Error = io::Error;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, _uri: Uri) -> Self::Future {
+        let name = self.name.clone();
+        Box::pin(async move {
+            // Wait for an instance of the pipe to be free, for at most the connect timeout.
+            loop {
+                match ClientOptions::new().open(&*name) {
+                    Err(error) if error.raw_os_error() == Some(ERROR_PIPE_BUSY) => {}
+                    result => return result,
+                }
+                tokio::time::sleep(BUSY_RETRY_DELAY).await;
+            }
+        })
+    }
+}
@@ -0,0 +1,95 @@
+use crate::{BoxError, BoxFuture};
+
+use http::{uri::Authority, Uri};
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+use tokio::net::TcpStream;
+use tower_service::Service;
+
+/// Connects to the address of the last successful connection rather than resolving the URI's
+/// host again, until connecting to that address fails.
+#[derive(Debug, Clone)]
+pub(crate) struct PinAddr<C> {
+    inner: C,
+    pinned: Arc<Mutex<Option<SocketAddr>>>,
+}
+
+impl<C> PinAddr<C> {
+    pub(crate) fn new(inner: C) -> Self {
+        PinAddr {
+            inner,
+            pinned: Arc::default(),
+        }
+    }
+}
+
+impl<C> Service<Uri> for PinAddr<C>
+where
+    C: Service<Uri, Response = TcpStream>,
+    C::Error: Into<BoxError>,
+    C::Future: Send + 'static,
+{
+    type Response = TcpStream;
+    type Error = BoxError;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        let pinned = *self.pinned.lock().unwrap();
+        let connect = match pinned {
+            Some(addr) => self.inner.call(with_addr(uri, addr)),
+            None => self.inner.call(uri),
+        };
+        let state = self.pinned.clone();
+
+        Box::pin(async move {
+            match connect.await {
+                Ok(io) => {
+                    if pinned.is_none() {
+                        if let Ok(addr) = io.peer_addr() {
+                            tracing::debug!(%addr, "pinning connections to address");
+                            *state.lock().unwrap() = Some(addr);
+                        }
+                    }
+                    Ok(io)
+                }
+                Err(error) => {
+                    if let Some(addr) = pinned {
+                        tracing::debug!(%addr, "failed to connect to pinned address, unpinning");
+                        *state.lock().unwrap() = None;
+                    }
+                    Err(error.into())
+                }
+            }
+        })
+    }
+}
+
+/// `uri` with its host and port replaced by `addr`.
+fn with_addr(uri: Uri, addr: SocketAddr) -> Uri {
+    let mut parts = uri.into_parts();
+    parts.authority =
+        Some(Authority::try_from(addr.to_string()).expect("socket addresses are authorities"));
+    Uri::from_parts(parts).expect("replacing the authority keeps the URI valid")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replaces_authority() {
+        let uri = Uri::from_static("https://example.com:8443/");
+        let addr: SocketAddr = "[::1]:8443".parse().unwrap();
+        assert_eq!(
+            with_addr(uri, addr),
+            Uri::from_static("https://[::1]:8443/")
+        );
+    }
+}
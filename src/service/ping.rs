@@ -0,0 +1,765 @@
+use crate::server::{Connected, StreamActivity};
+use crate::{Error, Result};
+
+use bytes::Bytes;
+use futures_util::task::AtomicWaker;
+use hyper::client::connect::{Connected as HyperConnected, Connection};
+use std::{
+    collections::VecDeque,
+    fmt, io, mem,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    sync::oneshot,
+    time::Instant,
+};
+
+const PREFACE_LEN: usize = 24;
+const FRAME_HEADER_LEN: usize = 9;
+const DATA_FRAME: u8 = 0x0;
+const HEADERS_FRAME: u8 = 0x1;
+const PUSH_PROMISE_FRAME: u8 = 0x5;
+const PING_FRAME: u8 = 0x6;
+const GO_AWAY_FRAME: u8 = 0x7;
+const WINDOW_UPDATE_FRAME: u8 = 0x8;
+const CONTINUATION_FRAME: u8 = 0x9;
+const ACK_FLAG: u8 = 0x1;
+const END_HEADERS_FLAG: u8 = 0x4;
+const PING_PAYLOAD_LEN: usize = 8;
+const PING_FRAME_LEN: usize = FRAME_HEADER_LEN + PING_PAYLOAD_LEN;
+// A frame type which HTTP/2 doesn't define, so the peer ignores the frame.
+const UNKNOWN_FRAME: u8 = 0xff;
+// Marks the payloads of the PINGs sent by `PingRtt::ping`, the rest is a counter.
+const PROBE_TAG: u64 = 0x7470 << 48;
+// The last stream ID and error code which start a GOAWAY's payload.
+const GO_AWAY_HEADER_LEN: usize = 8;
+// The most debug data kept from a GOAWAY.
+const MAX_GO_AWAY_DEBUG_DATA: usize = 1024;
+
+// The most PINGs which are waiting for an acknowledgement at once.
+const MAX_PENDING_PINGS: usize = 8;
+
+/// The round-trip time of the most recently acknowledged HTTP/2 PING on a connection, and how
+/// long the oldest PING has been waiting for an acknowledgement.
+#[derive(Debug, Default)]
+pub(crate) struct PingRtt {
+    rtt: AtomicU64,
+    unacked_since: Mutex<Option<Instant>>,
+    probes: Mutex<Probes>,
+    // Whether `probes` has requested PINGs, so the IO need not lock it otherwise.
+    requested: AtomicBool,
+    // Wakes the connection's task to send requested PINGs.
+    waker: AtomicWaker,
+}
+
+/// The PINGs requested with [`PingRtt::ping`] which haven't been sent yet.
+#[derive(Debug, Default)]
+struct Probes {
+    requested: VecDeque<oneshot::Sender<Duration>>,
+    // The number of `PingIo`s which can send the PINGs.
+    connections: usize,
+}
+
+impl PingRtt {
+    /// Send a PING on the connection and wait for its acknowledgement, returning its round-trip
+    /// time.
+    pub(crate) async fn ping(&self) -> Result<Duration> {
+        let (tx, rx) = oneshot::channel();
+        {
+            let mut probes = self.probes.lock().unwrap();
+            if probes.connections == 0 {
+                return Err(Error::ConnectionClosed);
+            }
+            probes.requested.push_back(tx);
+            self.requested.store(true, Ordering::Release);
+        }
+        self.waker.wake();
+        rx.await.map_err(|_| Error::ConnectionClosed)
+    }
+
+    /// Take a requested PING to send, skipping those whose caller has stopped waiting.
+    fn take_requested(&self) -> Option<oneshot::Sender<Duration>> {
+        if !self.requested.load(Ordering::Acquire) {
+            return None;
+        }
+        let mut probes = self.probes.lock().unwrap();
+        let tx = std::iter::from_fn(|| probes.requested.pop_front()).find(|tx| !tx.is_closed());
+        self.requested
+            .store(!probes.requested.is_empty(), Ordering::Release);
+        tx
+    }
+
+    fn connect(&self) {
+        self.probes.lock().unwrap().connections += 1;
+    }
+
+    /// Fail the requested PINGs when the last connection which could send them closes.
+    fn disconnect(&self) {
+        let mut probes = self.probes.lock().unwrap();
+        probes.connections -= 1;
+        if probes.connections == 0 {
+            probes.requested.clear();
+            self.requested.store(false, Ordering::Release);
+        }
+    }
+
+    pub(crate) fn get(&self) -> Option<Duration> {
+        match self.rtt.load(Ordering::Relaxed) {
+            0 => None,
+            nanos => Some(Duration::from_nanos(nanos)),
+        }
+    }
+
+    /// How long the oldest PING which has not been acknowledged was sent ago.
+    pub(crate) fn unacked_for(&self) -> Option<Duration> {
+        self.unacked_since
+            .lock()
+            .unwrap()
+            .map(|sent| sent.elapsed())
+    }
+
+    fn record(&self, rtt: Duration) {
+        let nanos = rtt.as_nanos().clamp(1, u64::MAX as u128) as u64;
+        self.rtt.store(nanos, Ordering::Relaxed);
+    }
+
+    fn set_unacked_since(&self, sent: Option<Instant>) {
+        *self.unacked_since.lock().unwrap() = sent;
+    }
+}
+
+pub(crate) type GoAwayHook = Arc<dyn Fn(GoAway) + Send + Sync + 'static>;
+
+/// A GOAWAY frame received from a server, which is about to close the connection.
+///
+/// Servers may explain why in the frame's debug data, for example `max_age` when a connection
+/// has reached its maximum age. See
+/// [`ChannelBuilder::on_go_away`](crate::ChannelBuilder::on_go_away).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GoAway {
+    reason: h2::Reason,
+    debug_data: Bytes,
+}
+
+impl GoAway {
+    fn parse(payload: &[u8]) -> Self {
+        let reason = match payload.get(4..GO_AWAY_HEADER_LEN) {
+            Some(&[c0, c1, c2, c3]) => u32::from_be_bytes([c0, c1, c2, c3]),
+            _ => 0,
+        };
+        GoAway {
+            reason: reason.into(),
+            debug_data: Bytes::copy_from_slice(payload.get(GO_AWAY_HEADER_LEN..).unwrap_or(&[])),
+        }
+    }
+
+    /// The error code, which is `NO_ERROR` if the server is shutting down gracefully.
+    pub fn reason(&self) -> h2::Reason {
+        self.reason
+    }
+
+    /// The debug data sent by the server, which is empty if it sent none.
+    ///
+    /// Only the first kilobyte is kept.
+    pub fn debug_data(&self) -> &[u8] {
+        &self.debug_data
+    }
+}
+
+impl fmt::Display for GoAway {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the server sent GOAWAY with {:?}", self.reason)?;
+        if !self.debug_data.is_empty() {
+            write!(f, ": {}", String::from_utf8_lossy(&self.debug_data))?;
+        }
+        Ok(())
+    }
+}
+
+/// An IO wrapper which measures the round-trip time of the PINGs sent by the HTTP/2 connection
+/// over it, by following the frames in each direction.
+///
+/// It also notices when the peer sends GOAWAY, which hyper does not report, and sends the PINGs
+/// requested with [`PingRtt::ping`] between the connection's frames, but not within a header
+/// block, where any other frame is a protocol error. Their acknowledgements are hidden from the
+/// connection, which would otherwise warn about PINGs it didn't send. On a
+/// server, it records the client's activity on its streams, see [`StreamActivity`].
+pub(crate) struct PingIo<T> {
+    inner: T,
+    rtt: Arc<PingRtt>,
+    on_go_away: Option<GoAwayHook>,
+    activity: Option<Arc<StreamActivity>>,
+    read: FrameParser,
+    write: FrameParser,
+    // The payloads of PINGs which have been sent, and when.
+    pending: VecDeque<([u8; PING_PAYLOAD_LEN], Instant)>,
+    // The requested PINGs which have been sent, and when.
+    probes: Vec<([u8; PING_PAYLOAD_LEN], Instant, oneshot::Sender<Duration>)>,
+    next_probe: u64,
+    // A requested PING which is being written, and how much of it has been written.
+    sending: Option<([u8; PING_FRAME_LEN], usize)>,
+    flush: bool,
+    // Bytes read which might be the start of an acknowledgement of a requested PING.
+    held: Vec<u8>,
+}
+
+impl<T> PingIo<T> {
+    /// Wrap the IO of a client connection, which writes the connection preface. `on_go_away`
+    /// is called when the server sends GOAWAY.
+    pub(crate) fn client(inner: T, rtt: Arc<PingRtt>, on_go_away: Option<GoAwayHook>) -> Self {
+        let mut io = PingIo::new(inner, rtt, 0, PREFACE_LEN);
+        io.on_go_away = on_go_away;
+        io
+    }
+
+    /// Wrap the IO of a server connection, which reads the connection preface. The client's
+    /// activity is recorded in `activity`.
+    pub(crate) fn server(
+        inner: T,
+        rtt: Arc<PingRtt>,
+        activity: Option<Arc<StreamActivity>>,
+    ) -> Self {
+        let mut io = PingIo::new(inner, rtt, PREFACE_LEN, 0);
+        io.activity = activity;
+        io
+    }
+
+    fn new(inner: T, rtt: Arc<PingRtt>, read_preface: usize, write_preface: usize) -> Self {
+        rtt.connect();
+        PingIo {
+            inner,
+            rtt,
+            on_go_away: None,
+            activity: None,
+            read: FrameParser::new(read_preface),
+            write: FrameParser::new(write_preface),
+            pending: VecDeque::new(),
+            probes: Vec::new(),
+            next_probe: 0,
+            sending: None,
+            flush: false,
+            held: Vec::new(),
+        }
+    }
+
+    /// Follow the frames in `data`, which has just been read, hiding the acknowledgements of
+    /// requested PINGs by changing them to a frame type which the connection ignores.
+    fn parse_read(&mut self, data: &mut [u8]) {
+        let (pending, probes, rtt) = (&mut self.pending, &mut self.probes, &self.rtt);
+        let (on_go_away, activity) = (&self.on_go_away, &self.activity);
+        let mut hidden = Vec::new();
+        self.read.feed(data, |frame, end| match frame {
+            Frame::Ping(true, payload) => {
+                if let Some(index) = probes.iter().position(|(sent, ..)| *sent == payload) {
+                    let (_, sent_at, tx) = probes.swap_remove(index);
+                    rtt.record(sent_at.elapsed());
+                    let _ = tx.send(sent_at.elapsed());
+                    if let Some(start) = end.checked_sub(PING_FRAME_LEN) {
+                        hidden.push(start);
+                    }
+                } else if let Some(index) = pending.iter().position(|(sent, _)| *sent == payload) {
+                    let (_, sent_at) = pending.remove(index).expect("index is in range");
+                    rtt.record(sent_at.elapsed());
+                    rtt.set_unacked_since(pending.front().map(|(_, sent_at)| *sent_at));
+                }
+            }
+            Frame::Ping(false, _) => {}
+            Frame::GoAway(go_away) => {
+                if let Some(on_go_away) = on_go_away {
+                    on_go_away(go_away);
+                }
+            }
+            Frame::Activity => {
+                if let Some(activity) = activity {
+                    activity.record();
+                }
+            }
+        });
+        for start in hidden {
+            data[start + 3] = UNKNOWN_FRAME;
+        }
+    }
+}
+
+impl<T: AsyncWrite + Unpin> PingIo<T> {
+    /// Write the requested PINGs between the frames written by the connection.
+    fn poll_send_probes(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        loop {
+            if let Some((frame, written)) = &mut self.sending {
+                while *written < frame.len() {
+                    let n = futures_util::ready!(
+                        Pin::new(&mut self.inner).poll_write(cx, &frame[*written..])
+                    )?;
+                    if n == 0 {
+                        return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+                    }
+                    *written += n;
+                }
+                self.sending = None;
+                self.flush = true;
+            }
+            if !self.write.between_frames() {
+                break;
+            }
+            let Some(tx) = self.rtt.take_requested() else {
+                break;
+            };
+            let payload = (PROBE_TAG | self.next_probe).to_be_bytes();
+            self.next_probe += 1;
+            self.probes.push((payload, Instant::now(), tx));
+            let mut frame = [0; PING_FRAME_LEN];
+            frame[..FRAME_HEADER_LEN].copy_from_slice(&[0, 0, 8, PING_FRAME, 0, 0, 0, 0, 0]);
+            frame[FRAME_HEADER_LEN..].copy_from_slice(&payload);
+            self.sending = Some((frame, 0));
+        }
+        if self.flush {
+            futures_util::ready!(Pin::new(&mut self.inner).poll_flush(cx))?;
+            self.flush = false;
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin> PingIo<T> {
+    /// Read while requested PINGs await their acknowledgements. The bytes of a frame which
+    /// might be one are held back until the whole frame has been read, so that it can be hidden.
+    fn poll_read_probes(
+        &mut self,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        loop {
+            let before = buf.filled().len();
+            let held = mem::take(&mut self.held);
+            if buf.remaining() <= held.len() {
+                // There's no room to read more, so pass on what fits without hiding anything.
+                let len = buf.remaining();
+                buf.put_slice(&held[..len]);
+                self.held = held[len..].to_vec();
+                self.parse_read(&mut buf.filled_mut()[before..]);
+                return Poll::Ready(Ok(()));
+            }
+
+            let unfilled = buf.initialize_unfilled();
+            let mut read = ReadBuf::new(&mut unfilled[held.len()..]);
+            match Pin::new(&mut self.inner).poll_read(cx, &mut read) {
+                Poll::Ready(Ok(())) => {}
+                poll => {
+                    self.held = held;
+                    return poll;
+                }
+            }
+            let len = read.filled().len();
+            if len == 0 {
+                // The end of the stream, so the held bytes are an incomplete frame.
+                return Poll::Ready(Ok(()));
+            }
+            unfilled[..held.len()].copy_from_slice(&held);
+            buf.advance(held.len() + len);
+            self.parse_read(&mut buf.filled_mut()[before..]);
+
+            let filled = buf.filled().len();
+            let hold = self.read.partial_ping_ack();
+            if !self.probes.is_empty() && hold > 0 && hold <= filled - before {
+                self.held = buf.filled()[filled - hold..].to_vec();
+                buf.set_filled(filled - hold);
+                self.read.restart_frame();
+            }
+            if buf.filled().len() > before {
+                return Poll::Ready(Ok(()));
+            }
+        }
+    }
+}
+
+impl<T> Drop for PingIo<T> {
+    fn drop(&mut self) {
+        self.rtt.disconnect();
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin> AsyncRead for PingIo<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        // The connection always reads, so it is woken here to send requested PINGs, even when
+        // it has nothing to write.
+        this.rtt.waker.register(cx.waker());
+        if let Poll::Ready(Err(error)) = this.poll_send_probes(cx) {
+            return Poll::Ready(Err(error));
+        }
+
+        if this.probes.is_empty() && this.held.is_empty() {
+            let before = buf.filled().len();
+            futures_util::ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+            this.parse_read(&mut buf.filled_mut()[before..]);
+            Poll::Ready(Ok(()))
+        } else {
+            this.poll_read_probes(cx, buf)
+        }
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for PingIo<T> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        futures_util::ready!(this.poll_send_probes(cx))?;
+        let written = futures_util::ready!(Pin::new(&mut this.inner).poll_write(cx, buf))?;
+
+        let (pending, rtt) = (&mut this.pending, &this.rtt);
+        this.write.feed(&buf[..written], |frame, _| {
+            let payload = match frame {
+                Frame::Ping(false, payload) => payload,
+                _ => return,
+            };
+            if pending.len() == MAX_PENDING_PINGS {
+                pending.pop_front();
+            }
+            pending.push_back((payload, Instant::now()));
+            rtt.set_unacked_since(pending.front().map(|(_, sent_at)| *sent_at));
+        });
+        // Send a requested PING now if the connection's write ended a frame, an error is
+        // returned by the next write.
+        let _ = this.poll_send_probes(cx);
+        Poll::Ready(Ok(written))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+impl<T: Connection> Connection for PingIo<T> {
+    fn connected(&self) -> HyperConnected {
+        self.inner.connected()
+    }
+}
+
+impl<T: Connected> Connected for PingIo<T> {
+    type ConnectInfo = T::ConnectInfo;
+
+    fn connect_info(&self) -> Result<Self::ConnectInfo> {
+        self.inner.connect_info()
+    }
+}
+
+/// The frames found by a [`FrameParser`].
+#[derive(Debug, PartialEq, Eq)]
+enum Frame {
+    /// A PING, whether it is an acknowledgement, and its payload.
+    Ping(bool, [u8; PING_PAYLOAD_LEN]),
+    GoAway(GoAway),
+    /// A HEADERS, DATA or WINDOW_UPDATE frame, which shows that the peer is active.
+    Activity,
+}
+
+/// Follows the HTTP/2 frames in one direction of a connection, to find PING and GOAWAY frames,
+/// and the frames which show activity.
+struct FrameParser {
+    // Bytes of the connection preface which are still to be skipped.
+    preface: usize,
+    header: [u8; FRAME_HEADER_LEN],
+    header_len: usize,
+    // Bytes of the current frame's payload which are still to be read.
+    remaining: usize,
+    // The current frame, if it is a PING: whether it is an acknowledgement, and its payload.
+    ping: Option<(bool, [u8; PING_PAYLOAD_LEN])>,
+    // The current frame's payload, if it is a GOAWAY.
+    go_away: Option<Vec<u8>>,
+    // Whether a whole frame header has been parsed.
+    started: bool,
+    // Whether a header block is open, which must be continued by CONTINUATION frames until one
+    // has the END_HEADERS flag.
+    header_block: bool,
+}
+
+impl FrameParser {
+    fn new(preface: usize) -> Self {
+        FrameParser {
+            preface,
+            header: [0; FRAME_HEADER_LEN],
+            header_len: 0,
+            remaining: 0,
+            ping: None,
+            go_away: None,
+            started: false,
+            header_block: false,
+        }
+    }
+
+    /// Whether the data parsed so far ends with a whole frame, after the first frame and outside
+    /// a header block, so that another frame can be inserted.
+    fn between_frames(&self) -> bool {
+        self.started
+            && self.preface == 0
+            && self.header_len == 0
+            && self.remaining == 0
+            && !self.header_block
+    }
+
+    /// The number of bytes parsed of the current frame if it might be a PING acknowledgement
+    /// which is incomplete, otherwise zero.
+    fn partial_ping_ack(&self) -> usize {
+        match self.ping {
+            Some((true, _)) if self.remaining > 0 => {
+                FRAME_HEADER_LEN + PING_PAYLOAD_LEN - self.remaining
+            }
+            _ if self.preface > 0 || self.remaining > 0 => 0,
+            _ if self.header_len > 3 && self.header[3] != PING_FRAME => 0,
+            _ if self.header_len > 4 && self.header[4] & ACK_FLAG == 0 => 0,
+            _ => self.header_len,
+        }
+    }
+
+    /// Forget the current frame, so that it is parsed again from its start.
+    fn restart_frame(&mut self) {
+        self.header_len = 0;
+        self.remaining = 0;
+        self.ping = None;
+    }
+
+    /// Parse `data`, calling `on_frame` with each frame found, and the offset in `data` of the
+    /// frame's end, or of the header's end for frames which show activity.
+    fn feed(&mut self, data: &[u8], mut on_frame: impl FnMut(Frame, usize)) {
+        let total = data.len();
+        let mut data = data;
+        while !data.is_empty() {
+            if self.preface > 0 {
+                let len = self.preface.min(data.len());
+                self.preface -= len;
+                data = &data[len..];
+            } else if self.remaining > 0 {
+                let len = self.remaining.min(data.len());
+                if let Some((_, payload)) = &mut self.ping {
+                    let start = PING_PAYLOAD_LEN - self.remaining;
+                    payload[start..start + len].copy_from_slice(&data[..len]);
+                }
+                if let Some(payload) = &mut self.go_away {
+                    let max = GO_AWAY_HEADER_LEN + MAX_GO_AWAY_DEBUG_DATA;
+                    let kept = (max - payload.len()).min(len);
+                    payload.extend_from_slice(&data[..kept]);
+                }
+                self.remaining -= len;
+                data = &data[len..];
+                if self.remaining == 0 {
+                    let end = total - data.len();
+                    if let Some((ack, payload)) = self.ping.take() {
+                        on_frame(Frame::Ping(ack, payload), end);
+                    }
+                    if let Some(payload) = self.go_away.take() {
+                        on_frame(Frame::GoAway(GoAway::parse(&payload)), end);
+                    }
+                }
+            } else {
+                let len = (FRAME_HEADER_LEN - self.header_len).min(data.len());
+                self.header[self.header_len..self.header_len + len].copy_from_slice(&data[..len]);
+                self.header_len += len;
+                data = &data[len..];
+                if self.header_len == FRAME_HEADER_LEN {
+                    self.header_len = 0;
+                    self.started = true;
+                    let [l0, l1, l2, kind, flags, ..] = self.header;
+                    self.remaining = u32::from_be_bytes([0, l0, l1, l2]) as usize;
+                    if [HEADERS_FRAME, PUSH_PROMISE_FRAME, CONTINUATION_FRAME].contains(&kind) {
+                        self.header_block = flags & END_HEADERS_FLAG == 0;
+                    }
+                    // A PING within a header block is a protocol error, which is left for the
+                    // connection to report rather than hidden.
+                    if kind == PING_FRAME
+                        && self.remaining == PING_PAYLOAD_LEN
+                        && !self.header_block
+                    {
+                        self.ping = Some((flags & ACK_FLAG != 0, [0; PING_PAYLOAD_LEN]));
+                    } else if kind == GO_AWAY_FRAME && self.remaining == 0 {
+                        on_frame(Frame::GoAway(GoAway::parse(&[])), total - data.len());
+                    } else if kind == GO_AWAY_FRAME {
+                        self.go_away = Some(Vec::new());
+                    } else if matches!(kind, DATA_FRAME | HEADERS_FRAME | WINDOW_UPDATE_FRAME) {
+                        on_frame(Frame::Activity, total - data.len());
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ping(ack: bool, payload: u8) -> Vec<u8> {
+        let mut frame = vec![0, 0, 8, PING_FRAME, ack as u8, 0, 0, 0, 0];
+        frame.extend_from_slice(&[payload; PING_PAYLOAD_LEN]);
+        frame
+    }
+
+    fn go_away(debug_data: &[u8]) -> Vec<u8> {
+        let len = (GO_AWAY_HEADER_LEN + debug_data.len()) as u8;
+        // The last stream ID is 1, and the error code is NO_ERROR.
+        let mut frame = vec![
+            0,
+            0,
+            len,
+            GO_AWAY_FRAME,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            1,
+            0,
+            0,
+            0,
+            0,
+        ];
+        frame.extend_from_slice(debug_data);
+        frame
+    }
+
+    #[test]
+    fn finds_ping_and_go_away_frames() {
+        let mut data = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n".to_vec();
+        // A SETTINGS frame with one setting.
+        data.extend_from_slice(&[0, 0, 6, 0x4, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 100]);
+        data.extend(ping(false, 1));
+        // A WINDOW_UPDATE frame for stream 1.
+        data.extend_from_slice(&[0, 0, 4, WINDOW_UPDATE_FRAME, 0, 0, 0, 0, 1, 0, 0, 1, 0]);
+        data.extend(ping(true, 2));
+        data.extend(go_away(b"max_age"));
+
+        let mut parser = FrameParser::new(PREFACE_LEN);
+        let mut frames = Vec::new();
+        // Feed the data in small pieces, which split frame headers and payloads.
+        for chunk in data.chunks(5) {
+            parser.feed(chunk, |frame, _| frames.push(frame));
+        }
+        assert_eq!(
+            frames,
+            [
+                Frame::Ping(false, [1; PING_PAYLOAD_LEN]),
+                Frame::Activity,
+                Frame::Ping(true, [2; PING_PAYLOAD_LEN]),
+                Frame::GoAway(GoAway {
+                    reason: h2::Reason::NO_ERROR,
+                    debug_data: Bytes::from_static(b"max_age"),
+                }),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn waits_for_header_blocks_to_end() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let settings = [0, 0, 0, 0x4, 0, 0, 0, 0, 0];
+        // A header block for stream 1, split into HEADERS and CONTINUATION with END_HEADERS.
+        let headers = [0, 0, 3, HEADERS_FRAME, 0, 0, 0, 0, 1, 1, 2, 3];
+        let continuation = [0, 0, 2, 0x9, 0x4, 0, 0, 0, 1, 4, 5];
+        let (io, mut client) = tokio::io::duplex(1024);
+        let rtt = Arc::new(PingRtt::default());
+        let (mut read, mut write) = tokio::io::split(PingIo::server(io, rtt.clone(), None));
+        write.write_all(&settings).await.unwrap();
+        write.write_all(&headers).await.unwrap();
+
+        let ping = tokio::spawn(async move { rtt.ping().await });
+        let read = tokio::spawn(async move { read.read_to_end(&mut Vec::new()).await });
+        for _ in 0..10 {
+            tokio::task::yield_now().await;
+        }
+        write.write_all(&continuation).await.unwrap();
+
+        let mut written = vec![0; settings.len() + headers.len() + continuation.len()];
+        client.read_exact(&mut written).await.unwrap();
+        assert_eq!(written, [&settings[..], &headers, &continuation].concat());
+        let mut probe = [0; PING_FRAME_LEN];
+        client.read_exact(&mut probe).await.unwrap();
+        assert_eq!(probe[3..5], [PING_FRAME, 0]);
+        ping.abort();
+        read.abort();
+    }
+
+    #[test]
+    fn ignores_pings_within_header_blocks() {
+        let mut parser = FrameParser::new(0);
+        let mut frames = Vec::new();
+        parser.feed(&[0, 0, 1, HEADERS_FRAME, 0, 0, 0, 0, 1, 0], |f, _| {
+            frames.push(f)
+        });
+        assert!(!parser.between_frames());
+        parser.feed(&ping(true, 1), |f, _| frames.push(f));
+        parser.feed(
+            &[0, 0, 0, CONTINUATION_FRAME, END_HEADERS_FLAG, 0, 0, 0, 1],
+            |_, _| {},
+        );
+        assert!(parser.between_frames());
+        assert_eq!(frames, [Frame::Activity]);
+    }
+
+    #[tokio::test]
+    async fn sends_requested_pings() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let settings = [0, 0, 0, 0x4, 0, 0, 0, 0, 0];
+        let settings_ack = [0, 0, 0, 0x4, ACK_FLAG, 0, 0, 0, 0];
+        let (io, mut client) = tokio::io::duplex(1024);
+        let rtt = Arc::new(PingRtt::default());
+        let mut io = PingIo::server(io, rtt.clone(), None);
+        io.write_all(&settings).await.unwrap();
+
+        let ping = tokio::spawn(async move { rtt.ping().await });
+        let read = tokio::spawn(async move {
+            let mut read = Vec::new();
+            io.read_to_end(&mut read).await.unwrap();
+            read
+        });
+
+        let mut written = [0; FRAME_HEADER_LEN + PING_FRAME_LEN];
+        client.read_exact(&mut written).await.unwrap();
+        assert_eq!(written[..FRAME_HEADER_LEN], settings);
+        let mut ack = written[FRAME_HEADER_LEN..].to_vec();
+        assert_eq!(ack[3..5], [PING_FRAME, 0]);
+        ack[4] = ACK_FLAG;
+
+        // Send the acknowledgement in pieces, between other frames.
+        client
+            .write_all(b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n")
+            .await
+            .unwrap();
+        client.write_all(&settings_ack).await.unwrap();
+        client.write_all(&ack[..5]).await.unwrap();
+        tokio::task::yield_now().await;
+        client.write_all(&ack[5..]).await.unwrap();
+        client.write_all(&settings_ack).await.unwrap();
+        drop(client);
+
+        assert!(ping.await.unwrap().is_ok());
+        let read = read.await.unwrap();
+        let mut hidden = ack.clone();
+        hidden[3] = UNKNOWN_FRAME;
+        assert_eq!(
+            read[PREFACE_LEN..],
+            [&settings_ack[..], &hidden, &settings_ack].concat()
+        );
+    }
+}
@@ -1,3 +1,4 @@
+use super::backoff::{Backoff, ConnectBackoff};
 use crate::BoxError;
 
 use pin_project::pin_project;
@@ -5,12 +6,20 @@ use std::{
     fmt,
     future::Future,
     pin::Pin,
+    sync::Arc,
     task::{Context, Poll},
+    time::Duration,
 };
-use tower::make::MakeService;
+use tokio::{
+    sync::Notify,
+    time::{sleep, sleep_until, Instant, Sleep},
+};
+use tower::{make::MakeService, timeout::error::Elapsed};
 use tower_service::Service;
 use tracing::trace;
 
+pub(crate) type ErrorHook = Arc<dyn Fn(&BoxError) + Send + Sync + 'static>;
+
 pub(crate) struct Reconnect<M, Target>
 where
     M: Service<Target>,
@@ -22,12 +31,26 @@ where
     error: Option<BoxError>,
     has_been_connected: bool,
     is_lazy: bool,
+    backoff: Backoff,
+    reset_backoff_after: Duration,
+    connected_at: Option<Instant>,
+    // The earliest time at which the next connection attempt may start.
+    next_attempt: Instant,
+    on_failure: Option<Arc<Notify>>,
+    on_connect_error: Option<ErrorHook>,
+    // The number of times, and the backoff with which, a failed first connection is retried
+    // before the error is returned.
+    retries: Option<(u32, Backoff)>,
+    // The timeout of each connection attempt, rather than one based on the backoff.
+    connection_deadline: Option<Duration>,
 }
 
 #[derive(Debug)]
 enum State<F, S> {
     Idle,
-    Connecting(F),
+    Backoff(Pin<Box<Sleep>>),
+    // A connection attempt, and its timeout.
+    Connecting(F, Pin<Box<Sleep>>),
     Connected(S),
 }
 
@@ -36,7 +59,19 @@ where
     M: Service<Target>,
     M::Error: Into<BoxError>,
 {
-    pub(crate) fn new(mk_service: M, target: Target, is_lazy: bool) -> Self {
+    /// Create a reconnecting service, the backoff between attempts is reset once a connection
+    /// has been up for `reset_backoff_after`. `on_failure` is notified whenever a connection
+    /// attempt fails or a connection is lost, and `on_connect_error` is called with the error of
+    /// each failed connection attempt.
+    pub(crate) fn new(
+        mk_service: M,
+        target: Target,
+        is_lazy: bool,
+        backoff: ConnectBackoff,
+        reset_backoff_after: Duration,
+        on_failure: Option<Arc<Notify>>,
+        on_connect_error: Option<ErrorHook>,
+    ) -> Self {
         Reconnect {
             mk_service,
             state: State::Idle,
@@ -44,8 +79,44 @@ where
             error: None,
             has_been_connected: false,
             is_lazy,
+            backoff: Backoff::new(backoff),
+            reset_backoff_after,
+            connected_at: None,
+            next_attempt: Instant::now(),
+            on_failure,
+            on_connect_error,
+            retries: None,
+            connection_deadline: None,
+        }
+    }
+
+    /// Retry a failed first connection attempt of a service which isn't lazy up to `retries`
+    /// times, waiting for `backoff` after each failure, before returning the error.
+    pub(crate) fn connect_retries(self, retries: u32, backoff: ConnectBackoff) -> Self {
+        Reconnect {
+            retries: Some((retries, Backoff::new(backoff))),
+            ..self
         }
     }
+
+    /// Fail each connection attempt which takes longer than `deadline`.
+    pub(crate) fn connection_deadline(self, deadline: Option<Duration>) -> Self {
+        Reconnect {
+            connection_deadline: deadline,
+            ..self
+        }
+    }
+
+    fn notify_failure(&self) {
+        if let Some(on_failure) = &self.on_failure {
+            on_failure.notify_one();
+        }
+    }
+
+    fn backoff(&mut self) -> State<M::Future, M::Response> {
+        trace!(delay = ?self.next_attempt.saturating_duration_since(Instant::now()), "backing off before reconnecting");
+        State::Backoff(Box::pin(sleep_until(self.next_attempt)))
+    }
 }
 
 impl<M, Target, S, Request> Service<Request> for Reconnect<M, Target>
@@ -80,14 +151,35 @@ where
                         }
                     }
 
+                    let delay = self.backoff.next_delay();
+                    self.next_attempt = Instant::now() + delay;
+                    let timeout = self
+                        .connection_deadline
+                        .unwrap_or_else(|| delay.max(self.backoff.min_connect_timeout()));
+
                     let fut = self.mk_service.make_service(self.target.clone());
-                    self.state = State::Connecting(fut);
+                    self.state = State::Connecting(fut, Box::pin(sleep(timeout)));
                     continue;
                 }
-                State::Connecting(ref mut f) => {
+                State::Backoff(ref mut delay) => {
+                    trace!("poll_ready; backoff");
+                    if delay.as_mut().poll(cx).is_pending() {
+                        return Poll::Pending;
+                    }
+                    state = State::Idle;
+                }
+                State::Connecting(ref mut f, ref mut timeout) => {
                     trace!("poll_ready; connecting");
-                    match Pin::new(f).poll(cx) {
+                    let result = match Pin::new(f).poll(cx) {
+                        Poll::Ready(result) => Poll::Ready(result.map_err(Into::into)),
+                        Poll::Pending if timeout.as_mut().poll(cx).is_ready() => {
+                            Poll::Ready(Err(Elapsed::new().into()))
+                        }
+                        Poll::Pending => Poll::Pending,
+                    };
+                    match result {
                         Poll::Ready(Ok(service)) => {
+                            self.connected_at = Some(Instant::now());
                             state = State::Connected(service);
                         }
                         Poll::Pending => {
@@ -96,15 +188,27 @@ where
                         }
                         Poll::Ready(Err(e)) => {
                             trace!("poll_ready; error");
-
-                            state = State::Idle;
+                            self.notify_failure();
+                            if let Some(on_connect_error) = &self.on_connect_error {
+                                on_connect_error(&e);
+                            }
 
                             if !(self.has_been_connected || self.is_lazy) {
-                                return Poll::Ready(Err(e.into()));
+                                match &mut self.retries {
+                                    Some((retries, backoff)) if *retries > 0 => {
+                                        *retries -= 1;
+                                        let delay = backoff.next_delay();
+                                        tracing::debug!(error = %e, ?delay, "retrying connection");
+                                        self.next_attempt = Instant::now() + delay;
+                                        state = self.backoff();
+                                    }
+                                    _ => return Poll::Ready(Err(e)),
+                                }
                             } else {
-                                let error = e.into();
+                                let error = e;
                                 tracing::debug!("reconnect::poll_ready: {:?}", error);
                                 self.error = Some(error);
+                                state = self.backoff();
                                 break;
                             }
                         }
@@ -126,7 +230,18 @@ where
                         }
                         Poll::Ready(Err(_)) => {
                             trace!("poll_ready; error");
-                            state = State::Idle;
+                            self.notify_failure();
+                            let stable = matches!(
+                                self.connected_at.take(),
+                                Some(at) if at.elapsed() >= self.reset_backoff_after
+                            );
+                            if stable {
+                                // Reconnect immediately after losing a stable connection.
+                                self.backoff.reset();
+                                state = State::Idle;
+                            } else {
+                                state = self.backoff();
+                            }
                         }
                     }
                 }
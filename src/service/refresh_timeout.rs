@@ -0,0 +1,114 @@
+use crate::service::grpc_timeout::{encode_grpc_timeout, GRPC_TIMEOUT_HEADER};
+use crate::BoxError;
+
+use http::Request;
+use pin_project::pin_project;
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::time::Instant;
+use tonic::Status;
+use tower_service::Service;
+
+/// A request extension recording when the request's `grpc-timeout` expires.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Deadline(pub(crate) Instant);
+
+/// Middleware that rewrites the `grpc-timeout` header of requests with a [`Deadline`] to the time
+/// remaining, so that time spent queueing in the client is not given to the server.
+#[derive(Debug, Clone)]
+pub(crate) struct RefreshTimeout<S> {
+    inner: S,
+}
+
+impl<S> RefreshTimeout<S> {
+    pub(crate) fn new(inner: S) -> Self {
+        Self { inner }
+    }
+}
+
+impl<S, ReqBody> Service<Request<ReqBody>> for RefreshTimeout<S>
+where
+    S: Service<Request<ReqBody>>,
+    S::Error: Into<BoxError>,
+{
+    type Response = S::Response;
+    type Error = BoxError;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, mut req: Request<ReqBody>) -> Self::Future {
+        if let Some(&Deadline(deadline)) = req.extensions().get::<Deadline>() {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return ResponseFuture::Expired(Some(Status::deadline_exceeded(
+                    "deadline expired before the request was sent",
+                )));
+            }
+            req.headers_mut()
+                .insert(GRPC_TIMEOUT_HEADER, encode_grpc_timeout(remaining));
+        }
+        ResponseFuture::Inner(self.inner.call(req))
+    }
+}
+
+#[pin_project(project = ResponseFutureProj)]
+pub(crate) enum ResponseFuture<F> {
+    Inner(#[pin] F),
+    Expired(Option<Status>),
+}
+
+impl<F, Res, E> Future for ResponseFuture<F>
+where
+    F: Future<Output = Result<Res, E>>,
+    E: Into<BoxError>,
+{
+    type Output = Result<Res, BoxError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match self.project() {
+            ResponseFutureProj::Inner(inner) => inner.poll(cx).map_err(Into::into),
+            ResponseFutureProj::Expired(status) => {
+                let status = status.take().expect("polled after ready");
+                Poll::Ready(Err(status.into()))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tower::ServiceExt;
+
+    #[tokio::test(start_paused = true)]
+    async fn subtracts_time_spent_queued() {
+        let svc = RefreshTimeout::new(tower::service_fn(|req: Request<()>| async move {
+            Ok::<_, BoxError>(req.headers().get(GRPC_TIMEOUT_HEADER).cloned())
+        }));
+        let deadline = Deadline(Instant::now() + Duration::from_secs(10));
+
+        // The request waited for the channel to become ready.
+        tokio::time::advance(Duration::from_secs(3)).await;
+        let mut req = Request::new(());
+        req.extensions_mut().insert(deadline);
+        let timeout = svc.clone().oneshot(req).await.unwrap();
+        assert_eq!(
+            timeout.unwrap(),
+            encode_grpc_timeout(Duration::from_secs(7))
+        );
+
+        tokio::time::advance(Duration::from_secs(7)).await;
+        let mut req = Request::new(());
+        req.extensions_mut().insert(deadline);
+        let error = svc.oneshot(req).await.unwrap_err();
+        let status = error.downcast::<Status>().unwrap();
+        assert_eq!(status.code(), tonic::Code::DeadlineExceeded);
+    }
+}
@@ -0,0 +1,231 @@
+use crate::BoxBody;
+
+use bytes::Bytes;
+use http::HeaderMap;
+use http_body::{Body, SizeHint};
+use std::{
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+use tonic::Status;
+
+/// A request body which records the data it yields, so that the request can be sent again.
+///
+/// Every clone yields the whole body. Data is read from the original body by the most recent
+/// clone and recorded for the others, up to `max_len` bytes; once more data than that has been
+/// read the body can no longer be cloned.
+pub(crate) struct ReplayBody {
+    shared: Arc<Mutex<Shared>>,
+    generation: usize,
+    // The index of the next recorded chunk to yield.
+    position: usize,
+}
+
+struct Shared {
+    body: BoxBody,
+    chunks: Vec<Bytes>,
+    len: usize,
+    max_len: usize,
+    overflowed: bool,
+    data_done: bool,
+    trailers: Option<Option<HeaderMap>>,
+    // The generation of the newest clone, which is the only one allowed to read the body.
+    generation: usize,
+}
+
+impl ReplayBody {
+    pub(crate) fn new(body: BoxBody, max_len: usize) -> Self {
+        ReplayBody {
+            shared: Arc::new(Mutex::new(Shared {
+                body,
+                chunks: Vec::new(),
+                len: 0,
+                max_len,
+                overflowed: false,
+                data_done: false,
+                trailers: None,
+                generation: 0,
+            })),
+            generation: 0,
+            position: 0,
+        }
+    }
+
+    /// Returns a body which yields the data from the start, or `None` if too much data has been
+    /// read to replay it.
+    ///
+    /// The returned body supersedes all existing clones, which fail if they need to read more
+    /// data.
+    pub(crate) fn try_clone(&self) -> Option<ReplayBody> {
+        let mut shared = self.shared.lock().unwrap();
+        if shared.overflowed {
+            return None;
+        }
+        shared.generation += 1;
+        Some(ReplayBody {
+            shared: self.shared.clone(),
+            generation: shared.generation,
+            position: 0,
+        })
+    }
+}
+
+impl Body for ReplayBody {
+    type Data = Bytes;
+    type Error = Status;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let this = &mut *self;
+        let mut shared = this.shared.lock().unwrap();
+
+        if let Some(chunk) = shared.chunks.get(this.position) {
+            this.position += 1;
+            return Poll::Ready(Some(Ok(chunk.clone())));
+        }
+        if shared.data_done {
+            return Poll::Ready(None);
+        }
+        if shared.generation != this.generation {
+            return Poll::Ready(Some(Err(Status::cancelled("request body was replayed"))));
+        }
+
+        let chunk = match futures_util::ready!(Pin::new(&mut shared.body).poll_data(cx)) {
+            Some(Ok(chunk)) => chunk,
+            Some(Err(e)) => return Poll::Ready(Some(Err(e))),
+            None => {
+                shared.data_done = true;
+                return Poll::Ready(None);
+            }
+        };
+
+        if !shared.overflowed {
+            shared.len += chunk.len();
+            if shared.len > shared.max_len {
+                shared.overflowed = true;
+                shared.chunks = Vec::new();
+            } else {
+                shared.chunks.push(chunk.clone());
+                this.position += 1;
+            }
+        }
+        Poll::Ready(Some(Ok(chunk)))
+    }
+
+    fn poll_trailers(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        let this = &*self;
+        let mut shared = this.shared.lock().unwrap();
+
+        if let Some(trailers) = &shared.trailers {
+            return Poll::Ready(Ok(trailers.clone()));
+        }
+        if shared.generation != this.generation {
+            return Poll::Ready(Err(Status::cancelled("request body was replayed")));
+        }
+
+        let trailers = futures_util::ready!(Pin::new(&mut shared.body).poll_trailers(cx))?;
+        if !shared.overflowed {
+            shared.trailers = Some(trailers.clone());
+        }
+        Poll::Ready(Ok(trailers))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        let shared = self.shared.lock().unwrap();
+        if self.position < shared.chunks.len() {
+            return false;
+        }
+        match &shared.trailers {
+            Some(trailers) => trailers.is_none(),
+            None => shared.generation == self.generation && shared.body.is_end_stream(),
+        }
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        let shared = self.shared.lock().unwrap();
+        let recorded = shared.chunks.get(self.position..).unwrap_or_default();
+        let recorded = recorded.iter().map(|chunk| chunk.len() as u64).sum();
+        if shared.data_done {
+            return SizeHint::with_exact(recorded);
+        }
+        if shared.generation != self.generation {
+            return SizeHint::default();
+        }
+        // The recorded data which this clone hasn't yielded, and the rest of the body.
+        let remaining = shared.body.size_hint();
+        let mut hint = SizeHint::new();
+        hint.set_lower(remaining.lower() + recorded);
+        if let Some(upper) = remaining.upper() {
+            hint.set_upper(upper + recorded);
+        }
+        hint
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body::Full;
+
+    fn body(data: &'static str) -> BoxBody {
+        BoxBody::new(Full::new(Bytes::from_static(data.as_bytes())).map_err(|e| match e {}))
+    }
+
+    async fn read(mut body: ReplayBody) -> Result<Vec<u8>, Status> {
+        let mut data = Vec::new();
+        while let Some(chunk) = body.data().await {
+            data.extend_from_slice(&chunk?);
+        }
+        Ok(data)
+    }
+
+    #[tokio::test]
+    async fn replays_data() {
+        let mut first = ReplayBody::new(body("hello"), 16);
+        first.data().await.unwrap().unwrap();
+
+        let second = first.try_clone().unwrap();
+        assert_eq!(read(second).await.unwrap(), b"hello");
+    }
+
+    #[tokio::test]
+    async fn superseded_body_fails() {
+        let first = ReplayBody::new(body("hello"), 16);
+        let second = first.try_clone().unwrap();
+        assert!(read(first).await.is_err());
+        assert_eq!(read(second).await.unwrap(), b"hello");
+    }
+
+    #[tokio::test]
+    async fn forwards_end_of_stream_and_size() {
+        let mut first = ReplayBody::new(body("hello"), 16);
+        assert!(!first.is_end_stream());
+        assert_eq!(first.size_hint().exact(), Some(5));
+        first.data().await.unwrap().unwrap();
+        assert!(first.is_end_stream());
+        assert_eq!(first.size_hint().exact(), Some(0));
+
+        let mut second = first.try_clone().unwrap();
+        assert!(!second.is_end_stream());
+        assert_eq!(second.size_hint().exact(), Some(5));
+        second.data().await.unwrap().unwrap();
+        assert!(second.is_end_stream());
+        assert_eq!(second.size_hint().exact(), Some(0));
+
+        assert!(ReplayBody::new(BoxBody::default(), 16).is_end_stream());
+    }
+
+    #[tokio::test]
+    async fn overflow_prevents_clone() {
+        let first = ReplayBody::new(body("hello"), 4);
+        let second = first.try_clone().unwrap();
+        assert_eq!(read(second).await.unwrap(), b"hello");
+        assert!(first.try_clone().is_none());
+    }
+}
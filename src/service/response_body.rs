@@ -0,0 +1,135 @@
+use crate::BoxError;
+
+use bytes::Bytes;
+use http::HeaderMap;
+use http_body::{combinators::UnsyncBoxBody, Body as HttpBody, SizeHint};
+use std::{
+    any::Any,
+    fmt,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/// The body of a response received by a [`Channel`](crate::Channel).
+///
+/// It is the connection's [`Body`](crate::Body), unless it is wrapped by a timeout, such as
+/// [`ChannelBuilder::stream_inactivity_timeout`](crate::ChannelBuilder::stream_inactivity_timeout),
+/// or by a layer added with [`ChannelBuilder::layer`](crate::ChannelBuilder::layer).
+pub struct ResponseBody {
+    kind: Kind,
+}
+
+enum Kind {
+    Body(hyper::Body),
+    Boxed(UnsyncBoxBody<Bytes, BoxError>),
+}
+
+impl ResponseBody {
+    /// Wrap `body`, which is only boxed if it isn't already a `ResponseBody` or a
+    /// [`Body`](crate::Body).
+    pub(crate) fn new<B>(body: B) -> Self
+    where
+        B: HttpBody<Data = Bytes> + Send + 'static,
+        B::Error: Into<BoxError>,
+    {
+        let mut body = Some(body);
+        let any = &mut body as &mut dyn Any;
+        if let Some(body) = any.downcast_mut::<Option<ResponseBody>>() {
+            return body.take().expect("body is present");
+        }
+        if let Some(body) = any.downcast_mut::<Option<hyper::Body>>() {
+            return body.take().expect("body is present").into();
+        }
+        let body = body.expect("body is present").map_err(Into::into);
+        ResponseBody {
+            kind: Kind::Boxed(body.boxed_unsync()),
+        }
+    }
+}
+
+impl From<hyper::Body> for ResponseBody {
+    fn from(body: hyper::Body) -> Self {
+        ResponseBody {
+            kind: Kind::Body(body),
+        }
+    }
+}
+
+impl Default for ResponseBody {
+    fn default() -> Self {
+        hyper::Body::empty().into()
+    }
+}
+
+impl HttpBody for ResponseBody {
+    type Data = Bytes;
+    type Error = BoxError;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        match &mut self.kind {
+            Kind::Body(body) => Pin::new(body).poll_data(cx).map_err(Into::into),
+            Kind::Boxed(body) => Pin::new(body).poll_data(cx),
+        }
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        match &mut self.kind {
+            Kind::Body(body) => Pin::new(body).poll_trailers(cx).map_err(Into::into),
+            Kind::Boxed(body) => Pin::new(body).poll_trailers(cx),
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        match &self.kind {
+            Kind::Body(body) => body.is_end_stream(),
+            Kind::Boxed(body) => body.is_end_stream(),
+        }
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        match &self.kind {
+            Kind::Body(body) => body.size_hint(),
+            Kind::Boxed(body) => body.size_hint(),
+        }
+    }
+}
+
+impl fmt::Debug for ResponseBody {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.kind {
+            Kind::Body(_) => "Body",
+            Kind::Boxed(_) => "Boxed",
+        };
+        f.debug_struct("ResponseBody").field("kind", &kind).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn keeps_trailers() {
+        let mut trailers = HeaderMap::new();
+        trailers.insert("grpc-status", "0".parse().unwrap());
+        let (mut tx, body) = hyper::Body::channel();
+        tx.send_data(Bytes::from_static(b"message")).await.unwrap();
+        tx.send_trailers(trailers.clone()).await.unwrap();
+        drop(tx);
+
+        let mut body = ResponseBody::new(body.boxed_unsync());
+        assert!(matches!(body.kind, Kind::Boxed(_)));
+        assert_eq!(body.data().await.unwrap().unwrap(), "message");
+        assert!(body.data().await.is_none());
+        assert_eq!(body.trailers().await.unwrap(), Some(trailers));
+
+        let body = ResponseBody::new(ResponseBody::new(hyper::Body::empty()));
+        assert!(matches!(body.kind, Kind::Body(_)));
+    }
+}
@@ -1,24 +1,56 @@
+use crate::server::TcpConnectInfo;
 use crate::{BoxError, Error, Result};
 
 use axum::handler::Handler;
-use http::{Request, Response};
+use http::{
+    header::{HeaderName, HeaderValue, HOST, USER_AGENT},
+    uri::Authority,
+    Request, Response,
+};
 use hyper::Body;
 use pin_project::pin_project;
 use std::{
+    collections::HashMap,
     convert::Infallible,
     fmt,
     future::Future,
     pin::Pin,
     task::{Context, Poll},
 };
-use tonic::{body::BoxBody, transport::NamedService};
+use tonic::{body::BoxBody, transport::NamedService, Status};
 use tower::ServiceExt;
 use tower_service::Service;
 
 /// A [`Service`] router.
+///
+/// Services may be registered for a virtual host, in which case they only handle requests whose
+/// `:authority` (or `Host` header) is that host. Requests for hosts without any registered
+/// services are handled by the services registered without a host.
+///
+/// Services may also be registered for a request header value, for example to serve a new
+/// version of a service to requests with `x-api-version: v2`. Such a service handles requests
+/// for its service name which have that header value, in preference to routing by host.
 #[derive(Debug, Default, Clone)]
 pub struct Routes {
     router: axum::Router,
+    hosts: HashMap<String, axum::Router>,
+    headers: Vec<HeaderRoute>,
+    services: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+struct HeaderRoute {
+    name: HeaderName,
+    value: HeaderValue,
+    router: axum::Router,
+    services: Vec<String>,
+}
+
+impl HeaderRoute {
+    fn matches<B>(&self, service: &str, req: &Request<B>) -> bool {
+        self.services.iter().any(|name| name == service)
+            && req.headers().get(&self.name) == Some(&self.value)
+    }
 }
 
 impl Routes {
@@ -32,8 +64,35 @@ impl Routes {
         S::Future: Send + 'static,
         S::Error: Into<BoxError> + Send,
     {
-        let router = axum::Router::new().fallback(unimplemented.into_service());
-        Self { router }.add_service(svc)
+        Self::empty().add_service(svc)
+    }
+
+    pub(crate) fn empty() -> Self {
+        Self {
+            router: grpc_router(),
+            hosts: HashMap::new(),
+            headers: Vec::new(),
+            services: Vec::new(),
+        }
+    }
+
+    /// The names of the registered services, such as `helloworld.Greeter`.
+    pub fn services(&self) -> &[String] {
+        &self.services
+    }
+
+    /// Returns `true` if a service is registered for `path`.
+    ///
+    /// `path` is a gRPC method path such as `/helloworld.Greeter/SayHello`, or a service name.
+    pub fn contains(&self, path: &str) -> bool {
+        let name = service_name(path);
+        self.services.iter().any(|service| service == name)
+    }
+
+    fn record_service(&mut self, name: &str) {
+        if !self.services.iter().any(|service| service == name) {
+            self.services.push(name.to_owned());
+        }
     }
 
     pub(crate) fn add_service<S>(mut self, svc: S) -> Self
@@ -48,14 +107,111 @@ impl Routes {
     {
         let svc = svc.map_response(|res| res.map(axum::body::boxed));
         self.router = self.router.route(&format!("/{}/*rest", S::NAME), svc);
+        self.record_service(S::NAME);
+        self
+    }
+
+    pub(crate) fn add_service_for_host<S>(mut self, host: &str, svc: S) -> Self
+    where
+        S: Service<Request<Body>, Response = Response<BoxBody>, Error = Infallible>
+            + NamedService
+            + Clone
+            + Send
+            + 'static,
+        S::Future: Send + 'static,
+        S::Error: Into<BoxError> + Send,
+    {
+        let svc = svc.map_response(|res| res.map(axum::body::boxed));
+        let router = self
+            .hosts
+            .remove(&host.to_ascii_lowercase())
+            .unwrap_or_else(grpc_router)
+            .route(&format!("/{}/*rest", S::NAME), svc);
+        self.hosts.insert(host.to_ascii_lowercase(), router);
+        self.record_service(S::NAME);
         self
     }
+
+    pub(crate) fn add_service_for_header<S>(
+        mut self,
+        name: HeaderName,
+        value: HeaderValue,
+        svc: S,
+    ) -> Self
+    where
+        S: Service<Request<Body>, Response = Response<BoxBody>, Error = Infallible>
+            + NamedService
+            + Clone
+            + Send
+            + 'static,
+        S::Future: Send + 'static,
+        S::Error: Into<BoxError> + Send,
+    {
+        let svc = svc.map_response(|res| res.map(axum::body::boxed));
+        let index = match self
+            .headers
+            .iter()
+            .position(|route| route.name == name && route.value == value)
+        {
+            Some(index) => index,
+            None => {
+                self.headers.push(HeaderRoute {
+                    name,
+                    value,
+                    router: grpc_router(),
+                    services: Vec::new(),
+                });
+                self.headers.len() - 1
+            }
+        };
+
+        let route = &mut self.headers[index];
+        route.router = std::mem::take(&mut route.router).route(&format!("/{}/*rest", S::NAME), svc);
+        route.services.push(S::NAME.to_owned());
+        self.record_service(S::NAME);
+        self
+    }
+}
+
+/// The name of the service a gRPC method path is for.
+fn service_name(path: &str) -> &str {
+    path.trim_start_matches('/').split('/').next().unwrap_or("")
+}
+
+fn grpc_router() -> axum::Router {
+    axum::Router::new().fallback(unimplemented.into_service())
+}
+
+/// The host a request is for, from its `:authority` or `Host` header, without the port.
+fn request_host<B>(req: &Request<B>) -> Option<String> {
+    let host = match req.uri().host() {
+        Some(host) => host.to_owned(),
+        None => {
+            let authority: Authority = req.headers().get(HOST)?.to_str().ok()?.parse().ok()?;
+            authority.host().to_owned()
+        }
+    };
+    Some(host.to_ascii_lowercase())
 }
 
-async fn unimplemented() -> impl axum::response::IntoResponse {
-    let status = http::StatusCode::OK;
-    let headers = [("grpc-status", "12"), ("content-type", "application/grpc")];
-    (status, headers)
+async fn unimplemented(req: Request<Body>) -> Response<BoxBody> {
+    let path = req.uri().path();
+    let peer = req
+        .extensions()
+        .get::<TcpConnectInfo>()
+        .and_then(TcpConnectInfo::remote_addr);
+    let user_agent = req
+        .headers()
+        .get(USER_AGENT)
+        .and_then(|value| value.to_str().ok());
+    tracing::info!(
+        %path,
+        ?peer,
+        ?user_agent,
+        "no service registered for request path"
+    );
+
+    Status::unimplemented(format!("no service registered for path `{}`", path)).to_http()
 }
 
 impl Service<Request<Body>> for Routes {
@@ -69,7 +225,21 @@ impl Service<Request<Body>> for Routes {
     }
 
     fn call(&mut self, req: Request<Body>) -> Self::Future {
-        RoutesFuture(self.router.call(req))
+        let service = service_name(req.uri().path());
+        let router = if let Some(route) = self
+            .headers
+            .iter_mut()
+            .find(|route| route.matches(service, &req))
+        {
+            &mut route.router
+        } else if self.hosts.is_empty() {
+            &mut self.router
+        } else {
+            request_host(&req)
+                .and_then(|host| self.hosts.get_mut(&host))
+                .unwrap_or(&mut self.router)
+        };
+        RoutesFuture(router.call(req))
     }
 }
 
@@ -107,3 +277,41 @@ where
     })
     .boxed_unsync()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn request_host_from_authority_or_header() {
+        let req = Request::builder()
+            .uri("https://Tenant-A.example.com:443/pkg.Greeter/SayHello")
+            .body(())
+            .unwrap();
+        assert_eq!(request_host(&req).unwrap(), "tenant-a.example.com");
+
+        let req = Request::builder()
+            .uri("/pkg.Greeter/SayHello")
+            .header(HOST, "tenant-b.example.com:8080")
+            .body(())
+            .unwrap();
+        assert_eq!(request_host(&req).unwrap(), "tenant-b.example.com");
+
+        let req = Request::builder()
+            .uri("/pkg.Greeter/SayHello")
+            .body(())
+            .unwrap();
+        assert!(request_host(&req).is_none());
+    }
+
+    #[test]
+    fn contains_service_paths() {
+        let mut routes = Routes::empty();
+        routes.record_service("pkg.Greeter");
+        assert_eq!(routes.services(), ["pkg.Greeter"]);
+        assert!(routes.contains("/pkg.Greeter/SayHello"));
+        assert!(routes.contains("pkg.Greeter"));
+        assert!(!routes.contains("/pkg.Store/Get"));
+        assert!(!routes.contains("/pkg/Greeter"));
+    }
+}
@@ -0,0 +1,355 @@
+use super::ResponseBody;
+use crate::{BoxError, OptionPin, OptionPinProj};
+
+use bytes::Bytes;
+use http::{HeaderMap, Response};
+use http_body::{Body as HttpBody, SizeHint};
+use hyper::Body;
+use pin_project::pin_project;
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::time::{Instant, Sleep};
+use tonic::Status;
+use tower_service::Service;
+
+/// Middleware that fails calls whose response headers don't arrive within `headers`, whose
+/// response doesn't start within `first_byte`, or whose response stream receives nothing for
+/// `inactivity`.
+///
+/// Unlike [`GrpcTimeout`](super::GrpcTimeout), which bounds the whole call, these timeouts only
+/// bound the waits for parts of the response, so long-lived streams can run for as long as they
+/// keep receiving messages.
+#[derive(Debug, Clone)]
+pub(crate) struct StreamTimeout<S> {
+    inner: S,
+    headers: Option<Duration>,
+    first_byte: Option<Duration>,
+    inactivity: Option<Duration>,
+}
+
+impl<S> StreamTimeout<S> {
+    pub(crate) fn new(
+        inner: S,
+        headers: Option<Duration>,
+        first_byte: Option<Duration>,
+        inactivity: Option<Duration>,
+    ) -> Self {
+        Self {
+            inner,
+            headers,
+            first_byte,
+            inactivity,
+        }
+    }
+}
+
+impl<S, Req> Service<Req> for StreamTimeout<S>
+where
+    S: Service<Req, Response = Response<Body>>,
+    S::Error: Into<BoxError>,
+{
+    type Response = Response<ResponseBody>;
+    type Error = BoxError;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, req: Req) -> Self::Future {
+        let now = Instant::now();
+        let headers = self.headers.map(|timeout| now + timeout);
+        let first_byte = self.first_byte.map(|timeout| now + timeout);
+        let deadline = match (headers, first_byte) {
+            (Some(headers), Some(first_byte)) => Some(headers.min(first_byte)),
+            (headers, first_byte) => headers.or(first_byte),
+        };
+        ResponseFuture {
+            inner: self.inner.call(req),
+            sleep: match deadline {
+                Some(deadline) => OptionPin::Some(tokio::time::sleep_until(deadline)),
+                None => OptionPin::None,
+            },
+            headers,
+            first_byte,
+            inactivity: self.inactivity,
+        }
+    }
+}
+
+#[pin_project]
+pub(crate) struct ResponseFuture<F> {
+    #[pin]
+    inner: F,
+    #[pin]
+    sleep: OptionPin<Sleep>,
+    headers: Option<Instant>,
+    first_byte: Option<Instant>,
+    inactivity: Option<Duration>,
+}
+
+impl<F, E> Future for ResponseFuture<F>
+where
+    F: Future<Output = Result<Response<Body>, E>>,
+    E: Into<BoxError>,
+{
+    type Output = Result<Response<ResponseBody>, BoxError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+
+        if let Poll::Ready(result) = this.inner.poll(cx) {
+            let response = result.map_err(Into::into)?;
+            if this.first_byte.is_none() && this.inactivity.is_none() {
+                return Poll::Ready(Ok(response.map(ResponseBody::from)));
+            }
+            let (first_byte, inactivity) = (*this.first_byte, *this.inactivity);
+            return Poll::Ready(Ok(response.map(|body| {
+                ResponseBody::new(TimeoutBody::new(body, first_byte, inactivity))
+            })));
+        }
+
+        if let OptionPinProj::Some(sleep) = this.sleep.project() {
+            futures_util::ready!(sleep.poll(cx));
+            let status = match (*this.headers, *this.first_byte) {
+                (Some(headers), Some(first_byte)) if first_byte < headers => first_byte_expired(),
+                (Some(_), _) => {
+                    Status::deadline_exceeded("no response headers within the headers timeout")
+                }
+                (None, _) => first_byte_expired(),
+            };
+            return Poll::Ready(Err(status.into()));
+        }
+
+        Poll::Pending
+    }
+}
+
+fn first_byte_expired() -> Status {
+    Status::deadline_exceeded("no response within the first byte timeout")
+}
+
+/// A body which ends with a `DEADLINE_EXCEEDED` status in its trailers, as a server would end
+/// it, if its first message or the next message doesn't arrive in time.
+///
+/// The inner body is dropped when a timeout expires, which resets the stream.
+#[pin_project]
+struct TimeoutBody {
+    inner: Option<Body>,
+    #[pin]
+    sleep: OptionPin<Sleep>,
+    // Whether the first byte timeout applies, until the first message is received.
+    first_byte: bool,
+    inactivity: Option<Duration>,
+    // The status the body ends with, once a timeout has expired.
+    expired: Option<Status>,
+}
+
+impl TimeoutBody {
+    fn new(inner: Body, first_byte: Option<Instant>, inactivity: Option<Duration>) -> Self {
+        let deadline = first_byte.or_else(|| inactivity.map(|timeout| Instant::now() + timeout));
+        TimeoutBody {
+            inner: Some(inner),
+            sleep: match deadline {
+                Some(deadline) => OptionPin::Some(tokio::time::sleep_until(deadline)),
+                None => OptionPin::None,
+            },
+            first_byte: first_byte.is_some(),
+            inactivity,
+            expired: None,
+        }
+    }
+
+    /// Returns `true` if a timeout has expired, and drops the inner body.
+    fn poll_expired(self: Pin<&mut Self>, cx: &mut Context<'_>) -> bool {
+        let mut this = self.project();
+        let expired = match this.sleep.as_mut().project() {
+            OptionPinProj::Some(sleep) => sleep.poll(cx).is_ready(),
+            OptionPinProj::None => false,
+        };
+        if !expired {
+            return false;
+        }
+        let status = if *this.first_byte {
+            first_byte_expired()
+        } else {
+            Status::deadline_exceeded("no message received within the inactivity timeout")
+        };
+        tracing::debug!(%status, "ending response stream");
+        *this.inner = None;
+        *this.expired = Some(status);
+        this.sleep.set(OptionPin::None);
+        true
+    }
+}
+
+impl HttpBody for TimeoutBody {
+    type Data = Bytes;
+    type Error = BoxError;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let mut this = self.as_mut().project();
+        let Some(inner) = this.inner.as_mut() else {
+            return Poll::Ready(None);
+        };
+        match Pin::new(inner).poll_data(cx) {
+            Poll::Ready(Some(Ok(data))) => {
+                *this.first_byte = false;
+                match *this.inactivity {
+                    Some(timeout) => {
+                        let deadline = Instant::now() + timeout;
+                        match this.sleep.as_mut().project() {
+                            OptionPinProj::Some(sleep) => sleep.reset(deadline),
+                            OptionPinProj::None => this
+                                .sleep
+                                .set(OptionPin::Some(tokio::time::sleep_until(deadline))),
+                        }
+                    }
+                    None => this.sleep.set(OptionPin::None),
+                }
+                Poll::Ready(Some(Ok(data)))
+            }
+            Poll::Ready(result) => Poll::Ready(result.map(|result| result.map_err(Into::into))),
+            Poll::Pending if self.poll_expired(cx) => Poll::Ready(None),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        if self.inner.is_some() {
+            let inner = self
+                .as_mut()
+                .project()
+                .inner
+                .as_mut()
+                .expect("body is present");
+            match Pin::new(inner).poll_trailers(cx) {
+                Poll::Ready(result) => return Poll::Ready(result.map_err(Into::into)),
+                Poll::Pending if self.as_mut().poll_expired(cx) => {}
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        match self.project().expired.take() {
+            Some(status) => Poll::Ready(status.to_header_map().map(Some).map_err(Into::into)),
+            None => Poll::Ready(Ok(None)),
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        match &self.inner {
+            Some(inner) => inner.is_end_stream(),
+            None => self.expired.is_none(),
+        }
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        match &self.inner {
+            Some(inner) => inner.size_hint(),
+            None => SizeHint::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tonic::Code;
+
+    fn watch(
+        body: Body,
+        first_byte: Option<Instant>,
+        inactivity: Option<Duration>,
+    ) -> ResponseBody {
+        ResponseBody::new(TimeoutBody::new(body, first_byte, inactivity))
+    }
+
+    async fn status(mut body: ResponseBody) -> (Vec<Bytes>, Option<Code>) {
+        let mut messages = Vec::new();
+        while let Some(data) = body.data().await {
+            messages.push(data.unwrap());
+        }
+        let trailers = body.trailers().await.unwrap();
+        let code = trailers.and_then(|trailers| Status::from_header_map(&trailers));
+        (messages, code.map(|status| status.code()))
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn ends_inactive_streams() {
+        let (mut tx, body) = Body::channel();
+        let body = watch(body, None, Some(Duration::from_secs(1)));
+        tokio::spawn(async move {
+            tx.send_data(Bytes::from("first")).await.unwrap();
+            tokio::time::sleep(Duration::from_millis(900)).await;
+            tx.send_data(Bytes::from("second")).await.unwrap();
+            tokio::time::sleep(Duration::from_secs(2)).await;
+            drop(tx);
+        });
+
+        let (messages, code) = status(body).await;
+        assert_eq!(messages, ["first", "second"]);
+        assert_eq!(code, Some(Code::DeadlineExceeded));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn forwards_complete_streams() {
+        let (mut tx, body) = Body::channel();
+        let first_byte = Some(Instant::now() + Duration::from_secs(1));
+        let body = watch(body, first_byte, None);
+        tokio::spawn(async move {
+            tx.send_data(Bytes::from("only")).await.unwrap();
+            let trailers = Status::new(Code::Ok, "").to_header_map().unwrap();
+            tx.send_trailers(trailers).await.unwrap();
+        });
+
+        let (messages, code) = status(body).await;
+        assert_eq!(messages, ["only"]);
+        assert_eq!(code, Some(Code::Ok));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn resets_streams_when_dropped() {
+        let (mut tx, body) = Body::channel();
+        let first_byte = Some(Instant::now() + Duration::from_secs(1));
+        let mut body = watch(body, first_byte, None);
+        tx.send_data(Bytes::from("first")).await.unwrap();
+        assert_eq!(body.data().await.unwrap().unwrap(), "first");
+
+        drop(body);
+        let ready = futures_util::future::poll_fn(|cx| tx.poll_ready(cx)).await;
+        assert!(ready.is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn fails_calls_without_headers() {
+        let inner = tower::service_fn(|()| async {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            Ok::<_, BoxError>(Response::new(Body::empty()))
+        });
+        let mut svc = StreamTimeout::new(
+            inner,
+            Some(Duration::from_secs(1)),
+            Some(Duration::from_secs(10)),
+            None,
+        );
+        let start = Instant::now();
+
+        let error = svc.call(()).await.unwrap_err();
+        let status = error.downcast::<Status>().unwrap();
+        assert_eq!(status.code(), Code::DeadlineExceeded);
+        assert_eq!(
+            status.message(),
+            "no response headers within the headers timeout"
+        );
+        assert_eq!(start.elapsed(), Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,182 @@
+use crate::channel::ChannelBuilder;
+use crate::{BoxError, BoxFuture};
+
+use http::Uri;
+use hyper::client::connect::HttpConnector;
+use std::{
+    io,
+    net::IpAddr,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::net::TcpStream;
+use tower_service::Service;
+
+/// Connects TCP streams with an [`HttpConnector`], and sets the socket options it doesn't
+/// support once they are connected.
+///
+/// Sockets which must be bound to a device are connected without the `HttpConnector`, since the
+/// device must be set before connecting.
+#[derive(Debug, Clone)]
+pub(crate) struct TcpConnector {
+    http: HttpConnector,
+    user_timeout: Option<Duration>,
+    device: Option<Arc<DeviceConnector>>,
+}
+
+impl TcpConnector {
+    pub(crate) fn new(endpoint: &ChannelBuilder) -> Self {
+        let options = &endpoint.socket_options;
+        let mut http = HttpConnector::new();
+        http.enforce_http(false);
+        http.set_nodelay(endpoint.tcp_nodelay);
+        http.set_keepalive(endpoint.tcp_keepalive);
+        http.set_local_address(endpoint.local_address);
+        http.set_send_buffer_size(options.send_buffer_size);
+        http.set_recv_buffer_size(options.recv_buffer_size);
+        http.set_reuse_address(options.reuse_address);
+
+        let device = endpoint.bind_device.as_ref().map(|device| {
+            Arc::new(DeviceConnector {
+                device: device.clone(),
+                nodelay: endpoint.tcp_nodelay,
+                keepalive: endpoint.tcp_keepalive,
+                local_address: endpoint.local_address,
+                send_buffer_size: options.send_buffer_size,
+                recv_buffer_size: options.recv_buffer_size,
+                reuse_address: options.reuse_address,
+            })
+        });
+        TcpConnector {
+            http,
+            user_timeout: options.user_timeout,
+            device,
+        }
+    }
+}
+
+impl Service<Uri> for TcpConnector {
+    type Response = TcpStream;
+    type Error = BoxError;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.http.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        let user_timeout = self.user_timeout;
+        let connect: BoxFuture<TcpStream, BoxError> = match &self.device {
+            Some(device) => {
+                let device = device.clone();
+                Box::pin(async move { Ok(device.connect(uri).await?) })
+            }
+            None => {
+                let connect = self.http.call(uri);
+                Box::pin(async move { Ok(connect.await?) })
+            }
+        };
+
+        Box::pin(async move {
+            let stream = connect.await?;
+            if let Some(timeout) = user_timeout {
+                set_user_timeout(&stream, timeout)?;
+            }
+            Ok(stream)
+        })
+    }
+}
+
+/// The settings to connect sockets bound to a device with, mirroring those of the
+/// `HttpConnector`.
+#[derive(Debug)]
+struct DeviceConnector {
+    device: String,
+    nodelay: bool,
+    keepalive: Option<Duration>,
+    local_address: Option<IpAddr>,
+    send_buffer_size: Option<usize>,
+    recv_buffer_size: Option<usize>,
+    reuse_address: bool,
+}
+
+impl DeviceConnector {
+    /// Connect to each address the URI's host resolves to in turn, until one accepts.
+    async fn connect(&self, uri: Uri) -> io::Result<TcpStream> {
+        let host = uri
+            .host()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "URI has no host"))?;
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        let port = uri
+            .port_u16()
+            .unwrap_or(if uri.scheme_str() == Some("https") {
+                443
+            } else {
+                80
+            });
+
+        let mut last_error = None;
+        for addr in tokio::net::lookup_host((host, port)).await? {
+            match self.connect_addr(addr).await {
+                Ok(stream) => return Ok(stream),
+                Err(error) => {
+                    tracing::debug!(%addr, %error, "failed to connect");
+                    last_error = Some(error);
+                }
+            }
+        }
+        Err(last_error.unwrap_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, "host resolved to no addresses")
+        }))
+    }
+
+    #[cfg(any(target_os = "android", target_os = "fuchsia", target_os = "linux"))]
+    async fn connect_addr(&self, addr: std::net::SocketAddr) -> io::Result<TcpStream> {
+        let socket = if addr.is_ipv4() {
+            tokio::net::TcpSocket::new_v4()?
+        } else {
+            tokio::net::TcpSocket::new_v6()?
+        };
+        socket.bind_device(Some(self.device.as_bytes()))?;
+        if let Some(size) = self.send_buffer_size {
+            socket.set_send_buffer_size(size.try_into().unwrap_or(u32::MAX))?;
+        }
+        if let Some(size) = self.recv_buffer_size {
+            socket.set_recv_buffer_size(size.try_into().unwrap_or(u32::MAX))?;
+        }
+        socket.set_reuseaddr(self.reuse_address)?;
+        if let Some(local) = self
+            .local_address
+            .filter(|local| local.is_ipv4() == addr.is_ipv4())
+        {
+            socket.bind((local, 0).into())?;
+        }
+
+        let stream = socket.connect(addr).await?;
+        stream.set_nodelay(self.nodelay)?;
+        if let Some(time) = self.keepalive {
+            let keepalive = socket2::TcpKeepalive::new().with_time(time);
+            socket2::SockRef::from(&stream).set_tcp_keepalive(&keepalive)?;
+        }
+        Ok(stream)
+    }
+
+    #[cfg(not(any(target_os = "android", target_os = "fuchsia", target_os = "linux")))]
+    async fn connect_addr(&self, _addr: std::net::SocketAddr) -> io::Result<TcpStream> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "binding to a device is not supported on this platform",
+        ))
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn set_user_timeout(stream: &TcpStream, timeout: Duration) -> io::Result<()> {
+    socket2::SockRef::from(stream).set_tcp_user_timeout(Some(timeout))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn set_user_timeout(_stream: &TcpStream, _timeout: Duration) -> io::Result<()> {
+    Ok(())
+}
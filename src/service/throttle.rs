@@ -0,0 +1,290 @@
+use crate::server::Connected;
+use crate::Result;
+
+use bytes::{Buf, Bytes};
+use hyper::client::connect::{Connected as HyperConnected, Connection};
+use std::{
+    collections::VecDeque,
+    future::Future,
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    time::{sleep, Instant, Sleep},
+};
+
+// The most data which is read ahead from the inner IO while it is being delayed.
+const MAX_DELAYED_LEN: usize = 256 * 1024;
+
+const READ_CHUNK_LEN: usize = 8 * 1024;
+
+/// Limits on the throughput and latency of a connection, for simulating slow networks in tests.
+///
+/// Set on the client with [`ChannelBuilder::throttle`](crate::ChannelBuilder::throttle) or on the
+/// server with [`Server::throttle`](crate::Server::throttle). The limits apply to each
+/// connection separately.
+///
+/// ```no_run
+/// # use tonic_transport::Throttle;
+/// # use std::time::Duration;
+/// // Roughly a 3G connection.
+/// let throttle = Throttle::new()
+///     .read_rate(100 * 1024)
+///     .write_rate(50 * 1024)
+///     .latency(Duration::from_millis(150));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Throttle {
+    read_rate: Option<u64>,
+    write_rate: Option<u64>,
+    latency: Option<Duration>,
+}
+
+impl Throttle {
+    /// Create a throttle with no limits.
+    pub fn new() -> Self {
+        Throttle::default()
+    }
+
+    /// Limit reads to `bytes_per_sec`.
+    pub fn read_rate(self, bytes_per_sec: u64) -> Self {
+        Throttle {
+            read_rate: Some(bytes_per_sec.max(1)),
+            ..self
+        }
+    }
+
+    /// Limit writes to `bytes_per_sec`.
+    pub fn write_rate(self, bytes_per_sec: u64) -> Self {
+        Throttle {
+            write_rate: Some(bytes_per_sec.max(1)),
+            ..self
+        }
+    }
+
+    /// Delay data received from the peer by `latency`.
+    pub fn latency(self, latency: Duration) -> Self {
+        Throttle {
+            latency: Some(latency),
+            ..self
+        }
+    }
+
+    fn is_unlimited(&self) -> bool {
+        self.read_rate.is_none() && self.write_rate.is_none() && self.latency.is_none()
+    }
+}
+
+/// An IO wrapper which applies a [`Throttle`].
+pub(crate) struct ThrottledIo<T> {
+    inner: T,
+    state: Option<Box<State>>,
+}
+
+struct State {
+    latency: Duration,
+    read: Option<Bucket>,
+    write: Option<Bucket>,
+    // Data which has been read from the inner IO, and when it may be delivered.
+    delayed: VecDeque<(Instant, Bytes)>,
+    delayed_len: usize,
+    eof: bool,
+    delay: Pin<Box<Sleep>>,
+}
+
+impl<T> ThrottledIo<T> {
+    pub(crate) fn new(inner: T, throttle: Option<&Throttle>) -> Self {
+        let state = throttle
+            .filter(|throttle| !throttle.is_unlimited())
+            .map(|throttle| {
+                Box::new(State {
+                    latency: throttle.latency.unwrap_or_default(),
+                    read: throttle.read_rate.map(Bucket::new),
+                    write: throttle.write_rate.map(Bucket::new),
+                    delayed: VecDeque::new(),
+                    delayed_len: 0,
+                    eof: false,
+                    delay: Box::pin(sleep(Duration::ZERO)),
+                })
+            });
+        ThrottledIo { inner, state }
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for ThrottledIo<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        let state = match &mut this.state {
+            Some(state) => state,
+            None => return Pin::new(&mut this.inner).poll_read(cx, buf),
+        };
+        if buf.remaining() == 0 {
+            return Poll::Ready(Ok(()));
+        }
+
+        // Read ahead so that the delay of each chunk starts when it arrives.
+        while !state.eof && state.delayed_len < MAX_DELAYED_LEN {
+            let mut chunk = [0; READ_CHUNK_LEN];
+            let mut chunk = ReadBuf::new(&mut chunk);
+            match Pin::new(&mut this.inner).poll_read(cx, &mut chunk)? {
+                Poll::Ready(()) if chunk.filled().is_empty() => state.eof = true,
+                Poll::Ready(()) => {
+                    let ready_at = Instant::now() + state.latency;
+                    state.delayed_len += chunk.filled().len();
+                    state
+                        .delayed
+                        .push_back((ready_at, Bytes::copy_from_slice(chunk.filled())));
+                }
+                Poll::Pending => break,
+            }
+        }
+
+        let ready_at = match state.delayed.front() {
+            Some((ready_at, _)) => *ready_at,
+            None if state.eof => return Poll::Ready(Ok(())),
+            None => return Poll::Pending,
+        };
+        if ready_at > Instant::now() {
+            state.delay.as_mut().reset(ready_at);
+            futures_util::ready!(state.delay.as_mut().poll(cx));
+        }
+
+        let mut len = buf.remaining();
+        if let Some(bucket) = &mut state.read {
+            len = futures_util::ready!(bucket.poll_acquire(cx, len));
+        }
+
+        let (_, chunk) = state.delayed.front_mut().expect("checked above");
+        let len = len.min(chunk.len());
+        buf.put_slice(&chunk[..len]);
+        chunk.advance(len);
+        if chunk.is_empty() {
+            state.delayed.pop_front();
+        }
+        state.delayed_len -= len;
+        if let Some(bucket) = &mut state.read {
+            bucket.consume(len);
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for ThrottledIo<T> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        let bucket = match this.state.as_mut().and_then(|state| state.write.as_mut()) {
+            Some(bucket) if !buf.is_empty() => bucket,
+            _ => return Pin::new(&mut this.inner).poll_write(cx, buf),
+        };
+
+        let len = futures_util::ready!(bucket.poll_acquire(cx, buf.len()));
+        let written = futures_util::ready!(Pin::new(&mut this.inner).poll_write(cx, &buf[..len]))?;
+        bucket.consume(written);
+        Poll::Ready(Ok(written))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+impl<T: Connection> Connection for ThrottledIo<T> {
+    fn connected(&self) -> HyperConnected {
+        self.inner.connected()
+    }
+}
+
+impl<T: Connected> Connected for ThrottledIo<T> {
+    type ConnectInfo = T::ConnectInfo;
+
+    fn connect_info(&self) -> Result<Self::ConnectInfo> {
+        self.inner.connect_info()
+    }
+}
+
+/// A token bucket which allows `rate` bytes per second, with bursts of up to a tenth of a second.
+struct Bucket {
+    rate: f64,
+    tokens: f64,
+    updated: Instant,
+    sleep: Pin<Box<Sleep>>,
+}
+
+impl Bucket {
+    fn new(rate: u64) -> Self {
+        Bucket {
+            rate: rate as f64,
+            tokens: 0.0,
+            updated: Instant::now(),
+            sleep: Box::pin(sleep(Duration::ZERO)),
+        }
+    }
+
+    fn capacity(&self) -> f64 {
+        (self.rate / 10.0).max(1.0)
+    }
+
+    /// Wait until at least one byte is available, and return how many of `want` bytes may be
+    /// transferred.
+    fn poll_acquire(&mut self, cx: &mut Context<'_>, want: usize) -> Poll<usize> {
+        loop {
+            let now = Instant::now();
+            let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+            self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity());
+            self.updated = now;
+
+            if self.tokens >= 1.0 {
+                return Poll::Ready(want.min(self.tokens as usize));
+            }
+
+            let wait = Duration::from_secs_f64((1.0 - self.tokens) / self.rate);
+            self.sleep.as_mut().reset(now + wait);
+            futures_util::ready!(self.sleep.as_mut().poll(cx));
+        }
+    }
+
+    fn consume(&mut self, len: usize) {
+        self.tokens -= len as f64;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test(start_paused = true)]
+    async fn limits_throughput_and_adds_latency() {
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let throttle = Throttle::new()
+            .read_rate(10 * 1024)
+            .latency(Duration::from_millis(100));
+        let mut client = ThrottledIo::new(client, Some(&throttle));
+        let mut server = server;
+
+        let start = Instant::now();
+        server.write_all(&[0; 20 * 1024]).await.unwrap();
+        let mut data = vec![0; 20 * 1024];
+        client.read_exact(&mut data).await.unwrap();
+
+        // 20 KiB at 10 KiB/s takes two seconds, less the initial burst, plus the latency.
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(1900), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_millis(2500), "{:?}", elapsed);
+    }
+}
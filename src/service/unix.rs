@@ -0,0 +1,72 @@
+use crate::BoxFuture;
+
+use http::Uri;
+use std::{
+    io,
+    path::PathBuf,
+    sync::Arc,
+    task::{Context, Poll},
+};
+use tokio::net::UnixStream;
+use tower_service::Service;
+
+#[derive(Debug)]
+enum Addr {
+    Path(PathBuf),
+    #[cfg(target_os = "linux")]
+    Abstract(String),
+}
+
+/// Connects to a Unix domain socket, ignoring the URI it is called with.
+#[derive(Debug, Clone)]
+pub(crate) struct UnixConnector {
+    addr: Arc<Addr>,
+}
+
+impl UnixConnector {
+    pub(crate) fn new(path: PathBuf) -> Self {
+        Self {
+            addr: Arc::new(Addr::Path(path)),
+        }
+    }
+
+    /// Connect to the socket called `name` in the abstract namespace.
+    #[cfg(target_os = "linux")]
+    pub(crate) fn new_abstract(name: String) -> Self {
+        Self {
+            addr: Arc::new(Addr::Abstract(name)),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn connect_abstract(name: &str) -> io::Result<UnixStream> {
+    use std::os::linux::net::SocketAddrExt;
+
+    let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+    // Connecting to a Unix domain socket doesn't block waiting for the server.
+    let stream = std::os::unix::net::UnixStream::connect_addr(&addr)?;
+    stream.set_nonblocking(true)?;
+    UnixStream::from_std(stream)
+}
+
+impl Service<Uri> for UnixConnector {
+    type Response = UnixStream;
+    type Error = io::Error;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, _uri: Uri) -> Self::Future {
+        let addr = self.addr.clone();
+        Box::pin(async move {
+            match &*addr {
+                Addr::Path(path) => UnixStream::connect(path).await,
+                #[cfg(target_os = "linux")]
+                Addr::Abstract(name) => connect_abstract(name),
+            }
+        })
+    }
+}
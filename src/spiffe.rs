@@ -0,0 +1,352 @@
+use crate::service::backoff::{Backoff, ConnectBackoff};
+use crate::tls::ReloadableTls;
+use crate::{BoxError, Error, Result};
+
+use http::{uri::PathAndQuery, Uri};
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tokio::{net::UnixStream, sync::watch, task::JoinHandle};
+use tonic::{
+    body::BoxBody, client::Grpc, codec::ProstCodec, metadata::MetadataValue, Request, Streaming,
+};
+
+/// The environment variable with the address of the Workload API.
+const ENDPOINT_SOCKET_ENV: &str = "SPIFFE_ENDPOINT_SOCKET";
+const FETCH_X509_SVID: &str = "/SpiffeWorkloadAPI/FetchX509SVID";
+// The Workload API rejects requests without this header.
+const SECURITY_HEADER: &str = "workload.spiffe.io";
+
+/// An X.509 identity document issued by the SPIFFE Workload API, with the trust bundle of its
+/// trust domain.
+///
+/// Certificates and keys are DER encoded.
+#[derive(Clone, PartialEq, Eq)]
+pub struct X509Svid {
+    spiffe_id: String,
+    cert_chain: Vec<Vec<u8>>,
+    private_key: Vec<u8>,
+    bundle: Vec<Vec<u8>>,
+}
+
+impl X509Svid {
+    fn from_proto(svid: proto::X509Svid) -> Result<Self> {
+        Ok(X509Svid {
+            spiffe_id: svid.spiffe_id,
+            cert_chain: split_certificates(&svid.x509_svid)?,
+            private_key: svid.x509_svid_key,
+            bundle: split_certificates(&svid.bundle)?,
+        })
+    }
+
+    /// The SPIFFE ID of the workload, such as `spiffe://cluster.local/ns/default/sa/server`.
+    pub fn spiffe_id(&self) -> &str {
+        &self.spiffe_id
+    }
+
+    /// The workload's certificate chain, leaf first.
+    pub fn cert_chain(&self) -> &[Vec<u8>] {
+        &self.cert_chain
+    }
+
+    /// The private key of the leaf certificate, in PKCS#8 format.
+    pub fn private_key(&self) -> &[u8] {
+        &self.private_key
+    }
+
+    /// The root certificates of the workload's trust domain, for verifying peers.
+    pub fn bundle(&self) -> &[Vec<u8>] {
+        &self.bundle
+    }
+}
+
+impl fmt::Debug for X509Svid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("X509Svid")
+            .field("spiffe_id", &self.spiffe_id)
+            .finish()
+    }
+}
+
+/// A source of X.509 identities which are fetched from the SPIFFE Workload API, such as a SPIRE
+/// agent, and rotated before they expire.
+///
+/// The source watches the Workload API in a background task, reconnecting if the connection is
+/// lost, until it is dropped. Use [`tls`](X509Source::tls) to build a [`ReloadableTls`] for a
+/// [`Server`](crate::Server) or [`Channel`](crate::Channel), which is rebuilt whenever the
+/// identity is rotated. Requires the `spiffe` feature.
+///
+/// ```no_run
+/// # use tonic_transport::{BoxError, Server, X509Source, X509Svid};
+/// fn pem(label: &str, der: &[u8]) -> String {
+///     let base64 = base64::encode(der);
+///     let lines: Vec<_> = base64.as_bytes().chunks(64).map(String::from_utf8_lossy).collect();
+///     format!("-----BEGIN {label}-----\n{}\n-----END {label}-----\n", lines.join("\n"))
+/// }
+///
+/// fn build_acceptor(svid: &X509Svid) -> Result<tokio_native_tls::TlsAcceptor, BoxError> {
+///     let chain: String = svid.cert_chain().iter().map(|cert| pem("CERTIFICATE", cert)).collect();
+///     let key = pem("PRIVATE KEY", svid.private_key());
+///     let identity = native_tls::Identity::from_pkcs8(chain.as_bytes(), key.as_bytes())?;
+///     Ok(native_tls::TlsAcceptor::new(identity)?.into())
+/// }
+///
+/// # async fn example() -> Result<(), tonic_transport::Error> {
+/// let source = X509Source::connect().await?;
+/// let tls = source.tls(build_acceptor)?;
+/// let server = Server::builder_with_reloadable_tls(tls);
+/// # Ok(())
+/// # }
+/// ```
+pub struct X509Source {
+    svid: watch::Receiver<Arc<X509Svid>>,
+    task: JoinHandle<()>,
+}
+
+impl X509Source {
+    /// Connect to the Workload API at the address in the `SPIFFE_ENDPOINT_SOCKET` environment
+    /// variable, and wait for the first identity.
+    pub async fn connect() -> Result<Self> {
+        let addr = std::env::var(ENDPOINT_SOCKET_ENV).map_err(|_| {
+            Error::from_source(format!("{} is not set", ENDPOINT_SOCKET_ENV).into())
+        })?;
+        X509Source::connect_to(&addr).await
+    }
+
+    /// Connect to the Workload API at `addr`, such as `unix:///run/spire/agent.sock`, and wait
+    /// for the first identity.
+    ///
+    /// Only Unix domain sockets are supported. A plain path is also accepted.
+    pub async fn connect_to(addr: &str) -> Result<Self> {
+        let path = socket_path(addr)?;
+        let mut updates = fetch(&path).await?;
+        let svid = next_svid(&mut updates)
+            .await?
+            .ok_or_else(|| Error::from_source("Workload API closed the stream".into()))?;
+        tracing::debug!(spiffe_id = %svid.spiffe_id, "fetched X.509 SVID");
+
+        let (tx, svid) = watch::channel(Arc::new(svid));
+        let task = tokio::spawn(watch_updates(path, updates, tx));
+        Ok(X509Source { svid, task })
+    }
+
+    /// The current identity.
+    pub fn svid(&self) -> Arc<X509Svid> {
+        self.svid.borrow().clone()
+    }
+
+    /// Build a TLS configuration with `build`, which is called again with the new identity each
+    /// time it is rotated.
+    ///
+    /// `build` typically uses the identity's certificate chain and key as the TLS identity and
+    /// its bundle as the only root certificates. If it fails for a rotated identity, the error
+    /// is logged and the previous configuration is kept.
+    pub fn tls<T, F>(&self, build: F) -> Result<ReloadableTls<T>>
+    where
+        F: Fn(&X509Svid) -> std::result::Result<T, BoxError> + Send + 'static,
+        T: Send + Sync + 'static,
+    {
+        let mut updates = self.svid.clone();
+        let svid = updates.borrow_and_update().clone();
+        let tls = ReloadableTls::new(build(&svid).map_err(Error::from_source)?);
+
+        let weak = tls.downgrade();
+        tokio::spawn(async move {
+            // Ends when the source is dropped.
+            while updates.changed().await.is_ok() {
+                let tls = match ReloadableTls::upgrade(&weak) {
+                    Some(tls) => tls,
+                    None => return,
+                };
+                let svid = updates.borrow_and_update().clone();
+                match build(&svid) {
+                    Ok(new) => tls.reload(new),
+                    Err(error) => tracing::warn!(
+                        %error,
+                        spiffe_id = %svid.spiffe_id,
+                        "failed to build TLS configuration from rotated X.509 SVID"
+                    ),
+                }
+            }
+        });
+        Ok(tls)
+    }
+}
+
+impl Drop for X509Source {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl fmt::Debug for X509Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("X509Source")
+            .field("svid", &*self.svid.borrow())
+            .finish()
+    }
+}
+
+fn socket_path(addr: &str) -> Result<PathBuf> {
+    if let Some(path) = addr.strip_prefix("unix://") {
+        Ok(PathBuf::from(path))
+    } else if let Some(path) = addr.strip_prefix("unix:") {
+        Ok(PathBuf::from(path))
+    } else if addr.contains("://") {
+        Err(Error::new_invalid_uri(addr.to_owned()))
+    } else {
+        Ok(PathBuf::from(addr))
+    }
+}
+
+/// Open a stream of X.509 SVID updates from the Workload API at `path`.
+async fn fetch(path: &Path) -> Result<Streaming<proto::X509SvidResponse>> {
+    let io = UnixStream::connect(path)
+        .await
+        .map_err(|e| Error::from_source(e.into()))?;
+    let (send_request, connection) = hyper::client::conn::Builder::new()
+        .http2_only(true)
+        .handshake::<_, BoxBody>(io)
+        .await
+        .map_err(|e| Error::from_source(e.into()))?;
+    tokio::spawn(async move {
+        if let Err(error) = connection.await {
+            tracing::debug!(%error, "Workload API connection error");
+        }
+    });
+
+    let mut grpc = Grpc::with_origin(send_request, Uri::from_static("http://localhost"));
+    grpc.ready()
+        .await
+        .map_err(|e| Error::from_source(e.into()))?;
+    let mut request = Request::new(proto::X509SvidRequest {});
+    request
+        .metadata_mut()
+        .insert(SECURITY_HEADER, MetadataValue::from_static("true"));
+    let response = grpc
+        .server_streaming(
+            request,
+            PathAndQuery::from_static(FETCH_X509_SVID),
+            ProstCodec::default(),
+        )
+        .await
+        .map_err(|e| Error::from_source(e.into()))?;
+    Ok(response.into_inner())
+}
+
+/// Wait for the next update, returning the workload's default (first) identity.
+async fn next_svid(updates: &mut Streaming<proto::X509SvidResponse>) -> Result<Option<X509Svid>> {
+    let response = match updates
+        .message()
+        .await
+        .map_err(|e| Error::from_source(e.into()))?
+    {
+        Some(response) => response,
+        None => return Ok(None),
+    };
+    let svid = response
+        .svids
+        .into_iter()
+        .next()
+        .ok_or_else(|| Error::from_source("Workload API returned no X.509 SVIDs".into()))?;
+    X509Svid::from_proto(svid).map(Some)
+}
+
+async fn watch_updates(
+    path: PathBuf,
+    mut updates: Streaming<proto::X509SvidResponse>,
+    tx: watch::Sender<Arc<X509Svid>>,
+) {
+    let mut backoff = Backoff::new(ConnectBackoff::default());
+    loop {
+        match next_svid(&mut updates).await {
+            Ok(Some(svid)) => {
+                if **tx.borrow() != svid {
+                    tracing::debug!(spiffe_id = %svid.spiffe_id, "X.509 SVID rotated");
+                    tx.send_replace(Arc::new(svid));
+                }
+                continue;
+            }
+            Ok(None) => tracing::debug!("Workload API closed the stream"),
+            Err(error) => tracing::warn!(%error, "failed to fetch X.509 SVID"),
+        }
+
+        // Keep the current identity while reconnecting.
+        updates = loop {
+            tokio::time::sleep(backoff.next_delay()).await;
+            match fetch(&path).await {
+                Ok(updates) => break updates,
+                Err(error) => tracing::debug!(%error, "failed to connect to Workload API"),
+            }
+        };
+        backoff.reset();
+    }
+}
+
+/// Split concatenated DER encoded certificates.
+fn split_certificates(mut der: &[u8]) -> Result<Vec<Vec<u8>>> {
+    let mut certs = Vec::new();
+    while !der.is_empty() {
+        let (rest, _) = x509_parser::parse_x509_certificate(der)
+            .map_err(|e| Error::from_source(format!("invalid certificate: {}", e).into()))?;
+        certs.push(der[..der.len() - rest.len()].to_vec());
+        der = rest;
+    }
+    Ok(certs)
+}
+
+/// Messages of the SPIFFE Workload API.
+mod proto {
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub(super) struct X509SvidRequest {}
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub(super) struct X509SvidResponse {
+        #[prost(message, repeated, tag = "1")]
+        pub(super) svids: Vec<X509Svid>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub(super) struct X509Svid {
+        #[prost(string, tag = "1")]
+        pub(super) spiffe_id: String,
+        #[prost(bytes = "vec", tag = "2")]
+        pub(super) x509_svid: Vec<u8>,
+        #[prost(bytes = "vec", tag = "3")]
+        pub(super) x509_svid_key: Vec<u8>,
+        #[prost(bytes = "vec", tag = "4")]
+        pub(super) bundle: Vec<u8>,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn der(pem: &str) -> Vec<u8> {
+        let (_, pem) = x509_parser::pem::parse_x509_pem(pem.as_bytes()).unwrap();
+        pem.contents
+    }
+
+    #[test]
+    fn splits_concatenated_certificates() {
+        let ca = der(include_str!("../examples/certs/ca.crt"));
+        let server = der(include_str!("../examples/certs/server.crt"));
+        let chain = [server.clone(), ca.clone()].concat();
+
+        assert_eq!(split_certificates(&chain).unwrap(), vec![server, ca]);
+        assert!(split_certificates(&chain[..chain.len() - 1]).is_err());
+        assert!(split_certificates(&[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn parses_socket_addresses() {
+        let path = |addr| socket_path(addr).unwrap();
+        assert_eq!(path("unix:///run/agent.sock"), Path::new("/run/agent.sock"));
+        assert_eq!(path("unix:/run/agent.sock"), Path::new("/run/agent.sock"));
+        assert_eq!(path("/run/agent.sock"), Path::new("/run/agent.sock"));
+        assert!(socket_path("tcp://127.0.0.1:8081").is_err());
+    }
+}
@@ -1,7 +1,12 @@
 use crate::server::Connected;
 use crate::service::io::BoxedIo;
 use crate::{Error, Result};
-use std::{fmt, sync::Arc};
+use std::{
+    error::Error as StdError,
+    fmt,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
 use tokio::io::{AsyncRead, AsyncWrite};
 use tokio_native_tls::TlsStream;
 
@@ -27,32 +32,202 @@ impl Certificate {
     }
 }
 
+/// TLS configuration which can be replaced while a [`Server`](crate::Server) or
+/// [`Channel`](crate::Channel) is using it, for example to rotate certificates.
+///
+/// Connections made after [`reload`](ReloadableTls::reload) use the new configuration;
+/// connections which are already established are not affected. Clones share the same
+/// configuration.
+///
+/// ```no_run
+/// # use tonic_transport::{ReloadableTls, Server};
+/// # fn example(acceptor: tokio_native_tls::TlsAcceptor, rotated: tokio_native_tls::TlsAcceptor) {
+/// let tls = ReloadableTls::new(acceptor);
+/// let server = Server::builder_with_reloadable_tls(tls.clone());
+/// // Later, when the certificate is renewed:
+/// tls.reload(rotated);
+/// # }
+/// ```
+pub struct ReloadableTls<T>(Arc<RwLock<Arc<T>>>);
+
+impl<T> ReloadableTls<T> {
+    /// Create a reloadable configuration from `tls`, a `tokio_native_tls::TlsAcceptor` or
+    /// `tokio_native_tls::TlsConnector`.
+    pub fn new(tls: T) -> Self {
+        ReloadableTls(Arc::new(RwLock::new(Arc::new(tls))))
+    }
+
+    /// Replace the configuration used for new connections.
+    pub fn reload(&self, tls: T) {
+        *self.0.write().unwrap() = Arc::new(tls);
+    }
+
+    /// An identifier of the configuration, which is shared by its clones.
+    pub(crate) fn id(&self) -> usize {
+        Arc::as_ptr(&self.0) as *const () as usize
+    }
+
+    pub(crate) fn current(&self) -> Arc<T> {
+        self.0.read().unwrap().clone()
+    }
+
+    /// A handle which doesn't keep the configuration alive, for tasks which reload it.
+    #[cfg(feature = "spiffe")]
+    pub(crate) fn downgrade(&self) -> std::sync::Weak<RwLock<Arc<T>>> {
+        Arc::downgrade(&self.0)
+    }
+
+    #[cfg(feature = "spiffe")]
+    pub(crate) fn upgrade(weak: &std::sync::Weak<RwLock<Arc<T>>>) -> Option<Self> {
+        weak.upgrade().map(ReloadableTls)
+    }
+}
+
+impl<T> Clone for ReloadableTls<T> {
+    fn clone(&self) -> Self {
+        ReloadableTls(self.0.clone())
+    }
+}
+
+impl<T> fmt::Debug for ReloadableTls<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReloadableTls").finish()
+    }
+}
+
 #[derive(Clone)]
 pub(crate) struct TlsConnector {
-    connector: Arc<tokio_native_tls::TlsConnector>,
+    connector: ReloadableTls<tokio_native_tls::TlsConnector>,
     domain: Arc<String>,
+    handshake_timeout: Option<Duration>,
+    #[cfg(feature = "x509")]
+    spiffe_id: Option<Arc<String>>,
+    // The server name sent instead of `domain`, which is then verified by this crate unless it
+    // is empty, when the connector sends no server name.
+    sni: Option<Arc<String>>,
 }
 
 impl TlsConnector {
-    pub(crate) fn new(connector: tokio_native_tls::TlsConnector, domain: String) -> TlsConnector {
+    pub(crate) fn new(
+        connector: ReloadableTls<tokio_native_tls::TlsConnector>,
+        domain: String,
+    ) -> TlsConnector {
         TlsConnector {
-            connector: Arc::new(connector),
+            connector,
             domain: Arc::new(domain),
+            handshake_timeout: None,
+            #[cfg(feature = "x509")]
+            spiffe_id: None,
+            sni: None,
         }
     }
 
+    /// Fail connections whose handshake doesn't complete within `timeout`.
+    pub(crate) fn with_handshake_timeout(self, timeout: Option<Duration>) -> TlsConnector {
+        TlsConnector {
+            handshake_timeout: timeout,
+            ..self
+        }
+    }
+
+    /// Require the server's certificate to have the SPIFFE ID `spiffe_id`.
+    #[cfg(feature = "x509")]
+    pub(crate) fn with_spiffe_id(self, spiffe_id: Option<String>) -> TlsConnector {
+        TlsConnector {
+            spiffe_id: spiffe_id.map(Arc::new),
+            ..self
+        }
+    }
+
+    /// Send `sni` as the server name, and verify the server's certificate for the domain.
+    pub(crate) fn with_sni(self, sni: Option<String>) -> TlsConnector {
+        TlsConnector {
+            sni: sni.map(Arc::new),
+            ..self
+        }
+    }
+
+    /// The server name which is different from the verified domain, if any.
+    fn sni(&self) -> Option<&str> {
+        self.sni
+            .as_deref()
+            .map(String::as_str)
+            .filter(|sni| !sni.is_empty())
+    }
+
+    /// The server name given to native-tls, which sends it unless it was built with
+    /// `use_sni(false)`.
+    fn server_name(&self) -> &str {
+        self.sni().unwrap_or(&self.domain)
+    }
+
     pub(crate) async fn connect<I>(&self, io: I) -> Result<BoxedIo>
     where
         I: AsyncRead + AsyncWrite + Send + Unpin + 'static,
     {
         let tls_io = {
-            let io = self.connector.connect(&self.domain, io).await?;
+            let connector = self.connector.current();
+            let handshake = connector.connect(self.server_name(), io);
+            let io = match self.handshake_timeout {
+                Some(timeout) => tokio::time::timeout(timeout, handshake)
+                    .await
+                    .map_err(|_| Error::TlsHandshakeTimeout(timeout))?,
+                None => handshake.await,
+            };
+            let io = io.map_err(|e| {
+                Error::TlsHandshake(Box::new(TlsHandshakeError::new(
+                    self.domain.to_string(),
+                    Some(e),
+                )))
+            })?;
 
             match io.get_ref().negotiated_alpn()? {
                 Some(b) if b == b"h2" => (),
-                _ => return Err(Error::H2NotNegotiated),
+                protocol => {
+                    let mut error = TlsHandshakeError::new(self.domain.to_string(), None);
+                    error.alpn_protocol = Some(protocol);
+                    #[cfg(feature = "x509")]
+                    {
+                        error.peer_certificate = io
+                            .get_ref()
+                            .peer_certificate()?
+                            .and_then(|cert| cert.to_der().ok())
+                            .and_then(|der| crate::server::PeerCertificate::from_der(&der));
+                    }
+                    return Err(Error::H2NotChosen(Box::new(error)));
+                }
             };
 
+            #[cfg(feature = "x509")]
+            if let Some(expected) = &self.spiffe_id {
+                let cert = io.get_ref().peer_certificate()?;
+                let actual = cert
+                    .and_then(|cert| cert.to_der().ok())
+                    .and_then(|der| crate::server::PeerCertificate::from_der(&der));
+                let actual = actual.as_ref().and_then(|cert| cert.spiffe_id());
+                if actual != Some(expected.as_str()) {
+                    tracing::debug!(?actual, %expected, "server's SPIFFE ID did not match");
+                    return Err(Error::SpiffeIdRejected);
+                }
+            }
+
+            #[cfg(feature = "x509")]
+            if self.sni().is_some() {
+                let cert = io
+                    .get_ref()
+                    .peer_certificate()?
+                    .and_then(|cert| cert.to_der().ok())
+                    .and_then(|der| crate::server::PeerCertificate::from_der(&der));
+                if !cert
+                    .as_ref()
+                    .is_some_and(|cert| cert.matches_name(&self.domain))
+                {
+                    let mut error = TlsHandshakeError::new(self.domain.to_string(), None);
+                    error.peer_certificate = cert;
+                    return Err(Error::TlsHandshake(Box::new(error)));
+                }
+            }
+
             BoxedIo::new(io)
         };
 
@@ -60,6 +235,85 @@ impl TlsConnector {
     }
 }
 
+/// Details of a failed TLS handshake with a server, see [`Error::TlsHandshake`] and
+/// [`Error::H2NotChosen`].
+///
+/// The server's certificate is only known if the handshake completed, since native-tls doesn't
+/// expose a certificate which failed verification.
+#[derive(Debug)]
+pub struct TlsHandshakeError {
+    domain: String,
+    // The protocol chosen with ALPN, if the handshake completed.
+    alpn_protocol: Option<Option<Vec<u8>>>,
+    #[cfg(feature = "x509")]
+    peer_certificate: Option<crate::server::PeerCertificate>,
+    source: Option<native_tls::Error>,
+}
+
+impl TlsHandshakeError {
+    fn new(domain: String, source: Option<native_tls::Error>) -> Self {
+        TlsHandshakeError {
+            domain,
+            alpn_protocol: None,
+            #[cfg(feature = "x509")]
+            peer_certificate: None,
+            source,
+        }
+    }
+
+    /// The domain the server's certificate was verified for.
+    pub fn domain(&self) -> &str {
+        &self.domain
+    }
+
+    /// The protocol the server chose with ALPN, if the handshake completed and the server
+    /// chose one.
+    pub fn alpn_protocol(&self) -> Option<&[u8]> {
+        self.alpn_protocol.as_ref()?.as_deref()
+    }
+
+    /// A summary of the certificate the server presented, if the handshake completed.
+    #[cfg(feature = "x509")]
+    pub fn peer_certificate(&self) -> Option<&crate::server::PeerCertificate> {
+        self.peer_certificate.as_ref()
+    }
+}
+
+impl fmt::Display for TlsHandshakeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "expected a certificate for `{}`", self.domain)?;
+        match &self.alpn_protocol {
+            Some(Some(protocol)) => write!(
+                f,
+                "; the server chose `{}` rather than `h2` with ALPN",
+                String::from_utf8_lossy(protocol)
+            )?,
+            Some(None) => write!(f, "; the server did not choose a protocol with ALPN")?,
+            None => {}
+        }
+        #[cfg(feature = "x509")]
+        if let Some(cert) = &self.peer_certificate {
+            write!(f, "; the server presented `{}`", cert.subject())?;
+            let names: Vec<_> = cert
+                .dns_names()
+                .iter()
+                .chain(cert.uris())
+                .map(String::as_str)
+                .collect();
+            if !names.is_empty() {
+                write!(f, " for {}", names.join(", "))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl StdError for TlsHandshakeError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        self.source.as_ref().map(|e| e as _)
+    }
+}
+
 impl fmt::Debug for TlsConnector {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("TlsConnector").finish()
@@ -67,10 +321,10 @@ impl fmt::Debug for TlsConnector {
 }
 
 #[derive(Clone)]
-pub(crate) struct TlsAcceptor(Arc<tokio_native_tls::TlsAcceptor>);
+pub(crate) struct TlsAcceptor(ReloadableTls<tokio_native_tls::TlsAcceptor>);
 
 impl TlsAcceptor {
-    pub(crate) fn new(acceptor: Arc<tokio_native_tls::TlsAcceptor>) -> Self {
+    pub(crate) fn new(acceptor: ReloadableTls<tokio_native_tls::TlsAcceptor>) -> Self {
         Self(acceptor)
     }
 
@@ -78,7 +332,7 @@ impl TlsAcceptor {
     where
         IO: AsyncRead + AsyncWrite + Connected + Unpin + Send + 'static,
     {
-        let acceptor = self.0.clone();
+        let acceptor = self.0.current();
         acceptor.accept(io).await.map_err(Into::into)
     }
 }